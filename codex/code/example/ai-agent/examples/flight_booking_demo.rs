@@ -6,8 +6,28 @@
 // 2. 与模型交互，触发工具调用
 // 3. 使用模型生成的参数调用外部函数
 // 4. 将结果返回给模型，生成自然语言回复
-
-use simple_ai_agent::{Agent, ModelClient};
+// 5. 结构化输出：让模型以符合 JSON Schema 的 JSON 返回行程，供前端直接使用
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use simple_ai_agent::settings::{CliOverrides, Settings};
+use simple_ai_agent::Agent;
+use std::io::Write;
+use std::sync::Arc;
+
+/// 机器可读的行程
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+struct Itinerary {
+    /// 出发城市
+    departure: String,
+    /// 到达城市
+    destination: String,
+    /// 出发日期（YYYY-MM-DD）
+    date: String,
+    flight_number: String,
+    /// 票价（元），查询不到时为 null
+    price: Option<f64>,
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -22,22 +42,16 @@ async fn main() -> anyhow::Result<()> {
     println!("🛫 航班查询系统启动...\n");
     println!("═════════════════════════════════════════════\n");
 
-    // 从环境变量获取配置
-    let api_key = std::env::var("OPENAI_API_KEY")
-        .unwrap_or_else(|_| {
-            eprintln!("⚠️  警告: 未设置 OPENAI_API_KEY 环境变量");
-            std::process::exit(1);
-        });
-
-    let model = std::env::var("MODEL").unwrap_or_else(|_| "glm-4-tools".to_string());
-    let base_url = std::env::var("API_BASE_URL")
-        .unwrap_or_else(|_| "https://open.bigmodel.cn/api/paas/v4/".to_string());
+    // 与主程序共用分层配置（配置文件、profile、环境变量）
+    let settings = Settings::load(&std::env::current_dir()?, &CliOverrides::default())?;
+    let model_client = settings.model_client().unwrap_or_else(|e| {
+        eprintln!("⚠️  警告: {}", e);
+        std::process::exit(1);
+    });
 
-    // 创建模型客户端
-    let model_client = ModelClient::new_with_config(api_key, model, base_url);
-
-    // 创建智能体
-    let mut agent = Agent::new(model_client);
+    // 创建智能体（路径访问策略同样来自配置）
+    let path_policy = settings.path_policy(&std::env::current_dir()?)?;
+    let mut agent = Agent::new_with_policy(model_client, Arc::new(path_policy));
 
     println!("💡 智能体就绪，可以开始查询航班信息\n");
     println!("═════════════════════════════════════════════\n");
@@ -50,7 +64,7 @@ async fn main() -> anyhow::Result<()> {
     println!("═════════════════════════════════════════════\n");
 
     // 演示对话流程
-    let demo_queries = vec![
+    let demo_queries = [
         "帮我查询2024年1月20日从北京前往上海的航班",
         "这趟航班的价格是多少？",
     ];
@@ -80,6 +94,16 @@ async fn main() -> anyhow::Result<()> {
         tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
     }
 
+    // 结构化输出：返回值直接是 Itinerary
+    println!("🧾 结构化行程：");
+    match agent
+        .run_structured::<Itinerary>("把刚才查询的航班整理成行程")
+        .await
+    {
+        Ok(itinerary) => println!("{}\n", serde_json::to_string_pretty(&itinerary)?),
+        Err(e) => eprintln!("❌ 错误: {}\n", e),
+    }
+
     println!("═════════════════════════════════════════════");
     println!("✨ 演示完成！");
     println!("═════════════════════════════════════════════\n");
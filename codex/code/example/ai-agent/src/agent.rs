@@ -1,12 +1,43 @@
 // 智能体核心实现 - 简化版 Codex + AgentControl
 
-use crate::client::ModelClient;
-use crate::protocol::{AgentStatus, AssistantMessage, ToolCall, UserMessage};
-use crate::tools::ToolRegistry;
+use crate::budget::{Budget, BudgetExceeded, BudgetSpent};
+use crate::client::{ApiError, ChatResponse, ModelClient, ResponseStream};
+use crate::config::ApprovalPolicy;
+use crate::protocol::{
+    AgentEvent, AgentStatus, ApprovalResponse, AssistantMessage, ContentPart, ImageAttachment, MessageContent,
+    ReasoningItem, ReviewDecision, ToolCall, ToolDefinition, ToolResult, UserMessage,
+};
+use crate::shell_session::{ShellSessionManager, ShellSessionTool};
+use crate::tools::{summary_line, ToolCatalog, ToolEffect, ToolExecutor, ToolOutputSink, ToolRegistry};
 use crate::flight_tools::{GetFlightNumberTool, GetTicketPriceTool};
+use crate::image_tools::{ImageAttachments, ViewImageTool};
+use crate::path_policy::PathPolicy;
+use crate::request_options::{RequestOptions, ToolChoice};
+use crate::router::{Candidate, ModelRoute, ModelRouter, RequestKind, RouteReason};
+use crate::session::{message_role, MessageRole};
+use crate::structured::{self, MAX_STRUCTURED_RETRIES};
+use crate::usage::{PriceTable, TokenUsage, UsageTotals};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
 use serde_json::{json, Value};
+use std::collections::HashSet;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Instant;
+use tokio::sync::{mpsc, RwLock};
+
+/// /compact 时请模型总结对话的提示
+const COMPACT_PROMPT: &str = "请总结到目前为止的对话，供之后继续对话时作为上下文使用。\
+保留用户的目标和要求、已经做出的决定、涉及的文件和命令、工具调用的关键结果以及尚未完成的事项，\
+省略寒暄和重复内容。只输出摘要本身。";
+
+/// 压缩后替换对话历史的摘要消息前缀
+const COMPACT_SUMMARY_PREFIX: &str = "以下是此前对话的摘要：\n\n";
+
+/// 一次模型请求的响应
+enum ModelResponse {
+    Stream(ResponseStream),
+    Complete(ChatResponse),
+}
 
 /// 智能体状态
 #[derive(Debug, Clone)]
@@ -18,67 +49,194 @@ pub struct AgentState {
 /// 简化版智能体（结合 Codex 和 AgentControl 的功能）
 pub struct Agent {
     model_client: ModelClient,
+    /// 按请求类型选择的模型和备用模型
+    router: ModelRouter,
+    /// 最近一次请求实际使用的模型，主模型时为 None
+    route: Option<ModelRoute>,
     tool_registry: ToolRegistry,
+    /// help 工具读取的工具目录，每次请求前按注册表刷新
+    tool_catalog: Arc<ToolCatalog>,
+    shell_sessions: Arc<ShellSessionManager>,
+    /// view_image 工具附加的图片，本批工具调用结束后写入对话历史
+    tool_images: Arc<ImageAttachments>,
+    /// 用户附加的图片（/image、--image），随下一条用户消息发送
+    pending_images: Vec<ContentPart>,
     state: Arc<RwLock<AgentState>>,
-    max_turns: usize,
+    /// 本轮已发出的模型请求次数
     current_turn: usize,
+    /// 每轮和整个会话的预算上限
+    budget: Budget,
+    /// 本轮开始的时间和已执行的工具调用次数（用于预算检查）
+    turn_started: Instant,
+    turn_tool_calls: u64,
+    approval_policy: ApprovalPolicy,
+    /// 前端答复审批请求的通道；未设置时需要审批的调用一律拒绝
+    approval_rx: Option<mpsc::UnboundedReceiver<ApprovalResponse>>,
+    /// 本次会话中已被“总是批准”的工具
+    approved_tools: HashSet<String>,
+    /// 结构化输出的格式要求，只在请求时追加到系统提示，不写入对话历史
+    response_instruction: Option<String>,
+    /// 计算费用用的模型价格表
+    price_table: PriceTable,
+    /// 本轮和整个会话的累计用量
+    turn_usage: UsageTotals,
+    session_usage: UsageTotals,
 }
 
 impl Agent {
+    /// 创建智能体（以当前目录为工作区，使用默认路径策略）
     #[allow(dead_code)]
-    pub fn new(model_client: ModelClient) -> Self {
+    pub fn new(model_client: ModelClient) -> Result<Self, anyhow::Error> {
+        Ok(Self::new_with_policy(model_client, Arc::new(PathPolicy::for_current_dir()?)))
+    }
+
+    /// 创建智能体（自定义文件工具的路径访问策略）
+    pub fn new_with_policy(model_client: ModelClient, path_policy: Arc<PathPolicy>) -> Self {
         let mut tool_registry = ToolRegistry::new();
+        let shell_sessions = Arc::new(ShellSessionManager::default());
+        let tool_images = Arc::new(ImageAttachments::default());
 
         // 注册内置工具
         println!("\n🔧 初始化工具系统...");
-        tool_registry.register(crate::tools::ShellTool);
+        tool_registry.register(crate::tools::ShellTool::new());
+        tool_registry.register(ShellSessionTool::new(shell_sessions.clone()));
         tool_registry.register(crate::tools::CurrentTimeTool);
-        tool_registry.register(crate::tools::ReadFileTool);
-        tool_registry.register(crate::tools::HelpTool::new(vec![
-            "shell".to_string(),
-            "current_time".to_string(),
-            "read_file".to_string(),
-            "help".to_string(),
-            "get_flight_number".to_string(),
-            "get_ticket_price".to_string(),
-        ]));
+        tool_registry.register(crate::tools::ReadFileTool::new(path_policy.clone()));
+        tool_registry.register(crate::file_tools::WriteFileTool::new(path_policy.clone()));
+        tool_registry.register(crate::file_tools::ApplyPatchTool::new(path_policy.clone()));
+        tool_registry.register(crate::search_tools::ListDirTool::new(path_policy.clone()));
+        tool_registry.register(crate::search_tools::GlobTool::new(path_policy.clone()));
+        tool_registry.register(crate::search_tools::GrepTool::new(path_policy.clone()));
+        tool_registry.register(ViewImageTool::new(path_policy, tool_images.clone()));
+        let tool_catalog = Arc::new(ToolCatalog::default());
+        tool_registry.register(crate::tools::HelpTool::new(tool_catalog.clone()));
 
         // 注册航班查询工具（基于 ChatGLM 教程）
         tool_registry.register(GetFlightNumberTool::new());
-        tool_registry.register(GetTicketPriceTool::default());
+        tool_registry.register(GetTicketPriceTool);
 
         println!("  ✅ 工具系统初始化完成\n");
 
-
         Self {
             model_client,
+            router: ModelRouter::default(),
+            route: None,
             tool_registry,
+            tool_catalog,
+            shell_sessions,
+            tool_images,
+            pending_images: Vec::new(),
             state: Arc::new(RwLock::new(AgentState {
                 status: AgentStatus::Idle,
                 conversation: Vec::new(),
             })),
-            max_turns: 10,
             current_turn: 0,
+            budget: Budget::default(),
+            turn_started: Instant::now(),
+            turn_tool_calls: 0,
+            approval_policy: ApprovalPolicy::Never,
+            approval_rx: None,
+            approved_tools: HashSet::new(),
+            response_instruction: None,
+            price_table: PriceTable::default(),
+            turn_usage: UsageTotals::default(),
+            session_usage: UsageTotals::default(),
         }
     }
 
+    /// 注册额外的工具（例如 MCP 服务器提供的工具）
+    pub fn register_tool<T>(&mut self, tool: T)
+    where
+        T: ToolExecutor + 'static,
+    {
+        self.tool_registry.register(tool);
+    }
+
+    pub fn tool_registry(&self) -> &ToolRegistry {
+        &self.tool_registry
+    }
+
+    /// 当前使用的模型
+    pub fn model(&self) -> &str {
+        self.model_client.model()
+    }
+
+    /// 切换模型，对话历史保留
+    pub fn set_model(&mut self, model: impl Into<String>) {
+        self.model_client.set_model(model);
+    }
+
+    /// 设置工具审批策略
+    pub fn set_approval_policy(&mut self, policy: ApprovalPolicy) {
+        self.approval_policy = policy;
+    }
+
+    pub fn approval_policy(&self) -> ApprovalPolicy {
+        self.approval_policy
+    }
+
+    /// 设置模型价格表（用于计算费用）
+    pub fn set_price_table(&mut self, price_table: PriceTable) {
+        self.price_table = price_table;
+    }
+
+    /// 设置每轮和整个会话的预算上限
+    pub fn set_budget(&mut self, budget: Budget) {
+        self.budget = budget;
+    }
+
+    pub fn budget(&self) -> &Budget {
+        &self.budget
+    }
+
+    /// 本会话的累计用量
+    pub fn usage(&self) -> &UsageTotals {
+        &self.session_usage
+    }
+
+    /// 最近一轮的用量
+    pub fn last_turn_usage(&self) -> &UsageTotals {
+        &self.turn_usage
+    }
+
+    /// 恢复已保存会话的累计用量
+    pub fn restore_usage(&mut self, usage: UsageTotals) {
+        self.session_usage = usage;
+        self.turn_usage = UsageTotals::default();
+    }
+
+    /// 创建审批通道：收到 `ApprovalRequest` 事件后，前端通过返回的 sender 答复
+    pub fn approval_channel(&mut self) -> mpsc::UnboundedSender<ApprovalResponse> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.approval_rx = Some(rx);
+        tx
+    }
+
+    /// 只保留满足条件的工具
+    pub fn retain_tools<F>(&mut self, keep: F)
+    where
+        F: FnMut(&str) -> bool,
+    {
+        self.tool_registry.retain(keep);
+    }
+
     /// 处理用户消息（流式输出版本）
     #[allow(dead_code)]
     pub async fn process_message_stream<F>(&mut self, user_input: &str, mut callback: F)
     where
-        F: FnMut(&str),
+        F: FnMut(&str) + Send,
     {
         // 更新状态
-        {
-            let mut state = self.state.write().await;
-            state.status = AgentStatus::Thinking;
-            state.conversation.push(json!(UserMessage {
-                content: user_input.to_string(),
-            }));
-        }
+        self.push_user_message(user_input).await;
 
         // 运行智能体循环（流式版本）
-        let _ = self.run_agent_loop_stream(user_input, &mut callback).await;
+        let _ = self
+            .run_agent_loop_stream(user_input, |event| {
+                if let AgentEvent::TextDelta { text } = event {
+                    callback(&text);
+                }
+            })
+            .await;
     }
 
     /// 处理用户消息（流式输出版本） - 返回 Result 版本
@@ -88,81 +246,219 @@ impl Agent {
         mut callback: F,
     ) -> Result<String, anyhow::Error>
     where
-        F: FnMut(&str),
+        F: FnMut(&str) + Send,
     {
         // 更新状态
-        {
-            let mut state = self.state.write().await;
-            state.status = AgentStatus::Thinking;
-            state.conversation.push(json!(UserMessage {
-                content: user_input.to_string(),
-            }));
-        }
+        self.push_user_message(user_input).await;
 
         // 运行智能体循环（流式版本）
-        self.run_agent_loop_stream(user_input, &mut callback).await
+        self.run_agent_loop_stream(user_input, |event| {
+            if let AgentEvent::TextDelta { text } = event {
+                callback(&text);
+            }
+        })
+        .await
+    }
+
+    /// 处理用户消息（事件版本） - 文本片段、工具调用和工具增量输出都以 AgentEvent 形式回调
+    pub async fn process_message_with_events<F>(
+        &mut self,
+        user_input: &str,
+        callback: F,
+    ) -> Result<String, anyhow::Error>
+    where
+        F: FnMut(AgentEvent) + Send,
+    {
+        // 上一轮被中断时可能还留着它的请求设置
+        self.clear_turn_settings();
+        self.run_turn(user_input, callback).await
+    }
+
+    /// 写入用户消息并运行一轮（调用方负责设置本轮的请求参数）
+    async fn run_turn<F>(&mut self, user_input: &str, callback: F) -> Result<String, anyhow::Error>
+    where
+        F: FnMut(AgentEvent) + Send,
+    {
+        // 更新状态
+        self.push_user_message(user_input).await;
+
+        self.run_agent_loop_stream(user_input, callback).await
+    }
+
+    /// 处理用户消息，本轮使用叠加了 options 的请求参数（如指定 temperature 或强制调用某个工具）
+    pub async fn process_message_with_options<F>(
+        &mut self,
+        user_input: &str,
+        options: RequestOptions,
+        callback: F,
+    ) -> Result<String, anyhow::Error>
+    where
+        F: FnMut(AgentEvent) + Send,
+    {
+        self.check_tool_choice(&options)?;
+        self.clear_turn_settings();
+        self.model_client.set_turn_options(options)?;
+        let result = self.run_turn(user_input, callback).await;
+        self.clear_turn_settings();
+        result
+    }
+
+    /// 处理用户消息，并把最终回复解析为 T（结构化输出）
+    ///
+    /// 接口支持时以 response_format 约束输出，否则只靠提示中的 JSON Schema；回复无法解析为 T 时
+    /// 把错误告诉模型并要求重新输出，最多 `MAX_STRUCTURED_RETRIES` 次。工具照常可用。
+    pub async fn run_structured<T>(&mut self, user_input: &str) -> Result<T, anyhow::Error>
+    where
+        T: DeserializeOwned + JsonSchema,
+    {
+        self.run_structured_with_events(user_input, |_| {}).await
+    }
+
+    /// 结构化输出（事件版本）
+    pub async fn run_structured_with_events<T, F>(&mut self, user_input: &str, mut callback: F) -> Result<T, anyhow::Error>
+    where
+        T: DeserializeOwned + JsonSchema,
+        F: FnMut(AgentEvent) + Send,
+    {
+        let (name, schema) = structured::schema_for::<T>();
+        let response_schema = structured::supports_response_format(&schema).then(|| (name, schema.clone()));
+        self.clear_turn_settings();
+        self.model_client.set_response_schema(response_schema);
+        self.response_instruction = Some(structured::instruction(&schema));
+        let result = self.run_structured_turns(user_input, &mut callback).await;
+        self.clear_turn_settings();
+        result
+    }
+
+    /// 清除只对一次调用生效的请求设置（本轮参数、结构化输出的 schema 和格式要求）
+    ///
+    /// 调用结束时清除；轮次被中断时由 abort_turn 或下一次调用清除
+    fn clear_turn_settings(&mut self) {
+        self.model_client.clear_turn_options();
+        self.model_client.set_response_schema(None);
+        self.response_instruction = None;
+    }
+
+    /// 运行一轮对话并解析最终回复，不符合 schema 时追加一轮要求重新输出
+    async fn run_structured_turns<T>(
+        &mut self,
+        user_input: &str,
+        on_event: &mut (dyn FnMut(AgentEvent) + Send),
+    ) -> Result<T, anyhow::Error>
+    where
+        T: DeserializeOwned,
+    {
+        let mut input = user_input.to_string();
+        let mut retries = 0;
+        loop {
+            self.run_turn(&input, &mut *on_event).await?;
+            let reply = self.last_reply().await.unwrap_or_default();
+            match structured::parse_reply::<T>(&reply) {
+                Ok(value) => return Ok(value),
+                Err(e) if retries < MAX_STRUCTURED_RETRIES => {
+                    println!("\n⚠️  回复不符合 JSON Schema（{}），要求模型重新输出", e);
+                    retries += 1;
+                    input = structured::retry_prompt(&e);
+                }
+                Err(e) => {
+                    return Err(anyhow::anyhow!(
+                        "模型的回复在重试 {} 次后仍不符合 JSON Schema: {}",
+                        MAX_STRUCTURED_RETRIES,
+                        e
+                    ))
+                }
+            }
+        }
+    }
+
+    /// 对话历史中最后一条 AI 消息的文本
+    async fn last_reply(&self) -> Option<String> {
+        let state = self.state.read().await;
+        state
+            .conversation
+            .iter()
+            .rev()
+            .find(|msg| message_role(msg) == MessageRole::Assistant)
+            .and_then(|msg| msg["content"].as_str().map(str::to_string))
     }
 
     /// 处理用户消息（类似 AgentControl::send_prompt）
     #[allow(dead_code)]
     pub async fn process_message(&mut self, user_input: &str) -> Result<String, anyhow::Error> {
         // 更新状态
-        {
-            let mut state = self.state.write().await;
-            state.status = AgentStatus::Thinking;
-            state.conversation.push(json!(UserMessage {
-                content: user_input.to_string(),
-            }));
-        }
+        self.push_user_message(user_input).await;
 
         // 运行智能体循环
         self.run_agent_loop(user_input).await
     }
 
+    /// 把用户输入写入对话历史；有附加的图片时以“文本 + 图片”的内容片段发送
+    async fn push_user_message(&mut self, user_input: &str) {
+        let content = if self.pending_images.is_empty() {
+            MessageContent::from(user_input)
+        } else {
+            let mut parts = vec![ContentPart::text(user_input)];
+            parts.append(&mut self.pending_images);
+            MessageContent::Parts(parts)
+        };
+        let mut state = self.state.write().await;
+        state.status = AgentStatus::Thinking;
+        state.conversation.push(json!(UserMessage { content }));
+    }
+
+    /// 附加一张图片，随下一条用户消息发送
+    pub fn attach_image(&mut self, image: ContentPart) {
+        self.pending_images.push(image);
+    }
+
+    /// 已附加、尚未发送的图片数
+    pub fn pending_image_count(&self) -> usize {
+        self.pending_images.len()
+    }
+
+    /// 设置默认的请求参数（采样参数、stop、seed、tool_choice 等），按接口支持的参数校验
+    pub fn set_request_options(&mut self, options: RequestOptions) -> Result<(), anyhow::Error> {
+        self.check_tool_choice(&options)?;
+        self.model_client.set_request_options(options)
+    }
+
+    /// tool_choice 指定的工具必须已注册
+    fn check_tool_choice(&self, options: &RequestOptions) -> Result<(), anyhow::Error> {
+        match &options.tool_choice {
+            Some(ToolChoice::Function(name)) if self.tool_registry.get(name).is_none() => {
+                Err(anyhow::anyhow!("tool_choice 指定的工具 '{}' 未注册", name))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// 设置模型路由和备用模型
+    pub fn set_router(&mut self, router: ModelRouter) {
+        self.router = router;
+    }
+
+    /// 配置的路由和备用模型（/status 使用）
+    pub fn router(&self) -> &ModelRouter {
+        &self.router
+    }
+
     /// 智能体主循环（流式版本 - 真正的异步流式）
     async fn run_agent_loop_stream<F>(&mut self, _initial_input: &str, mut callback: F) -> Result<String, anyhow::Error>
     where
-        F: FnMut(&str),
+        F: FnMut(AgentEvent) + Send,
     {
-        self.current_turn = 0;
+        self.start_turn();
         let mut full_response = String::new();
 
         loop {
-            self.current_turn += 1;
-            if self.current_turn > self.max_turns {
-                let msg = "\n🔄 已达到最大对话轮次，建议重新开始对话。";
-                callback(msg);
-                return Ok(msg.to_string());
+            // 发出请求前检查预算，超出时结束本轮（工具结果都已写入，对话历史完整）
+            if let Some(exceeded) = self.check_budget() {
+                return Err(self.stop_for_budget(exceeded, &mut callback).await);
             }
+            self.current_turn += 1;
 
-            // 获取工具定义
-            let tools = self.tool_registry.list_definitions();
-            let tools_json: Vec<Value> = tools
-                .iter()
-                .map(|t| json!({
-                    "type": "function",
-                    "function": {
-                        "name": t.name,
-                        "description": t.description,
-                        "parameters": t.parameters
-                    }
-                }))
-                .collect();
-
-            // 添加系统提示以强制使用工具
-            let mut messages = {
-                let state = self.state.read().await;
-                state.conversation.clone()
-            };
-
-            // 在对话开始时插入系统提示
-            if messages.is_empty() || !messages[0].get("role").is_some_and(|r| r == "system") {
-                let system_prompt = json!({
-                    "role": "system",
-                    "content": "You are a helpful AI assistant. When users ask for information that can be obtained through tools, you MUST use the available tools.\n\nAvailable tools:\n- current_time: Get current date and time\n- shell: Execute shell commands\n- read_file: Read text file contents\n- get_flight_number: Query flight number by departure, destination, and date\n- get_ticket_price: Query ticket price by flight number and date\n\nDo not guess or make up information. Always use tools when they are relevant. For flight queries, ask for missing required information if the user doesn't provide complete details."
-                });
-                messages.insert(0, system_prompt);
-            }
+            // 带系统提示的对话历史和当前注册的工具定义
+            let (messages, tools_json) = self.request_context().await;
 
             // 更新状态为思考
             {
@@ -170,20 +466,18 @@ impl Agent {
                 state.status = AgentStatus::Thinking;
             }
 
-            // 构建消息历史
-            let messages = {
-                let state = self.state.read().await;
-                state.conversation.clone()
+            // 调用大模型（真流式，messages 为带系统提示的对话历史）
+            let kind = self.request_kind();
+            let ModelResponse::Stream(mut stream) =
+                self.send_request(messages, Some(tools_json), kind, true, &mut callback).await?
+            else {
+                unreachable!("流式请求返回了完整响应");
             };
 
-            // 调用大模型（真流式）
-            let mut stream = self
-                .model_client
-                .chat_completion_stream(messages, Some(tools_json))
-                .await?;
-
             let mut turn_response = String::new();
+            let mut turn_reasoning = String::new();
             let mut final_tool_calls: Option<Vec<crate::protocol::ToolCall>> = None;
+            let mut request_usage: Option<TokenUsage> = None;
 
             // 逐个处理流式事件
             use futures::StreamExt;
@@ -192,42 +486,43 @@ impl Agent {
 
                 match event {
                     crate::client::SseEvent::TextDelta(text) => {
-                        callback(&text);
                         turn_response.push_str(&text);
                         full_response.push_str(&text);
+                        callback(AgentEvent::TextDelta { text });
                     }
+                    // 思考内容单独保存和展示，不计入回复
                     crate::client::SseEvent::ReasoningDelta(text) => {
-                        callback(&text);
-                        turn_response.push_str(&text);
-                        full_response.push_str(&text);
+                        turn_reasoning.push_str(&text);
+                        callback(AgentEvent::ReasoningDelta { text });
                     }
                     crate::client::SseEvent::ToolCalls(calls) => {
                         final_tool_calls = Some(calls);
                     }
+                    // 有的接口在每个数据块中都给出累计用量，以最后一次为准
+                    crate::client::SseEvent::Usage(usage) => {
+                        request_usage = Some(usage);
+                    }
                     crate::client::SseEvent::Done => {
                         break;
                     }
                 }
             }
 
-            // 添加助手响应到对话历史
-            {
-                let mut state = self.state.write().await;
-                state.status = AgentStatus::Idle;
-                state.conversation.push(json!(AssistantMessage {
-                    content: turn_response.clone(),
-                    tool_calls: final_tool_calls.clone(),
-                }));
+            if let Some(usage) = request_usage {
+                self.record_usage(usage, &mut callback);
             }
 
+            // 添加助手响应到对话历史（思考内容作为独立条目放在前面）
+            self.record_response(Some(turn_reasoning), turn_response, final_tool_calls.clone())
+                .await;
+
             // 检查是否需要执行工具
             if let Some(tool_calls) = final_tool_calls {
                 if !tool_calls.is_empty() {
                     println!("\n🔧 收到工具调用: {} 个工具", tool_calls.len());
-                    // 执行工具调用
-                    for call in &tool_calls {
-                        self.execute_tool_call(call).await?;
-                    }
+                    // 执行工具调用（按审批策略先征求用户同意）
+                    self.run_tool_calls(&tool_calls, &mut callback).await?;
+                    self.model_client.set_tool_choice_released(true);
 
                     // 继续循环以获取下一个响应
                     continue;
@@ -243,45 +538,52 @@ impl Agent {
         }
     }
 
+    /// 组装一次模型请求：对话历史（开头插入系统提示）和工具定义
+    ///
+    /// 工具列表在请求时从注册表生成，随后注册、过滤掉的工具都会反映在系统提示和 help 中
+    async fn request_context(&self) -> (Vec<Value>, Vec<Value>) {
+        let tools = self.tool_registry.list_definitions();
+        self.tool_catalog.update(&tools);
+
+        let mut messages = {
+            let state = self.state.read().await;
+            state.conversation.clone()
+        };
+        if messages.is_empty() || !messages[0].get("role").is_some_and(|r| r == "system") {
+            messages.insert(0, json!({ "role": "system", "content": system_prompt(&tools) }));
+        }
+        if let Some(instruction) = &self.response_instruction {
+            let content = format!("{}\n\n{}", messages[0]["content"].as_str().unwrap_or_default(), instruction);
+            messages[0]["content"] = json!(content);
+        }
+
+        let tools_json = tools
+            .iter()
+            .map(|t| json!({
+                "type": "function",
+                "function": {
+                    "name": t.name,
+                    "description": t.description,
+                    "parameters": t.parameters
+                }
+            }))
+            .collect();
+        (messages, tools_json)
+    }
+
     /// 智能体主循环（类似 CodexThread 的事件循环）
     #[allow(dead_code)]
     async fn run_agent_loop(&mut self, _initial_input: &str) -> Result<String, anyhow::Error> {
-        self.current_turn = 0;
+        self.start_turn();
 
         loop {
-            self.current_turn += 1;
-            if self.current_turn > self.max_turns {
-                return Ok("\n🔄 已达到最大对话轮次，建议重新开始对话。".to_string());
+            if let Some(exceeded) = self.check_budget() {
+                return Err(self.stop_for_budget(exceeded, &mut |_| {}).await);
             }
+            self.current_turn += 1;
 
-            // 获取工具定义
-            let tools = self.tool_registry.list_definitions();
-            let tools_json: Vec<Value> = tools
-                .iter()
-                .map(|t| json!({
-                    "type": "function",
-                    "function": {
-                        "name": t.name,
-                        "description": t.description,
-                        "parameters": t.parameters
-                    }
-                }))
-                .collect();
-
-            // 添加系统提示以强制使用工具
-            let mut messages = {
-                let state = self.state.read().await;
-                state.conversation.clone()
-            };
-
-            // 在对话开始时插入系统提示
-            if messages.is_empty() || !messages[0].get("role").is_some_and(|r| r == "system") {
-                let system_prompt = json!({
-                    "role": "system",
-                    "content": "You are a helpful AI assistant. When users ask for information that can be obtained through tools, you MUST use the available tools.\n\nAvailable tools:\n- current_time: Get current date and time\n- shell: Execute shell commands\n- read_file: Read text file contents\n- get_flight_number: Query flight number by departure, destination, and date\n- get_ticket_price: Query ticket price by flight number and date\n\nDo not guess or make up information. Always use tools when they are relevant. For flight queries, ask for missing required information if the user doesn't provide complete details."
-                });
-                messages.insert(0, system_prompt);
-            }
+            // 带系统提示的对话历史和当前注册的工具定义
+            let (messages, tools_json) = self.request_context().await;
 
             // 更新状态为思考
             {
@@ -289,35 +591,28 @@ impl Agent {
                 state.status = AgentStatus::Thinking;
             }
 
-            // 构建消息历史
-            let messages = {
-                let state = self.state.read().await;
-                state.conversation.clone()
+            // 调用大模型（类似 ModelClient::stream）
+            let kind = self.request_kind();
+            let ModelResponse::Complete(response) =
+                self.send_request(messages, Some(tools_json), kind, false, &mut |_| {}).await?
+            else {
+                unreachable!("非流式请求返回了流");
             };
 
-            // 调用大模型（类似 ModelClient::stream）
-            let response = self
-                .model_client
-                .chat_completion(messages, Some(tools_json))
-                .await?;
+            if let Some(usage) = response.usage {
+                self.record_usage(usage, &mut |_| {});
+            }
 
             // 添加助手响应到对话历史
-            {
-                let mut state = self.state.write().await;
-                state.status = AgentStatus::Idle;
-                state.conversation.push(json!(AssistantMessage {
-                    content: response.content.clone(),
-                    tool_calls: response.tool_calls.clone(),
-                }));
-            }
+            self.record_response(response.reasoning.clone(), response.content.clone(), response.tool_calls.clone())
+                .await;
 
             // 检查是否需要执行工具
             if let Some(tool_calls) = response.tool_calls {
                 if !tool_calls.is_empty() {
                     // 执行工具调用
-                    for call in &tool_calls {
-                        self.execute_tool_call(call).await?;
-                    }
+                    self.run_tool_calls(&tool_calls, &mut |_| {}).await?;
+                    self.model_client.set_tool_choice_released(true);
 
                     // 继续循环以获取下一个响应
                     continue;
@@ -329,9 +624,281 @@ impl Agent {
         }
     }
 
+    /// 开始新的一轮：重置本轮的请求数、用量、耗时和工具调用计数
+    fn start_turn(&mut self) {
+        self.current_turn = 0;
+        self.turn_usage = UsageTotals::default();
+        self.turn_started = Instant::now();
+        self.turn_tool_calls = 0;
+        self.tool_images.take();
+        self.model_client.set_tool_choice_released(false);
+        self.route = None;
+    }
+
+    /// 本轮下一次请求的类型：执行过工具之后按工具轮次路由
+    fn request_kind(&self) -> RequestKind {
+        if self.turn_tool_calls > 0 {
+            RequestKind::ToolTurn
+        } else {
+            RequestKind::Chat
+        }
+    }
+
+    /// 发出一次模型请求：按请求类型选择模型，失败且错误允许切换时依次改用后面的候选
+    ///
+    /// 流式请求只在拿到响应之前切换；实际使用的模型记录在 `self.route`，变化时发出 `ModelRouted` 事件。
+    async fn send_request(
+        &mut self,
+        messages: Vec<Value>,
+        tools: Option<Vec<Value>>,
+        kind: RequestKind,
+        stream: bool,
+        on_event: &mut (dyn FnMut(AgentEvent) + Send),
+    ) -> Result<ModelResponse, anyhow::Error> {
+        let candidates = self.router.candidates(kind);
+        let mut failure: Option<String> = None;
+        for (index, &candidate) in candidates.iter().enumerate() {
+            let is_last = index + 1 == candidates.len();
+            let (client, profile) = match self.router.target_mut(candidate) {
+                None => (&self.model_client, None),
+                Some(target) => {
+                    // 路由的模型沿用本轮的请求参数和结构化输出设置
+                    if let Err(e) = target.client.inherit_turn_state(&self.model_client) {
+                        if is_last {
+                            return Err(e);
+                        }
+                        println!("\n⚠️  跳过模型 {}: {}", target.client.model(), e);
+                        continue;
+                    }
+                    (&target.client, Some(target.profile.clone()))
+                }
+            };
+            let model = client.model().to_string();
+            let result = if stream {
+                client
+                    .chat_completion_stream(messages.clone(), tools.clone())
+                    .await
+                    .map(ModelResponse::Stream)
+            } else {
+                client
+                    .chat_completion(messages.clone(), tools.clone())
+                    .await
+                    .map(ModelResponse::Complete)
+            };
+            match result {
+                Ok(response) => {
+                    let reason = match candidate {
+                        _ if failure.is_some() => Some(RouteReason::Fallback),
+                        Candidate::Compact => Some(RouteReason::Compact),
+                        Candidate::ToolTurns => Some(RouteReason::ToolTurns),
+                        Candidate::Primary | Candidate::Fallback(_) => None,
+                    };
+                    let route = reason.map(|reason| ModelRoute { model, profile, reason, error: failure });
+                    self.select_route(route, on_event);
+                    return Ok(response);
+                }
+                Err(e) if !is_last && e.downcast_ref::<ApiError>().is_some_and(ApiError::allows_fallback) => {
+                    println!("\n⚠️  模型 {} 请求失败，改用下一个模型: {}", model, e);
+                    failure = Some(format!("{}: {}", model, e));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(anyhow::anyhow!("没有可用的模型"))
+    }
+
+    /// 记录本次请求使用的模型，与上一次不同时发出 `ModelRouted` 事件
+    fn select_route(&mut self, route: Option<ModelRoute>, on_event: &mut (dyn FnMut(AgentEvent) + Send)) {
+        if let Some(route) = route.as_ref().filter(|route| self.route.as_ref() != Some(*route)) {
+            println!("\n🔀 改用模型 {}", route);
+            on_event(AgentEvent::ModelRouted { route: route.clone() });
+        }
+        self.route = route;
+    }
+
+    /// 按本轮已用的资源检查预算（在每次模型请求之前调用）
+    fn check_budget(&self) -> Option<BudgetExceeded> {
+        self.budget.check(&BudgetSpent {
+            requests: self.current_turn as u64,
+            elapsed: self.turn_started.elapsed(),
+            turn: &self.turn_usage,
+            session: &self.session_usage,
+        })
+    }
+
+    /// 发出 `BudgetExceeded` 事件，返回结束本轮的错误（可 downcast 为 `BudgetExceeded`）
+    async fn stop_for_budget(
+        &self,
+        exceeded: BudgetExceeded,
+        on_event: &mut (dyn FnMut(AgentEvent) + Send),
+    ) -> anyhow::Error {
+        {
+            let mut state = self.state.write().await;
+            state.status = AgentStatus::Idle;
+        }
+        on_event(AgentEvent::BudgetExceeded {
+            message: exceeded.to_string(),
+            limit: exceeded.limit,
+            used: exceeded.used,
+            max: exceeded.max,
+        });
+        anyhow::Error::new(exceeded)
+    }
+
+    /// 累计一次模型请求的用量并发出 `TokenUsage` 事件
+    fn record_usage(&mut self, usage: TokenUsage, on_event: &mut (dyn FnMut(AgentEvent) + Send)) {
+        // 按实际处理请求的模型计费
+        let model = self.route.as_ref().map_or_else(|| self.model().to_string(), |route| route.model.clone());
+        let cost = self.price_table.cost(&model, &usage);
+        self.turn_usage.record(&usage, cost);
+        self.session_usage.record(&usage, cost);
+        on_event(AgentEvent::TokenUsage {
+            model,
+            usage,
+            cost,
+            turn: self.turn_usage.clone(),
+            session: self.session_usage.clone(),
+        });
+    }
+
+    /// 把一次模型响应写入对话历史：思考内容（非空时）在前，AI 消息在后
+    async fn record_response(&self, reasoning: Option<String>, content: String, tool_calls: Option<Vec<ToolCall>>) {
+        let mut state = self.state.write().await;
+        state.status = AgentStatus::Idle;
+        if let Some(reasoning) = reasoning.filter(|reasoning| !reasoning.trim().is_empty()) {
+            state.conversation.push(json!(ReasoningItem { reasoning }));
+        }
+        state.conversation.push(json!(AssistantMessage { content, tool_calls, route: self.route.clone() }));
+    }
+
+    /// 依次审批并执行一组工具调用
+    ///
+    /// 被拒绝的调用不会执行，拒绝信息作为工具结果返回给模型；用户选择中止或工具调用次数超出预算时，
+    /// 剩余的调用同样写入结果（保持对话历史完整），然后以错误结束本轮。
+    async fn run_tool_calls(
+        &mut self,
+        tool_calls: &[ToolCall],
+        on_event: &mut (dyn FnMut(AgentEvent) + Send),
+    ) -> Result<(), anyhow::Error> {
+        for (index, call) in tool_calls.iter().enumerate() {
+            if let Some(exceeded) = self.budget.check_tool_call(self.turn_tool_calls) {
+                for call in &tool_calls[index..] {
+                    self.record_tool_result(call, "本轮工具调用次数已达到预算上限，工具未执行".to_string(), false, on_event)
+                        .await;
+                }
+                self.record_tool_images().await;
+                return Err(self.stop_for_budget(exceeded, on_event).await);
+            }
+            match self.review_tool_call(call, on_event).await {
+                ReviewDecision::Approved | ReviewDecision::ApprovedForSession => {
+                    self.turn_tool_calls += 1;
+                    self.execute_tool_call(call, on_event).await?;
+                }
+                ReviewDecision::Denied => {
+                    self.record_tool_result(call, "用户拒绝执行此工具调用".to_string(), false, on_event)
+                        .await;
+                }
+                ReviewDecision::Abort => {
+                    for call in &tool_calls[index..] {
+                        self.record_tool_result(call, "用户中止了本轮对话，工具未执行".to_string(), false, on_event)
+                            .await;
+                    }
+                    self.record_tool_images().await;
+                    return Err(anyhow::anyhow!("用户中止了本轮对话"));
+                }
+            }
+        }
+        self.record_tool_images().await;
+        Ok(())
+    }
+
+    /// 把本批工具附加的图片作为 `ImageAttachment` 写在工具结果之后
+    async fn record_tool_images(&self) {
+        let attached_images = self.tool_images.take();
+        if attached_images.is_empty() {
+            return;
+        }
+        let mut state = self.state.write().await;
+        state.conversation.push(json!(ImageAttachment { attached_images }));
+    }
+
+    /// 按审批策略决定工具调用能否执行，需要时发出 `ApprovalRequest` 并等待答复
+    async fn review_tool_call(
+        &mut self,
+        call: &ToolCall,
+        on_event: &mut (dyn FnMut(AgentEvent) + Send),
+    ) -> ReviewDecision {
+        if !self.requires_approval(&call.name) {
+            return ReviewDecision::Approved;
+        }
+        let Some(approval_rx) = self.approval_rx.as_mut() else {
+            tracing::warn!("工具 {} 需要审批，但当前前端不支持审批，已拒绝", call.name);
+            return ReviewDecision::Denied;
+        };
+
+        {
+            let mut state = self.state.write().await;
+            state.status = AgentStatus::WaitingApproval;
+        }
+        on_event(AgentEvent::ApprovalRequest {
+            call_id: call.id.clone(),
+            name: call.name.clone(),
+            arguments: call.arguments.clone(),
+            hint: self.tool_registry.get(&call.name).and_then(|tool| tool.approval_hint()),
+        });
+
+        // 忽略针对其他（已过期）请求的答复；通道关闭视为拒绝
+        let decision = loop {
+            match approval_rx.recv().await {
+                Some(response) if response.call_id == call.id => break response.decision,
+                Some(_) => continue,
+                None => break ReviewDecision::Denied,
+            }
+        };
+        if decision == ReviewDecision::ApprovedForSession {
+            self.approved_tools.insert(call.name.clone());
+        }
+        decision
+    }
+
+    /// 按审批策略，该工具的调用是否需要用户确认（已被“总是批准”的除外）
+    fn requires_approval(&self, name: &str) -> bool {
+        let effect = self.tool_registry.get(name).map(|tool| tool.effect()).unwrap_or(ToolEffect::Unknown);
+        self.approval_policy.requires_approval(effect) && !self.approved_tools.contains(name)
+    }
+
+    /// 审查不经过模型、由外部直接发起的工具调用（MCP 服务器的 tools/call）
+    ///
+    /// 每次调用视为只含一次工具调用的一轮，与 run_tool_calls 使用同样的预算和审批策略；
+    /// 这类调用无法交互确认，需要审批的工具一律拒绝。
+    pub fn review_direct_tool_call(&self, name: &str) -> Result<(), String> {
+        let spent = BudgetSpent {
+            requests: 0,
+            elapsed: std::time::Duration::ZERO,
+            turn: &UsageTotals::default(),
+            session: &self.session_usage,
+        };
+        if let Some(exceeded) = self.budget.check_tool_call(0).or_else(|| self.budget.check(&spent)) {
+            return Err(exceeded.to_string());
+        }
+        if self.requires_approval(name) {
+            return Err(format!(
+                "工具 '{}' 需要审批（审批策略: {}），但调用方无法交互确认，已拒绝",
+                name, self.approval_policy
+            ));
+        }
+        Ok(())
+    }
+
     /// 执行工具调用（类似 ToolRouter::dispatch）
+    ///
+    /// 工具运行期间的增量输出以 `ToolOutputDelta` 事件回调，最终结果写入对话历史。
     #[allow(dead_code)]
-    async fn execute_tool_call(&self, call: &ToolCall) -> Result<(), anyhow::Error> {
+    async fn execute_tool_call(
+        &self,
+        call: &ToolCall,
+        on_event: &mut (dyn FnMut(AgentEvent) + Send),
+    ) -> Result<(), anyhow::Error> {
         // 更新状态为执行工具
         {
             let mut state = self.state.write().await;
@@ -341,23 +908,71 @@ impl Agent {
         println!("\n🔧 调用工具: {} ({})", call.name, call.id);
         println!("🔧 工具参数: {}", call.arguments); // 调试输出
 
-        // 执行工具
-        let result = self.tool_registry.execute(call).await.map_err(|e| {
-            anyhow::anyhow!("工具执行失败: {}", e)
-        })?;
+        on_event(AgentEvent::ToolCallBegin {
+            call_id: call.id.clone(),
+            name: call.name.clone(),
+            arguments: call.arguments.clone(),
+        });
 
-        println!("  ✅ 工具结果: {}", result.content);
+        // 执行工具，同时转发增量输出
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let execution = self.tool_registry.execute_streaming(call, ToolOutputSink::new(tx));
+        tokio::pin!(execution);
 
-        // 将工具结果添加到对话历史
-        {
-            let mut state = self.state.write().await;
-            state.status = AgentStatus::Thinking;
-            state.conversation.push(json!(result));
+        let result = loop {
+            tokio::select! {
+                biased;
+                Some(chunk) = rx.recv() => on_event(AgentEvent::ToolOutputDelta {
+                    call_id: call.id.clone(),
+                    chunk,
+                }),
+                result = &mut execution => break result,
+            }
+        };
+        while let Ok(chunk) = rx.try_recv() {
+            on_event(AgentEvent::ToolOutputDelta {
+                call_id: call.id.clone(),
+                chunk,
+            });
+        }
+
+        // 工具失败（包括路径策略拒绝）不会中断对话，错误信息作为工具结果返回给模型
+        match result {
+            Ok(result) => {
+                println!("  ✅ 工具结果: {}", result.content);
+                self.record_tool_result(call, result.content, true, on_event).await;
+            }
+            Err(e) => {
+                println!("  ✗ 工具失败: {}", e);
+                self.record_tool_result(call, format!("错误: {}", e), false, on_event).await;
+            }
         }
 
         Ok(())
     }
 
+    /// 发出 `ToolCallEnd` 事件并把工具结果写入对话历史
+    async fn record_tool_result(
+        &self,
+        call: &ToolCall,
+        content: String,
+        success: bool,
+        on_event: &mut (dyn FnMut(AgentEvent) + Send),
+    ) {
+        on_event(AgentEvent::ToolCallEnd {
+            call_id: call.id.clone(),
+            output: content.clone(),
+            success,
+        });
+
+        let mut state = self.state.write().await;
+        state.status = AgentStatus::Thinking;
+        state.conversation.push(json!(ToolResult {
+            tool_call_id: call.id.clone(),
+            content,
+        }));
+    }
+
     /// 获取当前状态
     #[allow(dead_code)]
     pub async fn get_status(&self) -> AgentStatus {
@@ -365,6 +980,108 @@ impl Agent {
         state.status
     }
 
+    /// 当前对话历史的快照（用于保存会话）
+    pub async fn conversation(&self) -> Vec<Value> {
+        self.state.read().await.conversation.clone()
+    }
+
+    /// 恢复已保存的对话历史
+    pub async fn restore_conversation(&mut self, conversation: Vec<Value>) {
+        let mut state = self.state.write().await;
+        state.status = AgentStatus::Idle;
+        state.conversation = conversation;
+        self.current_turn = 0;
+    }
+
+    /// 撤销最近一轮对话：删除最后一条用户消息及其后的所有消息，返回删除的消息数
+    pub async fn undo_last_turn(&mut self) -> usize {
+        let mut state = self.state.write().await;
+        let Some(start) = state
+            .conversation
+            .iter()
+            .rposition(|msg| message_role(msg) == MessageRole::User)
+        else {
+            return 0;
+        };
+        let removed = state.conversation.len() - start;
+        state.conversation.truncate(start);
+        state.status = AgentStatus::Idle;
+        removed
+    }
+
+    /// 本轮被中断（如 Ctrl-C）后整理对话历史
+    ///
+    /// 模型要求每个工具调用都有对应的结果，因此为尚未执行完的工具调用补上结果，
+    /// 否则下一轮请求会被拒绝。
+    pub async fn abort_turn(&mut self) {
+        // 被中断的轮次没有机会清理自己的请求设置
+        self.clear_turn_settings();
+        let mut state = self.state.write().await;
+        state.status = AgentStatus::Idle;
+        let Some(index) = state
+            .conversation
+            .iter()
+            .rposition(|msg| message_role(msg) == MessageRole::Assistant)
+        else {
+            return;
+        };
+
+        let answered: HashSet<&str> = state.conversation[index + 1..]
+            .iter()
+            .filter_map(|msg| msg["tool_call_id"].as_str())
+            .collect();
+        let missing: Vec<String> = state.conversation[index]["tool_calls"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|call| call["id"].as_str())
+            .filter(|id| !answered.contains(id))
+            .map(str::to_string)
+            .collect();
+        for tool_call_id in missing {
+            state.conversation.push(json!(ToolResult {
+                tool_call_id,
+                content: "用户中断了本轮对话，工具未执行完成".to_string(),
+            }));
+        }
+    }
+
+    /// 压缩对话历史（类似 Codex 的 /compact）：请模型总结目前的对话，用摘要替换历史，返回摘要
+    pub async fn compact(&mut self) -> Result<String, anyhow::Error> {
+        let mut messages = self.conversation().await;
+        if messages.is_empty() {
+            return Err(anyhow::anyhow!("对话为空，无需压缩"));
+        }
+        messages.push(json!(UserMessage {
+            content: COMPACT_PROMPT.into(),
+        }));
+
+        let ModelResponse::Complete(response) =
+            self.send_request(messages, None, RequestKind::Compact, false, &mut |_| {}).await?
+        else {
+            unreachable!("非流式请求返回了流");
+        };
+        if let Some(usage) = response.usage {
+            self.record_usage(usage, &mut |_| {});
+        }
+        let summary = response.content.trim().to_string();
+        if summary.is_empty() {
+            return Err(anyhow::anyhow!("模型没有返回摘要，对话历史未改动"));
+        }
+
+        let mut state = self.state.write().await;
+        state.status = AgentStatus::Idle;
+        let mut summary_message = json!(UserMessage {
+            content: format!("{}{}", COMPACT_SUMMARY_PREFIX, summary).into(),
+        });
+        // 摘要由路由的模型生成时记录在摘要消息上（发送时忽略该字段）
+        if let Some(route) = &self.route {
+            summary_message["route"] = json!(route);
+        }
+        state.conversation = vec![summary_message];
+        Ok(summary)
+    }
+
     /// 重置对话
     #[allow(dead_code)]
     pub async fn reset(&mut self) {
@@ -372,12 +1089,31 @@ impl Agent {
         state.status = AgentStatus::Idle;
         state.conversation.clear();
         self.current_turn = 0;
+        self.approved_tools.clear();
+        self.pending_images.clear();
+
+        // 新对话使用新的 shell 会话
+        self.shell_sessions.kill().await;
     }
 }
 
+/// 系统提示：要求模型使用工具，并列出本次请求中可用的工具
+fn system_prompt(tools: &[ToolDefinition]) -> String {
+    let mut prompt = "You are a helpful AI assistant. When users ask for information that can be obtained through tools, you MUST use the available tools.\n\nAvailable tools:\n".to_string();
+    for tool in tools {
+        prompt.push_str(&format!("- {}: {}\n", tool.name, summary_line(&tool.description)));
+    }
+    prompt.push_str("\nDo not guess or make up information. Always use tools when they are relevant.");
+    if tools.iter().any(|tool| tool.name == "get_flight_number") {
+        prompt.push_str(" For flight queries, ask for missing required information if the user doesn't provide complete details.");
+    }
+    prompt
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::router::RouteTarget;
 
     #[tokio::test]
     async fn test_agent_creation() {
@@ -385,25 +1121,83 @@ mod tests {
             "test-key".to_string(),
             "gpt-4".to_string(),
         );
-        let agent = Agent::new(model_client);
+        let agent = Agent::new(model_client).unwrap();
 
         let status = agent.get_status().await;
         assert_eq!(status, AgentStatus::Idle);
     }
 
+    #[tokio::test]
+    async fn test_prompt_and_help_follow_registry() {
+        let mut agent = Agent::new(ModelClient::new("test-key".to_string(), "gpt-4".to_string())).unwrap();
+        agent.retain_tools(|name| !matches!(name, "shell" | "get_flight_number" | "get_ticket_price"));
+
+        let (messages, tools_json) = agent.request_context().await;
+        let prompt = messages[0]["content"].as_str().unwrap();
+        assert!(prompt.contains("- read_file: "));
+        assert!(!prompt.contains("- shell: "));
+        assert!(!prompt.contains("flight"));
+        assert_eq!(tools_json.len(), agent.tool_registry().names().len());
+
+        let help = agent.tool_registry().get("help").unwrap().execute(json!({})).await.unwrap();
+        assert!(help.contains("• read_file: "));
+        assert!(help.contains("• help: "));
+        assert!(!help.contains("• shell: "));
+    }
+
+    #[tokio::test]
+    async fn test_late_registered_tools_in_prompt_and_help() {
+        // MCP、命令和插件工具都在智能体创建后注册
+        let mut agent = Agent::new(ModelClient::new("test-key".to_string(), "gpt-4".to_string())).unwrap();
+        let config = crate::config::CommandToolConfig {
+            description: "Show deployment status\nRuns status.sh".to_string(),
+            command: vec!["status.sh".to_string(), "{service}".to_string()],
+            ..Default::default()
+        };
+        agent.register_tool(crate::command_tools::CommandTool::new("deploy_status", config).unwrap());
+
+        let (messages, tools_json) = agent.request_context().await;
+        assert!(messages[0]["content"].as_str().unwrap().contains("- deploy_status: Show deployment status\n"));
+        assert!(tools_json.iter().any(|t| t["function"]["name"] == "deploy_status"));
+
+        let help = agent.tool_registry().get("help").unwrap().execute(json!({})).await.unwrap();
+        assert!(help.contains("• deploy_status: Show deployment status\n"));
+    }
+
+    #[tokio::test]
+    async fn test_images_in_conversation() {
+        let mut agent = Agent::new(ModelClient::new("test-key".to_string(), "glm-4v".to_string())).unwrap();
+        agent.attach_image(ContentPart::image_url("https://example.com/a.png"));
+        agent.push_user_message("这是什么？").await;
+        agent.push_user_message("再看一张").await;
+        agent.tool_images.push(ContentPart::image_url("data:image/png;base64,AAAA"));
+        agent.record_tool_images().await;
+        agent.record_tool_images().await;
+
+        let conversation = agent.conversation().await;
+        assert_eq!(conversation.len(), 3);
+        assert_eq!(conversation[0]["content"][0], json!({ "type": "text", "text": "这是什么？" }));
+        assert_eq!(conversation[0]["content"][1]["image_url"]["url"], "https://example.com/a.png");
+        assert_eq!(conversation[1]["content"], "再看一张");
+        assert_eq!(message_role(&conversation[2]), MessageRole::Attachment);
+
+        // 撤销一轮时图片附件属于上一条用户消息的这一轮
+        assert_eq!(agent.undo_last_turn().await, 2);
+    }
+
     #[tokio::test]
     async fn test_agent_reset() {
         let model_client = ModelClient::new(
             "test-key".to_string(),
             "gpt-4".to_string(),
         );
-        let mut agent = Agent::new(model_client);
+        let mut agent = Agent::new(model_client).unwrap();
 
         // 先添加一些对话
         {
             let mut state = agent.state.write().await;
             state.conversation.push(json!(UserMessage {
-                content: "hello".to_string(),
+                content: "hello".into(),
             }));
         }
 
@@ -413,4 +1207,332 @@ mod tests {
         assert!(state.conversation.is_empty());
         assert_eq!(state.status, AgentStatus::Idle);
     }
+
+    #[tokio::test]
+    async fn test_tool_call_emits_output_deltas() {
+        let model_client = ModelClient::new(
+            "test-key".to_string(),
+            "gpt-4".to_string(),
+        );
+        let agent = Agent::new(model_client).unwrap();
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            name: "shell".to_string(),
+            arguments: json!({"command": "echo streamed"}),
+        };
+
+        let mut events = Vec::new();
+        agent
+            .execute_tool_call(&call, &mut |event| events.push(event))
+            .await
+            .unwrap();
+
+        assert!(matches!(events.first(), Some(AgentEvent::ToolCallBegin { .. })));
+        assert!(events.contains(&AgentEvent::ToolOutputDelta {
+            call_id: "call_1".to_string(),
+            chunk: "streamed\n".to_string(),
+        }));
+        assert!(matches!(
+            events.last(),
+            Some(AgentEvent::ToolCallEnd { success: true, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_policy_denial_becomes_tool_result() {
+        let workspace = tempfile::tempdir().unwrap();
+        let policy = Arc::new(PathPolicy::for_workspace(workspace.path()).unwrap());
+        let model_client = ModelClient::new(
+            "test-key".to_string(),
+            "gpt-4".to_string(),
+        );
+        let agent = Agent::new_with_policy(model_client, policy);
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            name: "read_file".to_string(),
+            arguments: json!({"path": "~/.ssh/id_rsa"}),
+        };
+
+        agent.execute_tool_call(&call, &mut |_| {}).await.unwrap();
+
+        let state = agent.state.read().await;
+        let result = state.conversation.last().unwrap();
+        assert_eq!(result["tool_call_id"], "call_1");
+        assert!(result["content"].as_str().unwrap().contains("access denied by policy"));
+    }
+
+    #[tokio::test]
+    async fn test_approval_decisions() {
+        let model_client = ModelClient::new(
+            "test-key".to_string(),
+            "gpt-4".to_string(),
+        );
+        let mut agent = Agent::new(model_client).unwrap();
+        agent.set_approval_policy(ApprovalPolicy::OnRequest);
+        let approvals = agent.approval_channel();
+        let shell_call = |id: &str| ToolCall {
+            id: id.to_string(),
+            name: "shell".to_string(),
+            arguments: json!({"command": "echo approved"}),
+        };
+
+        // 拒绝：工具不执行，拒绝信息作为工具结果
+        approvals
+            .send(ApprovalResponse { call_id: "call_1".into(), decision: ReviewDecision::Denied })
+            .unwrap();
+        let mut events = Vec::new();
+        agent
+            .run_tool_calls(&[shell_call("call_1")], &mut |event| events.push(event))
+            .await
+            .unwrap();
+        assert!(matches!(events[0], AgentEvent::ApprovalRequest { .. }));
+        assert!(matches!(events[1], AgentEvent::ToolCallEnd { success: false, .. }));
+
+        // 本次会话总是批准：之后同名工具不再请求审批
+        approvals
+            .send(ApprovalResponse { call_id: "call_2".into(), decision: ReviewDecision::ApprovedForSession })
+            .unwrap();
+        agent.run_tool_calls(&[shell_call("call_2")], &mut |_| {}).await.unwrap();
+        let mut events = Vec::new();
+        agent
+            .run_tool_calls(&[shell_call("call_3")], &mut |event| events.push(event))
+            .await
+            .unwrap();
+        assert!(matches!(events.first(), Some(AgentEvent::ToolCallBegin { .. })));
+
+        // 只读工具不需要审批
+        let read_call = ToolCall {
+            id: "call_4".to_string(),
+            name: "current_time".to_string(),
+            arguments: json!({}),
+        };
+        let mut events = Vec::new();
+        agent.run_tool_calls(&[read_call], &mut |event| events.push(event)).await.unwrap();
+        assert!(matches!(events.first(), Some(AgentEvent::ToolCallBegin { .. })));
+
+        let state = agent.state.read().await;
+        assert_eq!(state.conversation[0]["content"], "用户拒绝执行此工具调用");
+    }
+
+    #[tokio::test]
+    async fn test_undo_last_turn() {
+        let model_client = ModelClient::new(
+            "test-key".to_string(),
+            "gpt-4".to_string(),
+        );
+        let mut agent = Agent::new(model_client).unwrap();
+        agent
+            .restore_conversation(vec![
+                json!(UserMessage { content: "第一轮".into() }),
+                json!(AssistantMessage { content: "好的".to_string(), tool_calls: None, route: None }),
+                json!(UserMessage { content: "第二轮".into() }),
+                json!(AssistantMessage { content: String::new(), tool_calls: Some(vec![]), route: None }),
+                json!(ToolResult { tool_call_id: "call_1".to_string(), content: "ok".to_string() }),
+            ])
+            .await;
+
+        assert_eq!(agent.undo_last_turn().await, 3);
+        assert_eq!(agent.conversation().await.len(), 2);
+        assert_eq!(agent.undo_last_turn().await, 2);
+        assert_eq!(agent.undo_last_turn().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_usage_accumulates_per_turn_and_session() {
+        let model_client = ModelClient::new(
+            "test-key".to_string(),
+            "glm-4".to_string(),
+        );
+        let mut agent = Agent::new(model_client).unwrap();
+        agent.set_price_table(PriceTable::new(std::collections::BTreeMap::from([(
+            "glm-*".to_string(),
+            crate::usage::ModelPrice { input: 1.0, output: 2.0, cached_input: None },
+        )])));
+        agent.restore_usage(UsageTotals { requests: 3, cost: 0.5, ..Default::default() });
+
+        let usage = TokenUsage { prompt_tokens: 1_000_000, completion_tokens: 500_000, ..Default::default() };
+        let mut events = Vec::new();
+        agent.record_usage(usage, &mut |event| events.push(event));
+        agent.record_usage(usage, &mut |_| {});
+
+        assert!(matches!(&events[0], AgentEvent::TokenUsage { cost: Some(cost), turn, .. } if *cost == 2.0 && turn.requests == 1));
+        assert_eq!(agent.last_turn_usage().requests, 2);
+        assert_eq!(agent.last_turn_usage().tokens.total(), 3_000_000);
+        assert_eq!(agent.usage().requests, 5);
+        assert_eq!(agent.usage().cost, 4.5);
+    }
+
+    #[tokio::test]
+    async fn test_budget_stops_turn() {
+        let model_client = ModelClient::new(
+            "test-key".to_string(),
+            "glm-4".to_string(),
+        );
+        let mut agent = Agent::new(model_client).unwrap();
+        agent.set_budget(Budget { max_tool_calls: Some(1), max_session_cost: Some(1.0), ..Default::default() });
+        let time_call = |id: &str| ToolCall {
+            id: id.to_string(),
+            name: "current_time".to_string(),
+            arguments: json!({}),
+        };
+
+        // 第二个工具调用超出预算：不执行，但仍写入结果
+        let mut events = Vec::new();
+        let err = agent
+            .run_tool_calls(&[time_call("call_1"), time_call("call_2")], &mut |event| events.push(event))
+            .await
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<BudgetExceeded>().unwrap().limit, crate::budget::BudgetLimit::ToolCalls);
+        assert!(matches!(&events[events.len() - 2], AgentEvent::ToolCallEnd { call_id, success: false, .. } if call_id == "call_2"));
+        assert!(matches!(events.last(), Some(AgentEvent::BudgetExceeded { used, max, .. }) if *used == 1.0 && *max == 1.0));
+        assert_eq!(agent.conversation().await.len(), 2);
+
+        // 会话费用已用完：发出请求之前就停止
+        agent.restore_usage(UsageTotals { requests: 4, cost: 1.2, ..Default::default() });
+        let mut events = Vec::new();
+        let err = agent
+            .process_message_with_events("再查一次", |event| events.push(event))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("budget.max_session_cost"));
+        assert!(matches!(events.as_slice(), [AgentEvent::BudgetExceeded { .. }]));
+        assert_eq!(agent.get_status().await, AgentStatus::Idle);
+    }
+
+    #[tokio::test]
+    async fn test_request_options() {
+        let mut agent = Agent::new(ModelClient::new("test-key".to_string(), "glm-4".to_string())).unwrap();
+        let forced = |name: &str| RequestOptions { tool_choice: Some(ToolChoice::Function(name.to_string())), ..Default::default() };
+        let err = agent.set_request_options(forced("missing")).unwrap_err();
+        assert_eq!(err.to_string(), "tool_choice 指定的工具 'missing' 未注册");
+        agent.set_request_options(forced("current_time")).unwrap();
+
+        // 本轮的参数在这一轮结束后清除（即使本轮出错）
+        agent.set_budget(Budget { max_requests: Some(0), ..Default::default() });
+        let turn = RequestOptions { temperature: Some(0.0), ..Default::default() };
+        let err = agent.process_message_with_options("现在几点？", turn, |_| {}).await.unwrap_err();
+        assert!(err.is::<BudgetExceeded>());
+        let options = agent.model_client.effective_options();
+        assert_eq!(options.temperature, None);
+        assert_eq!(options.tool_choice, Some(ToolChoice::Function("current_time".to_string())));
+
+        // 被中断（future 被丢弃）的轮次留下的参数由 abort_turn 或下一轮清除
+        let turn = || RequestOptions { temperature: Some(0.5), ..Default::default() };
+        agent.model_client.set_turn_options(turn()).unwrap();
+        agent.abort_turn().await;
+        assert_eq!(agent.model_client.effective_options().temperature, None);
+        agent.model_client.set_turn_options(turn()).unwrap();
+        agent.process_message_with_events("现在几点？", |_| {}).await.unwrap_err();
+        assert_eq!(agent.model_client.effective_options().temperature, None);
+    }
+
+    #[tokio::test]
+    async fn test_structured_instruction_not_in_history() {
+        type Prices = std::collections::HashMap<String, u32>;
+        let mut agent = Agent::new(ModelClient::new("test-key".to_string(), "glm-4".to_string())).unwrap();
+        agent.set_budget(Budget { max_requests: Some(0), ..Default::default() });
+        let err = agent.run_structured::<Prices>("整理票价").await.unwrap_err();
+        assert!(err.is::<BudgetExceeded>());
+
+        // 历史中只有原始输入，格式要求在调用结束后清除
+        let conversation = agent.conversation().await;
+        assert_eq!(conversation[0]["content"], "整理票价");
+        let (messages, _) = agent.request_context().await;
+        assert!(!messages[0]["content"].as_str().unwrap().contains("JSON Schema"));
+
+        // 进行中的结构化调用只在本次请求的系统提示中带上 schema
+        let (_, schema) = structured::schema_for::<Prices>();
+        agent.response_instruction = Some(structured::instruction(&schema));
+        let (messages, _) = agent.request_context().await;
+        assert!(messages[0]["content"].as_str().unwrap().ends_with(&structured::instruction(&schema)));
+        assert_eq!(messages[1]["content"], "整理票价");
+        agent.abort_turn().await;
+        assert!(agent.response_instruction.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_fallback_after_request_error() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // 主模型的地址拒绝连接，备用模型是只回复一次的本地服务
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let primary_url = format!("http://{}/v1", closed.local_addr().unwrap());
+        drop(closed);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let backup_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 65536];
+            let _ = socket.read(&mut buf).await;
+            let body = r#"{"choices":[{"message":{"role":"assistant","content":"用户在查询航班"}}]}"#;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        let mut agent = Agent::new(ModelClient::new_with_config("k".to_string(), "glm-4".to_string(), primary_url)).unwrap();
+        agent.set_router(ModelRouter::new().with_fallback(RouteTarget::new(
+            "local",
+            ModelClient::new_with_config("k".to_string(), "qwen2.5".to_string(), backup_url),
+        )));
+        agent.restore_conversation(vec![json!(UserMessage { content: "查一下航班".into() })]).await;
+
+        assert_eq!(agent.compact().await.unwrap(), "用户在查询航班");
+        let route: ModelRoute = serde_json::from_value(agent.conversation().await[0]["route"].clone()).unwrap();
+        assert_eq!(route.model, "qwen2.5");
+        assert_eq!(route.profile.as_deref(), Some("local"));
+        assert_eq!(route.reason, RouteReason::Fallback);
+        assert!(route.error.unwrap().starts_with("glm-4: API 请求失败"));
+    }
+
+    #[tokio::test]
+    async fn test_reasoning_recorded_separately() {
+        let model_client = ModelClient::new(
+            "test-key".to_string(),
+            "gpt-4".to_string(),
+        );
+        let agent = Agent::new(model_client).unwrap();
+        agent.record_response(Some("先想一想".to_string()), "答案".to_string(), None).await;
+        agent.record_response(Some(String::new()), "第二个答案".to_string(), None).await;
+
+        let conversation = agent.conversation().await;
+        assert_eq!(conversation.len(), 3);
+        assert_eq!(message_role(&conversation[0]), MessageRole::Reasoning);
+        assert_eq!(conversation[0]["reasoning"], "先想一想");
+        assert_eq!(conversation[1]["content"], "答案");
+        assert_eq!(message_role(&conversation[2]), MessageRole::Assistant);
+    }
+
+    #[tokio::test]
+    async fn test_abort_turn_answers_pending_tool_calls() {
+        let model_client = ModelClient::new(
+            "test-key".to_string(),
+            "gpt-4".to_string(),
+        );
+        let mut agent = Agent::new(model_client).unwrap();
+        let call = |id: &str| ToolCall {
+            id: id.to_string(),
+            name: "shell".to_string(),
+            arguments: json!({"command": "sleep 60"}),
+        };
+        agent
+            .restore_conversation(vec![
+                json!(UserMessage { content: "跑两个命令".into() }),
+                json!(AssistantMessage { content: String::new(), tool_calls: Some(vec![call("call_1"), call("call_2")]), route: None }),
+                json!(ToolResult { tool_call_id: "call_1".to_string(), content: "ok".to_string() }),
+            ])
+            .await;
+
+        agent.abort_turn().await;
+        let conversation = agent.conversation().await;
+        assert_eq!(conversation.len(), 4);
+        assert_eq!(conversation[3]["tool_call_id"], "call_2");
+
+        // 没有未完成的调用时不改动
+        agent.abort_turn().await;
+        assert_eq!(agent.conversation().await.len(), 4);
+    }
 }
@@ -0,0 +1,716 @@
+// 补丁解析与应用 - 支持 unified diff 和 Codex 风格补丁（*** Begin Patch）
+//
+// 先在内存中校验所有 hunk 并计算出新内容，全部成功后才写入磁盘；
+// 写入过程中出错会回滚已经写入的文件。
+
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// 补丁中的单个文件变更
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileChange {
+    Add {
+        path: String,
+        content: String,
+    },
+    Delete {
+        path: String,
+    },
+    Update {
+        path: String,
+        move_to: Option<String>,
+        hunks: Vec<Hunk>,
+    },
+}
+
+/// 一个修改块
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Hunk {
+    /// Codex 格式中 `@@ ` 后的定位行（先找到这一行再匹配）
+    pub context_hint: Option<String>,
+    /// unified diff 中原文件的起始行（从 1 开始），用于在多处匹配时选择最近的位置
+    pub old_start: Option<usize>,
+    pub lines: Vec<HunkLine>,
+    /// 修改位于文件末尾（`*** End of File`）
+    pub end_of_file: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+impl Hunk {
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Context(text) | HunkLine::Remove(text) => Some(text.as_str()),
+                HunkLine::Add(_) => None,
+            })
+            .collect()
+    }
+
+    fn new_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Context(text) | HunkLine::Add(text) => Some(text.as_str()),
+                HunkLine::Remove(_) => None,
+            })
+            .collect()
+    }
+}
+
+/// 补丁应用结果中的单个文件摘要
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ChangedFile {
+    pub path: String,
+    /// add / update / delete / move
+    pub action: &'static str,
+    pub added_lines: usize,
+    pub removed_lines: usize,
+}
+
+/// 解析补丁文本（自动识别格式）
+pub fn parse_patch(patch: &str) -> Result<Vec<FileChange>, anyhow::Error> {
+    let trimmed = patch.trim();
+    let changes = if trimmed.starts_with("*** Begin Patch") {
+        parse_codex_patch(trimmed)?
+    } else {
+        parse_unified_diff(trimmed)?
+    };
+
+    if changes.is_empty() {
+        return Err(anyhow::anyhow!("补丁中没有任何文件变更"));
+    }
+    Ok(changes)
+}
+
+/// 解析 Codex 风格补丁
+fn parse_codex_patch(patch: &str) -> Result<Vec<FileChange>, anyhow::Error> {
+    let lines: Vec<&str> = patch.lines().collect();
+    if lines.last().map(|l| l.trim()) != Some("*** End Patch") {
+        return Err(anyhow::anyhow!("补丁缺少 '*** End Patch' 结尾"));
+    }
+
+    let mut changes = Vec::new();
+    let mut i = 1;
+    while i < lines.len() - 1 {
+        let line = lines[i];
+        if let Some(path) = line.strip_prefix("*** Add File: ") {
+            i += 1;
+            let mut content = String::new();
+            while i < lines.len() - 1 && !lines[i].starts_with("*** ") {
+                // 空行视为新增空行（模型有时会省略 '+'）
+                let added = lines[i]
+                    .strip_prefix('+')
+                    .or_else(|| lines[i].is_empty().then_some(""))
+                    .ok_or_else(|| anyhow::anyhow!("第 {} 行: 新增文件的内容必须以 '+' 开头", i + 1))?;
+                content.push_str(added);
+                content.push('\n');
+                i += 1;
+            }
+            changes.push(FileChange::Add {
+                path: path.trim().to_string(),
+                content,
+            });
+        } else if let Some(path) = line.strip_prefix("*** Delete File: ") {
+            changes.push(FileChange::Delete {
+                path: path.trim().to_string(),
+            });
+            i += 1;
+        } else if let Some(path) = line.strip_prefix("*** Update File: ") {
+            i += 1;
+            let mut move_to = None;
+            if let Some(target) = lines.get(i).and_then(|l| l.strip_prefix("*** Move to: ")) {
+                move_to = Some(target.trim().to_string());
+                i += 1;
+            }
+
+            let mut hunks: Vec<Hunk> = Vec::new();
+            let mut current = Hunk::default();
+            while i < lines.len() - 1 {
+                let line = lines[i];
+                if line == "*** End of File" {
+                    current.end_of_file = true;
+                    i += 1;
+                    continue;
+                }
+                if line.starts_with("*** ") {
+                    break;
+                }
+                if let Some(header) = line.strip_prefix("@@") {
+                    if !current.lines.is_empty() {
+                        hunks.push(std::mem::take(&mut current));
+                    }
+                    let hint = header.trim();
+                    current.context_hint = (!hint.is_empty()).then(|| hint.to_string());
+                } else if let Some(text) = line.strip_prefix('+') {
+                    current.lines.push(HunkLine::Add(text.to_string()));
+                } else if let Some(text) = line.strip_prefix('-') {
+                    current.lines.push(HunkLine::Remove(text.to_string()));
+                } else if let Some(text) = line.strip_prefix(' ') {
+                    current.lines.push(HunkLine::Context(text.to_string()));
+                } else if line.is_empty() {
+                    current.lines.push(HunkLine::Context(String::new()));
+                } else {
+                    return Err(anyhow::anyhow!("第 {} 行: 无法识别的补丁行: {}", i + 1, line));
+                }
+                i += 1;
+            }
+            if !current.lines.is_empty() {
+                hunks.push(current);
+            }
+            if hunks.is_empty() && move_to.is_none() {
+                return Err(anyhow::anyhow!("文件 {} 的更新没有任何修改块", path.trim()));
+            }
+
+            changes.push(FileChange::Update {
+                path: path.trim().to_string(),
+                move_to,
+                hunks,
+            });
+        } else if line.trim().is_empty() {
+            i += 1;
+        } else {
+            return Err(anyhow::anyhow!("第 {} 行: 无法识别的补丁指令: {}", i + 1, line));
+        }
+    }
+
+    Ok(changes)
+}
+
+/// 解析 unified diff（git diff / diff -u 输出）
+fn parse_unified_diff(patch: &str) -> Result<Vec<FileChange>, anyhow::Error> {
+    let lines: Vec<&str> = patch.lines().collect();
+    let mut changes = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let Some(old_header) = lines[i].strip_prefix("--- ") else {
+            // diff --git、index 等元信息行直接跳过
+            i += 1;
+            continue;
+        };
+        let new_header = lines
+            .get(i + 1)
+            .and_then(|l| l.strip_prefix("+++ "))
+            .ok_or_else(|| anyhow::anyhow!("第 {} 行: '---' 之后缺少 '+++' 行", i + 1))?;
+        let old_path = diff_path(old_header);
+        let new_path = diff_path(new_header);
+        i += 2;
+
+        let mut hunks = Vec::new();
+        while i < lines.len() && lines[i].starts_with("@@") {
+            let (old_start, mut old_count, mut new_count) = parse_hunk_header(lines[i])
+                .ok_or_else(|| anyhow::anyhow!("第 {} 行: 无效的 hunk 头: {}", i + 1, lines[i]))?;
+            let mut hunk = Hunk {
+                old_start: Some(old_start),
+                ..Hunk::default()
+            };
+            i += 1;
+
+            // 按 hunk 头中的行数读取，避免把以 "---" 开头的删除行误判为文件头
+            while (old_count > 0 || new_count > 0) && i < lines.len() {
+                let line = lines[i];
+                if let Some(text) = line.strip_prefix('+') {
+                    hunk.lines.push(HunkLine::Add(text.to_string()));
+                    new_count = new_count.saturating_sub(1);
+                } else if let Some(text) = line.strip_prefix('-') {
+                    hunk.lines.push(HunkLine::Remove(text.to_string()));
+                    old_count = old_count.saturating_sub(1);
+                } else if line.starts_with('\\') {
+                    // "\ No newline at end of file"
+                } else {
+                    let text = line.strip_prefix(' ').unwrap_or(line);
+                    hunk.lines.push(HunkLine::Context(text.to_string()));
+                    old_count = old_count.saturating_sub(1);
+                    new_count = new_count.saturating_sub(1);
+                }
+                i += 1;
+            }
+            while i < lines.len() && lines[i].starts_with('\\') {
+                i += 1;
+            }
+            hunks.push(hunk);
+        }
+
+        match (old_path, new_path) {
+            (None, Some(path)) => {
+                let mut content = String::new();
+                for line in hunks.iter().flat_map(|h| h.new_lines()) {
+                    content.push_str(line);
+                    content.push('\n');
+                }
+                changes.push(FileChange::Add { path, content });
+            }
+            (Some(path), None) => changes.push(FileChange::Delete { path }),
+            (Some(path), Some(new_path)) => {
+                let move_to = (new_path != path).then_some(new_path);
+                changes.push(FileChange::Update { path, move_to, hunks });
+            }
+            (None, None) => return Err(anyhow::anyhow!("补丁的新旧文件都是 /dev/null")),
+        }
+    }
+
+    Ok(changes)
+}
+
+/// 提取 diff 头中的路径（去掉 a/ b/ 前缀和时间戳，/dev/null 返回 None）
+fn diff_path(header: &str) -> Option<String> {
+    let path = header.split('\t').next().unwrap_or(header).trim();
+    if path == "/dev/null" {
+        return None;
+    }
+    let path = path
+        .strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .unwrap_or(path);
+    Some(path.to_string())
+}
+
+/// 解析 "@@ -l,s +l,s @@"，返回 (旧起始行, 旧行数, 新行数)
+fn parse_hunk_header(line: &str) -> Option<(usize, usize, usize)> {
+    let mut parts = line.strip_prefix("@@ ")?.split_whitespace();
+    let old = parts.next()?.strip_prefix('-')?;
+    let new = parts.next()?.strip_prefix('+')?;
+
+    let range = |spec: &str| -> Option<(usize, usize)> {
+        match spec.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((spec.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_count) = range(old)?;
+    let (_, new_count) = range(new)?;
+    Some((old_start, old_count, new_count))
+}
+
+/// 将修改块应用到文件内容上，返回新内容
+pub fn apply_hunks(path: &str, original: &str, hunks: &[Hunk]) -> Result<String, anyhow::Error> {
+    let mut lines: Vec<String> = original.lines().map(|l| l.to_string()).collect();
+    // 下一个 hunk 只在上一个 hunk 之后查找
+    let mut cursor = 0;
+    // 已应用的 hunk 造成的行号偏移（用于 unified diff 的起始行提示）
+    let mut offset: isize = 0;
+
+    for (index, hunk) in hunks.iter().enumerate() {
+        // `@@ <上下文>` 用于定位重复出现的代码，找不到时修改块可能落在错误的位置，因此直接报错
+        if let Some(hint) = &hunk.context_hint {
+            let pos = seek_sequence(&lines, &[hint.as_str()], cursor, None)
+                .or_else(|| seek_sequence(&lines, &[hint.as_str()], 0, None))
+                .ok_or_else(|| {
+                    anyhow::anyhow!("{} 的第 {} 个修改块的上下文 \"@@ {}\" 在文件中未找到", path, index + 1, hint)
+                })?;
+            cursor = pos + 1;
+        }
+
+        let old = hunk.old_lines();
+        let new = hunk.new_lines();
+        let expected = hunk
+            .old_start
+            .map(|start| (start as isize - 1 + offset).max(0) as usize);
+
+        let position = if old.is_empty() {
+            // 纯插入：有行号提示按行号插入，否则追加到文件末尾
+            expected.map(|p| p.min(lines.len())).unwrap_or(lines.len())
+        } else if hunk.end_of_file {
+            seek_sequence(&lines, &old, lines.len().saturating_sub(old.len()), None)
+                .or_else(|| seek_sequence(&lines, &old, cursor, expected))
+                .ok_or_else(|| hunk_mismatch(path, index, &old))?
+        } else {
+            seek_sequence(&lines, &old, cursor, expected)
+                .or_else(|| seek_sequence(&lines, &old, 0, expected))
+                .ok_or_else(|| hunk_mismatch(path, index, &old))?
+        };
+
+        lines.splice(
+            position..position + old.len(),
+            new.iter().map(|line| line.to_string()),
+        );
+        cursor = position + new.len();
+        offset += new.len() as isize - old.len() as isize;
+    }
+
+    // 保持原文件的换行符和末尾换行，避免小改动变成整个文件的改写
+    let line_ending = if original.contains("\r\n") { "\r\n" } else { "\n" };
+    let final_newline = original.is_empty() || original.ends_with('\n');
+    let mut content = lines.join(line_ending);
+    if final_newline && !content.is_empty() {
+        content.push_str(line_ending);
+    }
+    Ok(content)
+}
+
+fn hunk_mismatch(path: &str, index: usize, old: &[&str]) -> anyhow::Error {
+    anyhow::anyhow!(
+        "{} 的第 {} 个修改块与文件当前内容不匹配，期望找到:\n{}",
+        path,
+        index + 1,
+        old.join("\n")
+    )
+}
+
+/// 在 lines 中从 start 开始查找 pattern，依次放宽匹配条件（精确 → 忽略行尾空白 → 忽略首尾空白）
+///
+/// 有多个匹配时选择离 expected 最近的一个。
+fn seek_sequence(lines: &[String], pattern: &[&str], start: usize, expected: Option<usize>) -> Option<usize> {
+    if pattern.len() > lines.len() {
+        return None;
+    }
+
+    let normalizers: [fn(&str) -> &str; 3] = [|s| s, |s| s.trim_end(), |s| s.trim()];
+    for normalize in normalizers {
+        let matches: Vec<usize> = (start..=lines.len() - pattern.len())
+            .filter(|&i| {
+                pattern
+                    .iter()
+                    .zip(&lines[i..i + pattern.len()])
+                    .all(|(p, l)| normalize(p) == normalize(l))
+            })
+            .collect();
+
+        let best = match expected {
+            Some(expected) => matches.into_iter().min_by_key(|&i| i.abs_diff(expected)),
+            None => matches.into_iter().next(),
+        };
+        if best.is_some() {
+            return best;
+        }
+    }
+    None
+}
+
+/// 计划中的一次文件写入（None 表示删除）
+struct PlannedWrite {
+    path: PathBuf,
+    content: Option<String>,
+}
+
+/// 校验并应用补丁（路径相对于 base_dir）
+///
+/// `resolve` 负责把补丁中的路径转换为实际路径，可在其中做访问控制。
+pub fn apply_patch<F>(patch: &str, base_dir: &Path, resolve: F) -> Result<Vec<ChangedFile>, anyhow::Error>
+where
+    F: Fn(&Path) -> Result<PathBuf, anyhow::Error>,
+{
+    let changes = parse_patch(patch)?;
+    let mut planned = Vec::new();
+    let mut summary = Vec::new();
+
+    // 第一阶段：读取当前内容并校验所有修改块，不写磁盘
+    for change in &changes {
+        match change {
+            FileChange::Add { path, content } => {
+                let target = resolve(&base_dir.join(path))?;
+                if target.exists() {
+                    return Err(anyhow::anyhow!("无法新增 {}: 文件已存在", path));
+                }
+                summary.push(ChangedFile {
+                    path: path.clone(),
+                    action: "add",
+                    added_lines: content.lines().count(),
+                    removed_lines: 0,
+                });
+                planned.push(PlannedWrite {
+                    path: target,
+                    content: Some(content.clone()),
+                });
+            }
+            FileChange::Delete { path } => {
+                let target = resolve(&base_dir.join(path))?;
+                // 按字节读取：非 UTF-8 或二进制文件同样可以删除（回滚时按字节恢复）
+                let original = std::fs::read(&target).map_err(|e| anyhow::anyhow!("无法删除 {}: {}", path, e))?;
+                summary.push(ChangedFile {
+                    path: path.clone(),
+                    action: "delete",
+                    added_lines: 0,
+                    removed_lines: String::from_utf8_lossy(&original).lines().count(),
+                });
+                planned.push(PlannedWrite {
+                    path: target,
+                    content: None,
+                });
+            }
+            FileChange::Update { path, move_to, hunks } => {
+                let source = resolve(&base_dir.join(path))?;
+                let original = std::fs::read_to_string(&source)
+                    .map_err(|e| anyhow::anyhow!("无法读取 {}: {}", path, e))?;
+                let updated = apply_hunks(path, &original, hunks)?;
+
+                let (added_lines, removed_lines) = hunks.iter().fold((0, 0), |(a, r), hunk| {
+                    let added = hunk.lines.iter().filter(|l| matches!(l, HunkLine::Add(_))).count();
+                    let removed = hunk.lines.iter().filter(|l| matches!(l, HunkLine::Remove(_))).count();
+                    (a + added, r + removed)
+                });
+
+                match move_to {
+                    Some(new_path) => {
+                        let target = resolve(&base_dir.join(new_path))?;
+                        summary.push(ChangedFile {
+                            path: format!("{} -> {}", path, new_path),
+                            action: "move",
+                            added_lines,
+                            removed_lines,
+                        });
+                        planned.push(PlannedWrite {
+                            path: target,
+                            content: Some(updated),
+                        });
+                        planned.push(PlannedWrite {
+                            path: source,
+                            content: None,
+                        });
+                    }
+                    None => {
+                        summary.push(ChangedFile {
+                            path: path.clone(),
+                            action: "update",
+                            added_lines,
+                            removed_lines,
+                        });
+                        planned.push(PlannedWrite {
+                            path: source,
+                            content: Some(updated),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    // 同一文件的多个修改都基于磁盘上的原内容计算，后者会覆盖前者
+    let mut seen = HashSet::new();
+    if let Some(duplicate) = planned.iter().find(|write| !seen.insert(&write.path)) {
+        return Err(anyhow::anyhow!(
+            "补丁中多次修改了 {}，请把对同一文件的修改合并到一个段落中",
+            duplicate.path.display()
+        ));
+    }
+
+    // 第二阶段：写入磁盘，失败时回滚（包括为新文件创建的目录）
+    let mut backups: Vec<(PathBuf, Option<Vec<u8>>)> = Vec::new();
+    let mut created_dirs: Vec<PathBuf> = Vec::new();
+    for write in &planned {
+        backups.push((write.path.clone(), std::fs::read(&write.path).ok()));
+        created_dirs.extend(missing_parents(&write.path));
+        let result = match &write.content {
+            Some(content) => write_atomic(&write.path, content.as_bytes()),
+            None => std::fs::remove_file(&write.path).map_err(anyhow::Error::from),
+        };
+
+        if let Err(e) = result {
+            rollback(&backups, &mut created_dirs);
+            return Err(anyhow::anyhow!("写入 {} 失败，已回滚: {}", write.path.display(), e));
+        }
+    }
+
+    Ok(summary)
+}
+
+/// path 的上级目录中尚不存在的部分（写入时会被创建）
+fn missing_parents(path: &Path) -> Vec<PathBuf> {
+    path.ancestors()
+        .skip(1)
+        .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+        .map(Path::to_path_buf)
+        .collect()
+}
+
+/// 恢复写入前的文件状态，再由深到浅删除写入时创建的目录（只删除空目录）
+fn rollback(backups: &[(PathBuf, Option<Vec<u8>>)], created_dirs: &mut [PathBuf]) {
+    for (path, original) in backups.iter().rev() {
+        let _ = match original {
+            Some(bytes) => write_atomic(path, bytes),
+            None => std::fs::remove_file(path).map_err(anyhow::Error::from),
+        };
+    }
+    created_dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+    for dir in created_dirs.iter() {
+        let _ = std::fs::remove_dir(dir);
+    }
+}
+
+/// 原子写入：先写同目录下的临时文件，再重命名覆盖
+pub fn write_atomic(path: &Path, content: &[u8]) -> Result<(), anyhow::Error> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("无效的文件路径: {}", path.display()))?
+        .to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.{}.tmp", file_name, uuid::Uuid::new_v4().simple()));
+
+    std::fs::write(&temp, content)?;
+    // 保留原文件权限（例如可执行脚本）
+    if let Ok(metadata) = std::fs::metadata(path) {
+        let _ = std::fs::set_permissions(&temp, metadata.permissions());
+    }
+    std::fs::rename(&temp, path).inspect_err(|_| {
+        let _ = std::fs::remove_file(&temp);
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ok(path: &Path) -> Result<PathBuf, anyhow::Error> {
+        Ok(path.to_path_buf())
+    }
+
+    #[test]
+    fn test_apply_codex_patch() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("main.rs"), "fn main() {\n    println!(\"hi\");\n}\n").unwrap();
+        std::fs::write(dir.path().join("old.txt"), "bye\n").unwrap();
+
+        let patch = "*** Begin Patch
+*** Add File: notes/new.txt
++hello
++world
+*** Update File: main.rs
+@@ fn main() {
+-    println!(\"hi\");
++    println!(\"你好\");
+*** Delete File: old.txt
+*** End Patch";
+
+        let summary = apply_patch(patch, dir.path(), ok).unwrap();
+        assert_eq!(summary.len(), 3);
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("notes/new.txt")).unwrap(),
+            "hello\nworld\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("main.rs")).unwrap(),
+            "fn main() {\n    println!(\"你好\");\n}\n"
+        );
+        assert!(!dir.path().join("old.txt").exists());
+    }
+
+    #[test]
+    fn test_delete_binary_file() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("image.bin"), [0xff, 0xfe, 0x00, b'\n', 0x80]).unwrap();
+
+        let summary = apply_patch("*** Begin Patch\n*** Delete File: image.bin\n*** End Patch", dir.path(), ok).unwrap();
+        assert_eq!(summary[0].action, "delete");
+        assert!(!dir.path().join("image.bin").exists());
+    }
+
+    #[test]
+    fn test_apply_unified_diff() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "one\ntwo\nthree\n").unwrap();
+
+        let patch = "diff --git a/a.txt b/a.txt
+--- a/a.txt
++++ b/a.txt
+@@ -1,3 +1,3 @@
+ one
+-two
++2
+ three
+";
+
+        let summary = apply_patch(patch, dir.path(), ok).unwrap();
+        assert_eq!(summary[0].action, "update");
+        assert_eq!(summary[0].added_lines, 1);
+        assert_eq!(std::fs::read_to_string(dir.path().join("a.txt")).unwrap(), "one\n2\nthree\n");
+    }
+
+    #[test]
+    fn test_keeps_line_endings() {
+        let changes = parse_patch("*** Begin Patch\n*** Update File: a.txt\n@@\n-two\n+2\n*** End Patch").unwrap();
+        let FileChange::Update { hunks, .. } = &changes[0] else {
+            panic!("应为 Update");
+        };
+        assert_eq!(apply_hunks("a.txt", "one\r\ntwo\r\nthree\r\n", hunks).unwrap(), "one\r\n2\r\nthree\r\n");
+        assert_eq!(apply_hunks("a.txt", "one\ntwo", hunks).unwrap(), "one\n2");
+    }
+
+    #[test]
+    fn test_unmatched_context_hint_is_an_error() {
+        let changes =
+            parse_patch("*** Begin Patch\n*** Update File: a.rs\n@@ fn foo() {\n-    1\n+    2\n*** End Patch").unwrap();
+        let FileChange::Update { hunks, .. } = &changes[0] else {
+            panic!("应为 Update");
+        };
+        let err = apply_hunks("a.rs", "fn bar() {\n    1\n}\n", hunks).unwrap_err();
+        assert!(err.to_string().contains("@@ fn foo() {"), "{}", err);
+        assert_eq!(apply_hunks("a.rs", "fn foo() {\n    1\n}\n", hunks).unwrap(), "fn foo() {\n    2\n}\n");
+    }
+
+    #[test]
+    fn test_rejects_duplicate_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "one\ntwo\n").unwrap();
+
+        let patch = "*** Begin Patch
+*** Update File: a.txt
+@@
+-one
++1
+*** Update File: a.txt
+@@
+-two
++2
+*** End Patch";
+
+        let err = apply_patch(patch, dir.path(), ok).unwrap_err();
+        assert!(err.to_string().contains("多次修改了"));
+        assert_eq!(std::fs::read_to_string(dir.path().join("a.txt")).unwrap(), "one\ntwo\n");
+    }
+
+    #[test]
+    fn test_rollback_removes_created_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "alpha\n").unwrap();
+        std::fs::write(dir.path().join("b.txt"), "beta\n").unwrap();
+
+        // a.txt 是文件，移动到 a.txt/b.txt 在写入阶段失败，此前新增的文件和目录都要撤销
+        let patch = "*** Begin Patch
+*** Add File: deep/nested/new.txt
++new
+*** Update File: b.txt
+*** Move to: a.txt/b.txt
+@@
+-beta
++gamma
+*** End Patch";
+
+        let err = apply_patch(patch, dir.path(), ok).unwrap_err();
+        assert!(err.to_string().contains("已回滚"), "{}", err);
+        assert!(!dir.path().join("deep").exists());
+        assert_eq!(std::fs::read_to_string(dir.path().join("b.txt")).unwrap(), "beta\n");
+        assert_eq!(std::fs::read_to_string(dir.path().join("a.txt")).unwrap(), "alpha\n");
+    }
+
+    #[test]
+    fn test_mismatched_hunk_leaves_files_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "alpha\n").unwrap();
+
+        let patch = "*** Begin Patch
+*** Add File: b.txt
++new
+*** Update File: a.txt
+@@
+-beta
++gamma
+*** End Patch";
+
+        let err = apply_patch(patch, dir.path(), ok).unwrap_err();
+        assert!(err.to_string().contains("不匹配"));
+        assert!(!dir.path().join("b.txt").exists());
+        assert_eq!(std::fs::read_to_string(dir.path().join("a.txt")).unwrap(), "alpha\n");
+    }
+}
@@ -0,0 +1,247 @@
+// 任务预算 - 限制一轮对话（一次任务）的模型请求数、工具调用数、耗时、token 和费用
+//
+// - 模型请求数、耗时、token、费用在每次模型请求之前检查
+// - 工具调用数在每次执行工具之前检查
+// 超出时智能体发出 BudgetExceeded 事件，并以 BudgetExceeded 错误结束本轮，对话历史保持完整。
+// 已经发出的请求无法撤回，所以 token 和费用最多超出一次请求的用量；耗时不会打断进行中的请求或工具。
+
+use crate::config::{merge_fields, set_entries};
+use crate::usage::{format_count, UsageTotals};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// 未配置 max_requests 时，每轮最多的模型请求次数
+pub const DEFAULT_MAX_REQUESTS: u64 = 10;
+
+/// `exec` 因超出预算结束时的退出码
+pub const BUDGET_EXCEEDED_EXIT_CODE: i32 = 3;
+
+/// 预算上限，省略的项不限制
+///
+/// ```toml
+/// [budget]                 # 或 [profiles.<name>.budget]
+/// max_requests = 20        # 每轮最多的模型请求次数（默认 10）
+/// max_tool_calls = 50      # 每轮最多执行的工具调用次数
+/// max_duration_secs = 600  # 每轮最长耗时
+/// max_tokens = 200000      # 每轮最多使用的 token
+/// max_cost = 0.5           # 每轮最多的费用（按 [prices] 计算）
+/// max_session_tokens = 2000000
+/// max_session_cost = 5.0   # 整个会话（含恢复前）最多的费用
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Budget {
+    pub max_requests: Option<u64>,
+    pub max_tool_calls: Option<u64>,
+    pub max_duration_secs: Option<u64>,
+    pub max_tokens: Option<u64>,
+    pub max_cost: Option<f64>,
+    pub max_session_tokens: Option<u64>,
+    pub max_session_cost: Option<f64>,
+}
+
+/// 本轮已经用掉的资源
+#[derive(Debug, Clone, Copy)]
+pub struct BudgetSpent<'a> {
+    /// 已发出的模型请求次数
+    pub requests: u64,
+    pub elapsed: Duration,
+    pub turn: &'a UsageTotals,
+    pub session: &'a UsageTotals,
+}
+
+impl Budget {
+    /// 用 other 中已设置的字段覆盖当前值
+    pub fn merge(&mut self, other: Budget) {
+        merge_fields!(
+            self,
+            other,
+            max_requests, max_tool_calls, max_duration_secs, max_tokens, max_cost, max_session_tokens,
+            max_session_cost
+        );
+    }
+
+    pub fn max_requests(&self) -> u64 {
+        self.max_requests.unwrap_or(DEFAULT_MAX_REQUESTS)
+    }
+
+    /// 是否设置了费用上限（模型没有价格时费用上限不起作用）
+    pub fn limits_cost(&self) -> bool {
+        self.max_cost.is_some() || self.max_session_cost.is_some()
+    }
+
+    /// 发出下一次模型请求之前检查，返回第一项超出的预算
+    pub fn check(&self, spent: &BudgetSpent) -> Option<BudgetExceeded> {
+        let count = |limit, used: u64, max: Option<u64>| {
+            max.filter(|max| used >= *max).map(|max| BudgetExceeded::new(limit, used as f64, max as f64))
+        };
+        let cost = |limit, used: f64, max: Option<f64>| {
+            max.filter(|max| used >= *max).map(|max| BudgetExceeded::new(limit, used, max))
+        };
+        count(BudgetLimit::Requests, spent.requests, Some(self.max_requests()))
+            .or_else(|| count(BudgetLimit::Duration, spent.elapsed.as_secs(), self.max_duration_secs))
+            .or_else(|| count(BudgetLimit::Tokens, spent.turn.tokens.total(), self.max_tokens))
+            .or_else(|| cost(BudgetLimit::Cost, spent.turn.cost, self.max_cost))
+            .or_else(|| count(BudgetLimit::SessionTokens, spent.session.tokens.total(), self.max_session_tokens))
+            .or_else(|| cost(BudgetLimit::SessionCost, spent.session.cost, self.max_session_cost))
+    }
+
+    /// 执行下一个工具调用之前检查，tool_calls 为本轮已执行的次数
+    pub fn check_tool_call(&self, tool_calls: u64) -> Option<BudgetExceeded> {
+        self.max_tool_calls
+            .filter(|max| tool_calls >= *max)
+            .map(|max| BudgetExceeded::new(BudgetLimit::ToolCalls, tool_calls as f64, max as f64))
+    }
+
+    /// 已设置的各项（`config show` 和 /status 使用）
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        set_entries([
+            ("max_requests", self.max_requests.map(|v| v.to_string())),
+            ("max_tool_calls", self.max_tool_calls.map(|v| v.to_string())),
+            ("max_duration_secs", self.max_duration_secs.map(|v| v.to_string())),
+            ("max_tokens", self.max_tokens.map(|v| v.to_string())),
+            ("max_cost", self.max_cost.map(|v| v.to_string())),
+            ("max_session_tokens", self.max_session_tokens.map(|v| v.to_string())),
+            ("max_session_cost", self.max_session_cost.map(|v| v.to_string())),
+        ])
+    }
+}
+
+/// 超出的是哪一项预算
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetLimit {
+    Requests,
+    ToolCalls,
+    Duration,
+    Tokens,
+    Cost,
+    SessionTokens,
+    SessionCost,
+}
+
+impl BudgetLimit {
+    /// 对应的配置项
+    pub fn key(&self) -> &'static str {
+        match self {
+            BudgetLimit::Requests => "max_requests",
+            BudgetLimit::ToolCalls => "max_tool_calls",
+            BudgetLimit::Duration => "max_duration_secs",
+            BudgetLimit::Tokens => "max_tokens",
+            BudgetLimit::Cost => "max_cost",
+            BudgetLimit::SessionTokens => "max_session_tokens",
+            BudgetLimit::SessionCost => "max_session_cost",
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            BudgetLimit::Requests => "本轮模型请求次数",
+            BudgetLimit::ToolCalls => "本轮工具调用次数",
+            BudgetLimit::Duration => "本轮耗时",
+            BudgetLimit::Tokens => "本轮 token",
+            BudgetLimit::Cost => "本轮费用",
+            BudgetLimit::SessionTokens => "会话 token",
+            BudgetLimit::SessionCost => "会话费用",
+        }
+    }
+
+    fn format(&self, value: f64) -> String {
+        match self {
+            BudgetLimit::Duration => format!("{}s", value as u64),
+            BudgetLimit::Tokens | BudgetLimit::SessionTokens => format_count(value as u64),
+            BudgetLimit::Cost | BudgetLimit::SessionCost => format!("{:.4}", value),
+            BudgetLimit::Requests | BudgetLimit::ToolCalls => (value as u64).to_string(),
+        }
+    }
+}
+
+/// 超出预算：已用量达到了上限
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BudgetExceeded {
+    pub limit: BudgetLimit,
+    pub used: f64,
+    pub max: f64,
+}
+
+impl BudgetExceeded {
+    pub fn new(limit: BudgetLimit, used: f64, max: f64) -> Self {
+        Self { limit, used, max }
+    }
+}
+
+impl std::fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "超出预算：{} {} 已达到上限 {}（budget.{}），本轮已停止",
+            self.limit.label(),
+            self.limit.format(self.used),
+            self.limit.format(self.max),
+            self.limit.key()
+        )
+    }
+}
+
+impl std::error::Error for BudgetExceeded {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usage::TokenUsage;
+
+    fn totals(tokens: u64, cost: f64) -> UsageTotals {
+        let mut totals = UsageTotals::default();
+        totals.record(&TokenUsage { prompt_tokens: tokens, ..Default::default() }, Some(cost));
+        totals
+    }
+
+    #[test]
+    fn test_budget_check_order_and_limits() {
+        let turn = totals(1500, 0.2);
+        let session = totals(9000, 3.0);
+        let spent = |requests, secs| BudgetSpent {
+            requests,
+            elapsed: Duration::from_secs(secs),
+            turn: &turn,
+            session: &session,
+        };
+
+        // 只有默认的请求次数上限
+        let budget = Budget::default();
+        assert_eq!(budget.check(&spent(9, 0)), None);
+        assert_eq!(budget.check(&spent(10, 0)).unwrap().limit, BudgetLimit::Requests);
+
+        let budget = Budget {
+            max_duration_secs: Some(60),
+            max_tokens: Some(2000),
+            max_cost: Some(0.5),
+            max_session_cost: Some(3.0),
+            ..Default::default()
+        };
+        assert_eq!(budget.check(&spent(1, 61)).unwrap().limit, BudgetLimit::Duration);
+        let exceeded = budget.check(&spent(1, 0)).unwrap();
+        assert_eq!(exceeded, BudgetExceeded::new(BudgetLimit::SessionCost, 3.0, 3.0));
+        assert_eq!(
+            exceeded.to_string(),
+            "超出预算：会话费用 3.0000 已达到上限 3.0000（budget.max_session_cost），本轮已停止"
+        );
+
+        assert_eq!(budget.check_tool_call(100), None);
+        let budget = Budget { max_tool_calls: Some(2), ..Default::default() };
+        assert_eq!(budget.check_tool_call(1), None);
+        assert_eq!(budget.check_tool_call(2).unwrap().limit, BudgetLimit::ToolCalls);
+    }
+
+    #[test]
+    fn test_budget_merge() {
+        let mut budget = Budget { max_requests: Some(5), max_cost: Some(1.0), ..Default::default() };
+        budget.merge(Budget { max_cost: Some(0.2), max_tool_calls: Some(3), ..Default::default() });
+        assert_eq!(budget.max_requests, Some(5));
+        assert_eq!(budget.max_cost, Some(0.2));
+        assert_eq!(
+            budget.entries(),
+            vec![("max_requests", "5".to_string()), ("max_tool_calls", "3".to_string()), ("max_cost", "0.2".to_string())]
+        );
+    }
+}
@@ -0,0 +1,347 @@
+// 命令行定义 - 所有运行模式的统一入口
+//
+// 子命令：chat（默认）、tui、exec、serve、sessions、tools、config、completions
+// 全局选项可以写在子命令前后，例如 `simple-ai-agent exec -m glm-4-flash "..."`
+
+use crate::budget::Budget;
+use crate::config::{ApprovalPolicy, ModelSettings, ToolCalling};
+use crate::request_options::{RequestOptions, ToolChoice};
+use crate::settings::CliOverrides;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use std::path::PathBuf;
+
+/// 可执行文件名（用于补全脚本）
+pub const BIN_NAME: &str = "simple-ai-agent";
+
+/// 灵狐 AI Agent - 简化版 Codex
+#[derive(Debug, Parser)]
+#[command(name = BIN_NAME, version, about)]
+pub struct Cli {
+    #[command(flatten)]
+    pub global: GlobalArgs,
+
+    /// 省略时进入交互式对话
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// 全局选项（覆盖配置文件中的值）
+#[derive(Debug, Clone, Default, Args)]
+pub struct GlobalArgs {
+    /// 使用的模型
+    #[arg(short, long, global = true)]
+    pub model: Option<String>,
+
+    /// 使用配置文件中定义的 profile
+    #[arg(short, long, global = true)]
+    pub profile: Option<String>,
+
+    /// 提供商预设：zhipu / openai / deepseek / ollama
+    #[arg(long, global = true)]
+    pub provider: Option<String>,
+
+    /// 模型接口地址
+    #[arg(long, global = true, value_name = "URL")]
+    pub base_url: Option<String>,
+
+    /// 采样温度
+    #[arg(long, global = true)]
+    pub temperature: Option<f64>,
+
+    /// 核采样概率 top_p
+    #[arg(long, global = true, value_name = "P")]
+    pub top_p: Option<f64>,
+
+    /// 单次回复的最大输出 token
+    #[arg(long, global = true, value_name = "N")]
+    pub max_tokens: Option<u32>,
+
+    /// 停止序列，可重复指定
+    #[arg(long, global = true, value_name = "TEXT")]
+    pub stop: Vec<String>,
+
+    /// 随机种子（接口支持时输出可复现）
+    #[arg(long, global = true, value_name = "N")]
+    pub seed: Option<u64>,
+
+    /// 工具选择：auto / none / required / 工具名（强制调用该工具）
+    #[arg(long, global = true, value_name = "CHOICE")]
+    pub tool_choice: Option<ToolChoice>,
+
+    /// 一次回复最多调用一个工具
+    #[arg(long, global = true)]
+    pub no_parallel_tool_calls: bool,
+
+    /// 工具调用方式：native（tools 参数）/ text（工具写进提示，从回复文本解析调用）
+    #[arg(long, global = true, value_name = "MODE")]
+    pub tool_calling: Option<ToolCalling>,
+
+    /// 工具审批策略：never / on-request / untrusted
+    #[arg(long, global = true, value_name = "POLICY")]
+    pub approval_policy: Option<ApprovalPolicy>,
+
+    /// 每轮最多的模型请求次数（预算，默认 10）
+    #[arg(long, global = true, value_name = "N")]
+    pub max_requests: Option<u64>,
+
+    /// 每轮最多执行的工具调用次数（预算）
+    #[arg(long, global = true, value_name = "N")]
+    pub max_tool_calls: Option<u64>,
+
+    /// 每轮最长耗时，单位秒（预算）
+    #[arg(long, global = true, value_name = "SECS")]
+    pub max_duration: Option<u64>,
+
+    /// 每轮最多使用的 token（预算）
+    #[arg(long, global = true, value_name = "N")]
+    pub max_turn_tokens: Option<u64>,
+
+    /// 每轮最多的费用，按 [prices] 计算（预算）
+    #[arg(long, global = true, value_name = "AMOUNT")]
+    pub max_cost: Option<f64>,
+
+    /// 信任项目配置：允许它设置 base_url、api_key_env、approval_policy、[paths] 和外部工具
+    #[arg(long, global = true)]
+    pub trust_project: bool,
+
+    /// 工作目录（同时作为工作区根目录和项目配置的查找位置）
+    #[arg(short = 'C', long, global = true, value_name = "DIR")]
+    pub cwd: Option<PathBuf>,
+
+    /// 输出更详细的日志（可重复：-v 为 debug，-vv 为 trace）
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// 只输出错误日志
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+}
+
+impl GlobalArgs {
+    /// 转换为配置合并使用的命令行覆盖项
+    pub fn overrides(&self) -> CliOverrides {
+        CliOverrides {
+            profile: self.profile.clone(),
+            settings: ModelSettings {
+                provider: self.provider.clone(),
+                model: self.model.clone(),
+                base_url: self.base_url.clone(),
+                request: self.request_options(),
+                approval_policy: self.approval_policy,
+                tool_calling: self.tool_calling,
+                budget: self.budget(),
+                ..Default::default()
+            },
+            trust_project: self.trust_project,
+        }
+    }
+
+    /// 命令行设置的请求参数
+    fn request_options(&self) -> RequestOptions {
+        RequestOptions {
+            temperature: self.temperature,
+            top_p: self.top_p,
+            max_tokens: self.max_tokens,
+            stop: (!self.stop.is_empty()).then(|| self.stop.clone()),
+            seed: self.seed,
+            tool_choice: self.tool_choice.clone(),
+            parallel_tool_calls: self.no_parallel_tool_calls.then_some(false),
+        }
+    }
+
+    /// 命令行设置的预算项，全部省略时为 None
+    fn budget(&self) -> Option<Budget> {
+        let budget = Budget {
+            max_requests: self.max_requests,
+            max_tool_calls: self.max_tool_calls,
+            max_duration_secs: self.max_duration,
+            max_tokens: self.max_turn_tokens,
+            max_cost: self.max_cost,
+            ..Default::default()
+        };
+        (budget != Budget::default()).then_some(budget)
+    }
+
+    /// 日志级别
+    pub fn log_level(&self) -> tracing::Level {
+        match (self.quiet, self.verbose) {
+            (true, _) => tracing::Level::ERROR,
+            (false, 0) => tracing::Level::INFO,
+            (false, 1) => tracing::Level::DEBUG,
+            (false, _) => tracing::Level::TRACE,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// 交互式对话（默认）
+    Chat(ChatArgs),
+    /// 全屏终端界面
+    Tui(ChatArgs),
+    /// 非交互地执行一条指令，输出最终回复
+    Exec(ExecArgs),
+    /// 以 MCP 服务器模式运行（stdio）
+    #[command(alias = "mcp-server")]
+    Serve,
+    /// 管理保存的会话
+    Sessions {
+        #[command(subcommand)]
+        command: SessionsCommand,
+    },
+    /// 查看可用工具
+    Tools {
+        #[command(subcommand)]
+        command: ToolsCommand,
+    },
+    /// 查看生效的配置
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// 生成 shell 补全脚本
+    Completions {
+        /// bash / zsh / fish / powershell / elvish
+        shell: Shell,
+    },
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct ChatArgs {
+    /// 恢复指定会话（支持 id 前缀）
+    #[arg(long, value_name = "ID")]
+    pub resume: Option<String>,
+
+    /// 恢复最近的会话
+    #[arg(long, conflicts_with = "resume")]
+    pub last: bool,
+
+    /// 不保存本次会话
+    #[arg(long)]
+    pub ephemeral: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ExecArgs {
+    /// 要执行的指令；省略或为 "-" 时从标准输入读取
+    pub prompt: Option<String>,
+
+    /// 以 JSON Lines 输出智能体事件，而不是纯文本
+    #[arg(long)]
+    pub json: bool,
+
+    /// 不保存本次会话
+    #[arg(long)]
+    pub ephemeral: bool,
+
+    /// 随指令附加的图片（本地路径或 URL），可重复指定；需要模型支持图片输入
+    #[arg(short = 'i', long = "image", value_name = "PATH|URL")]
+    pub images: Vec<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SessionsCommand {
+    /// 列出保存的会话
+    List,
+    /// 显示会话内容
+    Show {
+        /// 会话 id（支持前缀）
+        id: String,
+    },
+    /// 删除会话
+    Delete {
+        /// 会话 id（支持前缀）
+        id: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ToolsCommand {
+    /// 列出已注册的工具（内置、命令工具、插件和 MCP 工具）
+    List {
+        /// 以 JSON 输出完整的工具定义
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// 打印生效的配置及其来源
+    Show,
+    /// 打印配置文件和会话存储的位置
+    Path,
+}
+
+/// 生成补全脚本
+pub fn completions(shell: Shell) -> String {
+    let mut out = Vec::new();
+    clap_complete::generate(shell, &mut Cli::command(), BIN_NAME, &mut out);
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once(BIN_NAME).chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn test_parse_subcommands_and_global_flags() {
+        let cli = parse(&[]).unwrap();
+        assert!(cli.command.is_none());
+
+        // 全局选项可以写在子命令之后
+        let cli = parse(&["exec", "修复测试", "-m", "glm-4-flash", "--approval-policy", "on-request", "-C", "/tmp", "-vv"]).unwrap();
+        let Some(Command::Exec(exec)) = &cli.command else { panic!("应解析为 exec") };
+        assert_eq!(exec.prompt.as_deref(), Some("修复测试"));
+        assert!(exec.images.is_empty());
+        assert_eq!(cli.global.cwd, Some(PathBuf::from("/tmp")));
+        assert_eq!(cli.global.log_level(), tracing::Level::TRACE);
+        let overrides = cli.global.overrides();
+        assert_eq!(overrides.settings.model.as_deref(), Some("glm-4-flash"));
+        assert_eq!(overrides.settings.approval_policy, Some(ApprovalPolicy::OnRequest));
+        assert_eq!(overrides.settings.budget, None);
+        assert_eq!(overrides.settings.request, RequestOptions::default());
+
+        let cli = parse(&["exec", "订票", "--tool-choice", "get_flight_number", "--stop", "END", "--no-parallel-tool-calls"]).unwrap();
+        let request = cli.global.overrides().settings.request;
+        assert_eq!(request.tool_choice, Some(ToolChoice::Function("get_flight_number".to_string())));
+        assert_eq!(request.stop, Some(vec!["END".to_string()]));
+        assert_eq!(request.parallel_tool_calls, Some(false));
+
+        let cli = parse(&["exec", "这是什么", "-i", "shot.png", "--image", "https://example.com/a.jpg"]).unwrap();
+        let Some(Command::Exec(exec)) = &cli.command else { panic!("应解析为 exec") };
+        assert_eq!(exec.images, ["shot.png", "https://example.com/a.jpg"]);
+
+        let cli = parse(&["exec", "跑批", "--max-cost", "0.5", "--max-tool-calls", "20", "--max-duration", "600"]).unwrap();
+        let budget = cli.global.overrides().settings.budget.unwrap();
+        assert_eq!(budget.max_cost, Some(0.5));
+        assert_eq!(budget.max_tool_calls, Some(20));
+        assert_eq!(budget.max_duration_secs, Some(600));
+        assert_eq!(budget.max_requests, None);
+
+        let cli = parse(&["-p", "fast", "sessions", "show", "abc"]).unwrap();
+        assert_eq!(cli.global.overrides().profile.as_deref(), Some("fast"));
+        assert!(matches!(cli.command, Some(Command::Sessions { command: SessionsCommand::Show { ref id } }) if id == "abc"));
+
+        // 兼容旧的 mcp-server 写法
+        assert!(matches!(parse(&["mcp-server"]).unwrap().command, Some(Command::Serve)));
+
+        assert!(matches!(parse(&["tui", "--last"]).unwrap().command, Some(Command::Tui(ChatArgs { last: true, .. }))));
+        assert!(parse(&["chat", "--resume", "abc", "--last"]).is_err());
+        assert!(parse(&["-q", "-v"]).is_err());
+        assert!(parse(&["--approval-policy", "sometimes"]).is_err());
+    }
+
+    #[test]
+    fn test_generate_completions() {
+        Cli::command().debug_assert();
+        let script = completions(Shell::Bash);
+        assert!(script.contains(BIN_NAME));
+        assert!(script.contains("sessions"));
+    }
+}
@@ -1,8 +1,13 @@
 // 模型客户端实现 - 完善的流式版本
 
-use futures::TryStreamExt;
+use crate::config::{ReasoningReplay, StructuredOutput, ToolCalling};
+use crate::request_options::{RequestCapabilities, RequestOptions, ToolChoice};
+use crate::session::{message_role, MessageRole};
+use crate::tool_emulation::{self, TextToolCallParser};
+use crate::usage::TokenUsage;
 use reqwest::Client as ReqwestClient;
 use serde_json::{json, Value};
+use std::collections::{BTreeMap, VecDeque};
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use std::time::Duration;
@@ -13,26 +18,204 @@ pub enum SseEvent {
     TextDelta(String),
     ReasoningDelta(String),
     ToolCalls(Vec<crate::protocol::ToolCall>),
+    /// 本次请求的 token 用量（通常在最后一个数据块中）
+    Usage(TokenUsage),
     Done,
 }
 
+/// 模型接口请求失败
+#[derive(Debug)]
+pub struct ApiError {
+    /// HTTP 状态码，网络错误（连接失败、超时）时为 None
+    pub status: Option<reqwest::StatusCode>,
+    pub message: String,
+}
+
+impl ApiError {
+    fn status(status: reqwest::StatusCode, message: String) -> Self {
+        Self { status: Some(status), message }
+    }
+
+    fn transport(error: reqwest::Error) -> Self {
+        Self { status: None, message: error.to_string() }
+    }
+
+    /// 换一个模型可能成功：网络错误、超时、额度不足（402）、限流（429）和服务端错误（5xx）
+    ///
+    /// 请求本身有问题（400）或认证失败（401/403）时换模型也无济于事。
+    pub fn allows_fallback(&self) -> bool {
+        match self.status {
+            None => true,
+            Some(status) => {
+                matches!(status.as_u16(), 402 | 408 | 429) || status.is_server_error()
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.status {
+            Some(status) => write!(f, "API 请求失败 ({}): {}", status, self.message),
+            None => write!(f, "API 请求失败: {}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
 /// 简化版模型客户端
 pub struct ModelClient {
     api_key: String,
     model: String,
     client: ReqwestClient,
     base_url: String,
+    /// 智能体的默认请求参数
+    request_options: RequestOptions,
+    /// 只对当前一轮生效的请求参数（覆盖默认值）
+    turn_options: RequestOptions,
+    /// 接口支持的请求参数
+    request_capabilities: RequestCapabilities,
+    /// 本轮已执行过工具，强制的 tool_choice 不再生效
+    tool_choice_released: bool,
+    reasoning_replay: ReasoningReplay,
+    /// 流式请求是否要求返回 usage
+    stream_usage: bool,
+    /// 接口支持的结构化输出方式
+    structured_output: StructuredOutput,
+    /// 结构化输出期间要求的 JSON Schema 及其名称，按 structured_output 转换为 response_format
+    response_schema: Option<(String, Value)>,
+    /// 工具调用方式（text 时工具写进提示，从回复文本中解析调用）
+    tool_calling: ToolCalling,
 }
 
+/// 默认 API 地址（智谱 AI 开放平台，兼容 OpenAI 格式）
+pub const DEFAULT_BASE_URL: &str = "https://open.bigmodel.cn/api/paas/v4/";
+
 impl ModelClient {
+    /// 创建模型客户端（使用默认 API 地址）
+    pub fn new(api_key: String, model: String) -> Self {
+        Self::new_with_config(api_key, model, DEFAULT_BASE_URL.to_string())
+    }
+
     /// 创建模型客户端（自定义配置）
     pub fn new_with_config(api_key: String, model: String, base_url: String) -> Self {
         Self {
             api_key,
             model,
             client: ReqwestClient::new(),
-            base_url,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            request_options: RequestOptions::default(),
+            turn_options: RequestOptions::default(),
+            request_capabilities: RequestCapabilities::default(),
+            tool_choice_released: false,
+            reasoning_replay: ReasoningReplay::default(),
+            stream_usage: true,
+            structured_output: StructuredOutput::default(),
+            response_schema: None,
+            tool_calling: ToolCalling::default(),
+        }
+    }
+
+    /// 设置默认的请求参数（不校验，校验见 `set_request_options`）
+    pub fn with_request_options(mut self, request_options: RequestOptions) -> Self {
+        self.request_options = request_options;
+        self
+    }
+
+    /// 设置接口支持的请求参数
+    pub fn with_request_capabilities(mut self, request_capabilities: RequestCapabilities) -> Self {
+        self.request_capabilities = request_capabilities;
+        self
+    }
+
+    /// 设置思考内容的回传方式
+    pub fn with_reasoning_replay(mut self, reasoning_replay: ReasoningReplay) -> Self {
+        self.reasoning_replay = reasoning_replay;
+        self
+    }
+
+    /// 设置流式请求是否发送 stream_options.include_usage（不支持该参数的接口应关闭）
+    pub fn with_stream_usage(mut self, stream_usage: bool) -> Self {
+        self.stream_usage = stream_usage;
+        self
+    }
+
+    /// 设置接口支持的结构化输出方式
+    pub fn with_structured_output(mut self, structured_output: StructuredOutput) -> Self {
+        self.structured_output = structured_output;
+        self
+    }
+
+    /// 设置工具调用方式
+    pub fn with_tool_calling(mut self, tool_calling: ToolCalling) -> Self {
+        self.tool_calling = tool_calling;
+        self
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn structured_output(&self) -> StructuredOutput {
+        self.structured_output
+    }
+
+    pub fn request_options(&self) -> &RequestOptions {
+        &self.request_options
+    }
+
+    /// 按接口支持的参数校验后替换默认的请求参数
+    pub fn set_request_options(&mut self, request_options: RequestOptions) -> Result<(), anyhow::Error> {
+        request_options.validate(&self.request_capabilities)?;
+        self.request_options = request_options;
+        Ok(())
+    }
+
+    /// 设置只对当前一轮生效的请求参数，校验叠加后的结果；传入默认值即清除
+    pub fn set_turn_options(&mut self, turn_options: RequestOptions) -> Result<(), anyhow::Error> {
+        self.request_options.merged(&turn_options).validate(&self.request_capabilities)?;
+        self.turn_options = turn_options;
+        Ok(())
+    }
+
+    /// 清除本轮的请求参数
+    pub fn clear_turn_options(&mut self) {
+        self.turn_options = RequestOptions::default();
+    }
+
+    /// 标记本轮是否已执行过工具：执行过之后强制的 tool_choice（required 或指定工具）恢复为 auto
+    pub fn set_tool_choice_released(&mut self, released: bool) {
+        self.tool_choice_released = released;
+    }
+
+    /// 下一次请求实际使用的参数
+    pub fn effective_options(&self) -> RequestOptions {
+        let mut options = self.request_options.merged(&self.turn_options);
+        if self.tool_choice_released && options.tool_choice.as_ref().is_some_and(ToolChoice::is_forced) {
+            options.tool_choice = Some(ToolChoice::Auto);
         }
+        options
+    }
+
+    /// 设置之后请求要求的 JSON Schema（作为 response_format 发送），None 表示不要求
+    pub fn set_response_schema(&mut self, response_schema: Option<(String, Value)>) {
+        self.response_schema = response_schema;
+    }
+
+    /// 沿用 primary 本轮的状态：本轮参数、tool_choice 是否已释放、结构化输出的 schema
+    ///
+    /// 路由或备用的客户端在发出请求前调用；本轮参数不适用于该接口时返回错误。
+    pub fn inherit_turn_state(&mut self, primary: &ModelClient) -> Result<(), anyhow::Error> {
+        self.set_turn_options(primary.turn_options.clone())?;
+        self.tool_choice_released = primary.tool_choice_released;
+        self.response_schema = primary.response_schema.clone();
+        Ok(())
+    }
+
+    /// 切换模型（同一 API 地址下的其他模型）
+    pub fn set_model(&mut self, model: impl Into<String>) {
+        self.model = model.into();
     }
 
     /// 发送消息并获取完整响应（非流式版本）
@@ -42,21 +225,8 @@ impl ModelClient {
         messages: Vec<Value>,
         tools: Option<Vec<Value>>,
     ) -> Result<ChatResponse, anyhow::Error> {
-        // 转换消息格式以兼容智谱 API
-        let formatted_messages = format_messages(messages);
-
-        let mut request_body = json!({
-            "model": self.model,
-            "messages": formatted_messages,
-            "stream": false
-        });
-
-        // 添加工具定义（智谱 AI 支持）
-        if let Some(tools) = tools {
-            if !tools.is_empty() {
-                request_body["tools"] = json!(tools);
-            }
-        }
+        let text_tools = self.text_tool_parser(tools.as_deref());
+        let request_body = self.request_body(messages, tools, false);
 
         // 发送请求
         let response = self
@@ -67,21 +237,24 @@ impl ModelClient {
             .timeout(Duration::from_secs(120))
             .json(&request_body)
             .send()
-            .await?;
+            .await
+            .map_err(ApiError::transport)?;
 
         if !response.status().is_success() {
             let status = response.status();
             let error_text = response.text().await.unwrap_or_default();
             eprintln!("⚠️  API 请求详情: {}", error_text);
-            return Err(anyhow::anyhow!(
-                "API 请求失败 ({}): {}",
-                status,
-                error_text
-            ));
+            return Err(ApiError::status(status, error_text).into());
         }
 
         let response_json: Value = response.json().await?;
-        self.parse_response(response_json)
+        let mut response = self.parse_response(response_json)?;
+        if let Some(mut parser) = text_tools {
+            let (content, calls) = parser.parse(&response.content);
+            response.content = content;
+            response.tool_calls = (!calls.is_empty()).then_some(calls);
+        }
+        Ok(response)
     }
 
     /// 发送消息并获取流式响应（真正的异步流式）
@@ -90,22 +263,8 @@ impl ModelClient {
         messages: Vec<Value>,
         tools: Option<Vec<Value>>,
     ) -> Result<ResponseStream, anyhow::Error> {
-        // 转换消息格式
-        let formatted_messages = format_messages(messages);
-
-        let mut request_body = json!({
-            "model": self.model,
-            "messages": formatted_messages,
-            "stream": true,
-            "tool_choice": "auto"
-        });
-
-        // 添加工具定义
-        if let Some(tools) = tools {
-            if !tools.is_empty() {
-                request_body["tools"] = json!(tools);
-            }
-        }
+        let text_tools = self.text_tool_parser(tools.as_deref());
+        let request_body = self.request_body(messages, tools, true);
 
         //println!("\n📤 请求体: {}", serde_json::to_string_pretty(&request_body).unwrap_or_default());
 
@@ -118,20 +277,60 @@ impl ModelClient {
             .timeout(Duration::from_secs(120))
             .json(&request_body)
             .send()
-            .await?;
+            .await
+            .map_err(ApiError::transport)?;
 
         if !response.status().is_success() {
             let status = response.status();
             let error_text = response.text().await.unwrap_or_default();
-            return Err(anyhow::anyhow!(
-                "API 请求失败 ({}): {}",
-                status,
-                error_text
-            ));
+            return Err(ApiError::status(status, error_text).into());
         }
 
         // 创建流式响应
-        Ok(ResponseStream::new(response))
+        Ok(ResponseStream::new(response, text_tools))
+    }
+
+    /// 文本工具调用时解析回复中工具调用的解析器（没有提供工具或 tool_choice 为 none 时为 None）
+    fn text_tool_parser(&self, tools: Option<&[Value]>) -> Option<TextToolCallParser> {
+        let tools = tools.filter(|tools| !tools.is_empty())?;
+        let provided = self.tool_calling == ToolCalling::Text && self.effective_options().tool_choice != Some(ToolChoice::None);
+        provided.then(|| TextToolCallParser::for_tools(tools))
+    }
+
+    /// 构造请求体：对话历史、工具定义、请求参数和 response_format
+    fn request_body(&self, messages: Vec<Value>, tools: Option<Vec<Value>>, stream: bool) -> Value {
+        // 转换消息格式以兼容智谱 API
+        let mut formatted_messages = format_messages(messages, self.reasoning_replay);
+        let options = self.effective_options();
+        let mut tools = tools.filter(|tools| !tools.is_empty());
+        // 文本工具调用：工具写进系统提示，历史中的工具调用和结果改写为文本，请求中不发送工具参数
+        if self.tool_calling == ToolCalling::Text {
+            let prompt = tools.take().and_then(|tools| tool_emulation::tools_prompt(&tools, &options));
+            formatted_messages = tool_emulation::to_text_messages(formatted_messages, prompt.as_deref());
+        }
+
+        let mut request_body = json!({
+            "model": self.model,
+            "messages": formatted_messages,
+            "stream": stream
+        });
+        if stream && self.stream_usage {
+            request_body["stream_options"] = json!({ "include_usage": true });
+        }
+
+        // 添加工具定义（没有工具时也不发送 tool_choice 等工具参数）
+        options.apply(&mut request_body, &self.request_capabilities, tools.is_some());
+        if let Some(tools) = tools {
+            request_body["tools"] = json!(tools);
+        }
+        let response_format = self
+            .response_schema
+            .as_ref()
+            .and_then(|(name, schema)| self.structured_output.response_format(name, schema));
+        if let Some(response_format) = response_format {
+            request_body["response_format"] = response_format;
+        }
+        request_body
     }
 
     /// 解析 API 响应
@@ -145,7 +344,10 @@ impl ModelClient {
                 .filter_map(|call| {
                     let id = call["id"].as_str()?;
                     let name = call["function"]["name"].as_str()?;
-                    let args = call["function"]["arguments"].clone();
+                    let args = match &call["function"]["arguments"] {
+                        Value::String(text) => parse_arguments(text),
+                        other => other.clone(),
+                    };
                     Some(crate::protocol::ToolCall {
                         id: id.to_string(),
                         name: name.to_string(),
@@ -159,9 +361,14 @@ impl ModelClient {
             .as_str()
             .unwrap_or("")
             .to_string();
+        let reasoning = reasoning_text(&assistant)
+            .filter(|reasoning| !reasoning.is_empty())
+            .map(str::to_string);
 
         Ok(ChatResponse {
             content,
+            reasoning,
+            usage: TokenUsage::from_response(&response["usage"]),
             tool_calls,
             finish_reason: response["choices"][0]["finish_reason"]
                 .as_str()
@@ -175,32 +382,51 @@ impl ModelClient {
 pub struct ResponseStream {
     byte_stream: Pin<Box<dyn futures::Stream<Item = Result<bytes::Bytes, reqwest::Error>> + Send>>,
     buffer: Vec<u8>,
+    parser: SseParser,
+    /// 已解析、尚未交给调用方的事件（一个数据块可能包含多行）
+    pending: VecDeque<SseEvent>,
     completed: bool,
-    // 累积工具调用部分数据（用于流式工具调用解析）
-    tool_call_buffer: std::collections::HashMap<String, PartialToolCall>,
-}
-
-/// 部分工具调用数据（用于累积流式工具调用）
-#[derive(Debug, Default)]
-struct PartialToolCall {
-    id: Option<String>,
-    name: Option<String>,
-    arguments: String,
+    /// 文本工具调用时从回复文本中解析工具调用
+    text_tools: Option<TextToolCallParser>,
 }
 
 impl ResponseStream {
-    fn new(response: reqwest::Response) -> Self {
+    fn new(response: reqwest::Response, text_tools: Option<TextToolCallParser>) -> Self {
         // 创建字节流
-        let byte_stream = Box::pin(
-            response.bytes_stream()
-                .map_err(|e| reqwest::Error::from(e))
-        );
+        let byte_stream = Box::pin(response.bytes_stream());
 
         Self {
             byte_stream,
             buffer: Vec::new(),
+            parser: SseParser::default(),
+            pending: VecDeque::new(),
             completed: false,
-            tool_call_buffer: std::collections::HashMap::new(),
+            text_tools,
+        }
+    }
+
+    /// 把解析出的事件放入待发送队列；文本工具调用时回复文本先经过解析器，工具调用在 Done 之前发出
+    fn queue(&mut self, events: Vec<SseEvent>) {
+        for event in events {
+            match (&mut self.text_tools, event) {
+                (Some(parser), SseEvent::TextDelta(text)) => {
+                    let text = parser.push(&text);
+                    if !text.is_empty() {
+                        self.pending.push_back(SseEvent::TextDelta(text));
+                    }
+                }
+                (Some(parser), SseEvent::Done) => {
+                    let (text, calls) = parser.finish();
+                    if !text.is_empty() {
+                        self.pending.push_back(SseEvent::TextDelta(text));
+                    }
+                    if !calls.is_empty() {
+                        self.pending.push_back(SseEvent::ToolCalls(calls));
+                    }
+                    self.pending.push_back(SseEvent::Done);
+                }
+                (_, event) => self.pending.push_back(event),
+            }
         }
     }
 }
@@ -209,220 +435,287 @@ impl futures::Stream for ResponseStream {
     type Item = Result<SseEvent, anyhow::Error>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let mut this = self.as_mut();
+        let this = self.as_mut().get_mut();
 
-        if this.completed {
-            return Poll::Ready(None);
-        }
-
-        // 轮询底层字节流
-        match this.byte_stream.as_mut().poll_next(cx) {
-            Poll::Ready(Some(Ok(bytes))) => {
-                // 处理收到的字节
-                this.buffer.extend_from_slice(&bytes);
-
-                // 逐行处理
-                while let Some(pos) = this.buffer.iter().position(|&b| b == b'\n') {
-                    let line_bytes = this.buffer.drain(..=pos).collect::<Vec<_>>();
-                    // 只有当 buffer 不为空时才移除换行符
-                    if !this.buffer.is_empty() {
-                        this.buffer.remove(0);
-                    }
+        loop {
+            if let Some(event) = this.pending.pop_front() {
+                return Poll::Ready(Some(Ok(event)));
+            }
+            if this.completed {
+                return Poll::Ready(None);
+            }
 
-                    // 转换为字符串
-                    if let Ok(line) = String::from_utf8(line_bytes) {
-                        // 处理 SSE 事件
-                        if let Some(event) = this.parse_sse_line(&line) {
-                            return Poll::Ready(Some(Ok(event)));
-                        }
+            // 轮询底层字节流；收到的数据没有产生事件时继续轮询，直到底层流返回 Pending
+            match this.byte_stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(bytes))) => {
+                    this.buffer.extend_from_slice(&bytes);
+
+                    // 逐行处理（多字节字符可能被拆在两个数据块中，所以按字节找换行）
+                    while let Some(pos) = this.buffer.iter().position(|&b| b == b'\n') {
+                        let line_bytes: Vec<u8> = this.buffer.drain(..=pos).collect();
+                        let line = String::from_utf8_lossy(&line_bytes);
+                        let events = this.parser.parse_line(&line);
+                        this.queue(events);
                     }
                 }
-
-                Poll::Pending
-            }
-            Poll::Ready(None) => {
-                // 流结束，检查是否有未完成的行
-                if !this.buffer.is_empty() {
-                    if let Ok(line) = String::from_utf8(std::mem::take(&mut this.buffer)) {
-                        if let Some(event) = this.parse_sse_line(&line) {
-                            return Poll::Ready(Some(Ok(event)));
-                        }
+                Poll::Ready(None) => {
+                    // 流结束：处理没有换行结尾的最后一行，补发未完成的工具调用
+                    if !this.buffer.is_empty() {
+                        let line_bytes = std::mem::take(&mut this.buffer);
+                        let events = this.parser.parse_line(&String::from_utf8_lossy(&line_bytes));
+                        this.queue(events);
                     }
+                    let events = this.parser.finish();
+                    this.queue(events);
+                    this.completed = true;
                 }
-                this.completed = true;
-                Poll::Ready(None)
-            }
-            Poll::Ready(Some(Err(e))) => {
-                Poll::Ready(Some(Err(anyhow::anyhow!("流读取错误: {}", e))))
+                Poll::Ready(Some(Err(e))) => {
+                    this.completed = true;
+                    return Poll::Ready(Some(Err(anyhow::anyhow!("流读取错误: {}", e))));
+                }
+                Poll::Pending => return Poll::Pending,
             }
-            Poll::Pending => Poll::Pending,
         }
     }
 }
 
-impl ResponseStream {
-    /// 解析 SSE 行
-    fn parse_sse_line(&mut self, line: &str) -> Option<SseEvent> {
+/// SSE 解析器：逐行解析 `data:` 行，累积流式分片的工具调用
+///
+/// 工具调用在收到 finish_reason、`[DONE]` 或流结束时发出，与 finish_reason 出现在哪个数据块无关。
+#[derive(Debug, Default)]
+struct SseParser {
+    /// 按 index 累积的工具调用分片
+    tool_call_buffer: BTreeMap<u64, PartialToolCall>,
+    /// 已发出 Done
+    done: bool,
+}
+
+/// 部分工具调用数据（用于累积流式工具调用）
+#[derive(Debug, Default)]
+struct PartialToolCall {
+    id: Option<String>,
+    name: Option<String>,
+    arguments: String,
+}
+
+impl SseParser {
+    /// 解析一行 SSE 数据，返回这一行产生的事件
+    fn parse_line(&mut self, line: &str) -> Vec<SseEvent> {
+        let line = line.trim_end_matches(['\r', '\n']);
         // SSE 格式: data: {...}
-        if !line.starts_with("data: ") {
-            return None;
+        let Some(data) = line.strip_prefix("data:") else {
+            return Vec::new();
+        };
+        if self.done {
+            return Vec::new();
         }
-
-        let json_str = &line[6..];
+        let data = data.trim_start();
 
         // 检查结束标记
-        if json_str == "[DONE]" || json_str == "DONE" {
-            return Some(SseEvent::Done);
+        if data == "[DONE]" || data == "DONE" {
+            return self.finish();
         }
 
-        // 解析 JSON
-        if let Ok(json_value) = serde_json::from_str::<Value>(json_str) {
-            let choices = &json_value["choices"];
-            if !choices.is_array() || choices.as_array().unwrap().is_empty() {
-                return None;
+        let Ok(json_value) = serde_json::from_str::<Value>(data) else {
+            return Vec::new();
+        };
+
+        // include_usage 时 usage 在单独的数据块中，choices 为空
+        let mut events: Vec<SseEvent> = TokenUsage::from_response(&json_value["usage"])
+            .map(SseEvent::Usage)
+            .into_iter()
+            .collect();
+        let Some(choice) = json_value["choices"].as_array().and_then(|choices| choices.first()) else {
+            return events;
+        };
+
+        let delta = &choice["delta"];
+
+        // 思考内容和回复可能出现在同一个数据块中，分别发出
+        if let Some(reasoning) = reasoning_text(delta) {
+            if !reasoning.is_empty() {
+                events.push(SseEvent::ReasoningDelta(reasoning.to_string()));
+            }
+        }
+        if let Some(content) = delta["content"].as_str() {
+            if !content.is_empty() {
+                events.push(SseEvent::TextDelta(content.to_string()));
             }
+        }
 
-            let delta = &choices[0]["delta"];
+        // 工具调用是流式分片的，按 index 累积
+        for call in delta["tool_calls"].as_array().into_iter().flatten() {
+            let index = call.get("index").and_then(|v| v.as_u64()).unwrap_or(0);
+            let partial = self.tool_call_buffer.entry(index).or_default();
 
-            // 智谱 AI 使用 reasoning_content 字段
-            if let Some(reasoning) = delta["reasoning_content"].as_str() {
-                if !reasoning.is_empty() {
-                    return Some(SseEvent::ReasoningDelta(reasoning.to_string()));
-                }
+            if let Some(id) = call.get("id").and_then(|v| v.as_str()) {
+                partial.id = Some(id.to_string());
             }
-
-            // 检查 content 字段（兼容性）
-            if let Some(content) = delta["content"].as_str() {
-                if !content.is_empty() {
-                    return Some(SseEvent::TextDelta(content.to_string()));
+            if let Some(func) = call.get("function") {
+                if let Some(name) = func.get("name").and_then(|v| v.as_str()) {
+                    partial.name = Some(name.to_string());
+                }
+                // 参数可能分多次到达；也有接口直接给出 JSON 对象
+                match func.get("arguments") {
+                    Some(Value::String(args)) => partial.arguments.push_str(args),
+                    Some(args) if args.is_object() => partial.arguments.push_str(&args.to_string()),
+                    _ => {}
                 }
             }
+        }
 
-            // 检查工具调用 - 智谱 AI 的工具调用是流式分片的
-            if let Some(calls) = delta["tool_calls"].as_array() {
-                if !calls.is_empty() {
-                    for call in calls {
-                        // 获取工具调用索引
-                        let index = call.get("index").and_then(|v| v.as_u64()).unwrap_or(0);
-                        let index_str = index.to_string();
-
-                        // 累积工具调用数据
-                        let partial = self.tool_call_buffer.entry(index_str.clone()).or_default();
-
-                        // 累积 ID
-                        if let Some(id) = call.get("id").and_then(|v| v.as_str()) {
-                            partial.id = Some(id.to_string());
-                        }
-
-                        // 累积函数名
-                        if let Some(func) = call.get("function") {
-                            if let Some(name) = func.get("name").and_then(|v| v.as_str()) {
-                                partial.name = Some(name.to_string());
-                            }
-
-                            // 累积参数（可能分多次到达）
-                            if let Some(args) = func.get("arguments").and_then(|v| v.as_str()) {
-                                partial.arguments.push_str(args);
-                            }
-                        }
-
-                        // 检查工具调用是否完成（finish_reason 为 "tool_calls"）
-                        if let Some(finish_reason) = json_value["choices"][0].get("finish_reason").and_then(|v| v.as_str()) {
-                            if finish_reason == "tool_calls" {
-                                // 构建完整的工具调用列表
-                                let mut tool_calls: Vec<crate::protocol::ToolCall> = Vec::new();
-
-                                for (_idx, partial) in self.tool_call_buffer.drain() {
-                                    if let (Some(id), Some(name)) = (partial.id, partial.name) {
-                                        // 解析参数
-                                        let arguments = if partial.arguments.is_empty() {
-                                            serde_json::json!({})
-                                        } else if let Ok(json) = serde_json::from_str::<serde_json::Value>(&partial.arguments) {
-                                            json
-                                        } else {
-                                            serde_json::json!({"raw": partial.arguments})
-                                        };
-
-                                        tool_calls.push(crate::protocol::ToolCall {
-                                            id,
-                                            name,
-                                            arguments,
-                                        });
-                                    }
-                                }
+        // 本次响应结束（finish_reason 可能和最后一个工具调用分片在不同的数据块中）
+        if choice["finish_reason"].is_string() {
+            events.extend(self.flush_tool_calls());
+        }
+        events
+    }
 
-                                if !tool_calls.is_empty() {
-                                    println!("\n✅ 解析工具调用: {} 个", tool_calls.len());
-                                    for tc in &tool_calls {
-                                        println!("  - {} ({})", tc.name, tc.id);
-                                    }
-                                    return Some(SseEvent::ToolCalls(tool_calls));
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+    /// 流结束：发出尚未发出的工具调用和 Done
+    fn finish(&mut self) -> Vec<SseEvent> {
+        if self.done {
+            return Vec::new();
+        }
+        self.done = true;
+        let mut events: Vec<SseEvent> = self.flush_tool_calls().into_iter().collect();
+        events.push(SseEvent::Done);
+        events
+    }
+
+    /// 把累积的工具调用组装为完整的列表
+    fn flush_tool_calls(&mut self) -> Option<SseEvent> {
+        let tool_calls: Vec<crate::protocol::ToolCall> = std::mem::take(&mut self.tool_call_buffer)
+            .into_values()
+            .filter_map(|partial| {
+                Some(crate::protocol::ToolCall {
+                    id: partial.id?,
+                    name: partial.name?,
+                    arguments: parse_arguments(&partial.arguments),
+                })
+            })
+            .collect();
+
+        if tool_calls.is_empty() {
+            return None;
+        }
+        println!("\n✅ 解析工具调用: {} 个", tool_calls.len());
+        for tc in &tool_calls {
+            println!("  - {} ({})", tc.name, tc.id);
         }
+        Some(SseEvent::ToolCalls(tool_calls))
+    }
+}
 
-        None
+/// 解析工具调用参数（JSON 字符串），无法解析时原样放在 raw 字段中
+fn parse_arguments(arguments: &str) -> Value {
+    if arguments.trim().is_empty() {
+        json!({})
+    } else if let Ok(json) = serde_json::from_str::<Value>(arguments) {
+        json
+    } else {
+        json!({"raw": arguments})
     }
 }
 
+/// 思考内容：智谱、DeepSeek 使用 reasoning_content，Ollama 等使用 reasoning
+fn reasoning_text(message: &Value) -> Option<&str> {
+    message["reasoning_content"]
+        .as_str()
+        .or_else(|| message["reasoning"].as_str())
+}
+
 /// 聊天响应
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub struct ChatResponse {
     pub content: String,
+    /// 思考内容（不计入回复）
+    pub reasoning: Option<String>,
+    /// token 用量（接口没有返回时为 None）
+    pub usage: Option<TokenUsage>,
     pub tool_calls: Option<Vec<crate::protocol::ToolCall>>,
     #[allow(dead_code)]
     pub finish_reason: String,
 }
 
-/// 格式化消息列表
-fn format_messages(messages: Vec<Value>) -> Vec<Value> {
-    messages.iter().filter_map(|msg| {
-        // 用户消息
-        if msg.get("content").is_some() && msg.get("tool_call_id").is_none() && msg.get("tool_calls").is_none() {
-            Some(json!({
+/// 发送工具附加的图片时放在图片前的说明
+const ATTACHMENT_PREFIX: &str = "以下是 view_image 工具附加的图片：";
+
+/// 格式化消息列表：把对话历史转换为 OpenAI 格式
+///
+/// 已带 role 的消息（如系统提示）原样发送；思考条目按 `reasoning_replay` 附加到紧随其后的
+/// 助手消息的 reasoning_content 字段，或者丢弃。
+fn format_messages(messages: Vec<Value>, reasoning_replay: ReasoningReplay) -> Vec<Value> {
+    let is_history = |msg: &Value| msg.get("role").is_none();
+    // 本轮从最后一条用户消息开始
+    let turn_start = messages
+        .iter()
+        .rposition(|msg| is_history(msg) && message_role(msg) == MessageRole::User)
+        .unwrap_or(0);
+
+    let mut reasoning: Option<&str> = None;
+    let mut formatted = Vec::new();
+    for (index, msg) in messages.iter().enumerate() {
+        if !is_history(msg) {
+            formatted.push(msg.clone());
+            continue;
+        }
+        match message_role(msg) {
+            MessageRole::User => formatted.push(json!({
                 "role": "user",
                 "content": msg["content"]
-            }))
-        }
-        // 助手消息（可能包含工具调用）
-        else if msg.get("content").is_some() || msg.get("tool_calls").is_some() {
-            let mut msg_obj = json!({
-                "role": "assistant",
-                "content": msg["content"].as_str().unwrap_or("")
-            });
-            if let Some(tool_calls) = msg.get("tool_calls") {
-                let converted_tool_calls: Vec<Value> = tool_calls.as_array()
-                    .map(|arr| arr.iter().map(|call| {
-                        json!({
-                            "id": call["id"],
-                            "type": "function",
-                            "function": {
-                                "name": call["name"],
-                                "arguments": call["arguments"]
-                            }
+            })),
+            // 工具附加的图片：工具结果只能是文本，以用户消息发送
+            MessageRole::Attachment => {
+                let mut content = vec![json!({ "type": "text", "text": ATTACHMENT_PREFIX })];
+                content.extend(msg["attached_images"].as_array().into_iter().flatten().cloned());
+                formatted.push(json!({ "role": "user", "content": content }));
+            }
+            MessageRole::Reasoning => reasoning = msg["reasoning"].as_str(),
+            // 助手消息（可能包含工具调用）
+            MessageRole::Assistant => {
+                let mut msg_obj = json!({
+                    "role": "assistant",
+                    "content": msg["content"].as_str().unwrap_or("")
+                });
+                let tool_calls = msg["tool_calls"].as_array().filter(|calls| !calls.is_empty());
+                if let Some(tool_calls) = tool_calls {
+                    let converted_tool_calls: Vec<Value> = tool_calls
+                        .iter()
+                        .map(|call| {
+                            // OpenAI 格式要求参数为 JSON 字符串
+                            let arguments = match &call["arguments"] {
+                                Value::String(text) => text.clone(),
+                                other => other.to_string(),
+                            };
+                            json!({
+                                "id": call["id"],
+                                "type": "function",
+                                "function": {
+                                    "name": call["name"],
+                                    "arguments": arguments
+                                }
+                            })
                         })
-                    }).collect())
-                    .unwrap_or_default();
-                msg_obj["tool_calls"] = json!(converted_tool_calls);
+                        .collect();
+                    msg_obj["tool_calls"] = json!(converted_tool_calls);
+                }
+                if let Some(text) = reasoning.take() {
+                    if let Some(replayed) = reasoning_replay.replayed(text, index > turn_start) {
+                        msg_obj["reasoning_content"] = json!(replayed);
+                    }
+                }
+                formatted.push(msg_obj);
             }
-            Some(msg_obj)
-        }
-        // 工具返回消息
-        else if msg.get("tool_call_id").is_some() {
-            Some(json!({
+            // 工具返回消息
+            MessageRole::Tool => formatted.push(json!({
                 "role": "tool",
                 "content": msg["content"],
                 "tool_call_id": msg["tool_call_id"]
-            }))
-        } else {
-            None
+            })),
         }
-    }).collect()
+    }
+    formatted
 }
 
 #[cfg(test)]
@@ -439,4 +732,174 @@ mod tests {
         assert_eq!(client.model, "gpt-4");
         assert_eq!(client.api_key, "test-key");
     }
+
+    #[test]
+    fn test_request_body_options() {
+        let mut client = ModelClient::new("test-key".to_string(), "gpt-4o".to_string())
+            .with_request_capabilities(RequestCapabilities { max_tokens_field: "max_completion_tokens", ..Default::default() })
+            .with_request_options(RequestOptions { temperature: Some(0.3), max_tokens: Some(256), ..Default::default() });
+        let tools = Some(vec![json!({ "type": "function", "function": { "name": "current_time" } })]);
+
+        let body = client.request_body(vec![], tools.clone(), true);
+        assert_eq!(body["temperature"], 0.3);
+        assert_eq!(body["max_completion_tokens"], 256);
+        assert!(body.get("max_tokens").is_none());
+        assert!(body.get("tool_choice").is_none());
+
+        // 本轮参数覆盖默认值；执行过工具后强制的 tool_choice 恢复为 auto
+        let turn = RequestOptions { temperature: Some(0.0), tool_choice: Some(ToolChoice::Required), ..Default::default() };
+        client.set_turn_options(turn).unwrap();
+        let body = client.request_body(vec![], tools.clone(), true);
+        assert_eq!(body["temperature"], 0.0);
+        assert_eq!(body["tool_choice"], "required");
+        client.set_tool_choice_released(true);
+        assert_eq!(client.request_body(vec![], tools.clone(), true)["tool_choice"], "auto");
+        // 没有工具时不发送 tool_choice
+        assert!(client.request_body(vec![], None, false).get("tool_choice").is_none());
+
+        client.clear_turn_options();
+        assert_eq!(client.request_body(vec![], tools, false)["temperature"], 0.3);
+        assert!(client
+            .set_request_options(RequestOptions { temperature: Some(3.0), ..Default::default() })
+            .is_err());
+        assert_eq!(client.request_options().temperature, Some(0.3));
+    }
+
+    #[test]
+    fn test_request_body_text_tool_calling() {
+        let client = ModelClient::new("test-key".to_string(), "qwen2.5".to_string())
+            .with_tool_calling(ToolCalling::Text)
+            .with_request_options(RequestOptions { tool_choice: Some(ToolChoice::Required), ..Default::default() });
+        let tools = vec![json!({ "type": "function", "function": { "name": "current_time", "description": "Get time" } })];
+        let messages = vec![
+            json!({ "content": "几点了" }),
+            json!({ "content": "", "tool_calls": [{ "id": "call_1", "name": "current_time", "arguments": {} }] }),
+            json!({ "tool_call_id": "call_1", "content": "12:00" }),
+        ];
+
+        let body = client.request_body(messages, Some(tools.clone()), true);
+        assert!(body.get("tools").is_none());
+        assert!(body.get("tool_choice").is_none());
+        assert_eq!(body["messages"][0]["role"], "system");
+        assert!(body["messages"][0]["content"].as_str().unwrap().contains("## current_time\nGet time"));
+        assert_eq!(body["messages"][2]["content"], "<tool_call>\n{\"name\": \"current_time\", \"arguments\": {}}\n</tool_call>");
+        assert_eq!(body["messages"][3]["role"], "user");
+        assert!(client.text_tool_parser(Some(&tools)).is_some());
+        assert!(client.text_tool_parser(None).is_none());
+    }
+
+    fn parse_all(lines: &[&str]) -> Vec<SseEvent> {
+        let mut parser = SseParser::default();
+        let mut events: Vec<SseEvent> = lines.iter().flat_map(|line| parser.parse_line(line)).collect();
+        events.extend(parser.finish());
+        events
+    }
+
+    #[test]
+    fn test_sse_reasoning_and_content_in_same_chunk() {
+        let events = parse_all(&[
+            r#"data: {"choices":[{"delta":{"reasoning_content":"想一想","content":"答案"}}]}"#,
+            "",
+            r#"data:{"choices":[{"delta":{"reasoning":"再想"}}]}"#,
+            "data: [DONE]",
+            r#"data: {"choices":[{"delta":{"content":"多余"}}]}"#,
+        ]);
+        assert_eq!(
+            events,
+            vec![
+                SseEvent::ReasoningDelta("想一想".into()),
+                SseEvent::TextDelta("答案".into()),
+                SseEvent::ReasoningDelta("再想".into()),
+                SseEvent::Done,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sse_tool_calls_and_usage_across_chunks() {
+        // finish_reason 在单独的数据块中
+        let events = parse_all(&[
+            r#"data: {"choices":[{"delta":{"tool_calls":[{"index":1,"id":"call_b","function":{"name":"glob","arguments":"{\"pattern\":"}}]}}]}"#,
+            r#"data: {"choices":[{"delta":{"tool_calls":[{"index":0,"id":"call_a","function":{"name":"list_dir","arguments":""}}]}}]}"#,
+            r#"data: {"choices":[{"delta":{"tool_calls":[{"index":1,"function":{"arguments":"\"*.rs\"}"}}]}}]}"#,
+            r#"data: {"choices":[{"delta":{},"finish_reason":"tool_calls"}]}"#,
+            "data: [DONE]",
+        ]);
+        let [SseEvent::ToolCalls(calls), SseEvent::Done] = events.as_slice() else {
+            panic!("意外的事件: {:?}", events);
+        };
+        assert_eq!(calls[0].name, "list_dir");
+        assert_eq!(calls[0].arguments, json!({}));
+        assert_eq!(calls[1].arguments, json!({"pattern": "*.rs"}));
+
+        // usage 可能在 finish_reason 之后单独到达（choices 为空）
+        let events = parse_all(&[
+            r#"data: {"choices":[{"delta":{"content":"好"},"finish_reason":"stop"}]}"#,
+            r#"data: {"choices":[],"usage":{"prompt_tokens":12,"completion_tokens":3,"prompt_tokens_details":{"cached_tokens":8}}}"#,
+        ]);
+        assert_eq!(
+            events[1],
+            SseEvent::Usage(TokenUsage { prompt_tokens: 12, completion_tokens: 3, reasoning_tokens: 0, cached_tokens: 8 })
+        );
+
+        // 没有 finish_reason 和 [DONE] 时在流结束时发出
+        let events = parse_all(&[r#"data: {"choices":[{"delta":{"tool_calls":[{"index":0,"id":"c","function":{"name":"current_time"}}]}}]}"#]);
+        assert!(matches!(events.as_slice(), [SseEvent::ToolCalls(calls), SseEvent::Done] if calls[0].id == "c"));
+    }
+
+    #[test]
+    fn test_format_messages_roles_and_reasoning_replay() {
+        let history = vec![
+            json!({ "role": "system", "content": "系统提示" }),
+            json!({ "content": "第一轮" }),
+            json!({ "reasoning": "旧的思考" }),
+            json!({ "content": "好的", "tool_calls": null }),
+            json!({ "content": "第二轮" }),
+            json!({ "reasoning": "先看时间" }),
+            json!({ "content": "", "tool_calls": [{ "id": "c1", "name": "current_time", "arguments": {} }] }),
+            json!({ "tool_call_id": "c1", "content": "10:30" }),
+        ];
+
+        let formatted = format_messages(history.clone(), ReasoningReplay::Drop);
+        let roles: Vec<&str> = formatted.iter().map(|msg| msg["role"].as_str().unwrap()).collect();
+        assert_eq!(roles, ["system", "user", "assistant", "user", "assistant", "tool"]);
+        assert!(formatted[2].get("tool_calls").is_none());
+        assert_eq!(formatted[4]["tool_calls"][0]["function"]["arguments"], "{}");
+        assert!(formatted.iter().all(|msg| msg.get("reasoning_content").is_none()));
+
+        // replay 只回传本轮的思考
+        let formatted = format_messages(history, ReasoningReplay::Replay);
+        assert!(formatted[2].get("reasoning_content").is_none());
+        assert_eq!(formatted[4]["reasoning_content"], "先看时间");
+    }
+
+    #[test]
+    fn test_format_messages_images() {
+        use crate::protocol::{ContentPart, ImageAttachment, MessageContent, UserMessage};
+        let user = UserMessage {
+            content: MessageContent::Parts(vec![
+                ContentPart::text("这张图里是什么？"),
+                ContentPart::image_url("https://example.com/cat.png"),
+            ]),
+        };
+        let attachment = ImageAttachment { attached_images: vec![ContentPart::image_url("data:image/png;base64,AAAA")] };
+        let history = vec![
+            serde_json::to_value(user).unwrap(),
+            json!({ "content": "", "tool_calls": [{ "id": "c1", "name": "view_image", "arguments": {} }] }),
+            json!({ "tool_call_id": "c1", "content": "已附加图片" }),
+            serde_json::to_value(attachment).unwrap(),
+        ];
+
+        let formatted = format_messages(history, ReasoningReplay::Drop);
+        assert_eq!(
+            formatted[0]["content"],
+            json!([
+                { "type": "text", "text": "这张图里是什么？" },
+                { "type": "image_url", "image_url": { "url": "https://example.com/cat.png" } }
+            ])
+        );
+        assert_eq!(formatted[3]["role"], "user");
+        assert_eq!(formatted[3]["content"][0]["text"], ATTACHMENT_PREFIX);
+        assert_eq!(formatted[3]["content"][1]["image_url"]["url"], "data:image/png;base64,AAAA");
+    }
 }
@@ -0,0 +1,356 @@
+// 命令工具 - 由配置文件声明、无需编写 Rust 代码的工具
+//
+// 命令以 argv 形式直接执行，不经过 `sh -c`；模板中的 `{name}` 只会被替换进
+// 所在的那一个参数里，参数值中的空格、引号、`;`、`$()` 等都不会被解释。
+//
+// 替换规则：
+// - 整个参数就是 `{name}`：字符串/数字/布尔值替换为一个参数，数组展开为多个参数，缺省时省略该参数
+// - 参数中嵌入 `{name}`（如 `--env={env}`）：只接受标量值，缺省时省略整个参数
+// - `{{` / `}}` 表示字面量的花括号
+
+use crate::config::CommandToolConfig;
+use crate::exec::{run_command, ExecParams, ExecPolicy, MAX_TIMEOUT_MS};
+use crate::tools::{ToolEffect, ToolExecutor, ToolOutputSink};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// argv 模板中的片段
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+}
+
+/// 解析单个参数模板
+fn parse_template(arg: &str) -> Result<Vec<Segment>, anyhow::Error> {
+    let mut segments = Vec::new();
+    let mut literal = String::new();
+    let mut chars = arg.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        closed = true;
+                        break;
+                    }
+                    name.push(c);
+                }
+                if !closed || name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    return Err(anyhow::anyhow!("参数模板 '{}' 中的占位符 '{{{}}}' 无效", arg, name));
+                }
+                if !literal.is_empty() {
+                    segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                }
+                segments.push(Segment::Param(name));
+            }
+            '}' => return Err(anyhow::anyhow!("参数模板 '{}' 中有未配对的 '}}'", arg)),
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        segments.push(Segment::Literal(literal));
+    }
+    Ok(segments)
+}
+
+/// 标量值转换为参数文本
+fn scalar_to_arg(name: &str, value: &Value) -> Result<String, anyhow::Error> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        _ => Err(anyhow::anyhow!("参数 '{}' 必须是字符串、数字或布尔值", name)),
+    }
+}
+
+/// 检查参数值是否符合 schema 中声明的基本类型
+fn check_type(name: &str, value: &Value, schema: &Value) -> Result<(), anyhow::Error> {
+    let ok = match schema["type"].as_str() {
+        Some("string") => value.is_string(),
+        Some("integer") => value.is_i64() || value.is_u64(),
+        Some("number") => value.is_number(),
+        Some("boolean") => value.is_boolean(),
+        Some("array") => value.is_array(),
+        _ => true,
+    };
+    if !ok {
+        return Err(anyhow::anyhow!("参数 '{}' 的类型应为 {}", name, schema["type"]));
+    }
+    if let Some(allowed) = schema["enum"].as_array() {
+        if !allowed.contains(value) {
+            return Err(anyhow::anyhow!("参数 '{}' 只能是 {}", name, schema["enum"]));
+        }
+    }
+    Ok(())
+}
+
+/// 由配置声明的命令工具
+pub struct CommandTool {
+    name: String,
+    config: CommandToolConfig,
+    parameters: Value,
+    program: String,
+    args: Vec<Vec<Segment>>,
+    policy: ExecPolicy,
+}
+
+impl CommandTool {
+    /// 校验配置并创建工具
+    pub fn new(name: &str, config: CommandToolConfig) -> Result<Self, anyhow::Error> {
+        if name.is_empty() || name.len() > 64 || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(anyhow::anyhow!("命令工具名 '{}' 无效（只能包含字母、数字、'_' 和 '-'）", name));
+        }
+        let (program, rest) = config
+            .command
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("命令工具 '{}' 的 command 不能为空", name))?;
+        if program.contains('{') || program.contains('}') {
+            return Err(anyhow::anyhow!("命令工具 '{}' 的程序名不能包含占位符", name));
+        }
+
+        let args = rest.iter().map(|arg| parse_template(arg)).collect::<Result<Vec<_>, _>>()?;
+        let mut placeholders: Vec<&str> = Vec::new();
+        for segment in args.iter().flatten() {
+            if let Segment::Param(param) = segment {
+                if !placeholders.contains(&param.as_str()) {
+                    placeholders.push(param);
+                }
+            }
+        }
+
+        // 未声明 schema 时，按占位符生成：全部为必填字符串
+        let parameters = match &config.parameters {
+            Some(schema) => {
+                for param in &placeholders {
+                    if schema["properties"].get(*param).is_none() {
+                        return Err(anyhow::anyhow!("命令工具 '{}' 的占位符 '{{{}}}' 未在 parameters 中声明", name, param));
+                    }
+                }
+                schema.clone()
+            }
+            None => {
+                let properties: serde_json::Map<String, Value> = placeholders
+                    .iter()
+                    .map(|param| (param.to_string(), json!({ "type": "string" })))
+                    .collect();
+                json!({ "type": "object", "properties": properties, "required": placeholders })
+            }
+        };
+
+        let mut policy = ExecPolicy::default();
+        policy.env_allowlist.extend(config.pass_env.iter().cloned());
+        if let Some(timeout) = config.timeout_sec {
+            policy.default_timeout_ms = timeout.saturating_mul(1000).min(MAX_TIMEOUT_MS);
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            program: program.clone(),
+            config,
+            parameters,
+            args,
+            policy,
+        })
+    }
+
+    /// 校验参数并生成 argv
+    pub fn build_argv(&self, arguments: &Value) -> Result<Vec<String>, anyhow::Error> {
+        let empty = serde_json::Map::new();
+        let provided = match arguments {
+            Value::Object(map) => map,
+            Value::Null => &empty,
+            _ => return Err(anyhow::anyhow!("参数必须是 JSON 对象")),
+        };
+
+        let properties = &self.parameters["properties"];
+        for required in self.parameters["required"].as_array().into_iter().flatten() {
+            let required = required.as_str().unwrap_or_default();
+            if provided.get(required).is_none_or(|v| v.is_null()) {
+                return Err(anyhow::anyhow!("缺少必填参数 '{}'", required));
+            }
+        }
+        for (key, value) in provided {
+            let schema = properties
+                .get(key)
+                .ok_or_else(|| anyhow::anyhow!("未知参数 '{}'", key))?;
+            if !value.is_null() {
+                check_type(key, value, schema)?;
+            }
+        }
+
+        let lookup = |param: &str| provided.get(param).filter(|v| !v.is_null());
+        let mut argv = vec![self.program.clone()];
+        for segments in &self.args {
+            // 整个参数就是一个占位符
+            if let [Segment::Param(param)] = segments.as_slice() {
+                match lookup(param) {
+                    None => {}
+                    Some(Value::Array(items)) => {
+                        for item in items {
+                            argv.push(scalar_to_arg(param, item)?);
+                        }
+                    }
+                    Some(value) => argv.push(scalar_to_arg(param, value)?),
+                }
+                continue;
+            }
+
+            let mut arg = String::new();
+            let mut complete = true;
+            for segment in segments {
+                match segment {
+                    Segment::Literal(text) => arg.push_str(text),
+                    Segment::Param(param) => match lookup(param) {
+                        Some(value) => arg.push_str(&scalar_to_arg(param, value)?),
+                        None => complete = false,
+                    },
+                }
+            }
+            if complete {
+                argv.push(arg);
+            }
+        }
+        Ok(argv)
+    }
+}
+
+#[async_trait]
+impl ToolExecutor for CommandTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.config.description
+    }
+
+    fn parameters(&self) -> Value {
+        self.parameters.clone()
+    }
+
+    fn effect(&self) -> ToolEffect {
+        ToolEffect::Mutating
+    }
+
+    async fn execute(&self, arguments: Value) -> Result<String, Box<dyn std::error::Error + Send>> {
+        self.execute_streaming(arguments, ToolOutputSink::noop()).await
+    }
+
+    async fn execute_streaming(
+        &self,
+        arguments: Value,
+        sink: ToolOutputSink,
+    ) -> Result<String, Box<dyn std::error::Error + Send>> {
+        println!("\n🧰 命令工具 {} 接收到参数: {}", self.name, arguments); // 调试输出
+
+        let argv = self.build_argv(&arguments)?;
+        println!("🧰 执行: {:?}", argv);
+
+        let output = run_command(
+            ExecParams {
+                command: argv,
+                workdir: self.config.workdir.clone(),
+                timeout: Duration::from_millis(self.policy.default_timeout_ms),
+            },
+            &self.policy,
+            &sink,
+        )
+        .await?;
+
+        // 与 shell 工具一致：返回结构化结果，由模型判断退出码
+        Ok(serde_json::to_string(&output)
+            .map_err(|e| anyhow::anyhow!("JSON 序列化失败: {}", e))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tool(command: &[&str], parameters: Option<Value>) -> Result<CommandTool, anyhow::Error> {
+        CommandTool::new(
+            "deploy_status",
+            CommandToolConfig {
+                description: "test".to_string(),
+                parameters,
+                command: command.iter().map(|s| s.to_string()).collect(),
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_build_argv_substitutes_without_shell() {
+        let tool = tool(
+            &["status.sh", "--service", "{service}", "--env={env}", "{tags}", "{{literal}}"],
+            Some(json!({
+                "type": "object",
+                "properties": {
+                    "service": { "type": "string" },
+                    "env": { "type": "string", "enum": ["prod", "staging"] },
+                    "tags": { "type": "array" }
+                },
+                "required": ["service"]
+            })),
+        )
+        .unwrap();
+
+        let argv = tool
+            .build_argv(&json!({ "service": "api; rm -rf / $(id)", "tags": ["a", "b c"] }))
+            .unwrap();
+        assert_eq!(argv, vec!["status.sh", "--service", "api; rm -rf / $(id)", "a", "b c", "{literal}"]);
+
+        let argv = tool.build_argv(&json!({ "service": "api", "env": "prod" })).unwrap();
+        assert_eq!(argv, vec!["status.sh", "--service", "api", "--env=prod", "{literal}"]);
+
+        assert!(tool.build_argv(&json!({})).is_err());
+        assert!(tool.build_argv(&json!({ "service": "api", "env": "dev" })).is_err());
+        assert!(tool.build_argv(&json!({ "service": "api", "extra": 1 })).is_err());
+    }
+
+    #[test]
+    fn test_rejects_invalid_templates() {
+        assert!(tool(&[], None).is_err());
+        assert!(tool(&["{program}"], None).is_err());
+        assert!(tool(&["echo", "{bad name}"], None).is_err());
+        let schema = json!({ "type": "object", "properties": {} });
+        assert!(tool(&["echo", "{undeclared}"], Some(schema)).is_err());
+
+        // 未声明 schema 时按占位符生成
+        let generated = tool(&["echo", "{message}"], None).unwrap();
+        assert_eq!(generated.parameters()["required"], json!(["message"]));
+    }
+
+    #[test]
+    fn test_timeout_capped() {
+        let config = CommandToolConfig {
+            command: vec!["true".to_string()],
+            timeout_sec: Some(u64::MAX),
+            ..Default::default()
+        };
+        let tool = CommandTool::new("slow", config).unwrap();
+        assert_eq!(tool.policy.default_timeout_ms, MAX_TIMEOUT_MS);
+    }
+
+    #[tokio::test]
+    async fn test_execute_command_tool() {
+        let tool = tool(&["printf", "%s|", "{message}"], None).unwrap();
+        let output = tool.execute(json!({ "message": "a'b\"c $HOME" })).await.unwrap();
+        let output: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(output["exit_code"], 0);
+        assert_eq!(output["stdout"], "a'b\"c $HOME|");
+    }
+}
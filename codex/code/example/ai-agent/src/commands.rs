@@ -0,0 +1,685 @@
+// 斜杠命令 - 交互式对话中以 / 开头的输入（类似 Codex TUI 的 slash commands）
+//
+// 内置 /new、/reset、/model、/tools、/history、/save、/undo、/compact、/image、/status、/quit，
+// /help 由注册表自己处理。库的使用者可以实现 `SlashCommand` 并注册到 `CommandRegistry`
+// 来添加命令，同名命令会替换内置实现。
+
+use crate::budget::Budget;
+use crate::image_tools::image_part;
+use crate::session::{estimate_tokens, render_conversation, Session, SessionStore};
+use crate::tools::ToolEffect;
+use crate::usage::UsageTotals;
+use crate::Agent;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// /tools 中工具说明的最大字符数
+const TOOL_DESCRIPTION_CHARS: usize = 60;
+
+/// 命令执行时可以访问的 REPL 状态
+pub struct CommandContext<'a> {
+    pub agent: &'a mut Agent,
+    /// 当前会话（/new 会替换为新会话）
+    pub session: &'a mut Session,
+    /// 会话存储，None 表示不保存（--ephemeral）
+    pub store: Option<&'a SessionStore>,
+}
+
+/// 命令执行结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandOutcome {
+    /// 打印输出后继续对话（可以为空）
+    Output(String),
+    /// 退出 REPL
+    Quit,
+}
+
+/// 斜杠命令
+#[async_trait]
+pub trait SlashCommand: Send + Sync {
+    /// 命令名（不含 /）
+    fn name(&self) -> &str;
+
+    /// 别名（不含 /）
+    fn aliases(&self) -> &[&'static str] {
+        &[]
+    }
+
+    /// 参数格式，如 `[NAME]`
+    fn usage(&self) -> &str {
+        ""
+    }
+
+    /// 一行说明，显示在 /help 列表中
+    fn description(&self) -> &str;
+
+    /// 详细说明，`/help <命令>` 时显示；默认与一行说明相同
+    fn help(&self) -> &str {
+        self.description()
+    }
+
+    /// 执行命令，args 为命令名之后的文本（已去掉首尾空白）
+    async fn run(&self, ctx: &mut CommandContext<'_>, args: &str) -> anyhow::Result<CommandOutcome>;
+}
+
+/// 斜杠命令注册表，按注册顺序列出
+#[derive(Clone, Default)]
+pub struct CommandRegistry {
+    commands: Vec<Arc<dyn SlashCommand>>,
+}
+
+impl CommandRegistry {
+    /// 空注册表（只有 /help）
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 包含全部内置命令的注册表
+    pub fn with_builtin_commands() -> Self {
+        let mut registry = Self::new();
+        registry.register(NewCommand);
+        registry.register(ResetCommand);
+        registry.register(ModelCommand);
+        registry.register(ToolsCommand);
+        registry.register(HistoryCommand);
+        registry.register(SaveCommand);
+        registry.register(UndoCommand);
+        registry.register(CompactCommand);
+        registry.register(ImageCommand);
+        registry.register(StatusCommand);
+        registry.register(QuitCommand);
+        registry
+    }
+
+    /// 注册命令，替换同名命令
+    pub fn register<C>(&mut self, command: C)
+    where
+        C: SlashCommand + 'static,
+    {
+        let command: Arc<dyn SlashCommand> = Arc::new(command);
+        match self.commands.iter().position(|c| c.name() == command.name()) {
+            Some(index) => self.commands[index] = command,
+            None => self.commands.push(command),
+        }
+    }
+
+    /// 按名称或别名查找命令
+    pub fn get(&self, name: &str) -> Option<&dyn SlashCommand> {
+        self.commands
+            .iter()
+            .find(|c| c.name() == name || c.aliases().contains(&name))
+            .map(|c| c.as_ref())
+    }
+
+    /// 所有命令名（含 help），按注册顺序
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.commands.iter().map(|c| c.name().to_string()).collect();
+        names.push("help".to_string());
+        names
+    }
+
+    /// 拆分斜杠命令输入，返回 (命令名, 参数)；不是斜杠命令时返回 None
+    pub fn parse(input: &str) -> Option<(&str, &str)> {
+        let rest = input.trim().strip_prefix('/')?;
+        let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        if name.is_empty() {
+            return None;
+        }
+        Some((name, args.trim()))
+    }
+
+    /// Tab 补全：返回替换起点和候选项
+    ///
+    /// 补全命令名，以及 `/help` 的参数。
+    pub fn complete(&self, line: &str) -> (usize, Vec<String>) {
+        let Some(rest) = line.strip_prefix('/') else {
+            return (0, Vec::new());
+        };
+        match rest.split_once(' ') {
+            None => {
+                let candidates = self
+                    .names()
+                    .into_iter()
+                    .filter(|name| name.starts_with(rest))
+                    .map(|name| format!("/{}", name))
+                    .collect();
+                (0, candidates)
+            }
+            Some(("help", topic)) if !topic.contains(' ') => {
+                let candidates = self.names().into_iter().filter(|name| name.starts_with(topic)).collect();
+                (line.len() - topic.len(), candidates)
+            }
+            Some(_) => (0, Vec::new()),
+        }
+    }
+
+    /// 执行一条斜杠命令输入
+    pub async fn execute(&self, ctx: &mut CommandContext<'_>, input: &str) -> anyhow::Result<CommandOutcome> {
+        let (name, args) = Self::parse(input).ok_or_else(|| anyhow::anyhow!("不是斜杠命令: {}", input))?;
+        if name == "help" {
+            return Ok(CommandOutcome::Output(self.help(args)?));
+        }
+        let command = self
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("未知命令 /{}，输入 /help 查看可用命令", name))?;
+        command.run(ctx, args).await
+    }
+
+    /// /help 的输出：不带参数时列出所有命令，否则显示该命令的详细说明
+    pub fn help(&self, topic: &str) -> anyhow::Result<String> {
+        let topic = topic.trim_start_matches('/');
+        if topic.is_empty() {
+            let mut out = String::from("可用命令：\n");
+            for command in &self.commands {
+                let signature = format!("/{} {}", command.name(), command.usage());
+                out.push_str(&format!("  {:<18} {}\n", signature.trim_end(), command.description()));
+            }
+            out.push_str(&format!("  {:<18} {}\n", "/help [COMMAND]", "显示命令列表或某个命令的详细说明"));
+            out.push_str("输入 /help <命令> 查看详细说明，Tab 补全命令名");
+            return Ok(out);
+        }
+        if topic == "help" {
+            return Ok("/help [COMMAND]\n\n不带参数时列出所有命令；带命令名时显示该命令的详细说明。".to_string());
+        }
+
+        let command = self
+            .get(topic)
+            .ok_or_else(|| anyhow::anyhow!("未知命令 /{}，输入 /help 查看可用命令", topic))?;
+        let mut out = format!("/{} {}", command.name(), command.usage()).trim_end().to_string();
+        if !command.aliases().is_empty() {
+            let aliases: Vec<String> = command.aliases().iter().map(|alias| format!("/{}", alias)).collect();
+            out.push_str(&format!("（别名 {}）", aliases.join("、")));
+        }
+        out.push_str(&format!("\n\n{}", command.help()));
+        Ok(out)
+    }
+}
+
+/// 保存会话；未启用保存时什么也不做，返回是否已保存
+async fn save_session(ctx: &mut CommandContext<'_>) -> anyhow::Result<bool> {
+    let Some(store) = ctx.store else {
+        return Ok(false);
+    };
+    ctx.session.update(ctx.agent.conversation().await);
+    ctx.session.usage = ctx.agent.usage().clone();
+    store.save(ctx.session)?;
+    Ok(true)
+}
+
+/// 会话 ID 的短格式（前 8 位）
+fn short_id(session: &Session) -> &str {
+    &session.meta.id[..session.meta.id.len().min(8)]
+}
+
+/// /new：开始新会话
+struct NewCommand;
+
+#[async_trait]
+impl SlashCommand for NewCommand {
+    fn name(&self) -> &str {
+        "new"
+    }
+
+    fn description(&self) -> &str {
+        "保存当前会话并开始新会话"
+    }
+
+    fn help(&self) -> &str {
+        "保存当前会话，清空对话历史和“总是批准”的工具，然后以新的会话 ID 继续。\n之前的会话可以用 `chat --resume <ID>` 恢复。"
+    }
+
+    async fn run(&self, ctx: &mut CommandContext<'_>, _args: &str) -> anyhow::Result<CommandOutcome> {
+        let previous = short_id(ctx.session).to_string();
+        let saved = !ctx.agent.conversation().await.is_empty() && save_session(ctx).await?;
+
+        ctx.agent.reset().await;
+        ctx.agent.restore_usage(UsageTotals::default());
+        *ctx.session = Session::new(ctx.session.meta.cwd.clone(), ctx.agent.model().to_string());
+
+        let mut out = format!("🆕 已开始新会话 {}", short_id(ctx.session));
+        if saved {
+            out.push_str(&format!("（上一个会话 {} 已保存）", previous));
+        }
+        Ok(CommandOutcome::Output(out))
+    }
+}
+
+/// /reset：清空当前会话
+struct ResetCommand;
+
+#[async_trait]
+impl SlashCommand for ResetCommand {
+    fn name(&self) -> &str {
+        "reset"
+    }
+
+    fn description(&self) -> &str {
+        "清空当前会话的对话历史"
+    }
+
+    fn help(&self) -> &str {
+        "清空对话历史、“总是批准”的工具和持久 shell 会话，会话 ID 不变。\n需要保留当前对话时请使用 /new。"
+    }
+
+    async fn run(&self, ctx: &mut CommandContext<'_>, _args: &str) -> anyhow::Result<CommandOutcome> {
+        ctx.agent.reset().await;
+        save_session(ctx).await?;
+        Ok(CommandOutcome::Output("🧹 对话历史已清空".to_string()))
+    }
+}
+
+/// /model：查看或切换模型
+struct ModelCommand;
+
+#[async_trait]
+impl SlashCommand for ModelCommand {
+    fn name(&self) -> &str {
+        "model"
+    }
+
+    fn usage(&self) -> &str {
+        "[NAME]"
+    }
+
+    fn description(&self) -> &str {
+        "查看或切换模型"
+    }
+
+    fn help(&self) -> &str {
+        "不带参数时显示当前模型；带参数时切换到该模型，对话历史保留。\n新模型使用同一个 API 地址和 Key，切换提供方请用 --provider / --profile 重新启动。"
+    }
+
+    async fn run(&self, ctx: &mut CommandContext<'_>, args: &str) -> anyhow::Result<CommandOutcome> {
+        if args.is_empty() {
+            return Ok(CommandOutcome::Output(format!("🧠 当前模型: {}", ctx.agent.model())));
+        }
+        let previous = ctx.agent.model().to_string();
+        ctx.agent.set_model(args);
+        ctx.session.meta.model = args.to_string();
+        Ok(CommandOutcome::Output(format!("🧠 模型已切换: {} → {}", previous, args)))
+    }
+}
+
+/// /tools：列出工具
+struct ToolsCommand;
+
+#[async_trait]
+impl SlashCommand for ToolsCommand {
+    fn name(&self) -> &str {
+        "tools"
+    }
+
+    fn description(&self) -> &str {
+        "列出可用的工具"
+    }
+
+    fn help(&self) -> &str {
+        "列出智能体可以调用的工具（内置工具、命令工具、插件和 MCP 工具）。\n标记 ✎ 的工具有副作用，? 表示未声明，审批策略据此决定是否需要确认。"
+    }
+
+    async fn run(&self, ctx: &mut CommandContext<'_>, _args: &str) -> anyhow::Result<CommandOutcome> {
+        let registry = ctx.agent.tool_registry();
+        let mut definitions = registry.list_definitions();
+        definitions.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut out = format!("🔧 {} 个工具：\n", definitions.len());
+        for definition in definitions {
+            let mark = match registry.get(&definition.name).map(|tool| tool.effect()) {
+                Some(ToolEffect::ReadOnly) => " ",
+                Some(ToolEffect::Mutating) => "✎",
+                _ => "?",
+            };
+            let description = definition.description.lines().next().unwrap_or_default();
+            let summary: String = description.chars().take(TOOL_DESCRIPTION_CHARS).collect();
+            let ellipsis = if summary.len() < description.len() { "…" } else { "" };
+            out.push_str(&format!("  {} {:<20} {}{}\n", mark, definition.name, summary, ellipsis));
+        }
+        Ok(CommandOutcome::Output(out.trim_end().to_string()))
+    }
+}
+
+/// /history：显示对话历史
+struct HistoryCommand;
+
+#[async_trait]
+impl SlashCommand for HistoryCommand {
+    fn name(&self) -> &str {
+        "history"
+    }
+
+    fn usage(&self) -> &str {
+        "[N]"
+    }
+
+    fn description(&self) -> &str {
+        "显示对话历史"
+    }
+
+    fn help(&self) -> &str {
+        "显示当前会话的对话历史，包括工具调用和结果。\n带数字 N 时只显示最后 N 条消息。"
+    }
+
+    async fn run(&self, ctx: &mut CommandContext<'_>, args: &str) -> anyhow::Result<CommandOutcome> {
+        let conversation = ctx.agent.conversation().await;
+        if conversation.is_empty() {
+            return Ok(CommandOutcome::Output("（对话为空）".to_string()));
+        }
+        let skip = if args.is_empty() {
+            0
+        } else {
+            let count: usize = args.parse().map_err(|_| anyhow::anyhow!("消息条数必须是数字: {}", args))?;
+            conversation.len().saturating_sub(count)
+        };
+        Ok(CommandOutcome::Output(render_conversation(&conversation[skip..]).trim_end().to_string()))
+    }
+}
+
+/// /save：立即保存会话
+struct SaveCommand;
+
+#[async_trait]
+impl SlashCommand for SaveCommand {
+    fn name(&self) -> &str {
+        "save"
+    }
+
+    fn usage(&self) -> &str {
+        "[TITLE]"
+    }
+
+    fn description(&self) -> &str {
+        "保存会话，可以指定标题"
+    }
+
+    fn help(&self) -> &str {
+        "立即保存当前会话（每轮对话结束后也会自动保存）。\n带参数时把它作为会话标题，显示在 `sessions list` 中。"
+    }
+
+    async fn run(&self, ctx: &mut CommandContext<'_>, args: &str) -> anyhow::Result<CommandOutcome> {
+        if ctx.store.is_none() {
+            return Err(anyhow::anyhow!("当前会话未启用保存（--ephemeral）"));
+        }
+        if !args.is_empty() {
+            ctx.session.meta.title = args.to_string();
+        }
+        save_session(ctx).await?;
+        Ok(CommandOutcome::Output(format!("💾 会话已保存: {}", ctx.session.meta.id)))
+    }
+}
+
+/// /undo：撤销上一轮
+struct UndoCommand;
+
+#[async_trait]
+impl SlashCommand for UndoCommand {
+    fn name(&self) -> &str {
+        "undo"
+    }
+
+    fn description(&self) -> &str {
+        "撤销上一轮对话"
+    }
+
+    fn help(&self) -> &str {
+        "从对话历史中删除最后一条用户消息及之后的回复和工具结果。\n只影响对话历史，工具已经做出的修改（如写入的文件）不会撤销。"
+    }
+
+    async fn run(&self, ctx: &mut CommandContext<'_>, _args: &str) -> anyhow::Result<CommandOutcome> {
+        let removed = ctx.agent.undo_last_turn().await;
+        if removed == 0 {
+            return Ok(CommandOutcome::Output("没有可撤销的对话".to_string()));
+        }
+        save_session(ctx).await?;
+        Ok(CommandOutcome::Output(format!("↩️  已撤销上一轮（删除 {} 条消息）", removed)))
+    }
+}
+
+/// /image：附加图片，随下一条消息发送
+struct ImageCommand;
+
+#[async_trait]
+impl SlashCommand for ImageCommand {
+    fn name(&self) -> &str {
+        "image"
+    }
+
+    fn usage(&self) -> &str {
+        "<PATH|URL>"
+    }
+
+    fn description(&self) -> &str {
+        "附加图片，随下一条消息发送"
+    }
+
+    fn help(&self) -> &str {
+        "附加一张图片（本地 png/jpg/gif/webp 文件或 http(s) URL），随下一条消息一起发送给模型。\n可以多次使用以附加多张图片；需要模型支持图片输入。"
+    }
+
+    async fn run(&self, ctx: &mut CommandContext<'_>, args: &str) -> anyhow::Result<CommandOutcome> {
+        if args.is_empty() {
+            return Err(anyhow::anyhow!("用法: /image <PATH|URL>"));
+        }
+        ctx.agent.attach_image(image_part(args)?);
+        Ok(CommandOutcome::Output(format!(
+            "🖼️  已附加图片 {}（共 {} 张，随下一条消息发送）",
+            args,
+            ctx.agent.pending_image_count()
+        )))
+    }
+}
+
+/// /compact：用摘要替换对话历史
+struct CompactCommand;
+
+#[async_trait]
+impl SlashCommand for CompactCommand {
+    fn name(&self) -> &str {
+        "compact"
+    }
+
+    fn description(&self) -> &str {
+        "让模型总结对话，用摘要替换历史"
+    }
+
+    fn help(&self) -> &str {
+        "请模型总结目前的对话，然后用这份摘要替换对话历史，以节省上下文。\n摘要之前的细节（如完整的工具输出）之后将无法再被模型看到。"
+    }
+
+    async fn run(&self, ctx: &mut CommandContext<'_>, _args: &str) -> anyhow::Result<CommandOutcome> {
+        let before = ctx.agent.conversation().await;
+        let summary = ctx.agent.compact().await?;
+        let after = ctx.agent.conversation().await;
+        save_session(ctx).await?;
+        Ok(CommandOutcome::Output(format!(
+            "🗜️  对话已压缩：{} 条消息（≈{} tokens）→ {} 条（≈{} tokens）\n\n{}",
+            before.len(),
+            estimate_tokens(&before),
+            after.len(),
+            estimate_tokens(&after),
+            summary
+        )))
+    }
+}
+
+/// /status：显示当前状态
+struct StatusCommand;
+
+#[async_trait]
+impl SlashCommand for StatusCommand {
+    fn name(&self) -> &str {
+        "status"
+    }
+
+    fn description(&self) -> &str {
+        "显示模型、会话、用量和审批策略等状态"
+    }
+
+    async fn run(&self, ctx: &mut CommandContext<'_>, _args: &str) -> anyhow::Result<CommandOutcome> {
+        let conversation = ctx.agent.conversation().await;
+        let storage = match ctx.store {
+            Some(store) => format!("保存于 {}", store.dir().display()),
+            None => "不保存".to_string(),
+        };
+        let out = [
+            format!("🧠 模型: {}", ctx.agent.model()),
+            format!("📂 会话: {}（{}）", ctx.session.meta.id, storage),
+            format!("💬 消息: {} 条（≈{} tokens）", conversation.len(), estimate_tokens(&conversation)),
+            format!("📊 本轮用量: {}", usage_line(ctx.agent.last_turn_usage())),
+            format!("📊 会话用量: {}", usage_line(ctx.agent.usage())),
+            format!("⛔ 预算: {}", budget_line(ctx.agent.budget())),
+            format!("🛡️  审批策略: {}", ctx.agent.approval_policy()),
+            format!("🔧 工具: {} 个", ctx.agent.tool_registry().names().len()),
+            format!("📁 目录: {}", ctx.session.meta.cwd.display()),
+        ];
+        let routes = ctx.agent.router().describe().into_iter().map(|line| format!("🔀 {}", line));
+        Ok(CommandOutcome::Output(out.into_iter().chain(routes).collect::<Vec<_>>().join("\n")))
+    }
+}
+
+/// 用量说明，没有请求时提示尚无数据
+fn usage_line(usage: &UsageTotals) -> String {
+    if usage.requests == 0 {
+        "-（尚无请求）".to_string()
+    } else {
+        usage.to_string()
+    }
+}
+
+/// 预算说明：已设置的各项，未设置时只有默认的请求次数上限
+fn budget_line(budget: &Budget) -> String {
+    let mut entries = budget.entries();
+    if budget.max_requests.is_none() {
+        entries.insert(0, ("max_requests", budget.max_requests().to_string()));
+    }
+    entries
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// /quit：退出
+struct QuitCommand;
+
+#[async_trait]
+impl SlashCommand for QuitCommand {
+    fn name(&self) -> &str {
+        "quit"
+    }
+
+    fn aliases(&self) -> &[&'static str] {
+        &["exit"]
+    }
+
+    fn description(&self) -> &str {
+        "退出（会话已自动保存）"
+    }
+
+    async fn run(&self, _ctx: &mut CommandContext<'_>, _args: &str) -> anyhow::Result<CommandOutcome> {
+        Ok(CommandOutcome::Quit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::UserMessage;
+    use crate::ModelClient;
+    use serde_json::json;
+
+    /// 自定义命令：回显参数
+    struct EchoCommand;
+
+    #[async_trait]
+    impl SlashCommand for EchoCommand {
+        fn name(&self) -> &str {
+            "echo"
+        }
+
+        fn usage(&self) -> &str {
+            "TEXT"
+        }
+
+        fn description(&self) -> &str {
+            "回显参数"
+        }
+
+        async fn run(&self, _ctx: &mut CommandContext<'_>, args: &str) -> anyhow::Result<CommandOutcome> {
+            Ok(CommandOutcome::Output(args.to_string()))
+        }
+    }
+
+    #[test]
+    fn test_parse_and_complete() {
+        assert_eq!(CommandRegistry::parse("/model  glm-4-flash "), Some(("model", "glm-4-flash")));
+        assert_eq!(CommandRegistry::parse("/status"), Some(("status", "")));
+        assert_eq!(CommandRegistry::parse("你好 /status"), None);
+        assert_eq!(CommandRegistry::parse("/"), None);
+
+        let mut registry = CommandRegistry::with_builtin_commands();
+        registry.register(EchoCommand);
+        assert_eq!(registry.complete("/h"), (0, vec!["/history".to_string(), "/help".to_string()]));
+        assert_eq!(registry.complete("/e"), (0, vec!["/echo".to_string()]));
+        assert_eq!(registry.complete("/help mo"), (6, vec!["model".to_string()]));
+        assert_eq!(registry.complete("/model g"), (0, Vec::<String>::new()));
+        assert_eq!(registry.complete("hello"), (0, Vec::<String>::new()));
+
+        let help = registry.help("").unwrap();
+        assert!(help.contains("/model [NAME]"));
+        assert!(help.contains("/echo TEXT"));
+        assert!(registry.help("/exit").unwrap().starts_with("/quit（别名 /exit）"));
+    }
+
+    #[tokio::test]
+    async fn test_execute_commands() {
+        let store_dir = tempfile::tempdir().unwrap();
+        let store = SessionStore::new(store_dir.path().to_path_buf());
+        let mut agent = Agent::new(ModelClient::new("test-key".to_string(), "glm-4".to_string())).unwrap();
+        let mut session = Session::new(store_dir.path().to_path_buf(), "glm-4".to_string());
+        let registry = CommandRegistry::with_builtin_commands();
+        let mut ctx = CommandContext {
+            agent: &mut agent,
+            session: &mut session,
+            store: Some(&store),
+        };
+
+        let outcome = registry.execute(&mut ctx, "/model glm-4-flash").await.unwrap();
+        assert_eq!(outcome, CommandOutcome::Output("🧠 模型已切换: glm-4 → glm-4-flash".to_string()));
+        assert_eq!(ctx.agent.model(), "glm-4-flash");
+        assert_eq!(ctx.session.meta.model, "glm-4-flash");
+
+        ctx.agent
+            .restore_conversation(vec![json!(UserMessage { content: "你好".into() })])
+            .await;
+        ctx.agent.restore_usage(UsageTotals { requests: 2, cost: 0.25, ..Default::default() });
+        registry.execute(&mut ctx, "/save 问候").await.unwrap();
+        let first_id = ctx.session.meta.id.clone();
+        let saved = store.load(&first_id).unwrap();
+        assert_eq!(saved.meta.title, "问候");
+        assert_eq!(saved.usage.requests, 2);
+        let CommandOutcome::Output(status) = registry.execute(&mut ctx, "/status").await.unwrap() else {
+            panic!("/status 不应退出");
+        };
+        assert!(status.contains("📊 会话用量: 0 tokens（输入 0，输出 0），2 次请求，费用 0.2500"));
+        assert!(status.contains("⛔ 预算: max_requests=10"));
+
+        let CommandOutcome::Output(out) = registry.execute(&mut ctx, "/new").await.unwrap() else {
+            panic!("/new 不应退出");
+        };
+        assert!(out.contains("已保存"));
+        assert_ne!(ctx.session.meta.id, first_id);
+        assert!(ctx.agent.conversation().await.is_empty());
+        assert_eq!(ctx.agent.usage().requests, 0);
+
+        let outcome = registry.execute(&mut ctx, "/image https://example.com/a.png").await.unwrap();
+        assert_eq!(
+            outcome,
+            CommandOutcome::Output("🖼️  已附加图片 https://example.com/a.png（共 1 张，随下一条消息发送）".to_string())
+        );
+        assert!(registry.execute(&mut ctx, "/image").await.is_err());
+
+        assert_eq!(registry.execute(&mut ctx, "/exit").await.unwrap(), CommandOutcome::Quit);
+        let err = registry.execute(&mut ctx, "/nope").await.unwrap_err();
+        assert!(err.to_string().contains("未知命令 /nope"));
+    }
+}
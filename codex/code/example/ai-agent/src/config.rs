@@ -0,0 +1,787 @@
+// 配置文件 - 从 TOML 加载模型设置、profile、MCP 服务器、命令工具、WASM 插件等配置
+//
+// 本文件只负责单个配置文件的格式；与环境变量、命令行参数的分层合并见 settings.rs。
+//
+// 查找顺序（后者覆盖前者中同名的条目）：
+// 1. 全局配置：$XDG_CONFIG_HOME/simple-ai-agent/config.toml（默认 ~/.config/simple-ai-agent/config.toml）
+// 2. 项目配置：<工作区>/.simple-ai-agent/config.toml
+//
+// 项目配置随仓库分发，未受信任时只能设置模型、采样等无害选项（见 AgentConfig::restrict_to_project_scope）。
+
+use crate::budget::Budget;
+use crate::request_options::RequestOptions;
+use crate::tools::ToolEffect;
+use crate::usage::ModelPrice;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// 项目配置目录名
+pub const PROJECT_CONFIG_DIR: &str = ".simple-ai-agent";
+
+/// 配置文件名
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// 用 other 中已设置（Some）的字段覆盖 target 的同名字段（各配置结构的 merge 共用）
+macro_rules! merge_fields {
+    ($target:expr, $other:expr, $($field:ident),* $(,)?) => {
+        $(if $other.$field.is_some() {
+            $target.$field = $other.$field;
+        })*
+    };
+}
+pub(crate) use merge_fields;
+
+/// 只保留已设置的配置项（各配置结构的 entries 共用，用于 `config show` 和 /status）
+pub(crate) fn set_entries<const N: usize>(items: [(&'static str, Option<String>); N]) -> Vec<(&'static str, String)> {
+    items.into_iter().filter_map(|(key, value)| value.map(|value| (key, value))).collect()
+}
+
+/// 智能体配置
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AgentConfig {
+    /// 默认使用的 profile
+    pub profile: Option<String>,
+    /// 顶层的模型设置（被 profile 覆盖）
+    #[serde(flatten)]
+    pub model: ModelSettings,
+    /// 命名的 profile，键为 profile 名
+    pub profiles: BTreeMap<String, ModelSettings>,
+    /// MCP 服务器，键为服务器名（用作工具名前缀）
+    pub mcp_servers: BTreeMap<String, McpServerConfig>,
+    /// 命令工具，键为工具名
+    pub command_tools: BTreeMap<String, CommandToolConfig>,
+    /// WASM 插件工具，键为插件标识（工具名由插件自己声明）
+    pub plugins: BTreeMap<String, PluginConfig>,
+    /// 模型价格表，键为模型名或 glob 模式（用于计算费用）
+    pub prices: BTreeMap<String, ModelPrice>,
+    /// 文件类工具的路径访问规则
+    pub paths: PathRules,
+    /// 受信任的工作区目录（只在全局配置中生效），其项目配置可以设置全部选项
+    pub trusted_projects: Vec<PathBuf>,
+}
+
+/// 文件类工具（含插件的文件访问）的路径访问规则，glob 的写法见 `PathPolicy::new`
+///
+/// ```toml
+/// [paths]
+/// allow = ["/data/shared/**"]
+/// deny = ["secrets/**", "**/*.sqlite"]
+/// ```
+///
+/// deny 规则追加在默认列表（`.env`、`.ssh/` 等）之后，不会取代它。
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PathRules {
+    /// 工作区之外允许访问的路径
+    pub allow: Vec<String>,
+    /// 额外禁止访问的路径（优先级高于 allow）
+    pub deny: Vec<String>,
+}
+
+/// 模型与会话设置（配置文件顶层或 `[profiles.<name>]` 中，所有字段都可省略）
+///
+/// ```toml
+/// profile = "glm"
+///
+/// [profiles.glm]
+/// provider = "zhipu"
+/// model = "glm-4-plus"
+/// temperature = 0.3
+/// max_tokens = 4096
+///
+/// [profiles.local]
+/// provider = "ollama"
+/// model = "qwen2.5:14b"
+/// tools = ["read_file", "list_dir", "grep"]
+/// approval_policy = "on-request"
+///
+/// [profiles.reasoner]
+/// provider = "deepseek"
+/// model = "deepseek-reasoner"
+/// reasoning_replay = "replay"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelSettings {
+    /// 提供商预设：zhipu / openai / deepseek / ollama（决定默认 base_url 和模型）
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub base_url: Option<String>,
+    /// 从该环境变量读取 API Key（默认 OPENAI_API_KEY）
+    pub api_key_env: Option<String>,
+    /// 采样参数、停止序列、seed、工具选择等请求参数（与其他设置同级书写）
+    #[serde(flatten)]
+    pub request: RequestOptions,
+    /// 启用的工具（支持 glob，如 "weather__*"）；省略表示全部启用
+    pub tools: Option<Vec<String>>,
+    pub approval_policy: Option<ApprovalPolicy>,
+    /// 模型的思考内容如何回传给模型（省略时使用 provider 预设）
+    pub reasoning_replay: Option<ReasoningReplay>,
+    /// 流式请求是否发送 stream_options.include_usage（省略时使用 provider 预设）
+    pub stream_usage: Option<bool>,
+    /// 结构化输出使用的 response_format（省略时使用 provider 预设）
+    pub structured_output: Option<StructuredOutput>,
+    /// 工具调用方式：native（tools 参数）或 text（工具写进提示，从回复文本中解析调用）
+    pub tool_calling: Option<ToolCalling>,
+    /// 每轮（一次任务）和整个会话的预算上限，各层按字段覆盖
+    pub budget: Option<Budget>,
+    /// 请求失败（网络错误、限流、额度不足、服务端错误）时依次改用的 profile
+    pub fallback: Option<Vec<String>>,
+    /// 按请求类型改用其他 profile 的模型，各层按字段覆盖
+    pub routing: Option<Routing>,
+}
+
+impl ModelSettings {
+    /// 用 other 中已设置的字段覆盖当前值
+    pub fn merge(&mut self, other: ModelSettings) {
+        merge_fields!(
+            self,
+            other,
+            provider, model, base_url, api_key_env, tools, approval_policy, reasoning_replay, stream_usage,
+            structured_output, tool_calling, fallback
+        );
+        self.request.merge(other.request);
+        match (&mut self.budget, other.budget) {
+            (Some(budget), Some(other)) => budget.merge(other),
+            (None, Some(other)) => self.budget = Some(other),
+            _ => {}
+        }
+        match (&mut self.routing, other.routing) {
+            (Some(routing), Some(other)) => routing.merge(other),
+            (None, Some(other)) => self.routing = Some(other),
+            _ => {}
+        }
+    }
+}
+
+/// 按请求类型选择模型（值为 profile 名）
+///
+/// ```toml
+/// fallback = ["deepseek", "local"]
+///
+/// [routing]
+/// compact = "flash"
+/// tool_turns = "strong"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Routing {
+    /// /compact 压缩对话时使用
+    pub compact: Option<String>,
+    /// 本轮执行过工具之后的请求使用
+    pub tool_turns: Option<String>,
+}
+
+impl Routing {
+    /// 用 other 中已设置的字段覆盖当前值
+    pub fn merge(&mut self, other: Routing) {
+        merge_fields!(self, other, compact, tool_turns);
+    }
+}
+
+/// 工具执行前是否需要用户确认
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApprovalPolicy {
+    /// 从不询问
+    #[default]
+    Never,
+    /// 执行命令或修改文件的工具需要确认
+    OnRequest,
+    /// 除只读的内置工具外都需要确认
+    Untrusted,
+}
+
+impl ApprovalPolicy {
+    /// 具有该影响的工具在调用前是否需要用户确认
+    pub fn requires_approval(&self, effect: ToolEffect) -> bool {
+        match self {
+            ApprovalPolicy::Never => false,
+            ApprovalPolicy::OnRequest => effect == ToolEffect::Mutating,
+            ApprovalPolicy::Untrusted => effect != ToolEffect::ReadOnly,
+        }
+    }
+}
+
+impl std::fmt::Display for ApprovalPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ApprovalPolicy::Never => "never",
+            ApprovalPolicy::OnRequest => "on-request",
+            ApprovalPolicy::Untrusted => "untrusted",
+        })
+    }
+}
+
+impl std::str::FromStr for ApprovalPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(ApprovalPolicy::Never),
+            "on-request" => Ok(ApprovalPolicy::OnRequest),
+            "untrusted" => Ok(ApprovalPolicy::Untrusted),
+            other => Err(anyhow::anyhow!("未知的审批策略 '{}'（可选: never, on-request, untrusted）", other)),
+        }
+    }
+}
+
+/// 思考摘要的最大字符数（reasoning_replay = "summary"）
+const REASONING_SUMMARY_CHARS: usize = 300;
+
+/// 推理模型的思考内容（reasoning_content）在后续请求中如何处理
+///
+/// 思考内容总是单独保存在对话历史中，不会混入回复；这里只决定请求时是否带上。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReasoningReplay {
+    /// 不回传（多数接口会忽略或拒绝历史中的思考内容）
+    #[default]
+    Drop,
+    /// 只回传每段思考的最后一段（结论部分），节省 token
+    Summary,
+    /// 回传本轮（最后一条用户消息之后）的思考原文，用于要求在工具调用过程中回传思考的接口；
+    /// 之前轮次的思考仍然丢弃
+    Replay,
+}
+
+impl ReasoningReplay {
+    /// 助手消息要附带的思考内容，None 表示不附带
+    ///
+    /// current_turn 表示该消息属于本轮（最后一条用户消息之后）。
+    pub fn replayed(&self, reasoning: &str, current_turn: bool) -> Option<String> {
+        match self {
+            ReasoningReplay::Drop => None,
+            ReasoningReplay::Summary => Some(summarize_reasoning(reasoning)),
+            ReasoningReplay::Replay => current_turn.then(|| reasoning.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for ReasoningReplay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ReasoningReplay::Drop => "drop",
+            ReasoningReplay::Summary => "summary",
+            ReasoningReplay::Replay => "replay",
+        })
+    }
+}
+
+/// 接口对结构化输出（请求参数 response_format）的支持程度
+///
+/// 不论哪种方式，`Agent::run_structured` 都会把 JSON Schema 写进提示，并在回复无法解析时要求模型重新输出。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StructuredOutput {
+    /// 支持 `{"type": "json_schema"}`：由接口按 schema 约束输出
+    JsonSchema,
+    /// 只支持 `{"type": "json_object"}`：接口保证输出 JSON，字段由提示约束
+    JsonObject,
+    /// 不发送 response_format，只靠提示约束
+    #[default]
+    Prompt,
+}
+
+impl StructuredOutput {
+    /// 请求中的 response_format 参数，Prompt 模式为 None
+    pub fn response_format(&self, name: &str, schema: &serde_json::Value) -> Option<serde_json::Value> {
+        match self {
+            StructuredOutput::JsonSchema => Some(serde_json::json!({
+                "type": "json_schema",
+                "json_schema": { "name": name, "schema": schema }
+            })),
+            StructuredOutput::JsonObject => Some(serde_json::json!({ "type": "json_object" })),
+            StructuredOutput::Prompt => None,
+        }
+    }
+}
+
+impl std::fmt::Display for StructuredOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            StructuredOutput::JsonSchema => "json-schema",
+            StructuredOutput::JsonObject => "json-object",
+            StructuredOutput::Prompt => "prompt",
+        })
+    }
+}
+
+/// 工具调用的方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ToolCalling {
+    /// 通过请求的 tools 参数，由接口返回 tool_calls
+    #[default]
+    Native,
+    /// 工具定义写进系统提示，从回复文本中解析工具调用（用于不支持 tools 参数的模型）
+    Text,
+}
+
+impl std::fmt::Display for ToolCalling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ToolCalling::Native => "native",
+            ToolCalling::Text => "text",
+        })
+    }
+}
+
+impl std::str::FromStr for ToolCalling {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "native" => Ok(ToolCalling::Native),
+            "text" => Ok(ToolCalling::Text),
+            other => Err(anyhow::anyhow!("未知的工具调用方式 '{}'（可选: native, text）", other)),
+        }
+    }
+}
+
+/// 取思考内容的最后一个非空段落，超长时保留结尾部分
+fn summarize_reasoning(reasoning: &str) -> String {
+    let last = reasoning
+        .rsplit("\n\n")
+        .map(str::trim)
+        .find(|paragraph| !paragraph.is_empty())
+        .unwrap_or_default();
+    let chars: Vec<char> = last.chars().collect();
+    if chars.len() <= REASONING_SUMMARY_CHARS {
+        return last.to_string();
+    }
+    let tail: String = chars[chars.len() - REASONING_SUMMARY_CHARS..].iter().collect();
+    format!("…{}", tail)
+}
+
+/// 单个 MCP 服务器的配置（command 与 url 二选一）
+///
+/// ```toml
+/// [mcp_servers.weather]
+/// command = "npx"
+/// args = ["-y", "@example/weather-mcp"]
+/// env = { WEATHER_API_KEY = "..." }
+/// read_only_tools = ["get_forecast"]
+///
+/// [mcp_servers.docs]
+/// url = "https://mcp.example.com/mcp"
+/// bearer_token_env_var = "DOCS_MCP_TOKEN"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct McpServerConfig {
+    /// stdio 传输：启动的命令
+    pub command: Option<String>,
+    pub args: Vec<String>,
+    /// 额外的环境变量（子进程只继承白名单中的变量）
+    pub env: BTreeMap<String, String>,
+    pub cwd: Option<PathBuf>,
+
+    /// streamable HTTP 传输：服务器地址
+    pub url: Option<String>,
+    pub headers: BTreeMap<String, String>,
+    /// 从该环境变量读取 Bearer token
+    pub bearer_token_env_var: Option<String>,
+
+    /// 设为 false 可临时禁用
+    pub enabled: Option<bool>,
+    /// 启动并完成 initialize 的超时（秒）
+    pub startup_timeout_sec: Option<u64>,
+    /// 单次工具调用的超时（秒）
+    pub tool_timeout_sec: Option<u64>,
+
+    /// 确认该服务器的全部工具只读（审批时按只读工具处理）
+    pub read_only: Option<bool>,
+    /// 确认只读的工具（服务器上的原始工具名）；其余工具按会修改状态处理
+    pub read_only_tools: Vec<String>,
+}
+
+impl McpServerConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    /// 用户是否确认该工具只读（服务器自己的 readOnlyHint 不算数）
+    pub fn is_read_only_tool(&self, tool: &str) -> bool {
+        self.read_only.unwrap_or(false) || self.read_only_tools.iter().any(|name| name == tool)
+    }
+
+    /// 检查 command / url 是否恰好配置了一个
+    pub fn validate(&self, name: &str) -> Result<(), anyhow::Error> {
+        match (&self.command, &self.url) {
+            (Some(_), Some(_)) => Err(anyhow::anyhow!("MCP 服务器 '{}' 不能同时配置 command 和 url", name)),
+            (None, None) => Err(anyhow::anyhow!("MCP 服务器 '{}' 需要配置 command 或 url", name)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// 由配置声明的命令工具（不经过 shell，参数按 argv 逐项替换）
+///
+/// ```toml
+/// [command_tools.deploy_status]
+/// description = "查询服务的部署状态"
+/// command = ["./scripts/deploy-status.sh", "--service", "{service}", "--env={env}"]
+/// parameters = { type = "object", properties = { service = { type = "string" }, env = { type = "string" } }, required = ["service"] }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CommandToolConfig {
+    pub description: String,
+    /// 参数的 JSON Schema
+    pub parameters: Option<serde_json::Value>,
+    /// argv 模板，`{name}` 会被同名参数替换
+    pub command: Vec<String>,
+    pub workdir: Option<PathBuf>,
+    pub timeout_sec: Option<u64>,
+    /// 除默认白名单外，额外传给命令的环境变量名
+    pub pass_env: Vec<String>,
+}
+
+/// WASM 插件工具配置（需要启用 `wasm-plugins` 特性）
+///
+/// ```toml
+/// [plugins.word_count]
+/// path = "plugins/word_count.wasm"
+/// capabilities = ["fs-read"]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PluginConfig {
+    /// 组件文件路径（.wasm）
+    pub path: PathBuf,
+    /// 授予的能力：fs-read / fs-write / clock
+    pub capabilities: Vec<String>,
+    /// 单次执行的燃料上限（约等于指令数）
+    pub fuel: Option<u64>,
+    /// 线性内存上限（MB）
+    pub max_memory_mb: Option<u64>,
+    pub enabled: Option<bool>,
+}
+
+impl PluginConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+}
+
+impl AgentConfig {
+    /// 解析 TOML 配置
+    pub fn from_toml_str(text: &str) -> Result<Self, anyhow::Error> {
+        let config: Self = toml::from_str(text)?;
+        for (name, server) in &config.mcp_servers {
+            server.validate(name)?;
+        }
+        Ok(config)
+    }
+
+    /// 读取单个配置文件（不存在时返回默认配置）
+    pub fn from_file(path: &Path) -> Result<Self, anyhow::Error> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Self::from_toml_str(&text)
+                .map_err(|e| anyhow::anyhow!("配置文件 {} 无效: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(anyhow::anyhow!("读取配置文件 {} 失败: {}", path.display(), e)),
+        }
+    }
+
+    /// 加载全局配置，再用项目配置覆盖
+    pub fn load(workspace_root: &Path) -> Result<Self, anyhow::Error> {
+        let mut config = match global_config_path() {
+            Some(path) => Self::from_file(&path)?,
+            None => Self::default(),
+        };
+        let mut project = Self::from_file(&project_config_path(workspace_root))?;
+        if !config.is_trusted_project(workspace_root) {
+            project.restrict_to_project_scope();
+        }
+        config.merge(project);
+        Ok(config)
+    }
+
+    /// workspace_root 是否列在 trusted_projects 中（按规范化后的路径比较）
+    pub fn is_trusted_project(&self, workspace_root: &Path) -> bool {
+        let canonical = |path: &Path| std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let root = canonical(workspace_root);
+        self.trusted_projects.iter().any(|dir| canonical(dir) == root)
+    }
+
+    /// 去掉未受信任的项目配置不能设置的选项，返回被忽略的选项名
+    ///
+    /// 这些选项决定 API Key 发往哪里、工具调用是否需要审批、会启动哪些外部程序以及文件工具能访问的范围，
+    /// 只能来自全局配置、命令行或受信任的项目。
+    pub fn restrict_to_project_scope(&mut self) -> Vec<String> {
+        let mut ignored = Vec::new();
+        let mut restrict_model = |prefix: &str, settings: &mut ModelSettings| {
+            if settings.base_url.take().is_some() {
+                ignored.push(format!("{}base_url", prefix));
+            }
+            if settings.api_key_env.take().is_some() {
+                ignored.push(format!("{}api_key_env", prefix));
+            }
+            if settings.approval_policy.take().is_some() {
+                ignored.push(format!("{}approval_policy", prefix));
+            }
+        };
+        restrict_model("", &mut self.model);
+        for (name, profile) in &mut self.profiles {
+            restrict_model(&format!("profiles.{}.", name), profile);
+        }
+        if !std::mem::take(&mut self.mcp_servers).is_empty() {
+            ignored.push("mcp_servers".to_string());
+        }
+        if !std::mem::take(&mut self.command_tools).is_empty() {
+            ignored.push("command_tools".to_string());
+        }
+        if !std::mem::take(&mut self.plugins).is_empty() {
+            ignored.push("plugins".to_string());
+        }
+        if std::mem::take(&mut self.paths) != PathRules::default() {
+            ignored.push("paths".to_string());
+        }
+        if !std::mem::take(&mut self.trusted_projects).is_empty() {
+            ignored.push("trusted_projects".to_string());
+        }
+        ignored
+    }
+
+    /// 合并另一份配置（模型设置和 profile 按字段覆盖，路径规则累加，其余同名条目整体替换）
+    pub fn merge(&mut self, other: AgentConfig) {
+        if other.profile.is_some() {
+            self.profile = other.profile;
+        }
+        self.model.merge(other.model);
+        for (name, profile) in other.profiles {
+            self.profiles.entry(name).or_default().merge(profile);
+        }
+        self.mcp_servers.extend(other.mcp_servers);
+        self.command_tools.extend(other.command_tools);
+        self.plugins.extend(other.plugins);
+        self.prices.extend(other.prices);
+        self.paths.allow.extend(other.paths.allow);
+        self.paths.deny.extend(other.paths.deny);
+        self.trusted_projects.extend(other.trusted_projects);
+    }
+}
+
+/// 全局配置文件路径
+pub fn global_config_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("simple-ai-agent").join(CONFIG_FILE_NAME))
+}
+
+/// 数据目录（会话、日志等）：$XDG_DATA_HOME/simple-ai-agent，默认 ~/.local/share/simple-ai-agent
+pub fn data_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share")))?;
+    Some(base.join("simple-ai-agent"))
+}
+
+/// 项目配置文件路径
+pub fn project_config_path(workspace_root: &Path) -> PathBuf {
+    workspace_root.join(PROJECT_CONFIG_DIR).join(CONFIG_FILE_NAME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request_options::ToolChoice;
+
+    #[test]
+    fn test_parse_mcp_servers() {
+        let config = AgentConfig::from_toml_str(
+            r#"
+            [mcp_servers.weather]
+            command = "npx"
+            args = ["-y", "weather-mcp"]
+            env = { API_KEY = "abc" }
+            tool_timeout_sec = 30
+
+            [mcp_servers.docs]
+            url = "http://localhost:8080/mcp"
+            enabled = false
+            "#,
+        )
+        .unwrap();
+
+        let weather = &config.mcp_servers["weather"];
+        assert_eq!(weather.command.as_deref(), Some("npx"));
+        assert_eq!(weather.args, vec!["-y", "weather-mcp"]);
+        assert_eq!(weather.env["API_KEY"], "abc");
+        assert_eq!(weather.tool_timeout_sec, Some(30));
+        assert!(weather.is_enabled());
+        assert!(!config.mcp_servers["docs"].is_enabled());
+
+        let err = AgentConfig::from_toml_str("[mcp_servers.broken]\nargs = []\n").unwrap_err();
+        assert!(err.to_string().contains("command 或 url"));
+    }
+
+    #[test]
+    fn test_parse_command_tools() {
+        let config = AgentConfig::from_toml_str(
+            r#"
+            [command_tools.deploy_status]
+            description = "查询部署状态"
+            command = ["./deploy-status.sh", "{service}"]
+            timeout_sec = 20
+            pass_env = ["KUBECONFIG"]
+
+            [command_tools.deploy_status.parameters]
+            type = "object"
+            required = ["service"]
+            properties = { service = { type = "string", description = "服务名" } }
+            "#,
+        )
+        .unwrap();
+
+        let tool = &config.command_tools["deploy_status"];
+        assert_eq!(tool.command, vec!["./deploy-status.sh", "{service}"]);
+        assert_eq!(tool.pass_env, vec!["KUBECONFIG"]);
+        let parameters = tool.parameters.as_ref().unwrap();
+        assert_eq!(parameters["properties"]["service"]["type"], "string");
+        assert_eq!(parameters["required"][0], "service");
+    }
+
+    #[test]
+    fn test_project_config_overrides_global() {
+        let mut global = AgentConfig::from_toml_str("[mcp_servers.a]\ncommand = \"old\"\n[mcp_servers.b]\ncommand = \"b\"\n").unwrap();
+        let project = AgentConfig::from_toml_str("[mcp_servers.a]\ncommand = \"new\"\n").unwrap();
+        global.merge(project);
+
+        assert_eq!(global.mcp_servers["a"].command.as_deref(), Some("new"));
+        assert_eq!(global.mcp_servers["b"].command.as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn test_parse_prices() {
+        let config = AgentConfig::from_toml_str(
+            r#"
+            [prices."glm-4-plus"]
+            input = 5.0
+            output = 5.0
+
+            [prices."deepseek-*"]
+            input = 2.0
+            cached_input = 0.5
+            output = 3.0
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.prices["glm-4-plus"].cached_input, None);
+        assert_eq!(config.prices["deepseek-*"].cached_input, Some(0.5));
+    }
+
+    #[test]
+    fn test_parse_budget() {
+        let mut config = AgentConfig::from_toml_str(
+            r#"
+            [budget]
+            max_requests = 20
+            max_cost = 1.0
+
+            [profiles.batch.budget]
+            max_cost = 0.2
+            "#,
+        )
+        .unwrap();
+        let mut model = config.model.clone();
+        model.merge(config.profiles.remove("batch").unwrap());
+        let budget = model.budget.unwrap();
+        assert_eq!(budget.max_requests, Some(20));
+        assert_eq!(budget.max_cost, Some(0.2));
+    }
+
+    #[test]
+    fn test_parse_request_options() {
+        let mut config = AgentConfig::from_toml_str(
+            r#"
+            temperature = 0.3
+            stop = ["<END>"]
+
+            [profiles.extract]
+            temperature = 0
+            tool_choice = "get_ticket_price"
+            parallel_tool_calls = false
+            "#,
+        )
+        .unwrap();
+        let mut model = config.model.clone();
+        model.merge(config.profiles.remove("extract").unwrap());
+        assert_eq!(model.request.temperature, Some(0.0));
+        assert_eq!(model.request.stop, Some(vec!["<END>".to_string()]));
+        assert_eq!(model.request.tool_choice, Some(ToolChoice::Function("get_ticket_price".to_string())));
+        assert_eq!(model.request.parallel_tool_calls, Some(false));
+    }
+
+    #[test]
+    fn test_parse_tool_calling() {
+        let config = AgentConfig::from_toml_str("[profiles.local]\ntool_calling = \"text\"\n").unwrap();
+        assert_eq!(config.profiles["local"].tool_calling, Some(ToolCalling::Text));
+        assert_eq!("native".parse::<ToolCalling>().unwrap(), ToolCalling::Native);
+        assert!("json".parse::<ToolCalling>().is_err());
+    }
+
+    #[test]
+    fn test_parse_routing() {
+        let mut config = AgentConfig::from_toml_str(
+            r#"
+            fallback = ["deepseek", "local"]
+
+            [routing]
+            compact = "flash"
+
+            [profiles.agentic]
+            routing = { tool_turns = "strong" }
+            "#,
+        )
+        .unwrap();
+        let mut model = config.model.clone();
+        model.merge(config.profiles.remove("agentic").unwrap());
+        assert_eq!(model.fallback, Some(vec!["deepseek".to_string(), "local".to_string()]));
+        assert_eq!(
+            model.routing,
+            Some(Routing { compact: Some("flash".to_string()), tool_turns: Some("strong".to_string()) })
+        );
+    }
+
+    #[test]
+    fn test_structured_output_response_format() {
+        let config = AgentConfig::from_toml_str("structured_output = \"json-object\"\n").unwrap();
+        assert_eq!(config.model.structured_output, Some(StructuredOutput::JsonObject));
+
+        let schema = serde_json::json!({ "type": "object" });
+        assert_eq!(
+            StructuredOutput::JsonSchema.response_format("Itinerary", &schema).unwrap()["json_schema"]["name"],
+            "Itinerary"
+        );
+        assert_eq!(
+            StructuredOutput::JsonObject.response_format("Itinerary", &schema),
+            Some(serde_json::json!({ "type": "json_object" }))
+        );
+        assert_eq!(StructuredOutput::Prompt.response_format("Itinerary", &schema), None);
+    }
+
+    #[test]
+    fn test_reasoning_replay() {
+        let config = AgentConfig::from_toml_str("reasoning_replay = \"summary\"\n").unwrap();
+        assert_eq!(config.model.reasoning_replay, Some(ReasoningReplay::Summary));
+
+        let reasoning = "先看看有哪些文件。\n\n需要调用 list_dir。\n\n";
+        assert_eq!(ReasoningReplay::Drop.replayed(reasoning, true), None);
+        assert_eq!(ReasoningReplay::Summary.replayed(reasoning, false).as_deref(), Some("需要调用 list_dir。"));
+        assert_eq!(ReasoningReplay::Replay.replayed(reasoning, true).as_deref(), Some(reasoning));
+        assert_eq!(ReasoningReplay::Replay.replayed(reasoning, false), None);
+
+        let long = "想".repeat(REASONING_SUMMARY_CHARS + 10);
+        let summary = summarize_reasoning(&long);
+        assert!(summary.starts_with('…'));
+        assert_eq!(summary.chars().count(), REASONING_SUMMARY_CHARS + 1);
+    }
+}
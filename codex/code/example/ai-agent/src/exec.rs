@@ -0,0 +1,439 @@
+// 命令执行 - 超时、工作目录、环境变量白名单和输出截断（简化版 Codex exec.rs）
+
+use crate::tools::ToolOutputSink;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+
+/// 默认超时时间（毫秒）
+pub const DEFAULT_TIMEOUT_MS: u64 = 30_000;
+
+/// 允许模型请求的最大超时时间（毫秒）
+pub const MAX_TIMEOUT_MS: u64 = 600_000;
+
+/// 返回给模型的 stdout/stderr 各自的最大字节数
+pub const DEFAULT_MAX_OUTPUT_BYTES: usize = 16 * 1024;
+
+/// 默认传递给子进程的环境变量（其余变量一律不继承，避免泄露 API Key 等敏感信息）
+pub const DEFAULT_ENV_ALLOWLIST: &[&str] = &[
+    "PATH", "HOME", "USER", "LOGNAME", "SHELL", "TERM", "LANG", "LC_ALL", "LC_CTYPE", "TMPDIR",
+    "TZ",
+];
+
+/// 命令执行策略
+#[derive(Debug, Clone)]
+pub struct ExecPolicy {
+    /// 允许继承的环境变量名
+    pub env_allowlist: Vec<String>,
+    /// 未指定 timeout_ms 时使用的超时时间
+    pub default_timeout_ms: u64,
+    /// stdout/stderr 各自保留的最大字节数（超出部分保留头尾）
+    pub max_output_bytes: usize,
+}
+
+impl Default for ExecPolicy {
+    fn default() -> Self {
+        Self {
+            env_allowlist: DEFAULT_ENV_ALLOWLIST.iter().map(|s| s.to_string()).collect(),
+            default_timeout_ms: DEFAULT_TIMEOUT_MS,
+            max_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
+        }
+    }
+}
+
+/// 单次执行参数
+#[derive(Debug, Clone)]
+pub struct ExecParams {
+    /// argv 形式的命令（第一个元素为程序名）
+    pub command: Vec<String>,
+    pub workdir: Option<PathBuf>,
+    pub timeout: Duration,
+}
+
+/// 结构化执行结果（直接序列化后返回给模型）
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ExecOutput {
+    /// 退出码（被信号终止或超时时为 None）
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub duration_ms: u64,
+    pub timed_out: bool,
+    /// stdout 或 stderr 是否被截断
+    pub truncated: bool,
+}
+
+impl ExecOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0) && !self.timed_out
+    }
+}
+
+/// 执行命令并收集输出，运行过程中把 stdout/stderr 增量推送到 sink
+pub async fn run_command(
+    params: ExecParams,
+    policy: &ExecPolicy,
+    sink: &ToolOutputSink,
+) -> Result<ExecOutput, anyhow::Error> {
+    let (program, args) = params
+        .command
+        .split_first()
+        .ok_or_else(|| anyhow::anyhow!("命令为空"))?;
+
+    let mut command = tokio::process::Command::new(program);
+    command
+        .args(args)
+        .env_clear()
+        .envs(
+            policy
+                .env_allowlist
+                .iter()
+                .filter_map(|name| std::env::var(name).ok().map(|value| (name, value))),
+        )
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    if let Some(workdir) = &params.workdir {
+        if !workdir.is_dir() {
+            return Err(anyhow::anyhow!("工作目录不存在: {}", workdir.display()));
+        }
+        command.current_dir(workdir);
+    }
+
+    // 放入独立进程组，超时时连同子进程一起终止
+    #[cfg(unix)]
+    command.process_group(0);
+
+    let started = Instant::now();
+    let mut child = command
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("命令启动失败: {}", e))?;
+    let mut group_guard = ProcessGroupGuard { pgid: child.id() };
+
+    let mut stdout = child.stdout.take().ok_or_else(|| anyhow::anyhow!("无法获取 stdout"))?;
+    let mut stderr = child.stderr.take().ok_or_else(|| anyhow::anyhow!("无法获取 stderr"))?;
+
+    let mut stdout_buf = HeadTailBuffer::new(policy.max_output_bytes);
+    let mut stderr_buf = HeadTailBuffer::new(policy.max_output_bytes);
+    let mut stdout_chunk = [0u8; 8192];
+    let mut stderr_chunk = [0u8; 8192];
+    let mut stdout_open = true;
+    let mut stderr_open = true;
+    let mut stdout_decoder = Utf8ChunkDecoder::default();
+    let mut stderr_decoder = Utf8ChunkDecoder::default();
+
+    let deadline = tokio::time::sleep(params.timeout);
+    tokio::pin!(deadline);
+
+    let mut timed_out = false;
+    let mut exit_code = None;
+
+    // 同时读取 stdout 和 stderr，直到两者关闭或超时
+    while stdout_open || stderr_open {
+        tokio::select! {
+            read = stdout.read(&mut stdout_chunk), if stdout_open => match read {
+                Ok(0) | Err(_) => stdout_open = false,
+                Ok(n) => {
+                    stdout_buf.push(&stdout_chunk[..n]);
+                    sink.send(stdout_decoder.decode(&stdout_chunk[..n]));
+                }
+            },
+            read = stderr.read(&mut stderr_chunk), if stderr_open => match read {
+                Ok(0) | Err(_) => stderr_open = false,
+                Ok(n) => {
+                    stderr_buf.push(&stderr_chunk[..n]);
+                    sink.send(stderr_decoder.decode(&stderr_chunk[..n]));
+                }
+            },
+            _ = &mut deadline => {
+                timed_out = true;
+                break;
+            }
+        }
+    }
+
+    if !timed_out {
+        tokio::select! {
+            status = child.wait() => {
+                exit_code = status.ok().and_then(|s| s.code());
+            }
+            _ = &mut deadline => timed_out = true,
+        }
+    }
+
+    if timed_out {
+        kill_process_tree(&mut child).await;
+    }
+    // 子进程已结束并被回收，进程组 ID 可能被复用，不能再向它发信号
+    group_guard.disarm();
+
+    Ok(ExecOutput {
+        exit_code,
+        truncated: stdout_buf.truncated() || stderr_buf.truncated(),
+        stdout: stdout_buf.into_string(),
+        stderr: stderr_buf.into_string(),
+        duration_ms: started.elapsed().as_millis() as u64,
+        timed_out,
+    })
+}
+
+/// run_command 的 future 被丢弃（任务被 abort、Ctrl-C 中断本轮）时终止整个进程组
+///
+/// kill_on_drop 只会杀死直接子进程，`sh -c` 启动的其他进程会留在后台继续运行。
+struct ProcessGroupGuard {
+    pgid: Option<u32>,
+}
+
+impl ProcessGroupGuard {
+    fn disarm(&mut self) {
+        self.pgid = None;
+    }
+}
+
+impl Drop for ProcessGroupGuard {
+    fn drop(&mut self) {
+        let Some(pgid) = self.pgid.take() else {
+            return;
+        };
+        // SAFETY: killpg 只是发送信号；子进程尚未被回收，进程组 ID 不会被复用
+        #[cfg(unix)]
+        unsafe {
+            libc::killpg(pgid as libc::pid_t, libc::SIGKILL);
+        }
+        #[cfg(not(unix))]
+        let _ = pgid;
+    }
+}
+
+/// 终止子进程及其进程组
+async fn kill_process_tree(child: &mut tokio::process::Child) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        // SAFETY: killpg 只是发送信号，pid 来自刚创建的子进程组
+        unsafe {
+            libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+        }
+    }
+    let _ = child.kill().await;
+}
+
+/// 增量 UTF-8 解码器（多字节字符被拆到两次读取之间时，留到下一块再解码）
+#[derive(Debug, Default)]
+pub struct Utf8ChunkDecoder {
+    carry: Vec<u8>,
+}
+
+impl Utf8ChunkDecoder {
+    pub fn decode(&mut self, bytes: &[u8]) -> String {
+        self.carry.extend_from_slice(bytes);
+        let complete = utf8_prefix(&self.carry).len();
+        let text = String::from_utf8_lossy(&self.carry[..complete]).to_string();
+        self.carry.drain(..complete);
+        text
+    }
+}
+
+/// 只保留头部和尾部的输出缓冲区（内存占用有上限）
+#[derive(Debug)]
+pub struct HeadTailBuffer {
+    head: Vec<u8>,
+    tail: std::collections::VecDeque<u8>,
+    head_limit: usize,
+    tail_limit: usize,
+    total: usize,
+}
+
+impl HeadTailBuffer {
+    pub fn new(max_bytes: usize) -> Self {
+        let head_limit = max_bytes / 2;
+        Self {
+            head: Vec::new(),
+            tail: std::collections::VecDeque::new(),
+            head_limit,
+            tail_limit: max_bytes - head_limit,
+            total: 0,
+        }
+    }
+
+    pub fn push(&mut self, mut bytes: &[u8]) {
+        self.total += bytes.len();
+
+        if self.head.len() < self.head_limit {
+            let take = bytes.len().min(self.head_limit - self.head.len());
+            self.head.extend_from_slice(&bytes[..take]);
+            bytes = &bytes[take..];
+        }
+
+        self.tail.extend(bytes);
+        while self.tail.len() > self.tail_limit {
+            self.tail.pop_front();
+        }
+    }
+
+    pub fn truncated(&self) -> bool {
+        self.total > self.head.len() + self.tail.len()
+    }
+
+    /// 转换为字符串，被截断时在中间插入省略提示
+    pub fn into_string(self) -> String {
+        let omitted = self.total - self.head.len() - self.tail.len();
+        let tail: Vec<u8> = self.tail.into_iter().collect();
+        if omitted == 0 {
+            let mut all = self.head;
+            all.extend_from_slice(&tail);
+            return String::from_utf8_lossy(&all).to_string();
+        }
+
+        format!(
+            "{}\n... [省略 {} 字节] ...\n{}",
+            String::from_utf8_lossy(utf8_prefix(&self.head)),
+            omitted,
+            String::from_utf8_lossy(utf8_suffix(&tail)),
+        )
+    }
+}
+
+/// 截取到最后一个完整 UTF-8 字符为止
+fn utf8_prefix(bytes: &[u8]) -> &[u8] {
+    match std::str::from_utf8(bytes) {
+        Ok(_) => bytes,
+        Err(e) if e.error_len().is_none() => &bytes[..e.valid_up_to()],
+        Err(_) => bytes,
+    }
+}
+
+/// 跳过开头被截断的 UTF-8 续字节
+fn utf8_suffix(bytes: &[u8]) -> &[u8] {
+    let start = bytes
+        .iter()
+        .take(4)
+        .position(|&b| (b & 0b1100_0000) != 0b1000_0000)
+        .unwrap_or(0);
+    &bytes[start..]
+}
+
+/// 按字符边界截断字符串，保留头尾各一半
+pub fn truncate_head_tail(text: &str, max_bytes: usize) -> (String, bool) {
+    let mut buffer = HeadTailBuffer::new(max_bytes);
+    buffer.push(text.as_bytes());
+    let truncated = buffer.truncated();
+    (buffer.into_string(), truncated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shell(command: &str, timeout_ms: u64) -> ExecParams {
+        ExecParams {
+            command: vec!["sh".to_string(), "-c".to_string(), command.to_string()],
+            workdir: None,
+            timeout: Duration::from_millis(timeout_ms),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_command_captures_exit_code_and_streams() {
+        let output = run_command(shell("echo out; echo err >&2; exit 3", 5_000), &ExecPolicy::default(), &ToolOutputSink::noop())
+            .await
+            .unwrap();
+
+        assert_eq!(output.exit_code, Some(3));
+        assert_eq!(output.stdout, "out\n");
+        assert_eq!(output.stderr, "err\n");
+        assert!(!output.timed_out);
+    }
+
+    #[tokio::test]
+    async fn test_run_command_timeout() {
+        let output = run_command(shell("echo start; sleep 5", 200), &ExecPolicy::default(), &ToolOutputSink::noop())
+            .await
+            .unwrap();
+
+        assert!(output.timed_out);
+        assert_eq!(output.exit_code, None);
+        assert_eq!(output.stdout, "start\n");
+        assert!(output.duration_ms < 5_000);
+    }
+
+    #[tokio::test]
+    async fn test_run_command_env_allowlist() {
+        // cargo 为测试进程设置了 CARGO_MANIFEST_DIR，它不在白名单中，不应传给子进程
+        // （不用 set_var：测试并行运行，修改进程环境变量会和其他线程竞争）
+        assert!(std::env::var_os("CARGO_MANIFEST_DIR").is_some());
+        let output = run_command(
+            shell("echo \"[$CARGO_MANIFEST_DIR]\"", 5_000),
+            &ExecPolicy::default(),
+            &ToolOutputSink::noop(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(output.stdout, "[]\n");
+    }
+
+    #[tokio::test]
+    async fn test_run_command_streams_chunks_to_sink() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let output = run_command(shell("echo 第一行; echo 第二行", 5_000), &ExecPolicy::default(), &ToolOutputSink::new(tx))
+            .await
+            .unwrap();
+
+        let mut streamed = String::new();
+        while let Ok(chunk) = rx.try_recv() {
+            streamed.push_str(&chunk);
+        }
+        assert_eq!(streamed, output.stdout);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_dropping_run_command_kills_process_group() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let task = tokio::spawn(async move {
+            run_command(shell("sleep 30 & echo $!; wait", 60_000), &ExecPolicy::default(), &ToolOutputSink::new(tx)).await
+        });
+        let pid: u32 = rx.recv().await.unwrap().trim().parse().unwrap();
+        task.abort();
+        let _ = task.await;
+
+        // 后台的 sleep 应随进程组一起被杀死（已退出或只剩僵尸进程）
+        let running = || {
+            std::fs::read_to_string(format!("/proc/{}/stat", pid))
+                .is_ok_and(|stat| stat.rsplit(')').next().is_some_and(|rest| !rest.trim_start().starts_with('Z')))
+        };
+        for _ in 0..50 {
+            if !running() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("后台进程 {} 仍在运行", pid);
+    }
+
+    #[test]
+    fn test_utf8_chunk_decoder_joins_split_chars() {
+        let bytes = "航班".as_bytes();
+        let mut decoder = Utf8ChunkDecoder::default();
+
+        let first = decoder.decode(&bytes[..4]);
+        let second = decoder.decode(&bytes[4..]);
+        assert_eq!(first, "航");
+        assert_eq!(second, "班");
+    }
+
+    #[test]
+    fn test_truncate_head_tail_keeps_char_boundaries() {
+        let text = "你好世界".repeat(100);
+        let (truncated, was_truncated) = truncate_head_tail(&text, 64);
+
+        assert!(was_truncated);
+        assert!(truncated.starts_with("你好"));
+        assert!(truncated.contains("省略"));
+        assert!(!truncated.contains('\u{FFFD}'));
+    }
+}
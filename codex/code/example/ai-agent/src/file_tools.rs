@@ -0,0 +1,303 @@
+// 文件工具 - 路径解析、文本解码，以及 write_file / apply_patch 编辑工具
+
+use crate::apply_patch::{apply_patch, write_atomic};
+use crate::path_policy::PathPolicy;
+use crate::tools::{ToolEffect, ToolExecutor};
+use async_trait::async_trait;
+use serde_json::json;
+use std::path::Path;
+use std::sync::Arc;
+
+/// read_file 默认读取的行数
+pub const DEFAULT_READ_LIMIT: usize = 500;
+
+/// 单行最多保留的字符数（超出部分截断）
+pub const MAX_LINE_CHARS: usize = 1_000;
+
+/// read_file 单次返回的最大字节数
+pub const MAX_READ_OUTPUT_BYTES: usize = 32 * 1024;
+
+/// 允许读取的最大文件大小
+pub const MAX_READ_FILE_BYTES: u64 = 20 * 1024 * 1024;
+
+/// 解码后的文本文件
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedText {
+    pub text: String,
+    /// 检测到的编码（UTF-8 / UTF-16LE / UTF-16BE / GBK）
+    pub encoding: &'static str,
+    /// 解码过程中是否出现无法识别的字节（已替换为 U+FFFD）
+    pub lossy: bool,
+}
+
+/// 检测编码并解码文本文件；二进制文件返回错误
+pub fn decode_text(bytes: &[u8]) -> Result<DecodedText, anyhow::Error> {
+    // 带 BOM 的文件直接按 BOM 解码
+    if let Some((encoding, bom_len)) = encoding_rs::Encoding::for_bom(bytes) {
+        let (text, lossy) = encoding.decode_without_bom_handling(&bytes[bom_len..]);
+        return Ok(DecodedText {
+            text: text.into_owned(),
+            encoding: encoding.name(),
+            lossy,
+        });
+    }
+
+    let sample = &bytes[..bytes.len().min(8192)];
+    if sample.contains(&0) {
+        // 无 BOM 的 UTF-16：ASCII 字符的高字节为 0，集中出现在奇数或偶数位置
+        let encoding = match utf16_without_bom(sample) {
+            Some(encoding) => encoding,
+            None => return Err(anyhow::anyhow!("这是一个二进制文件，无法作为文本读取")),
+        };
+        let (text, lossy) = encoding.decode_without_bom_handling(bytes);
+        return Ok(DecodedText {
+            text: text.into_owned(),
+            encoding: encoding.name(),
+            lossy,
+        });
+    }
+
+    let (text, encoding, lossy) = match std::str::from_utf8(bytes) {
+        Ok(text) => (text.to_string(), "UTF-8", false),
+        Err(_) => {
+            let (text, lossy) = encoding_rs::GBK.decode_without_bom_handling(bytes);
+            (text.into_owned(), "GBK", lossy)
+        }
+    };
+
+    // 控制字符过多时视为二进制（例如压缩包、图片被误判为 GBK）
+    let control = text
+        .chars()
+        .take(8192)
+        .filter(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t' | '\x0c' | '\x1b'))
+        .count();
+    let replacement = text.chars().take(8192).filter(|&c| c == '\u{FFFD}').count();
+    let sampled = text.chars().take(8192).count().max(1);
+    if (control + replacement) * 10 > sampled {
+        return Err(anyhow::anyhow!("这是一个二进制文件，无法作为文本读取"));
+    }
+
+    Ok(DecodedText { text, encoding, lossy })
+}
+
+/// 判断无 BOM 的 UTF-16 文本
+fn utf16_without_bom(sample: &[u8]) -> Option<&'static encoding_rs::Encoding> {
+    let pairs = sample.len() / 2;
+    if pairs == 0 {
+        return None;
+    }
+    let even_zeros = sample.iter().step_by(2).filter(|&&b| b == 0).count();
+    let odd_zeros = sample.iter().skip(1).step_by(2).filter(|&&b| b == 0).count();
+
+    if odd_zeros * 10 >= pairs * 3 && even_zeros * 20 < pairs {
+        Some(encoding_rs::UTF_16LE)
+    } else if even_zeros * 10 >= pairs * 3 && odd_zeros * 20 < pairs {
+        Some(encoding_rs::UTF_16BE)
+    } else {
+        None
+    }
+}
+
+/// 生成带行号的输出（offset 从 1 开始）
+pub fn format_numbered_lines(text: &str, offset: usize, limit: usize) -> String {
+    let total = text.lines().count();
+    let start = offset.max(1);
+    if start > total.max(1) {
+        return format!("(文件共 {} 行，offset {} 超出范围)", total, start);
+    }
+
+    let mut output = String::new();
+    let mut last = start - 1;
+    for (index, line) in text.lines().enumerate().skip(start - 1).take(limit) {
+        let line = match line.char_indices().nth(MAX_LINE_CHARS) {
+            Some((cut, _)) => format!("{}... (该行被截断)", &line[..cut]),
+            None => line.to_string(),
+        };
+        let numbered = format!("{:>6}\t{}\n", index + 1, line);
+        if output.len() + numbered.len() > MAX_READ_OUTPUT_BYTES {
+            break;
+        }
+        output.push_str(&numbered);
+        last = index + 1;
+    }
+
+    if last < total {
+        output.push_str(&format!(
+            "... (共 {} 行，已显示第 {}-{} 行，使用 offset={} 继续读取)\n",
+            total,
+            start,
+            last,
+            last + 1
+        ));
+    }
+    output
+}
+
+/// 文件写入工具
+pub struct WriteFileTool {
+    policy: Arc<PathPolicy>,
+}
+
+impl WriteFileTool {
+    pub fn new(policy: Arc<PathPolicy>) -> Self {
+        Self { policy }
+    }
+}
+
+#[async_trait]
+impl ToolExecutor for WriteFileTool {
+    fn name(&self) -> &str {
+        "write_file"
+    }
+
+    fn description(&self) -> &str {
+        "Create or overwrite a text file with the given content. Parent directories are created automatically. Prefer apply_patch for small edits to existing files."
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": {
+                    "type": "string",
+                    "description": "Path of the file to write"
+                },
+                "content": {
+                    "type": "string",
+                    "description": "Full content of the file"
+                }
+            },
+            "required": ["path", "content"]
+        })
+    }
+
+    fn effect(&self) -> ToolEffect {
+        ToolEffect::Mutating
+    }
+
+    async fn execute(&self, arguments: serde_json::Value) -> Result<String, Box<dyn std::error::Error + Send>> {
+        println!("\n📝 WriteFile 工具接收到参数: {}", arguments["path"]); // 调试输出
+
+        let path = arguments["path"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("缺少 'path' 参数"))?;
+        let content = arguments["content"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("缺少 'content' 参数"))?
+            .to_string();
+
+        let target = self.policy.resolve(Path::new(path))?;
+        let created = !target.exists();
+        let bytes = content.len();
+
+        tokio::task::spawn_blocking(move || write_atomic(&target, content.as_bytes()))
+            .await
+            .map_err(|e| anyhow::anyhow!("写入任务失败: {}", e))?
+            .map_err(|e| anyhow::anyhow!("写入文件失败: {}", e))?;
+
+        println!("✓ 文件写入成功 ({} 字节)", bytes);
+        Ok(json!({
+            "path": path,
+            "bytes_written": bytes,
+            "created": created
+        })
+        .to_string())
+    }
+}
+
+/// 补丁应用工具
+pub struct ApplyPatchTool {
+    policy: Arc<PathPolicy>,
+}
+
+impl ApplyPatchTool {
+    pub fn new(policy: Arc<PathPolicy>) -> Self {
+        Self { policy }
+    }
+}
+
+#[async_trait]
+impl ToolExecutor for ApplyPatchTool {
+    fn name(&self) -> &str {
+        "apply_patch"
+    }
+
+    fn description(&self) -> &str {
+        "Apply a patch to one or more files. Accepts a unified diff (--- a/file, +++ b/file, @@ hunks) or the Codex patch format:\n*** Begin Patch\n*** Add File: path\n+line\n*** Update File: path\n@@ optional line to locate the hunk\n context\n-old line\n+new line\n*** Delete File: path\n*** End Patch\nAll hunks are checked against the current file contents before anything is written; if any hunk does not match, no file is changed."
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "patch": {
+                    "type": "string",
+                    "description": "Patch text in unified diff or Codex patch format"
+                }
+            },
+            "required": ["patch"]
+        })
+    }
+
+    fn effect(&self) -> ToolEffect {
+        ToolEffect::Mutating
+    }
+
+    async fn execute(&self, arguments: serde_json::Value) -> Result<String, Box<dyn std::error::Error + Send>> {
+        println!("\n🩹 ApplyPatch 工具接收到补丁"); // 调试输出
+
+        let patch = arguments["patch"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("缺少 'patch' 参数"))?
+            .to_string();
+
+        let policy = self.policy.clone();
+        let changed = tokio::task::spawn_blocking(move || {
+            apply_patch(&patch, policy.workspace_root(), |path| policy.resolve(path))
+        })
+            .await
+            .map_err(|e| anyhow::anyhow!("补丁任务失败: {}", e))?
+            .map_err(|e| anyhow::anyhow!("补丁应用失败: {}", e))?;
+
+        for file in &changed {
+            println!("✓ {} {} (+{} -{})", file.action, file.path, file.added_lines, file.removed_lines);
+        }
+        Ok(json!({ "changed_files": changed }).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_gbk_and_utf16() {
+        let (gbk, _, _) = encoding_rs::GBK.encode("航班查询");
+        let decoded = decode_text(&gbk).unwrap();
+        assert_eq!(decoded.encoding, "GBK");
+        assert_eq!(decoded.text, "航班查询");
+
+        let mut utf16 = vec![0xFF, 0xFE];
+        utf16.extend("票价 ok".encode_utf16().flat_map(|u| u.to_le_bytes()));
+        let decoded = decode_text(&utf16).unwrap();
+        assert_eq!(decoded.encoding, "UTF-16LE");
+        assert_eq!(decoded.text, "票价 ok");
+
+        let no_bom: Vec<u8> = "hello world".encode_utf16().flat_map(|u| u.to_le_bytes()).collect();
+        assert_eq!(decode_text(&no_bom).unwrap().text, "hello world");
+    }
+
+    #[test]
+    fn test_decode_rejects_binary() {
+        let binary = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0x0D, 0x49, 0x48, 0x44, 0x52];
+        assert!(decode_text(&binary).is_err());
+    }
+
+    #[test]
+    fn test_format_numbered_lines_with_range() {
+        let text = "一\n二\n三\n四\n";
+        let output = format_numbered_lines(text, 2, 2);
+        assert!(output.starts_with("     2\t二\n     3\t三\n"));
+        assert!(output.contains("offset=4"));
+    }
+}
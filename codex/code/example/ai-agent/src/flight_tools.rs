@@ -1,6 +1,6 @@
 // 航班查询工具示例 - 基于 ChatGLM 函数调用教程
 
-use crate::tools::ToolExecutor;
+use crate::tools::{ToolEffect, ToolExecutor};
 use async_trait::async_trait;
 use serde_json::{json, Value};
 use std::collections::HashMap;
@@ -78,6 +78,10 @@ impl ToolExecutor for GetFlightNumberTool {
         })
     }
 
+    fn effect(&self) -> ToolEffect {
+        ToolEffect::ReadOnly
+    }
+
     async fn execute(&self, arguments: Value) -> Result<String, Box<dyn std::error::Error + Send>> {
         println!("\n✈️  查询航班号工具接收到参数: {}", arguments);
 
@@ -151,6 +155,10 @@ impl ToolExecutor for GetTicketPriceTool {
         })
     }
 
+    fn effect(&self) -> ToolEffect {
+        ToolEffect::ReadOnly
+    }
+
     async fn execute(&self, arguments: Value) -> Result<String, Box<dyn std::error::Error + Send>> {
         println!("\n💰 查询票价工具接收到参数: {}", arguments);
 
@@ -184,7 +192,7 @@ impl ToolExecutor for GetTicketPriceTool {
 pub fn register_flight_tools(registry: &mut crate::tools::ToolRegistry) {
     println!("\n🛫 注册航班查询工具...");
     registry.register(GetFlightNumberTool::new());
-    registry.register(GetTicketPriceTool::default());
+    registry.register(GetTicketPriceTool);
     println!("  ✅ 航班查询工具注册完成\n");
 }
 
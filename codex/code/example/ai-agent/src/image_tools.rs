@@ -0,0 +1,177 @@
+// 图片输入 - 把本地图片或 URL 转换为用户消息中的图片内容片段，以及 view_image 工具
+//
+// - http(s) URL 和 data URL 原样发送，由接口自行下载
+// - 本地文件读取后编码为 `data:<mime>;base64,...`，按扩展名判断格式
+// - view_image 把图片放进共享的 ImageAttachments，智能体在本批工具结果之后把它们作为
+//   ImageAttachment 写入对话历史（工具结果只能是文本）
+
+use crate::path_policy::PathPolicy;
+use crate::protocol::ContentPart;
+use crate::tools::{ToolEffect, ToolExecutor};
+use async_trait::async_trait;
+use base64::Engine;
+use serde_json::json;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// 单张图片的大小上限（OpenAI 兼容接口一般限制在 20MB 以内）
+pub const MAX_IMAGE_BYTES: u64 = 10 * 1024 * 1024;
+
+/// 按扩展名判断图片的 MIME 类型，不支持的格式返回 None
+pub fn image_mime_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// 是否是可以直接发送给接口的图片地址
+fn is_image_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://") || source.starts_with("data:image/")
+}
+
+/// 图片来源（URL 或本地路径）转换为内容片段（`/image` 和 `exec --image` 使用）
+pub fn image_part(source: &str) -> Result<ContentPart, anyhow::Error> {
+    if is_image_url(source) {
+        return Ok(ContentPart::image_url(source));
+    }
+    let path = Path::new(source);
+    let mime = check_image_file(path, std::fs::metadata(path).map(|m| m.len()))?;
+    let bytes = std::fs::read(path).map_err(|e| anyhow::anyhow!("读取图片失败: {}", e))?;
+    Ok(ContentPart::image_url(data_url(mime, &bytes)))
+}
+
+/// 检查格式和大小，返回 MIME 类型
+fn check_image_file(path: &Path, len: std::io::Result<u64>) -> Result<&'static str, anyhow::Error> {
+    let mime = image_mime_type(path)
+        .ok_or_else(|| anyhow::anyhow!("不支持的图片格式: {}（支持 png、jpg、gif、webp）", path.display()))?;
+    let len = len.map_err(|e| anyhow::anyhow!("读取图片失败: {}: {}", path.display(), e))?;
+    if len > MAX_IMAGE_BYTES {
+        return Err(anyhow::anyhow!("图片过大 ({} 字节)，超过 {} 字节上限", len, MAX_IMAGE_BYTES));
+    }
+    Ok(mime)
+}
+
+fn data_url(mime: &str, bytes: &[u8]) -> String {
+    format!("data:{};base64,{}", mime, base64::engine::general_purpose::STANDARD.encode(bytes))
+}
+
+/// 工具在本批调用中附加的图片，由智能体在工具结果之后取走
+#[derive(Debug, Default)]
+pub struct ImageAttachments {
+    images: Mutex<Vec<ContentPart>>,
+}
+
+impl ImageAttachments {
+    pub fn push(&self, image: ContentPart) {
+        self.images.lock().unwrap().push(image);
+    }
+
+    /// 取走所有待附加的图片
+    pub fn take(&self) -> Vec<ContentPart> {
+        std::mem::take(&mut *self.images.lock().unwrap())
+    }
+}
+
+/// 查看本地图片（如截图），图片会附加到对话中供模型查看
+pub struct ViewImageTool {
+    policy: Arc<PathPolicy>,
+    attachments: Arc<ImageAttachments>,
+}
+
+impl ViewImageTool {
+    pub fn new(policy: Arc<PathPolicy>, attachments: Arc<ImageAttachments>) -> Self {
+        Self { policy, attachments }
+    }
+}
+
+#[async_trait]
+impl ToolExecutor for ViewImageTool {
+    fn name(&self) -> &str {
+        "view_image"
+    }
+
+    fn description(&self) -> &str {
+        "Attach a local image file (png, jpg, gif, webp) to the conversation so you can look at it, e.g. a screenshot found on disk. The image is shown to you after the tool results."
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": {
+                    "type": "string",
+                    "description": "Path to the image file"
+                }
+            },
+            "required": ["path"]
+        })
+    }
+
+    fn effect(&self) -> ToolEffect {
+        ToolEffect::ReadOnly
+    }
+
+    async fn execute(&self, arguments: serde_json::Value) -> Result<String, Box<dyn std::error::Error + Send>> {
+        println!("\n🖼️  ViewImage 工具接收到参数: {}", arguments); // 调试输出
+
+        let path = arguments["path"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("缺少 'path' 参数"))?;
+        let resolved = self.policy.resolve(Path::new(path))?;
+        let len = tokio::fs::metadata(&resolved).await.map(|m| m.len());
+        let mime = check_image_file(&resolved, len)?;
+        let bytes = tokio::fs::read(&resolved)
+            .await
+            .map_err(|e| anyhow::anyhow!("读取图片失败: {}", e))?;
+        self.attachments.push(ContentPart::image_url(data_url(mime, &bytes)));
+
+        println!("✓ 已附加图片 ({} 字节, {})", bytes.len(), mime);
+        Ok(format!("已附加图片 {}（{}，{} 字节），图片会在工具结果之后发送", path, mime, bytes.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_part() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("截图.PNG");
+        std::fs::write(&path, [0x89, b'P', b'N', b'G']).unwrap();
+
+        let ContentPart::ImageUrl { image_url } = image_part(path.to_str().unwrap()).unwrap() else {
+            panic!("应为图片");
+        };
+        assert_eq!(image_url.url, "data:image/png;base64,iVBORw==");
+
+        let url = "https://example.com/a.jpg";
+        assert_eq!(image_part(url).unwrap(), ContentPart::image_url(url));
+        assert!(image_part("notes.txt").unwrap_err().to_string().contains("不支持的图片格式"));
+        assert!(image_part("missing.png").unwrap_err().to_string().contains("读取图片失败"));
+    }
+
+    #[tokio::test]
+    async fn test_view_image_attaches() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("shot.jpg"), b"jpeg").unwrap();
+        let attachments = Arc::new(ImageAttachments::default());
+        let tool = ViewImageTool::new(
+            Arc::new(PathPolicy::for_workspace(dir.path()).unwrap()),
+            attachments.clone(),
+        );
+
+        let output = tool.execute(json!({"path": "shot.jpg"})).await.unwrap();
+        assert!(output.contains("image/jpeg"));
+        assert!(tool.execute(json!({"path": "../outside.png"})).await.is_err());
+
+        let images = attachments.take();
+        assert_eq!(images, vec![ContentPart::image_url("data:image/jpeg;base64,anBlZw==")]);
+        assert!(attachments.take().is_empty());
+    }
+}
@@ -0,0 +1,93 @@
+// 标准输出重定向 - 把 println! 调试输出转到 stderr，原 stdout 留给协议或界面独占使用
+//
+// 基于 dup/dup2 操作文件描述符，只在 Unix 上编译。
+
+use std::fs::File;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+
+/// 把 fd 1 重定向到 stderr，返回指向原 stdout 的文件
+///
+/// 之后所有 `println!`（包括各工具的调试输出）都会写到 stderr，
+/// 只有写入返回的 File 的内容才会出现在真正的 stdout 上。
+pub fn take_stdout() -> std::io::Result<File> {
+    use std::io::Write;
+    std::io::stdout().flush()?;
+
+    // SAFETY: dup/dup2 只操作进程自己的标准文件描述符，返回值都做了检查
+    unsafe {
+        let original = libc::dup(libc::STDOUT_FILENO);
+        if original < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            let err = std::io::Error::last_os_error();
+            libc::close(original);
+            return Err(err);
+        }
+        Ok(File::from_raw_fd(original))
+    }
+}
+
+/// 把 fd 1 和 fd 2 重定向到 log 文件，drop 时恢复
+///
+/// 全屏界面独占终端时使用：工具的 `println!` 调试输出和日志都写进 log 文件，
+/// 界面通过 [`StdioRedirect::terminal`] 绘制到原来的 stdout。
+pub struct StdioRedirect {
+    saved_stdout: RawFd,
+    saved_stderr: RawFd,
+}
+
+impl StdioRedirect {
+    pub fn to_file(log: &File) -> std::io::Result<Self> {
+        use std::io::Write;
+        std::io::stdout().flush()?;
+        std::io::stderr().flush()?;
+
+        // SAFETY: 同 take_stdout，只操作进程自己的标准文件描述符
+        unsafe {
+            let saved_stdout = libc::dup(libc::STDOUT_FILENO);
+            if saved_stdout < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let saved_stderr = libc::dup(libc::STDERR_FILENO);
+            if saved_stderr < 0 {
+                let err = std::io::Error::last_os_error();
+                libc::close(saved_stdout);
+                return Err(err);
+            }
+            let redirect = Self { saved_stdout, saved_stderr };
+            if libc::dup2(log.as_raw_fd(), libc::STDOUT_FILENO) < 0
+                || libc::dup2(log.as_raw_fd(), libc::STDERR_FILENO) < 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(redirect)
+        }
+    }
+
+    /// 指向原 stdout（终端）的文件
+    pub fn terminal(&self) -> std::io::Result<File> {
+        // SAFETY: saved_stdout 在 self 存活期间有效，dup 得到独立的描述符
+        unsafe {
+            let fd = libc::dup(self.saved_stdout);
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(File::from_raw_fd(fd))
+        }
+    }
+}
+
+impl Drop for StdioRedirect {
+    fn drop(&mut self) {
+        use std::io::Write;
+        std::io::stdout().flush().ok();
+        // SAFETY: 恢复 to_file 中保存的描述符后关闭副本
+        unsafe {
+            libc::dup2(self.saved_stdout, libc::STDOUT_FILENO);
+            libc::dup2(self.saved_stderr, libc::STDERR_FILENO);
+            libc::close(self.saved_stdout);
+            libc::close(self.saved_stderr);
+        }
+    }
+}
@@ -1,15 +1,46 @@
 // 库入口文件 - 导出公共 API
 
 pub mod agent;
+pub mod apply_patch;
+pub mod budget;
+pub mod cli;
 pub mod client;
+pub mod command_tools;
+pub mod commands;
+pub mod config;
+pub mod exec;
+pub mod file_tools;
+pub mod image_tools;
+#[cfg(unix)]
+pub mod io_redirect;
+pub mod markdown;
+pub mod mcp;
+pub mod path_policy;
 pub mod protocol;
+pub mod repl;
+pub mod request_options;
+pub mod router;
+pub mod search_tools;
+pub mod session;
+pub mod settings;
+pub mod shell_session;
+pub mod structured;
+pub mod tool_emulation;
 pub mod tools;
+pub mod tui;
+pub mod usage;
+#[cfg(feature = "wasm-plugins")]
+pub mod wasm_plugin;
 pub mod flight_tools;
 
 // 重新导出常用类型
 pub use agent::Agent;
 pub use client::ModelClient;
-pub use protocol::{AgentStatus, AssistantMessage, ToolCall, ToolResult, UserMessage};
+pub use config::AgentConfig;
+pub use protocol::{
+    AgentEvent, AgentStatus, ApprovalResponse, AssistantMessage, ContentPart, ImageAttachment, MessageContent, ReasoningItem,
+    ReviewDecision, ToolCall, ToolResult, UserMessage,
+};
 pub use flight_tools::{GetFlightNumberTool, GetTicketPriceTool};
 
 // 内部重新导出以方便内部使用
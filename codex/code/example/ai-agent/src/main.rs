@@ -1,71 +1,437 @@
 // 简易版 AI 智能体实现
 // 基于 Codex 架构，简化了核心功能
 
-mod agent;
-mod client;
-mod tools;
-mod protocol;
-mod flight_tools;
-
-use agent::Agent;
-use client::ModelClient;
+use clap::Parser;
+use simple_ai_agent::budget::{BudgetExceeded, BUDGET_EXCEEDED_EXIT_CODE};
+use simple_ai_agent::cli::{self, ChatArgs, Cli, Command, ConfigCommand, ExecArgs, SessionsCommand, ToolsCommand};
+use simple_ai_agent::command_tools::CommandTool;
+use simple_ai_agent::commands::{CommandContext, CommandOutcome, CommandRegistry};
+use simple_ai_agent::config::{global_config_path, project_config_path};
+use simple_ai_agent::image_tools::image_part;
+use simple_ai_agent::markdown::MarkdownStream;
+use simple_ai_agent::mcp::McpConnectionManager;
+use simple_ai_agent::path_policy::PathPolicy;
+use simple_ai_agent::repl::{self, Repl, ReplInput};
+use simple_ai_agent::session::{Session, SessionStore};
+use simple_ai_agent::settings::Settings;
+use simple_ai_agent::tui::{self, TuiOptions};
+use simple_ai_agent::usage::PriceTable;
+use simple_ai_agent::{Agent, AgentConfig, AgentEvent, ApprovalResponse, ModelClient, ReviewDecision};
 use std::env;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let command = cli.command.unwrap_or(Command::Chat(ChatArgs::default()));
+
+    // serve / exec / tools 的 stdout 只输出协议消息或结果，必须在任何输出之前重定向；
+    // 工具的调试输出等其余内容都转到 stderr
+    let clean_stdout = match command {
+        Command::Serve | Command::Exec(_) | Command::Tools { .. } => Some(take_stdout()?),
+        _ => None,
+    };
+
+    if let Some(dir) = &cli.global.cwd {
+        env::set_current_dir(dir).map_err(|e| anyhow::anyhow!("无法切换到工作目录 {}: {}", dir.display(), e))?;
+    }
+
+    // 不需要模型配置的子命令
+    match &command {
+        Command::Completions { shell } => {
+            std::io::stdout().write_all(cli::completions(*shell).as_bytes())?;
+            return Ok(());
+        }
+        Command::Sessions { command } => return run_sessions(command),
+        _ => {}
+    }
+
     // 加载 .env 文件
     dotenv::dotenv().ok();
 
-    // 初始化日志
+    // 合并配置文件、profile、环境变量和命令行参数
+    let cwd = env::current_dir()?;
+    let settings = Settings::load(&cwd, &cli.global.overrides())?;
+    if !settings.ignored_project_keys.is_empty() {
+        eprintln!(
+            "⚠️  项目配置未受信任，已忽略: {}（在全局配置的 trusted_projects 中加入该目录或使用 --trust-project）",
+            settings.ignored_project_keys.join(", ")
+        );
+    }
+    if let Command::Config { command } = &command {
+        match command {
+            ConfigCommand::Show => print!("{}", settings.render()),
+            ConfigCommand::Path => {
+                let global = global_config_path();
+                let project = project_config_path(&cwd);
+                for (label, path) in [("全局配置", global.as_deref()), ("项目配置", Some(project.as_path()))] {
+                    match path {
+                        Some(path) if path.exists() => println!("{}: {}", label, path.display()),
+                        Some(path) => println!("{}: {}（不存在）", label, path.display()),
+                        None => println!("{}: (未知)", label),
+                    }
+                }
+                match SessionStore::default_dir() {
+                    Some(dir) => println!("会话存储: {}", dir.display()),
+                    None => println!("会话存储: (未知)"),
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    // 初始化日志（输出到 stderr）
     tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::INFO)
+        .with_max_level(cli.global.log_level())
+        .with_writer(std::io::stderr)
         .init();
 
+    match command {
+        Command::Tools { command: ToolsCommand::List { json } } => {
+            // 列出工具不需要调用模型，没有 API Key 时用占位客户端
+            let model_client = settings.model_client().unwrap_or_else(|_| {
+                ModelClient::new_with_config(String::new(), settings.model.value.clone(), settings.base_url.value.clone())
+            });
+            let agent = build_agent(&settings, model_client).await?;
+            let mut out = clean_stdout.expect("tools 模式已重定向 stdout");
+            let definitions = agent.tool_registry().list_definitions();
+            if json {
+                writeln!(out, "{}", serde_json::to_string_pretty(&definitions)?)?;
+            } else {
+                for tool in definitions {
+                    let summary = tool.description.lines().next().unwrap_or_default();
+                    writeln!(out, "{:<24} {}", tool.name, summary)?;
+                }
+            }
+            Ok(())
+        }
+        Command::Serve => {
+            let agent = build_agent(&settings, require_model_client(&settings)).await?;
+            println!("🔌 以 MCP 服务器模式运行（stdio）");
+            simple_ai_agent::mcp::run_stdio_server(agent, clean_stdout.expect("serve 模式已重定向 stdout")).await
+        }
+        Command::Exec(args) => {
+            let out = clean_stdout.expect("exec 模式已重定向 stdout");
+            run_exec(&settings, args, out).await
+        }
+        Command::Chat(args) => run_chat(&settings, args).await,
+        Command::Tui(args) => run_tui(&settings, args).await,
+        Command::Completions { .. } | Command::Sessions { .. } | Command::Config { .. } => unreachable!("已在前面处理"),
+    }
+}
+
+/// 把 fd 1 重定向到 stderr，返回独占的原 stdout
+#[cfg(unix)]
+fn take_stdout() -> anyhow::Result<std::fs::File> {
+    Ok(simple_ai_agent::io_redirect::take_stdout()?)
+}
+
+#[cfg(not(unix))]
+fn take_stdout() -> anyhow::Result<std::fs::File> {
+    Err(anyhow::anyhow!("serve / exec / tools 目前只支持 Unix（需要把调试输出从 stdout 分离）"))
+}
+
+/// 创建模型客户端，缺少 API Key 时退出
+fn require_model_client(settings: &Settings) -> ModelClient {
+    settings.model_client().unwrap_or_else(|e| {
+        eprintln!("⚠️  {}", e);
+        std::process::exit(1);
+    })
+}
+
+/// 创建智能体并注册配置文件中的命令工具、WASM 插件和 MCP 服务器提供的工具
+async fn build_agent(settings: &Settings, model_client: ModelClient) -> anyhow::Result<Agent> {
+    // 文件类工具和插件共用同一个路径访问策略
+    let path_policy = Arc::new(settings.path_policy(&env::current_dir()?)?);
+    let mut agent = Agent::new_with_policy(model_client, path_policy.clone());
+
+    let config = &settings.config;
+    for (name, tool_config) in &config.command_tools {
+        if agent.tool_registry().get(name).is_some() {
+            eprintln!("⚠️  命令工具 '{}' 与已有工具重名，已跳过", name);
+            continue;
+        }
+        match CommandTool::new(name, tool_config.clone()) {
+            Ok(tool) => agent.register_tool(tool),
+            Err(e) => eprintln!("⚠️  {}", e),
+        }
+    }
+    load_plugins(&mut agent, config, path_policy).await;
+    if !config.mcp_servers.is_empty() {
+        println!("🔌 启动 MCP 服务器...");
+        let mcp = McpConnectionManager::start(&config.mcp_servers).await;
+        for tool in mcp.tools().await {
+            agent.register_tool(tool);
+        }
+        println!();
+    }
+    settings.apply_tool_filter(&mut agent)?;
+    agent.set_approval_policy(settings.approval_policy.value);
+    let prices = PriceTable::new(config.prices.clone());
+    if settings.budget.limits_cost() && prices.price(&settings.model.value).is_none() {
+        eprintln!("⚠️  设置了费用预算，但模型 {} 没有配置价格（[prices]），费用上限不会生效", settings.model.value);
+    }
+    agent.set_price_table(prices);
+    agent.set_budget(settings.budget.clone());
+    agent.set_request_options(settings.request_options.clone())?;
+    match settings.model_router() {
+        Ok(router) => agent.set_router(router),
+        Err(e) => eprintln!("⚠️  {}，只使用主模型", e),
+    }
+    Ok(agent)
+}
+
+/// 打开会话存储，失败时只警告（会话不会被保存）
+fn open_store(ephemeral: bool) -> Option<SessionStore> {
+    if ephemeral {
+        return None;
+    }
+    SessionStore::open_default()
+        .map_err(|e| eprintln!("⚠️  {}，本次会话不会被保存", e))
+        .ok()
+}
+
+/// 保存当前对话
+async fn save_session(store: Option<&SessionStore>, session: &mut Session, agent: &Agent) {
+    let Some(store) = store else {
+        return;
+    };
+    session.update(agent.conversation().await);
+    session.usage = agent.usage().clone();
+    if let Err(e) = store.save(session) {
+        eprintln!("⚠️  保存会话失败: {}", e);
+    }
+}
+
+/// sessions 子命令
+fn run_sessions(command: &SessionsCommand) -> anyhow::Result<()> {
+    let store = SessionStore::open_default()?;
+    match command {
+        SessionsCommand::List => {
+            let sessions = store.list()?;
+            if sessions.is_empty() {
+                println!("（没有保存的会话）");
+            }
+            for meta in sessions {
+                println!(
+                    "{}  {}  {:<16}  {}",
+                    &meta.id[..8.min(meta.id.len())],
+                    meta.updated_at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M"),
+                    meta.model,
+                    meta.title
+                );
+            }
+        }
+        SessionsCommand::Show { id } => print!("{}", store.load(id)?.render()),
+        SessionsCommand::Delete { id } => println!("🗑️  已删除会话 {}", store.delete(id)?),
+    }
+    Ok(())
+}
+
+/// exec 子命令：执行一条指令，最终回复（或 JSON 事件）写到 stdout
+async fn run_exec(settings: &Settings, args: ExecArgs, mut out: std::fs::File) -> anyhow::Result<()> {
+    let prompt = match args.prompt {
+        Some(prompt) if prompt != "-" => prompt,
+        _ => {
+            let mut input = String::new();
+            std::io::stdin().read_to_string(&mut input)?;
+            input
+        }
+    };
+    let prompt = prompt.trim();
+    if prompt.is_empty() {
+        return Err(anyhow::anyhow!("指令不能为空"));
+    }
+
+    let images = args.images.iter().map(|source| image_part(source)).collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut agent = build_agent(settings, require_model_client(settings)).await?;
+    for image in images {
+        agent.attach_image(image);
+    }
+    let store = open_store(args.ephemeral);
+    let mut session = Session::new(env::current_dir()?, settings.model.value.clone());
+
+    let json = args.json;
+    let mut events = out.try_clone()?;
+    let result = agent
+        .process_message_with_events(prompt, |event| {
+            if json {
+                if let Ok(line) = serde_json::to_string(&event) {
+                    writeln!(events, "{}", line).ok();
+                }
+            }
+        })
+        .await;
+    save_session(store.as_ref(), &mut session, &agent).await;
+
+    // 超出预算时使用单独的退出码，便于批处理脚本区分（说明已由智能体输出到 stderr）
+    let reply = match result {
+        Ok(reply) => reply,
+        Err(e) if e.is::<BudgetExceeded>() => {
+            eprintln!("⛔ {}", e);
+            std::process::exit(BUDGET_EXCEEDED_EXIT_CODE)
+        }
+        Err(e) => return Err(e),
+    };
+    if json {
+        let session_id = store.is_some().then_some(session.meta.id);
+        writeln!(
+            out,
+            "{}",
+            serde_json::json!({ "type": "result", "text": reply, "session_id": session_id, "usage": agent.usage() })
+        )?;
+    } else {
+        writeln!(out, "{}", reply)?;
+    }
+    Ok(())
+}
+
+/// 按 --resume / --last 恢复会话，否则新建
+fn open_session(settings: &Settings, args: &ChatArgs) -> anyhow::Result<(Option<SessionStore>, Session)> {
+    let store = open_store(args.ephemeral);
+    let resume_id = match (&args.resume, args.last) {
+        (Some(id), _) => Some(id.clone()),
+        (None, true) => Some(
+            SessionStore::open_default()?
+                .latest()?
+                .map(|meta| meta.id)
+                .ok_or_else(|| anyhow::anyhow!("没有可恢复的会话"))?,
+        ),
+        (None, false) => None,
+    };
+    let session = match resume_id {
+        Some(id) => SessionStore::open_default()?.load(&id)?,
+        None => Session::new(env::current_dir()?, settings.model.value.clone()),
+    };
+    Ok((store, session))
+}
+
+/// 在终端中询问用户是否批准工具调用；等待回答时按 Ctrl-C 返回 None
+async fn ask_approval(name: &str, arguments: &serde_json::Value, hint: Option<&str>) -> Option<ReviewDecision> {
+    println!("\n⚠️  智能体请求调用工具 {}: {}", name, arguments);
+    if let Some(hint) = hint {
+        println!("   ℹ️  {}", hint);
+    }
+    print!("   批准？[y] 是  [a] 本次会话总是批准  [n] 否  [q] 中止本轮: ");
+    std::io::stdout().flush().ok();
+
+    // 在阻塞线程中读取，不占用异步任务，Ctrl-C 可以随时中断
+    let cancel = Arc::new(AtomicBool::new(false));
+    let reader = tokio::task::spawn_blocking({
+        let cancel = cancel.clone();
+        move || read_line_cancellable(&cancel)
+    });
+    let answer = tokio::select! {
+        answer = reader => answer.ok().flatten(),
+        _ = tokio::signal::ctrl_c() => {
+            cancel.store(true, Ordering::Relaxed);
+            return None;
+        }
+    };
+    let Some(answer) = answer else {
+        return Some(ReviewDecision::Denied);
+    };
+    Some(match answer.trim() {
+        "y" | "Y" => ReviewDecision::Approved,
+        "a" | "A" => ReviewDecision::ApprovedForSession,
+        "q" | "Q" => ReviewDecision::Abort,
+        _ => ReviewDecision::Denied,
+    })
+}
+
+/// 从标准输入读取一行；cancel 置位后放弃等待，不会吞掉之后在提示符下的输入
+fn read_line_cancellable(cancel: &AtomicBool) -> Option<String> {
+    // 等到有输入时才读取，每 100ms 检查一次是否已取消
+    #[cfg(unix)]
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            return None;
+        }
+        let mut stdin = libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 };
+        // SAFETY: 传入指向一个有效 pollfd 的指针，数量为 1
+        let ready = unsafe { libc::poll(&mut stdin, 1, 100) };
+        if ready > 0 || (ready < 0 && std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted) {
+            break;
+        }
+    }
+    if cancel.load(Ordering::Relaxed) {
+        return None;
+    }
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).ok()?;
+    Some(answer)
+}
+
+/// tui 子命令：全屏终端界面
+async fn run_tui(settings: &Settings, args: ChatArgs) -> anyhow::Result<()> {
+    let model_client = require_model_client(settings);
+    let mut agent = build_agent(settings, model_client).await?;
+    let (store, session) = open_session(settings, &args)?;
+    if !session.conversation.is_empty() {
+        agent.restore_conversation(session.conversation.clone()).await;
+        agent.restore_usage(session.usage.clone());
+    }
+
+    let saved = store.is_some();
+    let session = tui::run(
+        agent,
+        TuiOptions {
+            model: settings.model.value.clone(),
+            session,
+            store,
+        },
+    )
+    .await?;
+
+    println!("👋 再见！");
+    if saved && !session.conversation.is_empty() {
+        println!("💾 会话已保存，使用 `simple-ai-agent tui --resume {}` 继续", &session.meta.id[..8]);
+    }
+    Ok(())
+}
+
+/// chat 子命令：交互式对话
+async fn run_chat(settings: &Settings, args: ChatArgs) -> anyhow::Result<()> {
     println!("🦊 灵狐 AI Agent 启动中...\n");
 
-    // 从环境变量获取配置
-    let api_key = env::var("OPENAI_API_KEY")
-        .unwrap_or_else(|_| {
-            eprintln!("⚠️  警告: 未设置 OPENAI_API_KEY 环境变量");
-            eprintln!("⚠️  请在 .env 文件中设置或导出环境变量");
-            std::process::exit(1);
-        });
-
-    let model = env::var("MODEL")
-        .unwrap_or_else(|_| {
-            eprintln!("⚠️  未设置 MODEL 环境变量，使用默认模型");
-            "glm-4-tools".to_string()
-        });
-
-    let base_url = env::var("API_BASE_URL")
-        .unwrap_or_else(|_| "https://open.bigmodel.cn/api/paas/v4/".to_string());
-
-    // 创建模型客户端
-    let model_client = ModelClient::new_with_config(
-        api_key,
-        model,
-        base_url,
-    );
-
-    // 创建智能体
-    let mut agent = Agent::new(model_client);
-
-    println!("💡 智能体就绪，输入消息开始对话（输入 'quit' 退出）\n");
+    let model_client = require_model_client(settings);
+    println!("🧠 模型: {} ({})", settings.model.value, settings.base_url.value);
+    let mut agent = build_agent(settings, model_client).await?;
+
+    // 恢复或新建会话
+    let (store, mut session) = open_session(settings, &args)?;
+    if !session.conversation.is_empty() {
+        println!("📂 已恢复会话 {}（{} 条消息）", session.meta.id, session.conversation.len());
+        agent.restore_conversation(session.conversation.clone()).await;
+        agent.restore_usage(session.usage.clone());
+    }
+
+    // 需要审批的工具调用在终端中询问
+    let approvals = agent.approval_channel();
+
+    // 行编辑器：斜杠命令补全、持久化输入历史、多行输入
+    let mut repl = Repl::new(CommandRegistry::with_builtin_commands(), repl::history_path())?;
+
+    println!("💡 智能体就绪，输入消息开始对话（/help 查看命令，输入 'quit' 或 Ctrl-D 退出）\n");
     println!("─────────────────────────────────────────────\n");
 
     // 主循环
     loop {
-        print!("👤 You: ");
-        use std::io::Write;
-        std::io::stdout().flush()?;
-
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
+        let input = match repl.read_input()? {
+            ReplInput::Line(line) => line,
+            ReplInput::Interrupted => {
+                println!("（输入已清空，Ctrl-D 或 /quit 退出）");
+                continue;
+            }
+            ReplInput::Eof => break,
+        };
         let input = input.trim();
 
         // 退出命令（只显式 quit，空输入继续等待）
         if input.eq_ignore_ascii_case("quit") {
-            println!("\n👋 再见！");
             break;
         }
 
@@ -73,24 +439,117 @@ async fn main() -> anyhow::Result<()> {
             continue; // 空输入跳过，不退出
         }
 
+        // 斜杠命令
+        if CommandRegistry::parse(input).is_some() {
+            let mut ctx = CommandContext {
+                agent: &mut agent,
+                session: &mut session,
+                store: store.as_ref(),
+            };
+            // /compact 等命令会调用模型，同样可以用 Ctrl-C 中断
+            let outcome = tokio::select! {
+                outcome = repl.commands().execute(&mut ctx, input) => outcome,
+                _ = tokio::signal::ctrl_c() => Err(anyhow::anyhow!("命令已中断")),
+            };
+            match outcome {
+                Ok(CommandOutcome::Quit) => break,
+                Ok(CommandOutcome::Output(out)) if !out.is_empty() => println!("{}\n", out),
+                Ok(CommandOutcome::Output(_)) => {}
+                Err(e) => eprintln!("❌ {}\n", e),
+            }
+            continue;
+        }
+
         print!("\n🤖 Agent: ");
         std::io::stdout().flush()?;
 
-        // 处理用户输入（流式输出）
-        match agent.process_message_stream_with_result(input, |chunk| {
-            print!("{}", chunk);
-            std::io::stdout().flush().ok();
-        }).await {
-            Ok(_) => {
-                println!("\n");
-                println!("─────────────────────────────────────────────\n");
-            }
-            Err(e) => {
-                eprintln!("\n❌ 错误: {}", e);
-                println!("─────────────────────────────────────────────\n");
+        // 处理用户输入（流式输出，工具运行中的输出也实时显示）；Ctrl-C 中断本轮而不是退出
+        // 回复边接收边按 Markdown 渲染，思考内容暗色显示，工具调用开始前先输出暂存的内容
+        // 审批请求转给这里询问用户，事件回调中不能阻塞（否则 Ctrl-C 无法中断本轮）
+        let mut markdown = MarkdownStream::for_stdout();
+        let (request_tx, mut request_rx) = tokio::sync::mpsc::unbounded_channel();
+        let result = {
+            let turn = agent.process_message_with_events(input, |event| {
+                match event {
+                    AgentEvent::TextDelta { text } => print!("{}", markdown.push(&text)),
+                    AgentEvent::ReasoningDelta { text } => print!("{}", markdown.push_reasoning(&text)),
+                    AgentEvent::ToolCallBegin { .. } => print!("{}", markdown.finish()),
+                    AgentEvent::ToolOutputDelta { chunk, .. } => print!("{}", chunk),
+                    AgentEvent::ApprovalRequest { call_id, name, arguments, hint } => {
+                        print!("{}", markdown.finish());
+                        request_tx.send((call_id, name, arguments, hint)).ok();
+                    }
+                    _ => {}
+                }
+                std::io::stdout().flush().ok();
+            });
+            tokio::pin!(turn);
+            loop {
+                tokio::select! {
+                    result = &mut turn => break Some(result),
+                    Some((call_id, name, arguments, hint)) = request_rx.recv() => {
+                        match ask_approval(&name, &arguments, hint.as_deref()).await {
+                            Some(decision) => {
+                                approvals.send(ApprovalResponse { call_id, decision }).ok();
+                            }
+                            None => break None,
+                        }
+                    }
+                    _ = tokio::signal::ctrl_c() => break None,
+                }
             }
+        };
+        print!("{}", markdown.finish());
+        let Some(result) = result else {
+            agent.abort_turn().await;
+            println!("\n\n⏹️  本轮已中断\n");
+            println!("─────────────────────────────────────────────\n");
+            save_session(store.as_ref(), &mut session, &agent).await;
+            continue;
+        };
+        match result {
+            Ok(_) => println!("\n"),
+            Err(e) if e.is::<BudgetExceeded>() => eprintln!("\n⛔ {}", e),
+            Err(e) => eprintln!("\n❌ 错误: {}", e),
+        }
+        let usage = agent.last_turn_usage();
+        if usage.requests > 0 {
+            println!("📊 本轮 {}", usage);
         }
+        println!("─────────────────────────────────────────────\n");
+        save_session(store.as_ref(), &mut session, &agent).await;
     }
 
+    println!("\n👋 再见！");
+    if store.is_some() && !session.conversation.is_empty() {
+        println!("💾 会话已保存，使用 `simple-ai-agent chat --resume {}` 继续", &session.meta.id[..8]);
+    }
     Ok(())
 }
+
+/// 加载配置中的 WASM 插件工具
+#[cfg(feature = "wasm-plugins")]
+async fn load_plugins(agent: &mut Agent, config: &AgentConfig, policy: Arc<PathPolicy>) {
+    use simple_ai_agent::tools::ToolExecutor;
+    use simple_ai_agent::wasm_plugin::WasmPluginTool;
+
+    for (id, plugin) in &config.plugins {
+        if !plugin.is_enabled() {
+            continue;
+        }
+        match WasmPluginTool::load(plugin, policy.clone()).await {
+            Ok(tool) if agent.tool_registry().get(tool.name()).is_some() => {
+                eprintln!("⚠️  插件 '{}' 的工具名 '{}' 与已有工具重名，已跳过", id, tool.name());
+            }
+            Ok(tool) => agent.register_tool(tool),
+            Err(e) => eprintln!("⚠️  插件 '{}' 加载失败: {}", id, e),
+        }
+    }
+}
+
+#[cfg(not(feature = "wasm-plugins"))]
+async fn load_plugins(_agent: &mut Agent, config: &AgentConfig, _policy: Arc<PathPolicy>) {
+    if !config.plugins.is_empty() {
+        eprintln!("⚠️  配置了 WASM 插件，但编译时未启用 wasm-plugins 特性，已忽略");
+    }
+}
@@ -0,0 +1,668 @@
+// 终端 Markdown 渲染 - 流式输出时边接收边渲染
+//
+// 按行处理：
+// - 段落、列表项和引用在文本到达时立即输出，行内的 **粗体**、*斜体*、`代码`、~~删除线~~
+//   遇到标记时切换样式（标记字符后面的字符到达后才能确定它是否为标记，最多暂缓一两个字符）；
+// - 标题、分隔线和代码块的行需要看到整行，表格需要看到整张表（列宽）才能输出；
+// - 代码块按语言用 syntect 逐行高亮。
+//
+// 模型的思考内容不做 Markdown 渲染，以 💭 开头暗色输出，回复开始时自动结束。
+//
+// stdout 不是终端或设置了 NO_COLOR 时原样输出 Markdown 文本。
+
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::as_24_bit_terminal_escaped;
+use unicode_width::UnicodeWidthChar;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const ITALIC: &str = "\x1b[3m";
+const UNDERLINE: &str = "\x1b[4m";
+const STRIKE: &str = "\x1b[9m";
+const CYAN: &str = "\x1b[36m";
+const MAGENTA: &str = "\x1b[35m";
+
+/// 分隔线的最大宽度
+const MAX_RULE_WIDTH: usize = 60;
+/// 代码高亮主题
+const CODE_THEME: &str = "base16-ocean.dark";
+
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn code_theme() -> &'static Theme {
+    static THEME: OnceLock<Theme> = OnceLock::new();
+    THEME.get_or_init(|| {
+        let mut themes = ThemeSet::load_defaults().themes;
+        themes.remove(CODE_THEME).unwrap_or_default()
+    })
+}
+
+/// 行内格式的流式渲染器（一行之内有效）
+struct Inline {
+    /// 整行的基础样式（如标题、引用）
+    base: String,
+    bold: bool,
+    italic: bool,
+    strike: bool,
+    code: bool,
+    /// 暂缓输出的 `*` / `~` 标记，需要看到下一个字符才能确定
+    pending: String,
+    /// 上一个输出的字符，用于判断标记是开始还是结束
+    prev: Option<char>,
+    /// 已输出文本的显示宽度（表格对齐用）
+    width: usize,
+}
+
+impl Inline {
+    fn new(base: impl Into<String>) -> Self {
+        Self {
+            base: base.into(),
+            bold: false,
+            italic: false,
+            strike: false,
+            code: false,
+            pending: String::new(),
+            prev: None,
+            width: 0,
+        }
+    }
+
+    /// 渲染一整段文本，返回 (带样式的文本, 显示宽度)
+    fn render(text: &str, base: &str) -> (String, usize) {
+        let mut inline = Self::new(base);
+        let mut out = base.to_string();
+        for c in text.chars() {
+            inline.push(c, &mut out);
+        }
+        inline.finish(&mut out);
+        (out, inline.width)
+    }
+
+    /// 当前样式的转义序列（先重置再叠加）
+    fn style(&self) -> String {
+        let mut style = format!("{}{}", RESET, self.base);
+        if self.bold {
+            style.push_str(BOLD);
+        }
+        if self.italic {
+            style.push_str(ITALIC);
+        }
+        if self.strike {
+            style.push_str(STRIKE);
+        }
+        if self.code {
+            style.push_str(CYAN);
+        }
+        style
+    }
+
+    fn push(&mut self, c: char, out: &mut String) {
+        if self.code {
+            if c == '`' {
+                self.code = false;
+                out.push_str(&self.style());
+            } else {
+                self.emit(c, out);
+            }
+            return;
+        }
+        if (c == '*' || c == '~') && (self.pending.is_empty() || self.pending.ends_with(c)) {
+            self.pending.push(c);
+            return;
+        }
+        self.resolve(Some(c), out);
+        match c {
+            '*' | '~' => self.pending.push(c),
+            '`' => {
+                self.code = true;
+                out.push_str(&self.style());
+            }
+            _ => self.emit(c, out),
+        }
+    }
+
+    /// 确定暂缓的标记：紧跟非空白字符时为开始标记，紧接在非空白字符之后时为结束标记，否则按原样输出
+    fn resolve(&mut self, next: Option<char>, out: &mut String) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let run = std::mem::take(&mut self.pending);
+        let opens = next.is_some_and(|c| !c.is_whitespace());
+        let closes = self.prev.is_some_and(|c| !c.is_whitespace());
+        let mut count = run.chars().count();
+        let mut changed = false;
+
+        if run.starts_with('~') {
+            if count == 2 && (if self.strike { closes } else { opens }) {
+                self.strike = !self.strike;
+                changed = true;
+                count = 0;
+            }
+        } else {
+            if count >= 2 && (if self.bold { closes } else { opens }) {
+                self.bold = !self.bold;
+                changed = true;
+                count -= 2;
+            }
+            if count >= 1 && (if self.italic { closes } else { opens }) {
+                self.italic = !self.italic;
+                changed = true;
+                count -= 1;
+            }
+        }
+
+        if changed {
+            out.push_str(&self.style());
+        }
+        let marker = run.chars().next().unwrap_or('*');
+        for _ in 0..count {
+            self.emit(marker, out);
+        }
+    }
+
+    fn emit(&mut self, c: char, out: &mut String) {
+        out.push(c);
+        self.prev = Some(c);
+        self.width += c.width().unwrap_or(0);
+    }
+
+    /// 行结束：输出暂缓的标记并清除样式（未闭合的标记不跨行）
+    fn finish(&mut self, out: &mut String) {
+        self.resolve(None, out);
+        if self.bold || self.italic || self.strike || self.code || !self.base.is_empty() {
+            out.push_str(RESET);
+        }
+        self.bold = false;
+        self.italic = false;
+        self.strike = false;
+        self.code = false;
+    }
+}
+
+/// 行首的块类型判断结果
+enum LineKind {
+    /// 还需要更多字符才能判断
+    Undecided,
+    /// 需要整行才能渲染（标题、表格、代码围栏、可能的分隔线）
+    Block,
+    /// 可以流式输出：先输出前缀，从 rest 字节处开始按行内格式输出
+    Inline { prefix: String, base: &'static str, rest: usize },
+}
+
+/// 根据行首的内容判断块类型；complete 为 true 时表示已经是整行
+fn classify(line: &str, complete: bool) -> LineKind {
+    let trimmed = line.trim_start_matches(' ');
+    let indent = line.len() - trimmed.len();
+    let paragraph = LineKind::Inline {
+        prefix: String::new(),
+        base: "",
+        rest: 0,
+    };
+    let undecided = if complete {
+        LineKind::Inline {
+            prefix: String::new(),
+            base: "",
+            rest: 0,
+        }
+    } else {
+        LineKind::Undecided
+    };
+
+    let Some(first) = trimmed.chars().next() else {
+        return undecided;
+    };
+    match first {
+        '#' => {
+            let hashes = trimmed.chars().take_while(|&c| c == '#').count();
+            match trimmed[hashes..].chars().next() {
+                Some(' ') if hashes <= 6 => LineKind::Block,
+                None => undecided,
+                _ => paragraph,
+            }
+        }
+        '|' => LineKind::Block,
+        '`' => {
+            let ticks = trimmed.chars().take_while(|&c| c == '`').count();
+            if ticks >= 3 {
+                LineKind::Block
+            } else if ticks == trimmed.len() {
+                undecided
+            } else {
+                paragraph
+            }
+        }
+        '>' => match trimmed[1..].chars().next() {
+            None => undecided,
+            next => LineKind::Inline {
+                prefix: format!("{}{}▎{} ", " ".repeat(indent), DIM, RESET),
+                base: DIM,
+                rest: indent + 1 + usize::from(next == Some(' ')),
+            },
+        },
+        '-' | '*' | '+' => match trimmed[1..].chars().next() {
+            None => undecided,
+            Some(' ') => LineKind::Inline {
+                prefix: format!("{}{}•{} ", " ".repeat(indent), CYAN, RESET),
+                base: "",
+                rest: indent + 2,
+            },
+            // `---` 分隔线或 `**粗体**`，需要整行判断
+            Some(c) if c == first && first != '+' => LineKind::Block,
+            _ => paragraph,
+        },
+        '0'..='9' => {
+            let digits = trimmed.chars().take_while(char::is_ascii_digit).count();
+            let mut rest = trimmed[digits..].chars();
+            match (rest.next(), rest.next()) {
+                (Some('.' | ')'), Some(' ')) => LineKind::Inline {
+                    prefix: format!("{}{}{}{} ", " ".repeat(indent), CYAN, &trimmed[..digits + 1], RESET),
+                    base: "",
+                    rest: indent + digits + 2,
+                },
+                (None, _) | (Some('.' | ')'), None) => undecided,
+                _ => paragraph,
+            }
+        }
+        _ => paragraph,
+    }
+}
+
+/// 分隔线：三个以上相同的 `-` / `*` / `_`，中间可以有空格
+fn is_rule(line: &str) -> bool {
+    let marks: Vec<char> = line.chars().filter(|c| !c.is_whitespace()).collect();
+    marks.len() >= 3 && matches!(marks[0], '-' | '*' | '_') && marks.iter().all(|&c| c == marks[0])
+}
+
+/// 拆分表格行的单元格
+fn split_row(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = line.strip_suffix('|').unwrap_or(line);
+    line.split('|').map(|cell| cell.trim().to_string()).collect()
+}
+
+/// 表头与表体之间的分隔行，如 `|---|:---:|`
+fn is_separator_row(cells: &[String]) -> bool {
+    cells
+        .iter()
+        .all(|cell| cell.contains('-') && cell.chars().all(|c| matches!(c, '-' | ':' | ' ')))
+}
+
+/// 代码块（语法高亮状态跨行保留）
+struct CodeBlock {
+    highlighter: HighlightLines<'static>,
+}
+
+impl CodeBlock {
+    fn new(lang: &str) -> Self {
+        let syntaxes = syntax_set();
+        let syntax = syntaxes
+            .find_syntax_by_token(lang)
+            .unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+        Self {
+            highlighter: HighlightLines::new(syntax, code_theme()),
+        }
+    }
+
+    fn highlight(&mut self, line: &str) -> String {
+        let text = format!("{}\n", line);
+        match self.highlighter.highlight_line(&text, syntax_set()) {
+            Ok(ranges) => {
+                let escaped = as_24_bit_terminal_escaped(&ranges, false);
+                format!("{}{}", escaped.trim_end_matches('\n'), RESET)
+            }
+            Err(_) => line.to_string(),
+        }
+    }
+}
+
+/// 当前行的状态
+enum LineState {
+    /// 行首，文本暂存在 `line` 中，直到能判断块类型
+    Pending,
+    /// 已输出前缀，余下内容流式输出
+    Streaming(Inline),
+}
+
+/// 流式 Markdown 渲染器：`push` 接收文本片段并返回可以立即输出的终端文本
+pub struct MarkdownStream {
+    /// false 时原样输出
+    enabled: bool,
+    /// 终端宽度（分隔线用）
+    width: usize,
+    line: String,
+    state: LineState,
+    code: Option<CodeBlock>,
+    /// 尚未输出的表格行
+    table: Vec<String>,
+    /// 正在输出思考内容
+    reasoning: bool,
+}
+
+impl MarkdownStream {
+    /// 渲染为终端样式
+    pub fn new(width: usize) -> Self {
+        Self {
+            enabled: true,
+            width,
+            line: String::new(),
+            state: LineState::Pending,
+            code: None,
+            table: Vec::new(),
+            reasoning: false,
+        }
+    }
+
+    /// 原样输出（不是终端时使用）
+    pub fn plain() -> Self {
+        Self {
+            enabled: false,
+            ..Self::new(0)
+        }
+    }
+
+    /// 根据 stdout 选择：终端且未设置 NO_COLOR 时渲染，否则原样输出
+    pub fn for_stdout() -> Self {
+        if !std::io::stdout().is_terminal() || std::env::var_os("NO_COLOR").is_some() {
+            return Self::plain();
+        }
+        let width = ratatui::crossterm::terminal::size().map_or(80, |(columns, _)| columns as usize);
+        Self::new(width)
+    }
+
+    /// 接收一段思考内容，返回暗色的终端文本
+    pub fn push_reasoning(&mut self, text: &str) -> String {
+        let mut out = String::new();
+        if !self.reasoning {
+            // 先输出暂存的回复内容，思考另起一行
+            out.push_str(&self.finish());
+            out.push('\n');
+            out.push_str(if self.enabled { DIM } else { "" });
+            out.push_str("💭 ");
+            self.reasoning = true;
+        }
+        out.push_str(text);
+        out
+    }
+
+    /// 思考结束：清除样式并空一行
+    fn end_reasoning(&mut self, out: &mut String) {
+        if self.reasoning {
+            self.reasoning = false;
+            if self.enabled {
+                out.push_str(RESET);
+            }
+            out.push_str("\n\n");
+        }
+    }
+
+    /// 接收一段文本，返回可以立即输出的内容
+    pub fn push(&mut self, text: &str) -> String {
+        let mut out = String::new();
+        self.end_reasoning(&mut out);
+        if !self.enabled {
+            out.push_str(text);
+            return out;
+        }
+        for c in text.chars() {
+            if c == '\n' {
+                self.end_line(&mut out);
+                continue;
+            }
+            match &mut self.state {
+                LineState::Streaming(inline) => inline.push(c, &mut out),
+                LineState::Pending => {
+                    self.line.push(c);
+                    self.try_stream(&mut out);
+                }
+            }
+        }
+        out
+    }
+
+    /// 回复结束（或被工具调用打断）：输出暂存的内容并清除样式
+    pub fn finish(&mut self) -> String {
+        let mut out = String::new();
+        self.end_reasoning(&mut out);
+        if !self.enabled {
+            out.pop();
+            return out;
+        }
+        match std::mem::replace(&mut self.state, LineState::Pending) {
+            LineState::Streaming(mut inline) => inline.finish(&mut out),
+            LineState::Pending => {
+                let line = std::mem::take(&mut self.line);
+                if !line.is_empty() {
+                    self.render_line(&line, &mut out);
+                }
+            }
+        }
+        self.flush_table(&mut out);
+        if self.code.take().is_some() {
+            out.push_str(&format!("{}└─{}\n", DIM, RESET));
+        }
+        if out.ends_with('\n') {
+            out.pop();
+        }
+        out
+    }
+
+    /// 行首的内容足够判断块类型时，开始流式输出
+    fn try_stream(&mut self, out: &mut String) {
+        if self.code.is_some() {
+            return;
+        }
+        if let LineKind::Inline { prefix, base, rest } = classify(&self.line, false) {
+            self.flush_table(out);
+            let line = std::mem::take(&mut self.line);
+            out.push_str(&prefix);
+            out.push_str(base);
+            let mut inline = Inline::new(base);
+            for c in line[rest..].chars() {
+                inline.push(c, out);
+            }
+            self.state = LineState::Streaming(inline);
+        }
+    }
+
+    fn end_line(&mut self, out: &mut String) {
+        match std::mem::replace(&mut self.state, LineState::Pending) {
+            LineState::Streaming(mut inline) => {
+                inline.finish(out);
+                out.push('\n');
+            }
+            LineState::Pending => {
+                let line = std::mem::take(&mut self.line);
+                self.render_line(&line, out);
+            }
+        }
+    }
+
+    /// 渲染一整行
+    fn render_line(&mut self, line: &str, out: &mut String) {
+        let trimmed = line.trim_start();
+
+        if let Some(code) = &mut self.code {
+            if trimmed.starts_with("```") {
+                self.code = None;
+                out.push_str(&format!("{}└─{}\n", DIM, RESET));
+            } else {
+                out.push_str(&format!("{}│{} {}\n", DIM, RESET, code.highlight(line)));
+            }
+            return;
+        }
+
+        if trimmed.starts_with('|') {
+            self.table.push(line.to_string());
+            return;
+        }
+        self.flush_table(out);
+
+        if let Some(fence) = trimmed.strip_prefix("```") {
+            let lang = fence.trim_start_matches('`').trim();
+            self.code = Some(CodeBlock::new(lang));
+            out.push_str(&format!("{}┌─ {}{}\n", DIM, lang, RESET));
+            return;
+        }
+        if is_rule(trimmed) {
+            out.push_str(&format!("{}{}{}\n", DIM, "─".repeat(self.width.clamp(3, MAX_RULE_WIDTH)), RESET));
+            return;
+        }
+        if trimmed.starts_with('#') {
+            let hashes = trimmed.chars().take_while(|&c| c == '#').count();
+            if let Some(title) = trimmed[hashes..].strip_prefix(' ') {
+                let base = match hashes {
+                    1 => format!("{}{}{}", BOLD, UNDERLINE, MAGENTA),
+                    2 => format!("{}{}", BOLD, MAGENTA),
+                    _ => BOLD.to_string(),
+                };
+                out.push_str(&Inline::render(title.trim(), &base).0);
+                out.push('\n');
+                return;
+            }
+        }
+
+        let (prefix, base, rest) = match classify(line, true) {
+            LineKind::Inline { prefix, base, rest } => (prefix, base, rest),
+            LineKind::Block | LineKind::Undecided => (String::new(), "", 0),
+        };
+        out.push_str(&prefix);
+        out.push_str(&Inline::render(&line[rest..], base).0);
+        out.push('\n');
+    }
+
+    /// 输出暂存的表格：按列宽对齐，第二行是分隔行时第一行作为表头
+    fn flush_table(&mut self, out: &mut String) {
+        if self.table.is_empty() {
+            return;
+        }
+        let rows: Vec<Vec<String>> = self.table.drain(..).map(|line| split_row(&line)).collect();
+        let has_header = rows.len() > 1 && is_separator_row(&rows[1]);
+        let rows: Vec<Vec<(String, usize)>> = rows
+            .iter()
+            .enumerate()
+            .filter(|(i, row)| !(row.is_empty() || *i == 1 && has_header))
+            .map(|(i, row)| {
+                let base = if i == 0 && has_header { BOLD } else { "" };
+                row.iter().map(|cell| Inline::render(cell, base)).collect()
+            })
+            .collect();
+
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        let widths: Vec<usize> = (0..columns)
+            .map(|c| rows.iter().filter_map(|row| row.get(c)).map(|cell| cell.1).max().unwrap_or(0))
+            .collect();
+        let border = |left: &str, middle: &str, right: &str| {
+            let segments: Vec<String> = widths.iter().map(|w| "─".repeat(w + 2)).collect();
+            format!("{}{}{}{}{}\n", DIM, left, segments.join(middle), right, RESET)
+        };
+
+        out.push_str(&border("┌", "┬", "┐"));
+        for (i, row) in rows.iter().enumerate() {
+            let mut line = String::new();
+            for (c, width) in widths.iter().enumerate() {
+                let (text, cell_width) = row.get(c).map_or(("", 0), |(text, w)| (text.as_str(), *w));
+                line.push_str(&format!("{}│{} {}{} ", DIM, RESET, text, " ".repeat(width - cell_width)));
+            }
+            out.push_str(&format!("{}{}│{}\n", line, DIM, RESET));
+            if i == 0 && has_header && rows.len() > 1 {
+                out.push_str(&border("├", "┼", "┤"));
+            }
+        }
+        out.push_str(&border("└", "┴", "┘"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 去掉 ANSI 转义序列
+    fn strip_ansi(text: &str) -> String {
+        let mut out = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            } else {
+                out.push(c);
+            }
+        }
+        out
+    }
+
+    const SAMPLE: &str = "# 标题\n\n这是 **粗体**、*斜体*、`code` 和 ~~删除~~，2 * 3 = 6。\n\n- 第一项\n  1. 子项\n> 引用\n\n| 名称 | 值 |\n|---|---:|\n| 超时 | 30s |\n\n```rust\nfn main() {}\n```\n---\n结尾没有换行";
+
+    #[test]
+    fn test_incremental_matches_whole_input() {
+        let mut whole = MarkdownStream::new(80);
+        let expected = whole.push(SAMPLE) + &whole.finish();
+
+        let mut streamed = MarkdownStream::new(80);
+        let mut actual = String::new();
+        for c in SAMPLE.chars() {
+            actual.push_str(&streamed.push(&c.to_string()));
+        }
+        actual.push_str(&streamed.finish());
+        assert_eq!(actual, expected);
+
+        let mut plain = MarkdownStream::plain();
+        assert_eq!(plain.push(SAMPLE) + &plain.finish(), SAMPLE);
+    }
+
+    #[test]
+    fn test_render_blocks_and_inline_styles() {
+        let mut markdown = MarkdownStream::new(20);
+        let rendered = markdown.push(SAMPLE) + &markdown.finish();
+        let text = strip_ansi(&rendered);
+
+        assert!(text.starts_with("标题\n"));
+        assert!(text.contains("这是 粗体、斜体、code 和 删除，2 * 3 = 6。"));
+        assert!(rendered.contains(&format!("{}{}粗体", RESET, BOLD)));
+        assert!(text.contains("• 第一项\n  1. 子项\n▎ 引用"));
+        assert!(text.contains("┌──────┬─────┐\n│ 名称 │ 值  │\n├──────┼─────┤\n│ 超时 │ 30s │\n└──────┴─────┘"));
+        assert!(text.contains("┌─ rust\n│ fn main() {}\n└─"));
+        assert!(rendered.contains("\x1b[38;2;"), "代码块应有 24 位色高亮");
+        assert!(text.contains(&"─".repeat(20)));
+        assert!(text.ends_with("结尾没有换行"));
+    }
+
+    #[test]
+    fn test_paragraph_streams_before_line_ends() {
+        let mut markdown = MarkdownStream::new(80);
+        assert_eq!(strip_ansi(&markdown.push("你好，wor")), "你好，wor");
+        // 标记要等下一个字符才能确定
+        assert_eq!(strip_ansi(&markdown.push("ld **")), "ld ");
+        assert_eq!(strip_ansi(&markdown.push("重点")), "重点");
+        // 标题要等整行
+        assert_eq!(markdown.push("**\n## 小"), format!("{}\n", RESET));
+        assert_eq!(strip_ansi(&markdown.push("节\n")), "小节\n");
+    }
+
+    #[test]
+    fn test_reasoning_dimmed_and_separated_from_answer() {
+        let mut markdown = MarkdownStream::new(80);
+        let mut out = markdown.push_reasoning("先想");
+        out.push_str(&markdown.push_reasoning("一想"));
+        out.push_str(&markdown.push("**答案**"));
+        out.push_str(&markdown.finish());
+        assert!(out.starts_with(&format!("\n{}💭 先想一想{}\n\n", DIM, RESET)));
+        assert_eq!(strip_ansi(&out), "\n💭 先想一想\n\n答案");
+
+        let mut plain = MarkdownStream::plain();
+        let out = plain.push("好的") + &plain.push_reasoning("再查一下") + &plain.finish();
+        assert_eq!(out, "好的\n💭 再查一下\n");
+    }
+}
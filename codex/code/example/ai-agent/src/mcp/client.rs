@@ -0,0 +1,404 @@
+// MCP 客户端 - JSON-RPC 2.0 over stdio / streamable HTTP（类似 Codex 的 RmcpClient）
+
+use crate::config::McpServerConfig;
+use crate::exec::DEFAULT_ENV_ALLOWLIST;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::oneshot;
+
+/// 客户端支持的 MCP 协议版本
+pub const PROTOCOL_VERSION: &str = "2025-03-26";
+
+/// 默认启动超时
+pub const DEFAULT_STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 默认工具调用超时
+pub const DEFAULT_TOOL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 服务器声明的工具（tools/list 的单个条目）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct McpToolInfo {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(rename = "inputSchema", default = "empty_schema")]
+    pub input_schema: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<McpToolAnnotations>,
+}
+
+/// 服务器声明的工具提示（仅作参考，不能替代审批）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct McpToolAnnotations {
+    #[serde(rename = "readOnlyHint", default, skip_serializing_if = "Option::is_none")]
+    pub read_only_hint: Option<bool>,
+}
+
+fn empty_schema() -> Value {
+    json!({ "type": "object", "properties": {} })
+}
+
+type PendingMap = Arc<Mutex<HashMap<u64, oneshot::Sender<Result<Value, String>>>>>;
+
+/// stdio 传输：每行一个 JSON-RPC 消息
+struct StdioTransport {
+    stdin: Arc<tokio::sync::Mutex<ChildStdin>>,
+    pending: PendingMap,
+    alive: Arc<AtomicBool>,
+    // 客户端被丢弃时终止子进程
+    _child: Child,
+}
+
+/// streamable HTTP 传输：每个请求一次 POST，响应可以是 JSON 或 SSE
+struct HttpTransport {
+    http: reqwest::Client,
+    url: String,
+    headers: reqwest::header::HeaderMap,
+    session_id: Mutex<Option<String>>,
+}
+
+enum Transport {
+    Stdio(StdioTransport),
+    Http(HttpTransport),
+}
+
+/// 与单个 MCP 服务器的连接
+pub struct McpClient {
+    server_name: String,
+    transport: Transport,
+    next_id: AtomicU64,
+}
+
+impl McpClient {
+    /// 启动（或连接）服务器并完成 initialize 握手
+    pub async fn connect(server_name: &str, config: &McpServerConfig) -> Result<Self, anyhow::Error> {
+        config.validate(server_name)?;
+        let transport = match (&config.command, &config.url) {
+            (Some(command), _) => Transport::Stdio(spawn_stdio(server_name, command, config)?),
+            (None, Some(url)) => Transport::Http(HttpTransport::new(url, config)?),
+            (None, None) => unreachable!("validate 已检查"),
+        };
+
+        let client = Self {
+            server_name: server_name.to_string(),
+            transport,
+            next_id: AtomicU64::new(1),
+        };
+
+        let timeout = config
+            .startup_timeout_sec
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_STARTUP_TIMEOUT);
+        let params = json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": {
+                "name": "simple-ai-agent",
+                "version": env!("CARGO_PKG_VERSION")
+            }
+        });
+        client
+            .request("initialize", params, timeout)
+            .await
+            .map_err(|e| anyhow::anyhow!("MCP 服务器 '{}' 初始化失败: {}", server_name, e))?;
+        client.notify("notifications/initialized", json!({})).await?;
+
+        Ok(client)
+    }
+
+    pub fn server_name(&self) -> &str {
+        &self.server_name
+    }
+
+    /// 连接是否仍然可用（stdio 子进程退出后返回 false）
+    pub fn is_alive(&self) -> bool {
+        match &self.transport {
+            Transport::Stdio(stdio) => stdio.alive.load(Ordering::SeqCst),
+            Transport::Http(_) => true,
+        }
+    }
+
+    /// 列出服务器的全部工具（自动处理分页）
+    pub async fn list_tools(&self, timeout: Duration) -> Result<Vec<McpToolInfo>, anyhow::Error> {
+        let mut tools = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let result = self.request("tools/list", params, timeout).await?;
+            let page: Vec<McpToolInfo> = serde_json::from_value(result["tools"].clone())
+                .map_err(|e| anyhow::anyhow!("tools/list 响应无效: {}", e))?;
+            tools.extend(page);
+
+            match result["nextCursor"].as_str() {
+                Some(next) if !next.is_empty() => cursor = Some(next.to_string()),
+                _ => return Ok(tools),
+            }
+        }
+    }
+
+    /// 调用工具，返回原始的 CallToolResult
+    pub async fn call_tool(&self, name: &str, arguments: Value, timeout: Duration) -> Result<Value, anyhow::Error> {
+        self.request("tools/call", json!({ "name": name, "arguments": arguments }), timeout)
+            .await
+    }
+
+    /// 发送请求并等待响应
+    async fn request(&self, method: &str, params: Value, timeout: Duration) -> Result<Value, anyhow::Error> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+
+        let response = match &self.transport {
+            Transport::Stdio(stdio) => {
+                let (tx, rx) = oneshot::channel();
+                stdio.pending.lock().unwrap().insert(id, tx);
+                if let Err(e) = stdio.send(&message).await {
+                    stdio.pending.lock().unwrap().remove(&id);
+                    return Err(e);
+                }
+                match tokio::time::timeout(timeout, rx).await {
+                    Ok(Ok(response)) => response.map_err(|e| anyhow::anyhow!(e))?,
+                    Ok(Err(_)) => return Err(anyhow::anyhow!("MCP 服务器 '{}' 已断开", self.server_name)),
+                    Err(_) => {
+                        stdio.pending.lock().unwrap().remove(&id);
+                        return Err(anyhow::anyhow!("MCP 请求 {} 超时 ({}s)", method, timeout.as_secs()));
+                    }
+                }
+            }
+            Transport::Http(http) => tokio::time::timeout(timeout, http.send(&message, Some(id)))
+                .await
+                .map_err(|_| anyhow::anyhow!("MCP 请求 {} 超时 ({}s)", method, timeout.as_secs()))??
+                .ok_or_else(|| anyhow::anyhow!("MCP 服务器 '{}' 没有返回响应", self.server_name))?,
+        };
+
+        into_result(response)
+    }
+
+    /// 发送通知（无需响应）
+    async fn notify(&self, method: &str, params: Value) -> Result<(), anyhow::Error> {
+        let message = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+        match &self.transport {
+            Transport::Stdio(stdio) => stdio.send(&message).await,
+            Transport::Http(http) => http.send(&message, None).await.map(|_| ()),
+        }
+    }
+}
+
+/// 把 JSON-RPC 响应转换为 result 或错误
+fn into_result(response: Value) -> Result<Value, anyhow::Error> {
+    if let Some(error) = response.get("error") {
+        return Err(anyhow::anyhow!(
+            "MCP 错误 {}: {}",
+            error["code"],
+            error["message"].as_str().unwrap_or("unknown error")
+        ));
+    }
+    Ok(response.get("result").cloned().unwrap_or(Value::Null))
+}
+
+/// 启动 stdio 服务器，后台任务读取 stdout 并按 id 分发响应
+fn spawn_stdio(server_name: &str, command: &str, config: &McpServerConfig) -> Result<StdioTransport, anyhow::Error> {
+    let mut cmd = Command::new(command);
+    cmd.args(&config.args)
+        .env_clear()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    for key in DEFAULT_ENV_ALLOWLIST {
+        if let Ok(value) = std::env::var(key) {
+            cmd.env(key, value);
+        }
+    }
+    cmd.envs(&config.env);
+    if let Some(cwd) = &config.cwd {
+        cmd.current_dir(cwd);
+    }
+
+    let mut child = cmd
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("启动 MCP 服务器 '{}' 失败 ({}): {}", server_name, command, e))?;
+    let stdin = Arc::new(tokio::sync::Mutex::new(child.stdin.take().expect("stdin 已设置为 piped")));
+    let stdout = child.stdout.take().expect("stdout 已设置为 piped");
+    let stderr = child.stderr.take().expect("stderr 已设置为 piped");
+
+    let pending: PendingMap = Arc::new(Mutex::new(HashMap::new()));
+    let alive = Arc::new(AtomicBool::new(true));
+
+    // 服务器的 stderr 只写入日志，避免干扰终端输出
+    let name = server_name.to_string();
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            tracing::debug!("[mcp:{}] {}", name, line);
+        }
+    });
+
+    let reader_pending = pending.clone();
+    let reader_alive = alive.clone();
+    let reader_stdin = stdin.clone();
+    let name = server_name.to_string();
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stdout).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let Ok(message) = serde_json::from_str::<Value>(&line) else {
+                tracing::debug!("[mcp:{}] 忽略非 JSON 输出: {}", name, line);
+                continue;
+            };
+            handle_incoming(message, &reader_pending, &reader_stdin).await;
+        }
+
+        // 子进程退出：标记连接失效，唤醒所有等待中的请求
+        reader_alive.store(false, Ordering::SeqCst);
+        for (_, tx) in reader_pending.lock().unwrap().drain() {
+            let _ = tx.send(Err(format!("MCP 服务器 '{}' 已退出", name)));
+        }
+    });
+
+    Ok(StdioTransport {
+        stdin,
+        pending,
+        alive,
+        _child: child,
+    })
+}
+
+/// 处理服务器发来的消息：响应交给等待者，ping 请求直接回复
+async fn handle_incoming(message: Value, pending: &PendingMap, stdin: &tokio::sync::Mutex<ChildStdin>) {
+    let id = message.get("id").cloned();
+    match (message.get("method").and_then(|m| m.as_str()), id) {
+        // 服务器发起的请求：只支持 ping，其余返回 method not found
+        (Some(method), Some(id)) => {
+            let reply = if method == "ping" {
+                json!({ "jsonrpc": "2.0", "id": id, "result": {} })
+            } else {
+                json!({ "jsonrpc": "2.0", "id": id, "error": { "code": -32601, "message": "method not found" } })
+            };
+            let mut line = reply.to_string();
+            line.push('\n');
+            let _ = stdin.lock().await.write_all(line.as_bytes()).await;
+        }
+        // 通知：忽略
+        (Some(_), None) => {}
+        // 响应
+        (None, Some(id)) => {
+            if let Some(tx) = id.as_u64().and_then(|id| pending.lock().unwrap().remove(&id)) {
+                let _ = tx.send(Ok(message));
+            }
+        }
+        (None, None) => {}
+    }
+}
+
+impl StdioTransport {
+    async fn send(&self, message: &Value) -> Result<(), anyhow::Error> {
+        if !self.alive.load(Ordering::SeqCst) {
+            return Err(anyhow::anyhow!("MCP 服务器已退出"));
+        }
+        let mut line = message.to_string();
+        line.push('\n');
+        let mut stdin = self.stdin.lock().await;
+        stdin.write_all(line.as_bytes()).await?;
+        stdin.flush().await?;
+        Ok(())
+    }
+}
+
+impl HttpTransport {
+    fn new(url: &str, config: &McpServerConfig) -> Result<Self, anyhow::Error> {
+        use reqwest::header::{HeaderName, HeaderValue, AUTHORIZATION};
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        for (key, value) in &config.headers {
+            headers.insert(HeaderName::from_bytes(key.as_bytes())?, HeaderValue::from_str(value)?);
+        }
+        if let Some(var) = &config.bearer_token_env_var {
+            let token = std::env::var(var).map_err(|_| anyhow::anyhow!("环境变量 {} 未设置", var))?;
+            headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", token))?);
+        }
+
+        Ok(Self {
+            http: reqwest::Client::new(),
+            url: url.to_string(),
+            headers,
+            session_id: Mutex::new(None),
+        })
+    }
+
+    /// POST 一条消息；expect_id 为 Some 时返回对应的响应
+    async fn send(&self, message: &Value, expect_id: Option<u64>) -> Result<Option<Value>, anyhow::Error> {
+        let mut request = self
+            .http
+            .post(&self.url)
+            .headers(self.headers.clone())
+            .header("Accept", "application/json, text/event-stream")
+            .json(message);
+        if let Some(session_id) = self.session_id.lock().unwrap().clone() {
+            request = request.header("Mcp-Session-Id", session_id);
+        }
+
+        let response = request.send().await?;
+        if let Some(session_id) = response.headers().get("mcp-session-id").and_then(|v| v.to_str().ok()) {
+            *self.session_id.lock().unwrap() = Some(session_id.to_string());
+        }
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow::anyhow!("MCP HTTP 错误 {}: {}", status, body));
+        }
+        let Some(id) = expect_id else {
+            return Ok(None);
+        };
+
+        let is_sse = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/event-stream"));
+
+        if !is_sse {
+            let body: Value = response.json().await?;
+            return Ok(find_response(body, id));
+        }
+
+        // SSE：逐个事件查找匹配 id 的响应
+        let mut stream = response.bytes_stream();
+        let mut buffer = String::new();
+        while let Some(chunk) = stream.next().await {
+            buffer.push_str(&String::from_utf8_lossy(&chunk?));
+            while let Some(end) = buffer.find("\n\n") {
+                let event: String = buffer.drain(..end + 2).collect();
+                let data: Vec<&str> = event
+                    .lines()
+                    .filter_map(|line| line.strip_prefix("data:"))
+                    .map(|data| data.trim_start())
+                    .collect();
+                if let Ok(body) = serde_json::from_str::<Value>(&data.join("\n")) {
+                    if let Some(response) = find_response(body, id) {
+                        return Ok(Some(response));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// 在单条消息或批量消息中查找指定 id 的响应
+fn find_response(body: Value, id: u64) -> Option<Value> {
+    match body {
+        Value::Array(items) => items.into_iter().find(|item| item["id"].as_u64() == Some(id)),
+        item if item["id"].as_u64() == Some(id) => Some(item),
+        _ => None,
+    }
+}
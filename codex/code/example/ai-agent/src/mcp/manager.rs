@@ -0,0 +1,382 @@
+// MCP 连接管理器 - 启动配置中的服务器、聚合工具列表、崩溃后自动重启
+
+use super::client::{McpClient, McpToolInfo, DEFAULT_STARTUP_TIMEOUT, DEFAULT_TOOL_TIMEOUT};
+use crate::config::McpServerConfig;
+use crate::tools::{ToolEffect, ToolExecutor};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 服务器名与工具名之间的分隔符
+pub const MCP_TOOL_NAME_DELIMITER: &str = "__";
+
+/// OpenAI 兼容接口对工具名的长度限制
+pub const MAX_TOOL_NAME_LENGTH: usize = 64;
+
+/// 单个服务器最多连续自动重启的次数（工具调用成功后重新计数）
+pub const MAX_RESTARTS: u32 = 3;
+
+/// 单个 MCP 服务器（持有当前连接，断开后按需重连）
+pub struct McpServer {
+    name: String,
+    config: McpServerConfig,
+    client: tokio::sync::Mutex<Option<Arc<McpClient>>>,
+    restarts: AtomicU32,
+}
+
+impl McpServer {
+    fn new(name: &str, config: McpServerConfig) -> Self {
+        Self {
+            name: name.to_string(),
+            config,
+            client: tokio::sync::Mutex::new(None),
+            restarts: AtomicU32::new(0),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn tool_timeout(&self) -> Duration {
+        self.config
+            .tool_timeout_sec
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_TOOL_TIMEOUT)
+    }
+
+    fn startup_timeout(&self) -> Duration {
+        self.config
+            .startup_timeout_sec
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_STARTUP_TIMEOUT)
+    }
+
+    /// 返回可用的连接；上一个连接已断开时重新启动服务器
+    async fn client(&self) -> Result<Arc<McpClient>, anyhow::Error> {
+        let mut guard = self.client.lock().await;
+        if let Some(client) = guard.as_ref() {
+            if client.is_alive() {
+                return Ok(client.clone());
+            }
+            let restarts = self.restarts.fetch_add(1, Ordering::SeqCst) + 1;
+            if restarts > MAX_RESTARTS {
+                return Err(anyhow::anyhow!(
+                    "MCP 服务器 '{}' 已崩溃 {} 次，不再自动重启",
+                    self.name,
+                    MAX_RESTARTS
+                ));
+            }
+            println!("🔄 MCP 服务器 '{}' 已退出，正在重启 ({}/{})", self.name, restarts, MAX_RESTARTS);
+        }
+
+        let client = Arc::new(McpClient::connect(&self.name, &self.config).await?);
+        *guard = Some(client.clone());
+        Ok(client)
+    }
+
+    /// 调用工具；服务器在调用过程中崩溃时返回错误，下一次调用会自动重启
+    ///
+    /// 不自动重试：工具调用不一定是幂等的。调用成功说明服务器已恢复，重启次数清零。
+    pub async fn call_tool(&self, tool: &str, arguments: Value) -> Result<Value, anyhow::Error> {
+        let client = self.client().await?;
+        let result = client.call_tool(tool, arguments, self.tool_timeout()).await?;
+        self.restarts.store(0, Ordering::SeqCst);
+        Ok(result)
+    }
+
+    async fn list_tools(&self) -> Result<Vec<McpToolInfo>, anyhow::Error> {
+        self.client().await?.list_tools(self.startup_timeout()).await
+    }
+}
+
+/// MCP 连接管理器（类似 Codex 的 McpConnectionManager）
+#[derive(Default)]
+pub struct McpConnectionManager {
+    servers: BTreeMap<String, Arc<McpServer>>,
+}
+
+impl McpConnectionManager {
+    /// 并发启动所有启用的服务器，启动失败的服务器只打印警告
+    pub async fn start(servers: &BTreeMap<String, McpServerConfig>) -> Self {
+        let candidates: Vec<Arc<McpServer>> = servers
+            .iter()
+            .filter(|(_, config)| config.is_enabled())
+            .map(|(name, config)| Arc::new(McpServer::new(name, config.clone())))
+            .collect();
+
+        let results = futures::future::join_all(candidates.iter().map(|server| server.client())).await;
+
+        let mut manager = Self::default();
+        for (server, result) in candidates.into_iter().zip(results) {
+            match result {
+                Ok(_) => {
+                    println!("  🔌 已连接 MCP 服务器: {}", server.name);
+                    manager.servers.insert(server.name.clone(), server);
+                }
+                Err(e) => eprintln!("⚠️  MCP 服务器 '{}' 启动失败: {}", server.name, e),
+            }
+        }
+        manager
+    }
+
+    pub fn server_names(&self) -> Vec<String> {
+        self.servers.keys().cloned().collect()
+    }
+
+    pub fn server(&self, name: &str) -> Option<Arc<McpServer>> {
+        self.servers.get(name).cloned()
+    }
+
+    /// 列出所有服务器的工具，转换为可注册到 ToolRegistry 的适配器
+    pub async fn tools(&self) -> Vec<McpTool> {
+        let mut tools = Vec::new();
+        let mut seen = HashSet::new();
+
+        for server in self.servers.values() {
+            let infos = match server.list_tools().await {
+                Ok(infos) => infos,
+                Err(e) => {
+                    eprintln!("⚠️  获取 MCP 服务器 '{}' 的工具列表失败: {}", server.name, e);
+                    continue;
+                }
+            };
+            for info in infos {
+                let qualified_name = qualify_tool_name(&server.name, &info.name);
+                if !seen.insert(qualified_name.clone()) {
+                    eprintln!("⚠️  跳过重名的 MCP 工具: {}", qualified_name);
+                    continue;
+                }
+                tools.push(McpTool {
+                    qualified_name,
+                    description: info.description.clone().unwrap_or_default(),
+                    info,
+                    server: server.clone(),
+                });
+            }
+        }
+        tools
+    }
+}
+
+/// 生成 `server__tool` 形式的限定名（只保留 [A-Za-z0-9_-]，超长时截断并附加哈希）
+pub fn qualify_tool_name(server: &str, tool: &str) -> String {
+    let raw = format!("{}{}{}", server, MCP_TOOL_NAME_DELIMITER, tool);
+    let mut name: String = raw
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+        .collect();
+
+    if name.len() > MAX_TOOL_NAME_LENGTH {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        raw.hash(&mut hasher);
+        let hash = format!("{:016x}", hasher.finish());
+        name.truncate(MAX_TOOL_NAME_LENGTH - hash.len());
+        name.push_str(&hash);
+    }
+    name
+}
+
+/// 把 CallToolResult 转换为交给模型的文本
+pub fn format_call_result(result: &Value) -> String {
+    let mut parts = Vec::new();
+    for item in result["content"].as_array().into_iter().flatten() {
+        match item["type"].as_str() {
+            Some("text") => parts.push(item["text"].as_str().unwrap_or_default().to_string()),
+            Some("image") | Some("audio") => parts.push(format!(
+                "[{}: {}]",
+                item["type"].as_str().unwrap_or_default(),
+                item["mimeType"].as_str().unwrap_or("unknown")
+            )),
+            Some("resource") => match item["resource"]["text"].as_str() {
+                Some(text) => parts.push(text.to_string()),
+                None => parts.push(format!("[resource: {}]", item["resource"]["uri"].as_str().unwrap_or_default())),
+            },
+            _ => parts.push(item.to_string()),
+        }
+    }
+
+    if parts.is_empty() {
+        if let Some(structured) = result.get("structuredContent") {
+            return structured.to_string();
+        }
+    }
+    parts.join("\n")
+}
+
+/// MCP 工具适配器：把 tools/call 包装成 ToolExecutor
+pub struct McpTool {
+    qualified_name: String,
+    description: String,
+    info: McpToolInfo,
+    server: Arc<McpServer>,
+}
+
+impl McpTool {
+    pub fn server_name(&self) -> &str {
+        &self.server.name
+    }
+
+    /// 服务器上的原始工具名
+    pub fn tool_name(&self) -> &str {
+        &self.info.name
+    }
+}
+
+#[async_trait]
+impl ToolExecutor for McpTool {
+    fn name(&self) -> &str {
+        &self.qualified_name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn parameters(&self) -> Value {
+        self.info.input_schema.clone()
+    }
+
+    /// 服务器的 readOnlyHint 不可信，只有用户在配置中确认的工具才按只读处理
+    fn effect(&self) -> ToolEffect {
+        if self.server.config.is_read_only_tool(&self.info.name) {
+            ToolEffect::ReadOnly
+        } else {
+            ToolEffect::Mutating
+        }
+    }
+
+    fn approval_hint(&self) -> Option<String> {
+        let read_only = self.info.annotations.as_ref().and_then(|a| a.read_only_hint) == Some(true);
+        read_only.then(|| format!("MCP 服务器 '{}' 声明该工具只读（readOnlyHint，未经确认）", self.server.name))
+    }
+
+    async fn execute(&self, arguments: Value) -> Result<String, Box<dyn std::error::Error + Send>> {
+        println!("\n🔌 MCP 工具 {} 接收到参数: {}", self.qualified_name, arguments); // 调试输出
+
+        let result = self.server.call_tool(&self.info.name, arguments).await?;
+        let output = format_call_result(&result);
+        if result["isError"].as_bool().unwrap_or(false) {
+            return Err(anyhow::anyhow!("{}", output).into());
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// 用 sh 实现的最小 MCP 服务器：echo 工具返回 pong，crash 工具直接退出
+    const FAKE_SERVER: &str = r#"
+while IFS= read -r line; do
+  id=$(printf '%s' "$line" | sed -n 's/.*"id":\([0-9][0-9]*\).*/\1/p')
+  case "$line" in
+    *'"method":"initialize"'*)
+      printf '{"jsonrpc":"2.0","id":%s,"result":{"protocolVersion":"2025-03-26","capabilities":{"tools":{}},"serverInfo":{"name":"fake","version":"0"}}}\n' "$id" ;;
+    *'"method":"tools/list"'*)
+      printf '{"jsonrpc":"2.0","id":%s,"result":{"tools":[{"name":"echo","description":"Echo","inputSchema":{"type":"object"}},{"name":"crash","inputSchema":{"type":"object"}}]}}\n' "$id" ;;
+    *'"name":"crash"'*)
+      exit 1 ;;
+    *'"method":"tools/call"'*)
+      printf '{"jsonrpc":"2.0","id":%s,"result":{"content":[{"type":"text","text":"pong"}]}}\n' "$id" ;;
+  esac
+done
+"#;
+
+    #[test]
+    fn test_qualify_tool_name() {
+        assert_eq!(qualify_tool_name("weather", "get.forecast"), "weather__get_forecast");
+
+        let long = qualify_tool_name("server", &"x".repeat(100));
+        assert_eq!(long.len(), MAX_TOOL_NAME_LENGTH);
+        assert_ne!(long, qualify_tool_name("server", &"x".repeat(101)));
+    }
+
+    #[test]
+    fn test_format_call_result() {
+        let result = json!({
+            "content": [
+                { "type": "text", "text": "第一段" },
+                { "type": "image", "data": "...", "mimeType": "image/png" }
+            ]
+        });
+        assert_eq!(format_call_result(&result), "第一段\n[image: image/png]");
+    }
+
+    #[test]
+    fn test_effect_requires_configured_read_only() {
+        let info: McpToolInfo = serde_json::from_value(json!({
+            "name": "search",
+            "annotations": { "readOnlyHint": true }
+        }))
+        .unwrap();
+        let tool = |config: McpServerConfig| McpTool {
+            qualified_name: qualify_tool_name("docs", &info.name),
+            description: String::new(),
+            info: info.clone(),
+            server: Arc::new(McpServer::new("docs", config)),
+        };
+
+        // 服务器自称只读不算数，只显示在审批提示中
+        let untrusted = tool(McpServerConfig::default());
+        assert_eq!(untrusted.effect(), ToolEffect::Mutating);
+        assert!(untrusted.approval_hint().unwrap().contains("readOnlyHint"));
+
+        let listed = tool(McpServerConfig { read_only_tools: vec!["search".to_string()], ..Default::default() });
+        assert_eq!(listed.effect(), ToolEffect::ReadOnly);
+        let whole_server = tool(McpServerConfig { read_only: Some(true), ..Default::default() });
+        assert_eq!(whole_server.effect(), ToolEffect::ReadOnly);
+        let other = tool(McpServerConfig { read_only_tools: vec!["fetch".to_string()], ..Default::default() });
+        assert_eq!(other.effect(), ToolEffect::Mutating);
+    }
+
+    #[tokio::test]
+    async fn test_stdio_server_tools_and_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("server.sh");
+        std::fs::write(&script, FAKE_SERVER).unwrap();
+
+        let mut servers = BTreeMap::new();
+        servers.insert(
+            "fake".to_string(),
+            McpServerConfig {
+                command: Some("sh".to_string()),
+                args: vec![script.display().to_string()],
+                tool_timeout_sec: Some(5),
+                ..Default::default()
+            },
+        );
+        let manager = McpConnectionManager::start(&servers).await;
+        assert_eq!(manager.server_names(), vec!["fake"]);
+
+        let tools = manager.tools().await;
+        let names: Vec<&str> = tools.iter().map(|t| t.name()).collect();
+        assert_eq!(names, vec!["fake__echo", "fake__crash"]);
+
+        let echo = &tools[0];
+        assert_eq!(echo.execute(json!({})).await.unwrap(), "pong");
+
+        // 服务器崩溃：本次调用失败，下一次调用自动重启
+        assert!(tools[1].execute(json!({})).await.is_err());
+        assert_eq!(echo.execute(json!({})).await.unwrap(), "pong");
+
+        // 成功的调用让重启次数清零，偶尔崩溃的服务器不会被永久放弃
+        for _ in 0..MAX_RESTARTS + 1 {
+            assert!(tools[1].execute(json!({})).await.is_err());
+            assert_eq!(echo.execute(json!({})).await.unwrap(), "pong");
+        }
+
+        // 连续崩溃超过上限后不再重启
+        for _ in 0..MAX_RESTARTS + 1 {
+            assert!(tools[1].execute(json!({})).await.is_err());
+        }
+        let error = echo.execute(json!({})).await.unwrap_err().to_string();
+        assert!(error.contains("不再自动重启"), "{}", error);
+    }
+}
@@ -0,0 +1,13 @@
+// MCP 集成 - 连接 Model Context Protocol 服务器，把它们的工具注册到 ToolRegistry
+//
+// - client.rs：单个服务器的 JSON-RPC 连接（stdio / streamable HTTP）
+// - manager.rs：按配置启动服务器、生成 `server__tool` 限定名、工具适配器
+// - server.rs：反向模式，通过 stdio 把本智能体的工具暴露给其他 MCP 客户端
+
+pub mod client;
+pub mod manager;
+pub mod server;
+
+pub use client::{McpClient, McpToolInfo, PROTOCOL_VERSION};
+pub use manager::{qualify_tool_name, McpConnectionManager, McpTool};
+pub use server::{run_stdio_server, MessageProcessor};
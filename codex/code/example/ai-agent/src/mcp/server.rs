@@ -0,0 +1,439 @@
+// MCP 服务器模式 - 通过 stdio 暴露 ToolRegistry 中的工具和一个高层的 agent 工具
+//
+// 对应 Codex 的 mcp-server/message_processor.rs：逐行读取 JSON-RPC 请求，
+// 每个请求在独立任务中处理，响应统一经写入任务输出到（原）stdout。
+// 客户端在 tools/call 的 `_meta.progressToken` 中提供令牌时，工具的增量输出以 notifications/progress 转发。
+
+use super::client::PROTOCOL_VERSION;
+use crate::agent::Agent;
+use crate::protocol::{AgentEvent, ToolCall};
+use crate::tools::{ToolOutputSink, ToolRegistry};
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, Mutex};
+
+/// 高层 agent 工具的名称
+pub const AGENT_TOOL_NAME: &str = "agent";
+
+/// 支持协商的协议版本
+const SUPPORTED_PROTOCOL_VERSIONS: &[&str] = &["2024-11-05", PROTOCOL_VERSION, "2025-06-18"];
+
+/// JSON-RPC 错误码
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// 处理 MCP 请求（类似 Codex 的 MessageProcessor）
+///
+/// 直接调用的工具只在审查时短暂持有 Agent 的锁，执行时使用克隆的注册表，彼此可以并发；
+/// agent 工具要修改对话历史，整个运行期间持有锁，因此依次执行。
+pub struct MessageProcessor {
+    agent: Mutex<Agent>,
+    /// 启动时生成的工具列表（注册表此后不再变化）
+    tools: Vec<Value>,
+    next_call_id: AtomicU64,
+    /// 发给客户端的通知（与响应经同一个写入任务输出）
+    notifications: mpsc::UnboundedSender<Value>,
+}
+
+impl MessageProcessor {
+    pub fn new(agent: Agent, notifications: mpsc::UnboundedSender<Value>) -> Self {
+        let mut tools: Vec<Value> = agent
+            .tool_registry()
+            .list_definitions()
+            .into_iter()
+            .map(|tool| {
+                json!({
+                    "name": tool.name,
+                    "description": tool.description,
+                    "inputSchema": tool.parameters
+                })
+            })
+            .collect();
+        tools.push(json!({
+            "name": AGENT_TOOL_NAME,
+            "description": "Run a prompt through the full simple-ai-agent loop (model plus all of the tools above) and return the final answer.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "prompt": {
+                        "type": "string",
+                        "description": "Task or question for the agent"
+                    },
+                    "new_conversation": {
+                        "type": "boolean",
+                        "description": "Start from an empty conversation (default true); set false to continue the previous one"
+                    }
+                },
+                "required": ["prompt"]
+            }
+        }));
+
+        Self {
+            agent: Mutex::new(agent),
+            tools,
+            next_call_id: AtomicU64::new(1),
+            notifications,
+        }
+    }
+
+    /// 处理一条消息；通知返回 None
+    pub async fn process(&self, message: Value) -> Option<Value> {
+        if let Value::Array(batch) = message {
+            let mut responses = Vec::new();
+            for item in batch {
+                if let Some(response) = Box::pin(self.process(item)).await {
+                    responses.push(response);
+                }
+            }
+            return (!responses.is_empty()).then_some(Value::Array(responses));
+        }
+
+        let id = message.get("id").cloned()?;
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = message.get("params").cloned().unwrap_or_else(|| json!({}));
+
+        let result = match method {
+            "initialize" => Ok(self.initialize(&params)),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({ "tools": self.tools })),
+            "tools/call" => self.call_tool(&params).await,
+            _ => Err((METHOD_NOT_FOUND, format!("method not found: {}", method))),
+        };
+
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => error_response(id, code, &message),
+        })
+    }
+
+    fn initialize(&self, params: &Value) -> Value {
+        let requested = params["protocolVersion"].as_str().unwrap_or(PROTOCOL_VERSION);
+        let version = if SUPPORTED_PROTOCOL_VERSIONS.contains(&requested) {
+            requested
+        } else {
+            PROTOCOL_VERSION
+        };
+        json!({
+            "protocolVersion": version,
+            "capabilities": { "tools": { "listChanged": false } },
+            "serverInfo": {
+                "name": "simple-ai-agent",
+                "version": env!("CARGO_PKG_VERSION")
+            }
+        })
+    }
+
+    /// tools/call：工具失败以 isError 结果返回，而不是 JSON-RPC 错误
+    ///
+    /// 直接调用的工具与模型发起的调用一样受审批策略和预算约束（见 `Agent::review_direct_tool_call`）。
+    async fn call_tool(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let name = params["name"]
+            .as_str()
+            .ok_or((INVALID_PARAMS, "缺少工具名 'name'".to_string()))?;
+        let arguments = params.get("arguments").cloned().unwrap_or_else(|| json!({}));
+        if !self.tools.iter().any(|tool| tool["name"] == name) {
+            return Err((INVALID_PARAMS, format!("工具 '{}' 未找到", name)));
+        }
+        let progress = params["_meta"]
+            .get("progressToken")
+            .filter(|token| token.is_string() || token.is_number())
+            .map(|token| ProgressReporter::new(token.clone(), self.notifications.clone()));
+
+        let outcome = if name == AGENT_TOOL_NAME {
+            self.run_agent(&arguments, progress).await
+        } else {
+            let call = ToolCall {
+                id: format!("mcp_call_{}", self.next_call_id.fetch_add(1, Ordering::SeqCst)),
+                name: name.to_string(),
+                arguments,
+            };
+            let registry = {
+                let agent = self.agent.lock().await;
+                agent.review_direct_tool_call(name).map(|()| agent.tool_registry().clone())
+            };
+            match registry {
+                Ok(registry) => execute_with_progress(&registry, &call, progress).await,
+                Err(e) => Err(e),
+            }
+        };
+
+        let (text, is_error) = match outcome {
+            Ok(text) => (text, false),
+            Err(e) => (e, true),
+        };
+        Ok(json!({
+            "content": [{ "type": "text", "text": text }],
+            "isError": is_error
+        }))
+    }
+
+    async fn run_agent(&self, arguments: &Value, progress: Option<ProgressReporter>) -> Result<String, String> {
+        let prompt = arguments["prompt"].as_str().ok_or("缺少 'prompt' 参数")?;
+        let mut agent = self.agent.lock().await;
+        if arguments["new_conversation"].as_bool().unwrap_or(true) {
+            agent.reset().await;
+        }
+        let result = match progress {
+            Some(mut progress) => {
+                agent
+                    .process_message_with_events(prompt, |event| {
+                        if let AgentEvent::ToolOutputDelta { chunk, .. } = event {
+                            progress.report(&chunk);
+                        }
+                    })
+                    .await
+            }
+            None => agent.process_message(prompt).await,
+        };
+        result.map_err(|e| e.to_string())
+    }
+}
+
+/// 把增量输出转发为 notifications/progress（progress 为已转发的片段数）
+struct ProgressReporter {
+    token: Value,
+    progress: u64,
+    notifications: mpsc::UnboundedSender<Value>,
+}
+
+impl ProgressReporter {
+    fn new(token: Value, notifications: mpsc::UnboundedSender<Value>) -> Self {
+        Self { token, progress: 0, notifications }
+    }
+
+    fn report(&mut self, message: &str) {
+        self.progress += 1;
+        let _ = self.notifications.send(json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/progress",
+            "params": { "progressToken": self.token, "progress": self.progress, "message": message }
+        }));
+    }
+}
+
+/// 执行注册表中的工具；有 progress 时边执行边转发增量输出
+async fn execute_with_progress(
+    registry: &ToolRegistry,
+    call: &ToolCall,
+    progress: Option<ProgressReporter>,
+) -> Result<String, String> {
+    let Some(mut progress) = progress else {
+        return registry.execute(call).await.map(|result| result.content);
+    };
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let execution = registry.execute_streaming(call, ToolOutputSink::new(tx));
+    tokio::pin!(execution);
+    let result = loop {
+        tokio::select! {
+            result = &mut execution => break result,
+            Some(chunk) = rx.recv() => progress.report(&chunk),
+        }
+    };
+    while let Ok(chunk) = rx.try_recv() {
+        progress.report(&chunk);
+    }
+    result.map(|result| result.content)
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+/// 运行 stdio MCP 服务器，直到 stdin 关闭
+///
+/// `stdout` 应来自 `io_redirect::take_stdout()`，保证调试输出不会混进协议流。
+pub async fn run_stdio_server(agent: Agent, stdout: std::fs::File) -> Result<(), anyhow::Error> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<Value>();
+    let processor = Arc::new(MessageProcessor::new(agent, tx.clone()));
+
+    let writer = tokio::spawn(async move {
+        let mut stdout = tokio::fs::File::from_std(stdout);
+        while let Some(message) = rx.recv().await {
+            let mut line = message.to_string();
+            line.push('\n');
+            if stdout.write_all(line.as_bytes()).await.is_err() || stdout.flush().await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message = match serde_json::from_str::<Value>(&line) {
+            Ok(message) => message,
+            Err(e) => {
+                let _ = tx.send(error_response(Value::Null, PARSE_ERROR, &format!("parse error: {}", e)));
+                continue;
+            }
+        };
+
+        let processor = processor.clone();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            if let Some(response) = processor.process(message).await {
+                let _ = tx.send(response);
+            }
+        });
+    }
+
+    // stdin 关闭后等待进行中的请求写完响应（处理器持有的通知发送端随之释放）
+    drop(tx);
+    drop(processor);
+    let _ = writer.await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ModelClient;
+
+    fn processor() -> MessageProcessor {
+        let client = ModelClient::new("test-key".to_string(), "gpt-4".to_string());
+        MessageProcessor::new(Agent::new(client).unwrap(), mpsc::unbounded_channel().0)
+    }
+
+    #[tokio::test]
+    async fn test_initialize_and_list_tools() {
+        let processor = processor();
+
+        let response = processor
+            .process(json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": { "protocolVersion": "2024-11-05" } }))
+            .await
+            .unwrap();
+        assert_eq!(response["result"]["protocolVersion"], "2024-11-05");
+
+        assert!(processor
+            .process(json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }))
+            .await
+            .is_none());
+
+        let response = processor
+            .process(json!({ "jsonrpc": "2.0", "id": 2, "method": "tools/list" }))
+            .await
+            .unwrap();
+        let names: Vec<&str> = response["result"]["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|tool| tool["name"].as_str().unwrap())
+            .collect();
+        assert!(names.contains(&"get_flight_number"));
+        assert!(names.contains(&"read_file"));
+        assert_eq!(names.last(), Some(&AGENT_TOOL_NAME));
+    }
+
+    #[tokio::test]
+    async fn test_call_registry_tool() {
+        let processor = processor();
+
+        let response = processor
+            .process(json!({
+                "jsonrpc": "2.0",
+                "id": 3,
+                "method": "tools/call",
+                "params": {
+                    "name": "get_flight_number",
+                    "arguments": { "departure": "北京", "destination": "上海", "date": "2024-01-01" }
+                }
+            }))
+            .await
+            .unwrap();
+        assert_eq!(response["result"]["isError"], false);
+        assert!(response["result"]["content"][0]["text"].as_str().unwrap().contains("1234"));
+
+        let response = processor
+            .process(json!({ "jsonrpc": "2.0", "id": 4, "method": "tools/call", "params": { "name": "missing" } }))
+            .await
+            .unwrap();
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+    }
+
+    #[tokio::test]
+    async fn test_call_refuses_mutating_tool_under_on_request() {
+        let workspace = tempfile::tempdir().unwrap();
+        let client = ModelClient::new("test-key".to_string(), "gpt-4".to_string());
+        let policy = crate::path_policy::PathPolicy::new(workspace.path(), &[], &[]).unwrap();
+        let mut agent = Agent::new_with_policy(client, Arc::new(policy));
+        agent.set_approval_policy(crate::config::ApprovalPolicy::OnRequest);
+        let processor = MessageProcessor::new(agent, mpsc::unbounded_channel().0);
+
+        let target = workspace.path().join("created.txt");
+        let call = |id: u64, name: &str, arguments: Value| {
+            json!({ "jsonrpc": "2.0", "id": id, "method": "tools/call", "params": { "name": name, "arguments": arguments } })
+        };
+        let response = processor
+            .process(call(1, "write_file", json!({ "path": target.to_string_lossy(), "content": "x" })))
+            .await
+            .unwrap();
+        assert_eq!(response["result"]["isError"], true);
+        assert!(response["result"]["content"][0]["text"].as_str().unwrap().contains("需要审批"));
+        assert!(!target.exists());
+
+        // 只读工具不受影响
+        let response = processor
+            .process(call(2, "list_dir", json!({ "path": workspace.path().to_string_lossy() })))
+            .await
+            .unwrap();
+        assert_eq!(response["result"]["isError"], false);
+    }
+
+    #[tokio::test]
+    async fn test_direct_calls_run_concurrently() {
+        let processor = processor();
+        let call = |id: u64| {
+            json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": "tools/call",
+                "params": { "name": "shell", "arguments": { "command": "sleep 0.5" } }
+            })
+        };
+
+        let started = std::time::Instant::now();
+        let (first, second) = tokio::join!(processor.process(call(1)), processor.process(call(2)));
+        assert_eq!(first.unwrap()["result"]["isError"], false);
+        assert_eq!(second.unwrap()["result"]["isError"], false);
+        assert!(started.elapsed() < std::time::Duration::from_millis(900), "{:?}", started.elapsed());
+    }
+
+    #[tokio::test]
+    async fn test_call_forwards_progress() {
+        let client = ModelClient::new("test-key".to_string(), "gpt-4".to_string());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let processor = MessageProcessor::new(Agent::new(client).unwrap(), tx);
+
+        let response = processor
+            .process(json!({
+                "jsonrpc": "2.0",
+                "id": 5,
+                "method": "tools/call",
+                "params": {
+                    "name": "shell",
+                    "arguments": { "command": "printf a; sleep 0.2; printf b" },
+                    "_meta": { "progressToken": "p1" }
+                }
+            }))
+            .await
+            .unwrap();
+        assert_eq!(response["result"]["isError"], false);
+
+        let mut output = String::new();
+        let mut last = 0;
+        while let Ok(notification) = rx.try_recv() {
+            assert_eq!(notification["method"], "notifications/progress");
+            assert_eq!(notification["params"]["progressToken"], "p1");
+            let progress = notification["params"]["progress"].as_u64().unwrap();
+            assert!(progress > last);
+            last = progress;
+            output.push_str(notification["params"]["message"].as_str().unwrap());
+        }
+        assert_eq!(output, "ab");
+    }
+}
@@ -0,0 +1,239 @@
+// 路径访问策略 - 以工作区为根的文件访问控制（所有文件类工具共用）
+//
+// 规则：
+// 1. 相对路径基于工作区根目录解析，`~` 展开为用户主目录
+// 2. 先消除 `..`，再解析符号链接（不存在的路径解析其最近的已存在祖先）
+// 3. 最终路径必须位于工作区内，或匹配 allow 列表
+// 4. 匹配 deny 列表的路径一律拒绝（优先级高于 allow）
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::path::{Component, Path, PathBuf};
+
+/// 默认禁止访问的路径（密钥、凭证等敏感文件）
+pub const DEFAULT_DENY: &[&str] = &[
+    "**/.env",
+    "**/.env.*",
+    "**/.ssh/**",
+    "**/.aws/**",
+    "**/.gnupg/**",
+    "**/.netrc",
+    "**/*.pem",
+    "**/*.key",
+    "**/id_rsa*",
+    "**/id_ed25519*",
+];
+
+/// 路径访问策略
+#[derive(Debug, Clone)]
+pub struct PathPolicy {
+    workspace_root: PathBuf,
+    allow: GlobSet,
+    deny: GlobSet,
+    deny_patterns: Vec<String>,
+}
+
+impl PathPolicy {
+    /// 使用指定的 allow/deny 规则创建策略
+    ///
+    /// 相对 glob 按工作区内的相对路径匹配，以 `/` 开头的 glob 按绝对路径匹配。
+    pub fn new(workspace_root: &Path, allow: &[String], deny: &[String]) -> Result<Self, anyhow::Error> {
+        let workspace_root = std::fs::canonicalize(workspace_root)
+            .map_err(|e| anyhow::anyhow!("工作区目录无效 {}: {}", workspace_root.display(), e))?;
+
+        Ok(Self {
+            workspace_root,
+            allow: build_globset(allow)?,
+            deny: build_globset(deny)?,
+            deny_patterns: deny.to_vec(),
+        })
+    }
+
+    /// 以 workspace_root 为根、使用默认 deny 列表的策略
+    pub fn for_workspace(workspace_root: &Path) -> Result<Self, anyhow::Error> {
+        let deny: Vec<String> = DEFAULT_DENY.iter().map(|s| s.to_string()).collect();
+        Self::new(workspace_root, &[], &deny)
+    }
+
+    /// 以当前工作目录为工作区、使用默认 deny 列表的策略
+    pub fn for_current_dir() -> Result<Self, anyhow::Error> {
+        let cwd = std::env::current_dir().map_err(|e| anyhow::anyhow!("无法获取当前目录: {}", e))?;
+        Self::for_workspace(&cwd)
+    }
+
+    pub fn workspace_root(&self) -> &Path {
+        &self.workspace_root
+    }
+
+    /// 解析并检查路径，返回解析后的真实路径
+    pub fn resolve(&self, path: &Path) -> Result<PathBuf, anyhow::Error> {
+        if path.as_os_str().is_empty() {
+            return Err(anyhow::anyhow!("路径不能为空"));
+        }
+
+        let expanded = expand_home(path);
+        let absolute = if expanded.is_absolute() {
+            expanded
+        } else {
+            self.workspace_root.join(expanded)
+        };
+        let resolved = resolve_symlinks(&normalize(&absolute));
+
+        let relative = resolved.strip_prefix(&self.workspace_root).ok();
+        let inside = relative.is_some();
+
+        if let Some(pattern) = self.denied_by(&resolved, relative) {
+            return Err(anyhow::anyhow!(
+                "访问被策略拒绝 (access denied by policy): {} 匹配禁止规则 '{}'",
+                path.display(),
+                pattern
+            ));
+        }
+
+        if !inside && !self.allow.is_match(&resolved) {
+            return Err(anyhow::anyhow!(
+                "访问被策略拒绝 (access denied by policy): {} 位于工作区 {} 之外",
+                path.display(),
+                self.workspace_root.display()
+            ));
+        }
+
+        Ok(resolved)
+    }
+
+    /// 返回命中的 deny 规则
+    fn denied_by(&self, absolute: &Path, relative: Option<&Path>) -> Option<&str> {
+        let mut matches = self.deny.matches(absolute);
+        if let Some(relative) = relative {
+            matches.extend(self.deny.matches(relative));
+        }
+        matches.first().map(|&i| self.deny_patterns[i].as_str())
+    }
+
+    /// 检查路径是否允许访问（用于遍历目录时过滤条目）
+    pub fn is_allowed(&self, path: &Path) -> bool {
+        self.resolve(path).is_ok()
+    }
+}
+
+fn build_globset(patterns: &[String]) -> Result<GlobSet, anyhow::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern).map_err(|e| anyhow::anyhow!("无效的 glob '{}': {}", pattern, e))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
+/// 展开开头的 `~`
+fn expand_home(path: &Path) -> PathBuf {
+    match path.strip_prefix("~") {
+        Ok(rest) => match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(rest),
+            None => path.to_path_buf(),
+        },
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+/// 按词法消除 `.` 和 `..`
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// 解析符号链接；路径不存在时解析最近的已存在祖先，再拼接剩余部分
+fn resolve_symlinks(path: &Path) -> PathBuf {
+    let mut existing = path.to_path_buf();
+    let mut rest = Vec::new();
+
+    loop {
+        if let Ok(canonical) = std::fs::canonicalize(&existing) {
+            let mut resolved = canonical;
+            for part in rest.iter().rev() {
+                resolved.push(part);
+            }
+            return resolved;
+        }
+        match (existing.file_name().map(|n| n.to_os_string()), existing.parent()) {
+            (Some(name), Some(parent)) => {
+                rest.push(name);
+                existing = parent.to_path_buf();
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workspace() -> (tempfile::TempDir, PathPolicy) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        std::fs::write(dir.path().join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(dir.path().join(".env"), "OPENAI_API_KEY=sk-secret").unwrap();
+        let policy = PathPolicy::for_workspace(dir.path()).unwrap();
+        (dir, policy)
+    }
+
+    #[test]
+    fn test_resolves_paths_inside_workspace() {
+        let (_dir, policy) = workspace();
+
+        let resolved = policy.resolve(Path::new("src/main.rs")).unwrap();
+        assert!(resolved.ends_with("src/main.rs"));
+
+        // 尚不存在的文件（写入场景）同样可以解析
+        assert!(policy.resolve(Path::new("src/new/mod.rs")).is_ok());
+    }
+
+    #[test]
+    fn test_denies_escapes_and_secrets() {
+        let (_dir, policy) = workspace();
+
+        let err = policy.resolve(Path::new("../outside.txt")).unwrap_err();
+        assert!(err.to_string().contains("access denied by policy"));
+        assert!(policy.resolve(Path::new("/etc/passwd")).is_err());
+        assert!(policy.resolve(Path::new("src/../.env")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_denies_symlink_escapes() {
+        let (dir, policy) = workspace();
+
+        // 通过符号链接逃逸到工作区外
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("secret.txt"), "x").unwrap();
+        std::os::unix::fs::symlink(outside.path(), dir.path().join("link")).unwrap();
+        assert!(policy.resolve(Path::new("link/secret.txt")).is_err());
+
+        // 指向 .env 的符号链接也会被拒绝
+        std::os::unix::fs::symlink(dir.path().join(".env"), dir.path().join("config")).unwrap();
+        assert!(policy.resolve(Path::new("config")).is_err());
+    }
+
+    #[test]
+    fn test_allow_list_grants_outside_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = tempfile::tempdir().unwrap();
+        let shared_root = std::fs::canonicalize(shared.path()).unwrap();
+        std::fs::write(shared_root.join("data.txt"), "x").unwrap();
+
+        let allow = vec![format!("{}/**", shared_root.display())];
+        let policy = PathPolicy::new(dir.path(), &allow, &[]).unwrap();
+
+        assert!(policy.resolve(&shared_root.join("data.txt")).is_ok());
+        assert!(policy.resolve(Path::new("/etc/passwd")).is_err());
+    }
+}
@@ -1,11 +1,72 @@
 // 协议定义 - 消息和事件类型
 
+use crate::budget::BudgetLimit;
+use crate::router::ModelRoute;
+use crate::usage::{TokenUsage, UsageTotals};
 use serde::{Deserialize, Serialize};
 
 /// 用户消息类型
 #[derive(Debug, Clone, Serialize)]
 pub struct UserMessage {
-    pub content: String,
+    pub content: MessageContent,
+}
+
+/// 用户消息的内容：纯文本，或文本与图片混合的内容片段（OpenAI 的 content parts 格式）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        MessageContent::Text(text)
+    }
+}
+
+impl From<&str> for MessageContent {
+    fn from(text: &str) -> Self {
+        MessageContent::Text(text.to_string())
+    }
+}
+
+/// 内容片段
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+impl ContentPart {
+    pub fn text(text: impl Into<String>) -> Self {
+        ContentPart::Text { text: text.into() }
+    }
+
+    /// 图片：http(s) URL，或 `data:image/png;base64,...` 形式的 data URL
+    pub fn image_url(url: impl Into<String>) -> Self {
+        ContentPart::ImageUrl {
+            image_url: ImageUrl { url: url.into(), detail: None },
+        }
+    }
+}
+
+/// 图片地址
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageUrl {
+    pub url: String,
+    /// 解析精度：low / high / auto（省略时由接口决定）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// 工具（如 view_image）附加到对话中的图片
+///
+/// 作为独立条目放在本次工具调用的结果之后；工具结果只能是文本，所以请求时以一条用户消息发送。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageAttachment {
+    pub attached_images: Vec<ContentPart>,
 }
 
 /// AI 消息类型
@@ -14,6 +75,18 @@ pub struct AssistantMessage {
     pub content: String,
     #[serde(default)]
     pub tool_calls: Option<Vec<ToolCall>>,
+    /// 由路由或备用模型（而不是主模型）生成时的记录
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub route: Option<ModelRoute>,
+}
+
+/// 模型的思考过程（推理模型的 reasoning_content）
+///
+/// 作为独立的条目保存在对话历史中，紧跟其后的是同一次响应的 AI 消息；
+/// 不计入最终回复，请求时按 `ReasoningReplay` 决定是否回传。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReasoningItem {
+    pub reasoning: String,
 }
 
 /// 工具调用请求
@@ -39,6 +112,8 @@ pub enum AgentStatus {
     Thinking,
     #[allow(dead_code)]
     ExecutingTool,
+    /// 等待用户审批工具调用
+    WaitingApproval,
     #[allow(dead_code)]
     Error,
 }
@@ -50,3 +125,76 @@ pub struct ToolDefinition {
     pub description: String,
     pub parameters: serde_json::Value,
 }
+
+/// 智能体事件（前端通过回调接收，用于实时展示）
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AgentEvent {
+    /// 模型输出的文本片段
+    TextDelta { text: String },
+    /// 模型的思考片段（前端应与回复区分显示，如暗色或折叠）
+    ReasoningDelta { text: String },
+    /// 开始执行工具
+    ToolCallBegin {
+        call_id: String,
+        name: String,
+        arguments: serde_json::Value,
+    },
+    /// 工具运行中的增量输出（仅用于展示，不会发送给模型）
+    ToolOutputDelta { call_id: String, chunk: String },
+    /// 工具执行结束，output 为交给模型的完整结果
+    ToolCallEnd {
+        call_id: String,
+        output: String,
+        success: bool,
+    },
+    /// 一次模型请求结束，附带本次用量和本轮、本会话的累计用量
+    TokenUsage {
+        model: String,
+        usage: TokenUsage,
+        /// 本次请求的费用，模型没有配置价格时为 None
+        cost: Option<f64>,
+        turn: UsageTotals,
+        session: UsageTotals,
+    },
+    /// 接下来的请求改由路由或备用模型处理（与上一次请求使用的模型不同时发出）
+    ModelRouted {
+        route: ModelRoute,
+    },
+    /// 超出预算，本轮在发出下一次请求（或执行下一个工具）之前停止
+    BudgetExceeded {
+        limit: BudgetLimit,
+        used: f64,
+        max: f64,
+        message: String,
+    },
+    /// 工具调用需要用户审批，前端通过 `Agent::approval_channel` 答复
+    ApprovalRequest {
+        call_id: String,
+        name: String,
+        arguments: serde_json::Value,
+        /// 工具提供的说明（见 `ToolExecutor::approval_hint`）
+        hint: Option<String>,
+    },
+}
+
+/// 用户对审批请求的决定
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewDecision {
+    /// 执行这一次调用
+    Approved,
+    /// 执行，并且本次会话中不再询问同名工具
+    ApprovedForSession,
+    /// 不执行，由模型决定下一步
+    Denied,
+    /// 不执行，并结束本轮对话
+    Abort,
+}
+
+/// 前端对某个审批请求的答复
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApprovalResponse {
+    pub call_id: String,
+    pub decision: ReviewDecision,
+}
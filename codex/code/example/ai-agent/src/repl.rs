@@ -0,0 +1,268 @@
+// 交互式对话（chat）的行编辑器 - 基于 rustyline
+//
+// - Tab 补全斜杠命令
+// - 持久化输入历史（数据目录下的 history 文件），Ctrl-R 反向搜索
+// - 粘贴的多行文本整体插入（bracketed paste），Alt+Enter 换行
+// - /multiline 切换多行模式：Enter 换行，在空行上按 Enter 发送
+
+use crate::commands::{CommandContext, CommandOutcome, CommandRegistry, SlashCommand};
+use async_trait::async_trait;
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Cmd, CompletionType, Config, Context, Editor, EventHandler, Helper, KeyCode, KeyEvent, Modifiers};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// 输入历史最多保留的条数
+const MAX_HISTORY_ENTRIES: usize = 1000;
+
+const PROMPT: &str = "👤 You: ";
+const MULTILINE_PROMPT: &str = "👤 You（多行）: ";
+
+/// REPL 使用的行编辑器
+pub type ReplEditor = Editor<ReplHelper, DefaultHistory>;
+
+/// 输入历史文件：数据目录下的 history
+pub fn history_path() -> Option<PathBuf> {
+    crate::config::data_dir().map(|dir| dir.join("history"))
+}
+
+/// 读取一次输入的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplInput {
+    Line(String),
+    /// 在提示符处按了 Ctrl-C（放弃当前输入）
+    Interrupted,
+    /// Ctrl-D 或 stdin 结束
+    Eof,
+}
+
+/// 判断输入是否可以提交
+///
+/// 多行模式下，只有在空行上按 Enter（输入以换行结尾）才提交；单行的斜杠命令总是直接提交。
+fn input_complete(input: &str, multiline: bool) -> bool {
+    if !multiline || input.trim().is_empty() {
+        return true;
+    }
+    if input.starts_with('/') && !input.contains('\n') {
+        return true;
+    }
+    input.ends_with('\n')
+}
+
+/// 行编辑器的补全和多行判断
+pub struct ReplHelper {
+    commands: Arc<CommandRegistry>,
+    multiline: Arc<AtomicBool>,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        // 只在光标位于行尾时补全
+        if pos != line.len() {
+            return Ok((pos, Vec::new()));
+        }
+        let (start, candidates) = self.commands.complete(line);
+        let pairs = candidates
+            .into_iter()
+            .map(|candidate| Pair {
+                display: candidate.clone(),
+                replacement: candidate,
+            })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if input_complete(ctx.input(), self.multiline.load(Ordering::Relaxed)) {
+            Ok(ValidationResult::Valid(None))
+        } else {
+            Ok(ValidationResult::Incomplete)
+        }
+    }
+}
+
+impl Helper for ReplHelper {}
+
+/// /multiline：切换多行输入模式（由 REPL 注册，因为模式属于行编辑器）
+struct MultilineCommand {
+    enabled: Arc<AtomicBool>,
+}
+
+#[async_trait]
+impl SlashCommand for MultilineCommand {
+    fn name(&self) -> &str {
+        "multiline"
+    }
+
+    fn usage(&self) -> &str {
+        "[on|off]"
+    }
+
+    fn description(&self) -> &str {
+        "切换多行输入模式"
+    }
+
+    fn help(&self) -> &str {
+        "多行模式下 Enter 换行，在空行上按 Enter 发送；单行的斜杠命令仍然直接执行。\n任何模式下都可以用 Alt+Enter 换行，粘贴的多行文本会整体插入而不会提前发送。"
+    }
+
+    async fn run(&self, _ctx: &mut CommandContext<'_>, args: &str) -> anyhow::Result<CommandOutcome> {
+        let enabled = match args {
+            "" => !self.enabled.load(Ordering::Relaxed),
+            "on" => true,
+            "off" => false,
+            _ => return Err(anyhow::anyhow!("用法: /multiline [on|off]")),
+        };
+        self.enabled.store(enabled, Ordering::Relaxed);
+        let message = if enabled {
+            "📝 多行模式已开启：Enter 换行，在空行上按 Enter 发送"
+        } else {
+            "📝 多行模式已关闭：Enter 发送，Alt+Enter 换行"
+        };
+        Ok(CommandOutcome::Output(message.to_string()))
+    }
+}
+
+/// 交互式对话的输入端：行编辑器 + 斜杠命令 + 输入历史
+pub struct Repl {
+    editor: ReplEditor,
+    commands: Arc<CommandRegistry>,
+    multiline: Arc<AtomicBool>,
+    history_path: Option<PathBuf>,
+}
+
+impl Repl {
+    /// 创建 REPL，history_path 为 None 时不持久化输入历史
+    pub fn new(mut commands: CommandRegistry, history_path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let multiline = Arc::new(AtomicBool::new(false));
+        commands.register(MultilineCommand {
+            enabled: multiline.clone(),
+        });
+        let commands = Arc::new(commands);
+
+        let config = Config::builder()
+            .completion_type(CompletionType::List)
+            .max_history_size(MAX_HISTORY_ENTRIES)?
+            .history_ignore_dups(true)?
+            .history_ignore_space(true)
+            .auto_add_history(false)
+            .bracketed_paste(true)
+            .build();
+        let mut editor = ReplEditor::with_config(config)?;
+        editor.set_helper(Some(ReplHelper {
+            commands: commands.clone(),
+            multiline: multiline.clone(),
+        }));
+        editor.bind_sequence(KeyEvent(KeyCode::Enter, Modifiers::ALT), EventHandler::Simple(Cmd::Newline));
+
+        if let Some(path) = &history_path {
+            // 第一次运行时还没有历史文件
+            if path.exists() {
+                if let Err(e) = editor.load_history(path) {
+                    eprintln!("⚠️  读取输入历史失败 {}: {}", path.display(), e);
+                }
+            }
+        }
+
+        Ok(Self {
+            editor,
+            commands,
+            multiline,
+            history_path,
+        })
+    }
+
+    pub fn commands(&self) -> &CommandRegistry {
+        &self.commands
+    }
+
+    /// 读取一次输入，非空输入写入历史
+    pub fn read_input(&mut self) -> anyhow::Result<ReplInput> {
+        let prompt = if self.multiline.load(Ordering::Relaxed) {
+            MULTILINE_PROMPT
+        } else {
+            PROMPT
+        };
+        let line = match self.editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => return Ok(ReplInput::Interrupted),
+            Err(ReadlineError::Eof) => return Ok(ReplInput::Eof),
+            Err(e) => return Err(e.into()),
+        };
+
+        if !line.trim().is_empty() {
+            self.record_history(&line);
+        }
+        Ok(ReplInput::Line(line.trim_end().to_string()))
+    }
+
+    /// 追加到历史文件（只写入新条目，多个会话同时运行也不会互相覆盖）
+    fn record_history(&mut self, line: &str) {
+        let line = line.trim_end();
+        match self.editor.add_history_entry(line) {
+            Ok(true) => {}
+            _ => return,
+        }
+        let Some(path) = &self.history_path else {
+            return;
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).ok();
+        }
+        if let Err(e) = self.editor.append_history(path) {
+            tracing::warn!("保存输入历史失败 {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multiline_input_completes_on_blank_line() {
+        assert!(input_complete("你好", false));
+        assert!(input_complete("第一行\n第二行", false));
+
+        assert!(!input_complete("第一行", true));
+        assert!(!input_complete("第一行\n第二行", true));
+        assert!(input_complete("第一行\n第二行\n", true));
+        assert!(input_complete("", true));
+        assert!(input_complete("/multiline off", true));
+    }
+
+    #[tokio::test]
+    async fn test_multiline_command_toggles_mode() {
+        let repl = Repl::new(CommandRegistry::with_builtin_commands(), None).unwrap();
+        let mut agent = crate::Agent::new(crate::ModelClient::new("test-key".to_string(), "glm-4".to_string())).unwrap();
+        let mut session = crate::session::Session::new(PathBuf::from("."), "glm-4".to_string());
+        let mut ctx = CommandContext {
+            agent: &mut agent,
+            session: &mut session,
+            store: None,
+        };
+
+        repl.commands().execute(&mut ctx, "/multiline").await.unwrap();
+        assert!(repl.multiline.load(Ordering::Relaxed));
+        repl.commands().execute(&mut ctx, "/multiline off").await.unwrap();
+        assert!(!repl.multiline.load(Ordering::Relaxed));
+        assert!(repl.commands().execute(&mut ctx, "/multiline maybe").await.is_err());
+        assert!(repl.commands().complete("/mu").1.contains(&"/multiline".to_string()));
+    }
+}
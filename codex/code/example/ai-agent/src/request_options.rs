@@ -0,0 +1,336 @@
+// 请求参数 - 采样参数、停止序列、seed 和工具选择，以及各提供商对它们的支持情况
+//
+// - 配置文件顶层或 profile 中直接写（与 model 同级），按字段覆盖
+// - Agent::set_request_options 设置智能体的默认值，process_message_with_options 只对一轮生效
+// - 发送前按 RequestCapabilities 校验：超出取值范围或提供商不支持的参数直接报错，而不是静默丢弃；
+//   max_tokens 按提供商映射为对应的字段名
+
+use crate::config::{merge_fields, set_entries};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// 请求参数（未设置的字段不发送，由服务端使用默认值）
+///
+/// ```toml
+/// temperature = 0.3
+/// top_p = 0.9
+/// max_tokens = 4096            # 单次回复的最大输出 token
+/// stop = ["<END>"]
+/// seed = 42
+/// tool_choice = "required"     # auto / none / required / 工具名
+/// parallel_tool_calls = false  # 一次回复最多调用一个工具
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RequestOptions {
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub max_tokens: Option<u32>,
+    pub stop: Option<Vec<String>>,
+    pub seed: Option<u64>,
+    pub tool_choice: Option<ToolChoice>,
+    pub parallel_tool_calls: Option<bool>,
+}
+
+impl RequestOptions {
+    /// 用 other 中已设置的字段覆盖当前值
+    pub fn merge(&mut self, other: RequestOptions) {
+        merge_fields!(self, other, temperature, top_p, max_tokens, stop, seed, tool_choice, parallel_tool_calls);
+    }
+
+    /// 在当前值之上叠加 other，返回新的参数
+    pub fn merged(&self, other: &RequestOptions) -> RequestOptions {
+        let mut merged = self.clone();
+        merged.merge(other.clone());
+        merged
+    }
+
+    /// 检查参数的取值范围，以及提供商是否支持
+    pub fn validate(&self, capabilities: &RequestCapabilities) -> Result<(), anyhow::Error> {
+        if let Some(temperature) = self.temperature {
+            if !(0.0..=capabilities.max_temperature).contains(&temperature) {
+                return Err(anyhow::anyhow!(
+                    "temperature 应在 0 到 {} 之间，实际为 {}",
+                    capabilities.max_temperature,
+                    temperature
+                ));
+            }
+        }
+        if let Some(top_p) = self.top_p {
+            if !(top_p > 0.0 && top_p <= 1.0) {
+                return Err(anyhow::anyhow!("top_p 应在 (0, 1] 之间，实际为 {}", top_p));
+            }
+        }
+        if self.max_tokens == Some(0) {
+            return Err(anyhow::anyhow!("max_tokens 必须大于 0"));
+        }
+        if let Some(stop) = &self.stop {
+            if stop.len() > capabilities.max_stop_sequences {
+                return Err(anyhow::anyhow!(
+                    "stop 最多 {} 个，实际为 {} 个",
+                    capabilities.max_stop_sequences,
+                    stop.len()
+                ));
+            }
+            if stop.iter().any(String::is_empty) {
+                return Err(anyhow::anyhow!("stop 不能包含空字符串"));
+            }
+        }
+        if self.seed.is_some() && !capabilities.seed {
+            return Err(anyhow::anyhow!("该提供商不支持 seed"));
+        }
+        match &self.tool_choice {
+            Some(ToolChoice::Auto) | None => {}
+            Some(choice) if !capabilities.tool_choice => {
+                return Err(anyhow::anyhow!("该提供商的 tool_choice 只支持 auto，不支持 {}", choice));
+            }
+            Some(ToolChoice::Function(name)) if name.trim().is_empty() => {
+                return Err(anyhow::anyhow!("tool_choice 指定的工具名不能为空"));
+            }
+            Some(_) => {}
+        }
+        if self.parallel_tool_calls.is_some() && !capabilities.parallel_tool_calls {
+            return Err(anyhow::anyhow!("该提供商不支持 parallel_tool_calls"));
+        }
+        Ok(())
+    }
+
+    /// 写入请求体；has_tools 为 false 时不发送工具相关的参数（接口会拒绝没有 tools 的 tool_choice）
+    pub fn apply(&self, request_body: &mut Value, capabilities: &RequestCapabilities, has_tools: bool) {
+        if let Some(temperature) = self.temperature {
+            request_body["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = self.top_p {
+            request_body["top_p"] = json!(top_p);
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            request_body[capabilities.max_tokens_field] = json!(max_tokens);
+        }
+        if let Some(stop) = &self.stop {
+            request_body["stop"] = json!(stop);
+        }
+        if let Some(seed) = self.seed {
+            request_body["seed"] = json!(seed);
+        }
+        if has_tools {
+            if let Some(tool_choice) = &self.tool_choice {
+                request_body["tool_choice"] = tool_choice.to_value();
+            }
+            if let Some(parallel_tool_calls) = self.parallel_tool_calls {
+                request_body["parallel_tool_calls"] = json!(parallel_tool_calls);
+            }
+        }
+    }
+
+    /// 已设置的各项（`config show` 使用）
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        set_entries([
+            ("temperature", self.temperature.map(|v| v.to_string())),
+            ("top_p", self.top_p.map(|v| v.to_string())),
+            ("max_tokens", self.max_tokens.map(|v| v.to_string())),
+            ("stop", self.stop.as_ref().map(|stop| format!("{:?}", stop))),
+            ("seed", self.seed.map(|v| v.to_string())),
+            ("tool_choice", self.tool_choice.as_ref().map(|v| v.to_string())),
+            ("parallel_tool_calls", self.parallel_tool_calls.map(|v| v.to_string())),
+        ])
+    }
+}
+
+/// 模型如何选择工具
+///
+/// 配置中写 `"auto"`、`"none"`、`"required"`，其他字符串表示必须调用该名称的工具。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum ToolChoice {
+    /// 由模型决定是否调用工具（默认）
+    Auto,
+    /// 不调用工具
+    None,
+    /// 必须调用至少一个工具
+    Required,
+    /// 必须调用指定的工具
+    Function(String),
+}
+
+impl ToolChoice {
+    /// 是否强制模型调用工具
+    ///
+    /// 强制只对一轮中的第一次请求生效，执行完工具后恢复为 auto，否则模型无法给出最终回复。
+    pub fn is_forced(&self) -> bool {
+        matches!(self, ToolChoice::Required | ToolChoice::Function(_))
+    }
+
+    /// 请求体中的 tool_choice
+    pub fn to_value(&self) -> Value {
+        match self {
+            ToolChoice::Function(name) => json!({ "type": "function", "function": { "name": name } }),
+            other => json!(other.to_string()),
+        }
+    }
+}
+
+impl From<String> for ToolChoice {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "auto" => ToolChoice::Auto,
+            "none" => ToolChoice::None,
+            "required" => ToolChoice::Required,
+            _ => ToolChoice::Function(value),
+        }
+    }
+}
+
+impl From<ToolChoice> for String {
+    fn from(choice: ToolChoice) -> Self {
+        choice.to_string()
+    }
+}
+
+impl std::str::FromStr for ToolChoice {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(ToolChoice::from(s.to_string()))
+    }
+}
+
+impl std::fmt::Display for ToolChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ToolChoice::Auto => "auto",
+            ToolChoice::None => "none",
+            ToolChoice::Required => "required",
+            ToolChoice::Function(name) => name,
+        })
+    }
+}
+
+/// 提供商对请求参数的支持情况
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RequestCapabilities {
+    /// temperature 的上限
+    pub max_temperature: f64,
+    /// max_tokens 在请求体中的字段名
+    pub max_tokens_field: &'static str,
+    /// stop 最多的序列数
+    pub max_stop_sequences: usize,
+    pub seed: bool,
+    /// 是否支持 auto 以外的 tool_choice
+    pub tool_choice: bool,
+    pub parallel_tool_calls: bool,
+}
+
+impl RequestCapabilities {
+    /// OpenAI 兼容接口的通用假设（非预设的提供商使用），不做提供商特有的限制
+    pub const OPENAI_COMPATIBLE: RequestCapabilities = RequestCapabilities {
+        max_temperature: 2.0,
+        max_tokens_field: "max_tokens",
+        max_stop_sequences: 4,
+        seed: true,
+        tool_choice: true,
+        parallel_tool_calls: true,
+    };
+}
+
+impl Default for RequestCapabilities {
+    fn default() -> Self {
+        Self::OPENAI_COMPATIBLE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_and_tool_choice() {
+        let options = RequestOptions {
+            temperature: Some(0.2),
+            max_tokens: Some(512),
+            stop: Some(vec!["<END>".to_string()]),
+            tool_choice: Some("get_flight_number".parse().unwrap()),
+            parallel_tool_calls: Some(false),
+            ..Default::default()
+        };
+        let capabilities = RequestCapabilities { max_tokens_field: "max_completion_tokens", ..Default::default() };
+
+        let mut body = json!({});
+        options.apply(&mut body, &capabilities, true);
+        assert_eq!(
+            body,
+            json!({
+                "temperature": 0.2,
+                "max_completion_tokens": 512,
+                "stop": ["<END>"],
+                "tool_choice": { "type": "function", "function": { "name": "get_flight_number" } },
+                "parallel_tool_calls": false
+            })
+        );
+
+        // 没有工具时不发送工具相关的参数
+        let mut body = json!({});
+        options.apply(&mut body, &capabilities, false);
+        assert!(body.get("tool_choice").is_none());
+        assert!(body.get("parallel_tool_calls").is_none());
+
+        assert_eq!(ToolChoice::Required.to_value(), json!("required"));
+        assert!(ToolChoice::Required.is_forced());
+        assert!(!ToolChoice::None.is_forced());
+    }
+
+    #[test]
+    fn test_parse_and_merge() {
+        let options: RequestOptions = toml::from_str(
+            r#"
+            top_p = 0.9
+            tool_choice = "none"
+            stop = ["a", "b"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(options.tool_choice, Some(ToolChoice::None));
+
+        let merged = options.merged(&RequestOptions { tool_choice: Some(ToolChoice::Auto), seed: Some(7), ..Default::default() });
+        assert_eq!(merged.tool_choice, Some(ToolChoice::Auto));
+        assert_eq!(merged.top_p, Some(0.9));
+        assert_eq!(
+            merged.entries(),
+            vec![
+                ("top_p", "0.9".to_string()),
+                ("stop", r#"["a", "b"]"#.to_string()),
+                ("seed", "7".to_string()),
+                ("tool_choice", "auto".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let generic = RequestCapabilities::default();
+        let strict = RequestCapabilities {
+            max_temperature: 1.0,
+            max_stop_sequences: 1,
+            seed: false,
+            tool_choice: false,
+            parallel_tool_calls: false,
+            ..Default::default()
+        };
+        let error = |options: RequestOptions, capabilities| options.validate(&capabilities).unwrap_err().to_string();
+
+        let options = RequestOptions { temperature: Some(1.5), ..Default::default() };
+        assert!(options.validate(&generic).is_ok());
+        assert_eq!(error(options, strict), "temperature 应在 0 到 1 之间，实际为 1.5");
+        assert!(error(RequestOptions { top_p: Some(0.0), ..Default::default() }, generic).starts_with("top_p"));
+        assert!(error(RequestOptions { max_tokens: Some(0), ..Default::default() }, generic).contains("大于 0"));
+        let stop = Some(vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(error(RequestOptions { stop, ..Default::default() }, strict), "stop 最多 1 个，实际为 2 个");
+        assert!(error(RequestOptions { seed: Some(1), ..Default::default() }, strict).contains("seed"));
+        assert_eq!(
+            error(RequestOptions { tool_choice: Some(ToolChoice::Required), ..Default::default() }, strict),
+            "该提供商的 tool_choice 只支持 auto，不支持 required"
+        );
+        assert!(RequestOptions { tool_choice: Some(ToolChoice::Auto), ..Default::default() }.validate(&strict).is_ok());
+        assert!(error(RequestOptions { parallel_tool_calls: Some(false), ..Default::default() }, strict)
+            .contains("parallel_tool_calls"));
+    }
+}
@@ -0,0 +1,203 @@
+// 模型路由 - 按请求类型选择模型，选中的模型失败时依次改用备用模型
+//
+// - routing.compact：/compact 压缩对话时使用（通常是更便宜的模型）
+// - routing.tool_turns：本轮执行过工具之后的请求使用（通常是更强的模型）
+// - fallback：模型因网络错误、限流、额度不足或服务端错误失败时，依次改用的 profile
+// 路由和备用模型都以 profile 名配置，由 Settings::model_router 为每个 profile 创建 ModelClient。
+// 流式回复开始之后的错误不会切换模型（已经输出的内容无法撤回）。
+
+use crate::client::ModelClient;
+use serde::{Deserialize, Serialize};
+
+/// 路由或备用的模型：profile 名和按该 profile 创建的客户端
+pub struct RouteTarget {
+    pub profile: String,
+    pub client: ModelClient,
+}
+
+impl RouteTarget {
+    pub fn new(profile: impl Into<String>, client: ModelClient) -> Self {
+        Self { profile: profile.into(), client }
+    }
+}
+
+/// 请求的类型，决定优先使用哪个模型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestKind {
+    /// 普通的对话请求
+    Chat,
+    /// 本轮已经执行过工具
+    ToolTurn,
+    /// 压缩对话历史
+    Compact,
+}
+
+/// 一次请求的候选模型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Candidate {
+    Primary,
+    Compact,
+    ToolTurns,
+    Fallback(usize),
+}
+
+/// 模型路由表（未配置任何路由时只使用主模型）
+#[derive(Default)]
+pub struct ModelRouter {
+    compact: Option<RouteTarget>,
+    tool_turns: Option<RouteTarget>,
+    fallbacks: Vec<RouteTarget>,
+}
+
+impl ModelRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 压缩对话时使用的模型
+    pub fn with_compact(mut self, target: RouteTarget) -> Self {
+        self.compact = Some(target);
+        self
+    }
+
+    /// 本轮执行过工具之后使用的模型
+    pub fn with_tool_turns(mut self, target: RouteTarget) -> Self {
+        self.tool_turns = Some(target);
+        self
+    }
+
+    /// 追加一个备用模型（按添加顺序尝试）
+    pub fn with_fallback(mut self, target: RouteTarget) -> Self {
+        self.fallbacks.push(target);
+        self
+    }
+
+    /// 按请求类型排出尝试顺序：路由选中的模型，其次是主模型，最后依次是备用模型
+    pub fn candidates(&self, kind: RequestKind) -> Vec<Candidate> {
+        let routed = match kind {
+            RequestKind::Compact if self.compact.is_some() => Some(Candidate::Compact),
+            RequestKind::ToolTurn if self.tool_turns.is_some() => Some(Candidate::ToolTurns),
+            _ => None,
+        };
+        routed
+            .into_iter()
+            .chain([Candidate::Primary])
+            .chain((0..self.fallbacks.len()).map(Candidate::Fallback))
+            .collect()
+    }
+
+    /// 候选对应的路由目标，主模型返回 None
+    pub fn target_mut(&mut self, candidate: Candidate) -> Option<&mut RouteTarget> {
+        match candidate {
+            Candidate::Primary => None,
+            Candidate::Compact => self.compact.as_mut(),
+            Candidate::ToolTurns => self.tool_turns.as_mut(),
+            Candidate::Fallback(index) => self.fallbacks.get_mut(index),
+        }
+    }
+
+    /// 配置的路由和备用模型（/status 使用），未配置时为空
+    pub fn describe(&self) -> Vec<String> {
+        let target = |target: &RouteTarget| format!("{}（{}）", target.profile, target.client.model());
+        let mut lines = Vec::new();
+        if let Some(compact) = &self.compact {
+            lines.push(format!("压缩对话: {}", target(compact)));
+        }
+        if let Some(tool_turns) = &self.tool_turns {
+            lines.push(format!("工具轮次: {}", target(tool_turns)));
+        }
+        if !self.fallbacks.is_empty() {
+            let fallbacks: Vec<String> = self.fallbacks.iter().map(target).collect();
+            lines.push(format!("备用: {}", fallbacks.join(" → ")));
+        }
+        lines
+    }
+}
+
+/// 选择模型的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteReason {
+    /// routing.compact
+    Compact,
+    /// routing.tool_turns
+    ToolTurns,
+    /// 之前的模型请求失败
+    Fallback,
+}
+
+impl std::fmt::Display for RouteReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            RouteReason::Compact => "压缩对话",
+            RouteReason::ToolTurns => "工具轮次",
+            RouteReason::Fallback => "备用",
+        })
+    }
+}
+
+/// 实际处理请求的模型不是主模型时的记录（写入 AI 消息的 route 字段，并发出 ModelRouted 事件）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelRoute {
+    pub model: String,
+    /// 模型所在的 profile，回落到主模型时为 None
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    pub reason: RouteReason,
+    /// 改用备用模型时，之前的模型的错误
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl std::fmt::Display for ModelRoute {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}（{}", self.model, self.reason)?;
+        if let Some(profile) = &self.profile {
+            write!(f, "，profile {}", profile)?;
+        }
+        f.write_str("）")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(profile: &str, model: &str) -> RouteTarget {
+        RouteTarget::new(profile, ModelClient::new("test-key".to_string(), model.to_string()))
+    }
+
+    #[test]
+    fn test_candidates() {
+        assert_eq!(ModelRouter::new().candidates(RequestKind::Compact), [Candidate::Primary]);
+
+        let router = ModelRouter::new()
+            .with_compact(target("flash", "glm-4-flash"))
+            .with_fallback(target("deepseek", "deepseek-chat"))
+            .with_fallback(target("local", "qwen2.5"));
+        assert_eq!(
+            router.candidates(RequestKind::Compact),
+            [Candidate::Compact, Candidate::Primary, Candidate::Fallback(0), Candidate::Fallback(1)]
+        );
+        // 没有配置 tool_turns 时仍从主模型开始
+        assert_eq!(router.candidates(RequestKind::ToolTurn)[0], Candidate::Primary);
+        assert_eq!(
+            router.describe(),
+            ["压缩对话: flash（glm-4-flash）", "备用: deepseek（deepseek-chat） → local（qwen2.5）"]
+        );
+    }
+
+    #[test]
+    fn test_route_record() {
+        let route = ModelRoute {
+            model: "deepseek-chat".to_string(),
+            profile: Some("deepseek".to_string()),
+            reason: RouteReason::Fallback,
+            error: Some("glm-4-plus: API 请求失败 (429 Too Many Requests)".to_string()),
+        };
+        assert_eq!(route.to_string(), "deepseek-chat（备用，profile deepseek）");
+        let value = serde_json::to_value(&route).unwrap();
+        assert_eq!(value["reason"], "fallback");
+        assert_eq!(serde_json::from_value::<ModelRoute>(value).unwrap(), route);
+    }
+}
@@ -0,0 +1,515 @@
+// 项目浏览工具 - list_dir / glob / grep
+//
+// 遍历目录时遵循 .gitignore，并通过 PathPolicy 过滤禁止访问的路径，
+// 避免模型通过 shell 拼凑 ls/find/grep（macOS 与 Linux 的参数并不一致）。
+
+use crate::file_tools::decode_text;
+use crate::path_policy::PathPolicy;
+use crate::tools::{ToolEffect, ToolExecutor};
+use async_trait::async_trait;
+use globset::GlobBuilder;
+use ignore::WalkBuilder;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// list_dir 默认深度
+pub const DEFAULT_LIST_DEPTH: usize = 1;
+
+/// list_dir 最大深度
+pub const MAX_LIST_DEPTH: usize = 5;
+
+/// 默认返回的最大条目数
+pub const DEFAULT_RESULT_LIMIT: usize = 200;
+
+/// grep 默认返回的最大匹配数
+pub const DEFAULT_GREP_LIMIT: usize = 100;
+
+/// grep 上下文行数上限
+pub const MAX_CONTEXT_LINES: usize = 10;
+
+/// grep 跳过超过该大小的文件
+const MAX_GREP_FILE_BYTES: u64 = 2 * 1024 * 1024;
+
+/// 创建遵循 .gitignore 的遍历器（显示隐藏文件，但跳过 .git 目录）
+fn walker(root: &Path, max_depth: Option<usize>) -> ignore::Walk {
+    WalkBuilder::new(root)
+        .hidden(false)
+        .require_git(false)
+        .max_depth(max_depth)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build()
+}
+
+/// 显示用的相对路径
+fn display_path(path: &Path, base: &Path) -> String {
+    path.strip_prefix(base)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .to_string()
+}
+
+fn resolve_base(policy: &PathPolicy, arguments: &serde_json::Value) -> Result<PathBuf, anyhow::Error> {
+    let path = arguments["path"].as_str().unwrap_or(".");
+    let base = policy.resolve(Path::new(path))?;
+    if !base.exists() {
+        return Err(anyhow::anyhow!("路径不存在: {}", path));
+    }
+    Ok(base)
+}
+
+fn limit_arg(arguments: &serde_json::Value, default: usize) -> usize {
+    arguments["limit"].as_u64().map(|n| n as usize).unwrap_or(default).max(1)
+}
+
+/// 列出目录内容
+pub fn list_dir(policy: &PathPolicy, base: &Path, depth: usize, limit: usize) -> String {
+    let mut lines = Vec::new();
+    let mut total = 0;
+
+    for entry in walker(base, Some(depth)).flatten() {
+        if entry.depth() == 0 || !policy.is_allowed(entry.path()) {
+            continue;
+        }
+        total += 1;
+        if lines.len() >= limit {
+            continue;
+        }
+
+        let indent = "  ".repeat(entry.depth() - 1);
+        let name = entry.file_name().to_string_lossy();
+        let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+        lines.push(format!("{}{}{}", indent, name, if is_dir { "/" } else { "" }));
+    }
+
+    if lines.is_empty() {
+        return "(空目录)".to_string();
+    }
+    let mut output = lines.join("\n");
+    if total > limit {
+        output.push_str(&format!("\n... (共 {} 项，只显示前 {} 项)", total, limit));
+    }
+    output
+}
+
+/// 按 glob 模式查找文件
+pub fn glob_files(policy: &PathPolicy, base: &Path, pattern: &str, limit: usize) -> Result<String, anyhow::Error> {
+    let matcher = GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .map_err(|e| anyhow::anyhow!("无效的 glob '{}': {}", pattern, e))?
+        .compile_matcher();
+
+    let mut matches = Vec::new();
+    let mut total = 0;
+    for entry in walker(base, None).flatten() {
+        let relative = display_path(entry.path(), base);
+        if entry.depth() == 0 || !matcher.is_match(&relative) || !policy.is_allowed(entry.path()) {
+            continue;
+        }
+        total += 1;
+        if matches.len() < limit {
+            matches.push(relative);
+        }
+    }
+
+    if matches.is_empty() {
+        return Ok(format!("没有匹配 '{}' 的文件", pattern));
+    }
+    let mut output = matches.join("\n");
+    if total > limit {
+        output.push_str(&format!("\n... (共 {} 个匹配，只显示前 {} 个)", total, limit));
+    }
+    Ok(output)
+}
+
+/// grep 选项
+#[derive(Debug, Clone)]
+pub struct GrepOptions {
+    pub pattern: String,
+    /// 只搜索匹配该 glob 的文件（如 "*.rs"）
+    pub include: Option<String>,
+    pub case_insensitive: bool,
+    /// 匹配行前后各显示的行数
+    pub context: usize,
+    pub limit: usize,
+}
+
+/// 在文件内容中搜索正则表达式（输出格式与 ripgrep 一致：匹配行用 ':'，上下文行用 '-'）
+pub fn grep_files(policy: &PathPolicy, base: &Path, options: &GrepOptions) -> Result<String, anyhow::Error> {
+    let regex = regex::RegexBuilder::new(&options.pattern)
+        .case_insensitive(options.case_insensitive)
+        .build()
+        .map_err(|e| anyhow::anyhow!("无效的正则表达式: {}", e))?;
+    let include = options
+        .include
+        .as_deref()
+        .map(|glob| {
+            GlobBuilder::new(glob)
+                .build()
+                .map(|g| g.compile_matcher())
+                .map_err(|e| anyhow::anyhow!("无效的 glob '{}': {}", glob, e))
+        })
+        .transpose()?;
+
+    let mut output = Vec::new();
+    let mut match_count = 0;
+    let mut truncated = false;
+
+    'files: for entry in walker(base, None).flatten() {
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+        let path = entry.path();
+        let relative = display_path(path, base);
+        let relative = if relative.is_empty() {
+            entry.file_name().to_string_lossy().to_string()
+        } else {
+            relative
+        };
+        if let Some(include) = &include {
+            if !include.is_match(&relative) && !include.is_match(entry.file_name()) {
+                continue;
+            }
+        }
+        if !policy.is_allowed(path) || entry.metadata().map(|m| m.len()).unwrap_or(0) > MAX_GREP_FILE_BYTES {
+            continue;
+        }
+        // 二进制文件直接跳过
+        let Ok(decoded) = std::fs::read(path).map_err(anyhow::Error::from).and_then(|b| decode_text(&b)) else {
+            continue;
+        };
+
+        let lines: Vec<&str> = decoded.text.lines().collect();
+        let mut printed_until = 0; // 已输出到的行（不含）
+        for (index, line) in lines.iter().enumerate() {
+            if !regex.is_match(line) {
+                continue;
+            }
+            if match_count >= options.limit {
+                truncated = true;
+                break 'files;
+            }
+            match_count += 1;
+
+            let start = index.saturating_sub(options.context).max(printed_until);
+            if printed_until > 0 && start > printed_until && options.context > 0 {
+                output.push("--".to_string());
+            }
+            for (i, context_line) in lines.iter().enumerate().take(index).skip(start) {
+                output.push(format!("{}-{}-{}", relative, i + 1, context_line));
+            }
+            if index >= printed_until {
+                output.push(format!("{}:{}:{}", relative, index + 1, line));
+            }
+
+            let end = (index + 1 + options.context).min(lines.len());
+            for (i, context_line) in lines.iter().enumerate().take(end).skip(index + 1) {
+                if regex.is_match(context_line) {
+                    // 后续匹配行由下一次循环输出
+                    break;
+                }
+                output.push(format!("{}-{}-{}", relative, i + 1, context_line));
+                printed_until = i + 1;
+            }
+            printed_until = printed_until.max(index + 1);
+        }
+    }
+
+    if output.is_empty() {
+        return Ok(format!("没有匹配 '{}' 的内容", options.pattern));
+    }
+    let mut text = output.join("\n");
+    if truncated {
+        text.push_str(&format!("\n... (已达到 {} 个匹配的上限)", options.limit));
+    }
+    Ok(text)
+}
+
+/// 目录列表工具
+pub struct ListDirTool {
+    policy: Arc<PathPolicy>,
+}
+
+impl ListDirTool {
+    pub fn new(policy: Arc<PathPolicy>) -> Self {
+        Self { policy }
+    }
+}
+
+#[async_trait]
+impl ToolExecutor for ListDirTool {
+    fn name(&self) -> &str {
+        "list_dir"
+    }
+
+    fn description(&self) -> &str {
+        "List the contents of a directory as an indented tree (directories end with '/'). Respects .gitignore."
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": {
+                    "type": "string",
+                    "description": "Directory to list (default: workspace root)"
+                },
+                "depth": {
+                    "type": "integer",
+                    "description": format!("How many levels to descend (default {}, max {})", DEFAULT_LIST_DEPTH, MAX_LIST_DEPTH)
+                },
+                "limit": {
+                    "type": "integer",
+                    "description": format!("Maximum number of entries (default {})", DEFAULT_RESULT_LIMIT)
+                }
+            },
+            "required": []
+        })
+    }
+
+    fn effect(&self) -> ToolEffect {
+        ToolEffect::ReadOnly
+    }
+
+    async fn execute(&self, arguments: serde_json::Value) -> Result<String, Box<dyn std::error::Error + Send>> {
+        println!("\n📂 ListDir 工具接收到参数: {}", arguments); // 调试输出
+
+        let base = resolve_base(&self.policy, &arguments)?;
+        if !base.is_dir() {
+            return Err(anyhow::anyhow!("{} 不是目录", base.display()).into());
+        }
+        let depth = arguments["depth"]
+            .as_u64()
+            .map(|n| n as usize)
+            .unwrap_or(DEFAULT_LIST_DEPTH)
+            .clamp(1, MAX_LIST_DEPTH);
+        let limit = limit_arg(&arguments, DEFAULT_RESULT_LIMIT);
+
+        let policy = self.policy.clone();
+        let output = tokio::task::spawn_blocking(move || list_dir(&policy, &base, depth, limit))
+            .await
+            .map_err(|e| anyhow::anyhow!("目录遍历失败: {}", e))?;
+        Ok(output)
+    }
+}
+
+/// glob 文件查找工具
+pub struct GlobTool {
+    policy: Arc<PathPolicy>,
+}
+
+impl GlobTool {
+    pub fn new(policy: Arc<PathPolicy>) -> Self {
+        Self { policy }
+    }
+}
+
+#[async_trait]
+impl ToolExecutor for GlobTool {
+    fn name(&self) -> &str {
+        "glob"
+    }
+
+    fn description(&self) -> &str {
+        "Find files whose path (relative to the search directory) matches a glob pattern such as '**/*.rs' or 'src/*.toml'. Respects .gitignore."
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "pattern": {
+                    "type": "string",
+                    "description": "Glob pattern, e.g. '**/*.rs'"
+                },
+                "path": {
+                    "type": "string",
+                    "description": "Directory to search in (default: workspace root)"
+                },
+                "limit": {
+                    "type": "integer",
+                    "description": format!("Maximum number of results (default {})", DEFAULT_RESULT_LIMIT)
+                }
+            },
+            "required": ["pattern"]
+        })
+    }
+
+    fn effect(&self) -> ToolEffect {
+        ToolEffect::ReadOnly
+    }
+
+    async fn execute(&self, arguments: serde_json::Value) -> Result<String, Box<dyn std::error::Error + Send>> {
+        println!("\n🔎 Glob 工具接收到参数: {}", arguments); // 调试输出
+
+        let pattern = arguments["pattern"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("缺少 'pattern' 参数"))?
+            .to_string();
+        let base = resolve_base(&self.policy, &arguments)?;
+        let limit = limit_arg(&arguments, DEFAULT_RESULT_LIMIT);
+
+        let policy = self.policy.clone();
+        let output = tokio::task::spawn_blocking(move || glob_files(&policy, &base, &pattern, limit))
+            .await
+            .map_err(|e| anyhow::anyhow!("文件查找失败: {}", e))??;
+        Ok(output)
+    }
+}
+
+/// 内容搜索工具
+pub struct GrepTool {
+    policy: Arc<PathPolicy>,
+}
+
+impl GrepTool {
+    pub fn new(policy: Arc<PathPolicy>) -> Self {
+        Self { policy }
+    }
+}
+
+#[async_trait]
+impl ToolExecutor for GrepTool {
+    fn name(&self) -> &str {
+        "grep"
+    }
+
+    fn description(&self) -> &str {
+        "Search file contents with a regular expression (Rust regex syntax). Output lines are 'path:line:text' for matches and 'path-line-text' for context lines. Respects .gitignore and skips binary files."
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "pattern": {
+                    "type": "string",
+                    "description": "Regular expression to search for"
+                },
+                "path": {
+                    "type": "string",
+                    "description": "File or directory to search (default: workspace root)"
+                },
+                "include": {
+                    "type": "string",
+                    "description": "Only search files matching this glob, e.g. '*.rs'"
+                },
+                "case_insensitive": {
+                    "type": "boolean",
+                    "description": "Ignore case when matching (default false)"
+                },
+                "context": {
+                    "type": "integer",
+                    "description": format!("Lines of context before and after each match (default 0, max {})", MAX_CONTEXT_LINES)
+                },
+                "limit": {
+                    "type": "integer",
+                    "description": format!("Maximum number of matches (default {})", DEFAULT_GREP_LIMIT)
+                }
+            },
+            "required": ["pattern"]
+        })
+    }
+
+    fn effect(&self) -> ToolEffect {
+        ToolEffect::ReadOnly
+    }
+
+    async fn execute(&self, arguments: serde_json::Value) -> Result<String, Box<dyn std::error::Error + Send>> {
+        println!("\n🔍 Grep 工具接收到参数: {}", arguments); // 调试输出
+
+        let options = GrepOptions {
+            pattern: arguments["pattern"]
+                .as_str()
+                .ok_or_else(|| anyhow::anyhow!("缺少 'pattern' 参数"))?
+                .to_string(),
+            include: arguments["include"].as_str().map(|s| s.to_string()),
+            case_insensitive: arguments["case_insensitive"].as_bool().unwrap_or(false),
+            context: arguments["context"]
+                .as_u64()
+                .map(|n| n as usize)
+                .unwrap_or(0)
+                .min(MAX_CONTEXT_LINES),
+            limit: limit_arg(&arguments, DEFAULT_GREP_LIMIT),
+        };
+        let base = resolve_base(&self.policy, &arguments)?;
+
+        let policy = self.policy.clone();
+        let output = tokio::task::spawn_blocking(move || grep_files(&policy, &base, &options))
+            .await
+            .map_err(|e| anyhow::anyhow!("内容搜索失败: {}", e))??;
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project() -> (tempfile::TempDir, PathPolicy) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("src/tools")).unwrap();
+        std::fs::create_dir_all(root.join("target/debug")).unwrap();
+        std::fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        std::fs::write(root.join(".env"), "OPENAI_API_KEY=sk-secret\n").unwrap();
+        std::fs::write(root.join("src/main.rs"), "fn main() {\n    let key = 1;\n    run();\n}\n").unwrap();
+        std::fs::write(root.join("src/tools/shell.rs"), "// shell\npub fn run() {}\n").unwrap();
+        std::fs::write(root.join("target/debug/build.rs"), "fn main() {}\n").unwrap();
+        let policy = PathPolicy::for_workspace(root).unwrap();
+        (dir, policy)
+    }
+
+    #[test]
+    fn test_list_dir_respects_gitignore_depth_and_policy() {
+        let (_dir, policy) = project();
+        let root = policy.workspace_root().to_path_buf();
+
+        let output = list_dir(&policy, &root, 2, 100);
+        assert!(output.contains("src/"));
+        assert!(output.contains("  main.rs"));
+        assert!(output.contains("  tools/"));
+        assert!(!output.contains("shell.rs"));
+        assert!(!output.contains("target"));
+        assert!(!output.contains(".env"));
+
+        let limited = list_dir(&policy, &root, 2, 1);
+        assert!(limited.contains("只显示前 1 项"));
+    }
+
+    #[test]
+    fn test_glob_files() {
+        let (_dir, policy) = project();
+        let root = policy.workspace_root().to_path_buf();
+
+        let output = glob_files(&policy, &root, "**/*.rs", 10).unwrap();
+        assert_eq!(output, "src/main.rs\nsrc/tools/shell.rs");
+    }
+
+    #[test]
+    fn test_grep_with_context() {
+        let (_dir, policy) = project();
+        let root = policy.workspace_root().to_path_buf();
+
+        let options = GrepOptions {
+            pattern: "KEY|key".to_string(),
+            include: None,
+            case_insensitive: false,
+            context: 1,
+            limit: 10,
+        };
+        let output = grep_files(&policy, &root, &options).unwrap();
+        assert_eq!(output, "src/main.rs-1-fn main() {\nsrc/main.rs:2:    let key = 1;\nsrc/main.rs-3-    run();");
+
+        let options = GrepOptions {
+            pattern: "fn".to_string(),
+            include: Some("shell.rs".to_string()),
+            context: 0,
+            ..options
+        };
+        let output = grep_files(&policy, &root, &options).unwrap();
+        assert_eq!(output, "src/tools/shell.rs:2:pub fn run() {}");
+    }
+}
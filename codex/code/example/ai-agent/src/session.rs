@@ -0,0 +1,338 @@
+// 会话存储 - 将对话历史保存为 JSON 文件，供 `sessions` 子命令和 `chat --resume` 使用
+//
+// 每个会话一个文件：`<数据目录>/sessions/<id>.json`，数据目录为
+// `$XDG_DATA_HOME/simple-ai-agent` 或 `~/.local/share/simple-ai-agent`。
+
+use crate::router::ModelRoute;
+use crate::usage::UsageTotals;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// 会话标题的最大字符数（取第一条用户消息）
+const TITLE_MAX_CHARS: usize = 60;
+
+/// 会话元信息
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SessionMeta {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// 会话所在的工作目录
+    pub cwd: PathBuf,
+    pub model: String,
+    #[serde(default)]
+    pub title: String,
+}
+
+/// 保存的会话：元信息 + 对话历史 + 累计用量
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub meta: SessionMeta,
+    pub conversation: Vec<Value>,
+    /// 接口返回的累计 token 用量和费用（旧版本保存的会话没有此字段）
+    #[serde(default)]
+    pub usage: UsageTotals,
+}
+
+impl Session {
+    /// 创建新会话
+    pub fn new(cwd: PathBuf, model: String) -> Self {
+        let now = Utc::now();
+        Self {
+            meta: SessionMeta {
+                id: uuid::Uuid::new_v4().to_string(),
+                created_at: now,
+                updated_at: now,
+                cwd,
+                model,
+                title: String::new(),
+            },
+            conversation: Vec::new(),
+            usage: UsageTotals::default(),
+        }
+    }
+
+    /// 更新对话历史，标题取第一条用户消息
+    pub fn update(&mut self, conversation: Vec<Value>) {
+        if self.meta.title.is_empty() {
+            if let Some(first) = conversation.iter().find(|msg| message_role(msg) == MessageRole::User) {
+                let text = message_text(first);
+                let line = text.lines().next().unwrap_or_default();
+                self.meta.title = line.chars().take(TITLE_MAX_CHARS).collect();
+            }
+        }
+        self.conversation = conversation;
+        self.meta.updated_at = Utc::now();
+    }
+
+    /// 以可读文本形式输出对话历史
+    pub fn render(&self) -> String {
+        let mut out = format!(
+            "会话 {}\n标题: {}\n模型: {}\n目录: {}\n创建: {}\n更新: {}\n",
+            self.meta.id,
+            self.meta.title,
+            self.meta.model,
+            self.meta.cwd.display(),
+            self.meta.created_at.format("%Y-%m-%d %H:%M:%S"),
+            self.meta.updated_at.format("%Y-%m-%d %H:%M:%S"),
+        );
+        if self.usage.requests > 0 {
+            out.push_str(&format!("用量: {}\n", self.usage));
+        }
+        out.push('\n');
+        out.push_str(&render_conversation(&self.conversation));
+        out
+    }
+}
+
+/// 以可读文本形式输出对话历史中的消息
+pub fn render_conversation(conversation: &[Value]) -> String {
+    let mut out = String::new();
+    for msg in conversation {
+        let content = message_text(msg);
+        match message_role(msg) {
+            MessageRole::User => out.push_str(&format!("👤 {}\n", content)),
+            MessageRole::Attachment => out.push_str(&format!("🖼️  {}\n", content)),
+            MessageRole::Reasoning => out.push_str(&format!("💭 {}\n", msg["reasoning"].as_str().unwrap_or_default().trim())),
+            MessageRole::Tool => out.push_str(&format!("🔧 [{}] {}\n", msg["tool_call_id"].as_str().unwrap_or_default(), content)),
+            MessageRole::Assistant => {
+                if !content.is_empty() {
+                    out.push_str(&format!("🤖 {}\n", content));
+                }
+                for call in msg["tool_calls"].as_array().into_iter().flatten() {
+                    out.push_str(&format!("🛠️  {}({})\n", call["name"].as_str().unwrap_or_default(), call["arguments"]));
+                }
+            }
+        }
+        // 由主模型以外的模型生成的消息
+        if let Ok(route) = ModelRoute::deserialize(&msg["route"]) {
+            out.push_str(&format!("🔀 {}\n", route));
+        }
+    }
+    out
+}
+
+/// 每张图片按固定的 token 数估算（data URL 的长度与实际计费无关）
+const IMAGE_TOKEN_ESTIMATE: usize = 1000;
+
+/// 对话历史的粗略 token 估算（约 4 字节一个 token；思考内容默认不回传，不计入）
+pub fn estimate_tokens(conversation: &[Value]) -> usize {
+    conversation
+        .iter()
+        .filter(|msg| message_role(msg) != MessageRole::Reasoning)
+        .map(|msg| {
+            let images = content_parts(msg).filter(|part| part["type"] == "image_url").count();
+            if images == 0 {
+                return msg.to_string().len() / 4;
+            }
+            let text: usize = content_parts(msg).filter_map(|part| part["text"].as_str()).map(str::len).sum();
+            text / 4 + images * IMAGE_TOKEN_ESTIMATE
+        })
+        .sum()
+}
+
+/// 消息的文本内容：内容片段中的文本按行拼接，图片显示为 `[图片]` 或其 URL（data URL 不展开）
+pub fn message_text(msg: &Value) -> String {
+    if let Some(text) = msg["content"].as_str() {
+        return text.to_string();
+    }
+    content_parts(msg)
+        .map(|part| match part["image_url"]["url"].as_str() {
+            Some(url) if url.starts_with("data:") => "[图片]".to_string(),
+            Some(url) => format!("[图片] {}", url),
+            None => part["text"].as_str().unwrap_or_default().to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// 用户消息或图片附件中的内容片段
+fn content_parts(msg: &Value) -> impl Iterator<Item = &Value> {
+    msg["content"]
+        .as_array()
+        .or_else(|| msg["attached_images"].as_array())
+        .into_iter()
+        .flatten()
+}
+
+/// 对话历史中消息的角色
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MessageRole {
+    User,
+    /// 工具附加的图片（`ImageAttachment`），不是新一轮的开始
+    Attachment,
+    /// 模型的思考过程（`ReasoningItem`）
+    Reasoning,
+    Assistant,
+    Tool,
+}
+
+/// 判断对话历史中消息的角色（与 client 中 format_messages 的规则一致）
+pub(crate) fn message_role(msg: &Value) -> MessageRole {
+    if msg.get("reasoning").is_some() {
+        MessageRole::Reasoning
+    } else if msg.get("attached_images").is_some() {
+        MessageRole::Attachment
+    } else if msg.get("tool_call_id").is_some() {
+        MessageRole::Tool
+    } else if msg.get("tool_calls").is_some() {
+        MessageRole::Assistant
+    } else {
+        MessageRole::User
+    }
+}
+
+/// 会话存储目录
+pub struct SessionStore {
+    dir: PathBuf,
+}
+
+impl SessionStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// 默认存储位置，无法确定主目录时返回 None
+    pub fn default_dir() -> Option<PathBuf> {
+        crate::config::data_dir().map(|dir| dir.join("sessions"))
+    }
+
+    /// 使用默认存储位置
+    pub fn open_default() -> Result<Self, anyhow::Error> {
+        Self::default_dir()
+            .map(Self::new)
+            .ok_or_else(|| anyhow::anyhow!("无法确定会话存储目录（未设置 HOME 或 XDG_DATA_HOME）"))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    /// 保存会话（先写临时文件再重命名，避免中断时留下半个文件）
+    pub fn save(&self, session: &Session) -> Result<(), anyhow::Error> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.path(&session.meta.id);
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(session)?)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    /// 列出所有会话，最近更新的在前
+    pub fn list(&self) -> Result<Vec<SessionMeta>, anyhow::Error> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut sessions = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            match Self::read(&path) {
+                Ok(session) => sessions.push(session.meta),
+                Err(e) => tracing::warn!("跳过无法解析的会话文件 {}: {}", path.display(), e),
+            }
+        }
+        sessions.sort_by_key(|meta| std::cmp::Reverse(meta.updated_at));
+        Ok(sessions)
+    }
+
+    fn read(path: &Path) -> Result<Session, anyhow::Error> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+
+    /// 按 id 或唯一前缀查找会话 id
+    pub fn resolve_id(&self, id: &str) -> Result<String, anyhow::Error> {
+        if id.is_empty() {
+            return Err(anyhow::anyhow!("会话 id 不能为空"));
+        }
+        let matches: Vec<String> = self
+            .list()?
+            .into_iter()
+            .map(|meta| meta.id)
+            .filter(|candidate| candidate.starts_with(id))
+            .collect();
+        match matches.as_slice() {
+            [only] => Ok(only.clone()),
+            [] => Err(anyhow::anyhow!("未找到会话 '{}'", id)),
+            _ => Err(anyhow::anyhow!("会话 id 前缀 '{}' 不唯一，匹配 {} 个会话", id, matches.len())),
+        }
+    }
+
+    /// 加载会话（支持 id 前缀）
+    pub fn load(&self, id: &str) -> Result<Session, anyhow::Error> {
+        let id = self.resolve_id(id)?;
+        Self::read(&self.path(&id))
+    }
+
+    /// 删除会话（支持 id 前缀），返回完整 id
+    pub fn delete(&self, id: &str) -> Result<String, anyhow::Error> {
+        let id = self.resolve_id(id)?;
+        std::fs::remove_file(self.path(&id))?;
+        Ok(id)
+    }
+
+    /// 最近更新的会话
+    pub fn latest(&self) -> Result<Option<SessionMeta>, anyhow::Error> {
+        Ok(self.list()?.into_iter().next())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_save_list_load_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SessionStore::new(dir.path().join("sessions"));
+        assert!(store.list().unwrap().is_empty());
+
+        let mut session = Session::new(PathBuf::from("/work"), "glm-4".to_string());
+        session.update(vec![
+            json!({ "content": "列出当前目录\n第二行" }),
+            json!({ "reasoning": "先列出目录" }),
+            json!({ "content": "", "tool_calls": [{ "id": "call_1", "name": "list_dir", "arguments": {} }] }),
+            json!({ "tool_call_id": "call_1", "content": "src/" }),
+            json!({
+                "content": "只有 src 目录",
+                "tool_calls": null,
+                "route": { "model": "deepseek-chat", "profile": "deepseek", "reason": "fallback" }
+            }),
+        ]);
+        session.usage.record(&crate::usage::TokenUsage { prompt_tokens: 900, completion_tokens: 100, ..Default::default() }, None);
+        store.save(&session).unwrap();
+        assert_eq!(session.meta.title, "列出当前目录");
+
+        let listed = store.list().unwrap();
+        assert_eq!(listed, vec![session.meta.clone()]);
+
+        let prefix = &session.meta.id[..8];
+        let loaded = store.load(prefix).unwrap();
+        assert_eq!(loaded.conversation.len(), 5);
+        assert_eq!(loaded.usage, session.usage);
+        let rendered = loaded.render();
+        assert!(rendered.contains("用量: 1.0k tokens（输入 900，输出 100），1 次请求\n"));
+        assert!(rendered.contains("👤 列出当前目录"));
+        assert!(rendered.contains("💭 先列出目录"));
+        assert!(rendered.contains("🛠️  list_dir({})"));
+        assert!(rendered.contains("🔧 [call_1] src/"));
+        assert!(rendered.contains("🤖 只有 src 目录"));
+        assert!(rendered.contains("🔀 deepseek-chat（备用，profile deepseek）"));
+        assert_eq!(rendered.matches("🔀").count(), 1);
+
+        assert_eq!(store.delete(prefix).unwrap(), session.meta.id);
+        assert!(store.load(prefix).is_err());
+    }
+}
@@ -0,0 +1,586 @@
+// 持久化 Shell 会话 - 基于 PTY 的有状态 shell（简化版 Codex unified exec）
+//
+// 每个对话持有一个常驻 shell，`cd`、`export`、激活的 virtualenv 等状态在多次调用之间保留。
+// 命令是否结束由提示符判断：PS1 被设为带退出码的结束标记，shell 每次回到提示符都会输出一次，
+// 命令被 Ctrl-C 中断（shell 放弃整段命令）时也不例外。
+
+use crate::exec::{truncate_head_tail, ExecPolicy, Utf8ChunkDecoder};
+use crate::tools::{ToolEffect, ToolExecutor, ToolOutputSink};
+use async_trait::async_trait;
+use portable_pty::{native_pty_system, Child, CommandBuilder, PtySize};
+use serde::Serialize;
+use serde_json::json;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+
+/// 默认等待输出的时间（毫秒）
+pub const DEFAULT_YIELD_MS: u64 = 1_000;
+
+/// 单次调用最长等待时间（毫秒）
+pub const MAX_YIELD_MS: u64 = 30_000;
+
+/// 结束标记前缀（后面跟随会话 ID 和退出码）
+const DONE_MARKER: &str = "__AI_AGENT_DONE_";
+
+/// 单次会话交互的结果
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SessionOutput {
+    pub output: String,
+    /// 上一个命令是否仍在运行（可以继续 write/read）
+    pub running: bool,
+    /// 命令结束时的退出码
+    pub exit_code: Option<i32>,
+    /// shell 进程是否已退出（下次调用会启动新会话）
+    pub session_closed: bool,
+    pub truncated: bool,
+}
+
+/// PTY 输出解码：多字节字符和 \r\n 都可能被拆到两次读取之间，跨块保留未完成的部分
+#[derive(Debug, Default)]
+struct PtyDecoder {
+    utf8: Utf8ChunkDecoder,
+    /// 上一块以 \r 结尾，等下一块确定是否为 \r\n
+    pending_cr: bool,
+}
+
+impl PtyDecoder {
+    fn decode(&mut self, bytes: &[u8]) -> String {
+        let mut text = self.utf8.decode(bytes);
+        if std::mem::take(&mut self.pending_cr) {
+            text.insert(0, '\r');
+        }
+        if text.ends_with('\r') {
+            text.pop();
+            self.pending_cr = true;
+        }
+        text.replace("\r\n", "\n")
+    }
+}
+
+/// 一个常驻的 PTY shell
+struct ShellSession {
+    // 保持 master 存活，关闭后 shell 会收到 SIGHUP
+    _master: Box<dyn portable_pty::MasterPty + Send>,
+    writer: Box<dyn Write + Send>,
+    child: Box<dyn Child + Send + Sync>,
+    output_rx: mpsc::UnboundedReceiver<Vec<u8>>,
+    decoder: PtyDecoder,
+    /// 尚未返回给模型的输出
+    pending: String,
+    /// pending 中已经推送给 sink 的字节数
+    streamed: usize,
+    /// 本会话的结束标记（由提示符输出）
+    marker: String,
+    /// 是否有运行中的命令
+    running: bool,
+    closed: bool,
+}
+
+impl ShellSession {
+    fn spawn(workdir: Option<PathBuf>, policy: &ExecPolicy) -> Result<Self, anyhow::Error> {
+        let pair = native_pty_system().openpty(PtySize {
+            rows: 40,
+            cols: 200,
+            pixel_width: 0,
+            pixel_height: 0,
+        })?;
+
+        let build = |program: &str| {
+            let mut cmd = CommandBuilder::new(program);
+            if program == "bash" {
+                cmd.args(["--noprofile", "--norc"]);
+            }
+            cmd.env_clear();
+            for name in &policy.env_allowlist {
+                if let Ok(value) = std::env::var(name) {
+                    cmd.env(name, value);
+                }
+            }
+            // 关闭颜色和提示符，尽量让输出保持纯文本
+            cmd.env("TERM", "dumb");
+            cmd.env("PS1", "");
+            cmd.env("PS2", "");
+            match &workdir {
+                Some(dir) => cmd.cwd(dir),
+                None => {
+                    if let Ok(dir) = std::env::current_dir() {
+                        cmd.cwd(dir);
+                    }
+                }
+            }
+            cmd
+        };
+
+        // 优先使用 bash（支持 source 激活 virtualenv），不可用时回退到 sh
+        let child = pair
+            .slave
+            .spawn_command(build("bash"))
+            .or_else(|_| pair.slave.spawn_command(build("sh")))?;
+        drop(pair.slave);
+
+        let mut reader = pair.master.try_clone_reader()?;
+        let writer = pair.master.take_writer()?;
+
+        // PTY 读取是阻塞的，放到独立线程中，通过 channel 推送给异步侧
+        let (tx, output_rx) = mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 8192];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if tx.send(buf[..n].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            _master: pair.master,
+            writer,
+            child,
+            output_rx,
+            decoder: PtyDecoder::default(),
+            pending: String::new(),
+            streamed: 0,
+            marker: format!("{}{}_", DONE_MARKER, uuid::Uuid::new_v4().simple()),
+            running: false,
+            closed: false,
+        })
+    }
+
+    /// 关闭回显、把结束标记设为提示符，并等待 shell 就绪（丢弃启动阶段的输出）
+    async fn initialize(&mut self) -> Result<(), anyhow::Error> {
+        // 提示符中的换行直接写在引号内（dash 不解析 PS1 中的 \n）
+        let setup = format!(
+            "stty -echo 2>/dev/null; [ -n \"$BASH_VERSION\" ] && set +H; PS1='{}$?__\n'\n",
+            self.marker
+        );
+        self.write_raw(&setup)?;
+        self.running = true;
+        let ready = self.collect(Duration::from_secs(5), &ToolOutputSink::noop()).await;
+        self.pending.clear();
+        self.streamed = 0;
+        if self.closed {
+            return Err(anyhow::anyhow!("shell 启动后立即退出"));
+        }
+        if ready.is_none() {
+            return Err(anyhow::anyhow!("shell 未输出结束标记（提示符不支持展开 $?）"));
+        }
+        Ok(())
+    }
+
+    /// 写入命令（结束后 shell 回到提示符，输出结束标记）
+    fn start_command(&mut self, command: &str) -> Result<(), anyhow::Error> {
+        // 用 { } 包裹成一条命令：多行命令只在全部执行完后才回到提示符
+        self.write_raw(&format!("{{ {}\n}}\n", command))?;
+        self.running = true;
+        Ok(())
+    }
+
+    fn write_raw(&mut self, input: &str) -> Result<(), anyhow::Error> {
+        self.writer.write_all(input.as_bytes())?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// 在 yield 时间内收集输出；命令结束或 shell 退出时提前返回
+    async fn collect(&mut self, yield_time: Duration, sink: &ToolOutputSink) -> Option<i32> {
+        let deadline = tokio::time::Instant::now() + yield_time;
+
+        let exit_code = loop {
+            if let Some(code) = self.take_exit_code() {
+                break Some(code);
+            }
+
+            tokio::select! {
+                chunk = self.output_rx.recv() => match chunk {
+                    Some(bytes) => {
+                        let text = self.decoder.decode(&bytes);
+                        self.pending.push_str(&text);
+                        self.stream_pending(sink, false);
+                    }
+                    None => {
+                        self.closed = true;
+                        self.running = false;
+                        break None;
+                    }
+                },
+                _ = tokio::time::sleep_until(deadline) => break None,
+            }
+        };
+
+        self.stream_pending(sink, true);
+        exit_code
+    }
+
+    /// 把尚未推送的输出发给 sink（结束标记可能跨块到达，未 flush 时末尾留一段不推送）
+    fn stream_pending(&mut self, sink: &ToolOutputSink, flush: bool) {
+        let mut end = match self.pending[self.streamed..].find(DONE_MARKER) {
+            Some(pos) => self.streamed + pos,
+            None if flush => self.pending.len(),
+            None => self.pending.len().saturating_sub(DONE_MARKER.len()),
+        };
+        while end > self.streamed && !self.pending.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end > self.streamed {
+            sink.send(&self.pending[self.streamed..end]);
+            self.streamed = end;
+        }
+    }
+
+    /// 查找并移除结束标记，返回运行中命令的退出码
+    ///
+    /// 没有运行中的命令时（如空闲时写入了换行）shell 也会输出提示符，这些标记只移除不返回。
+    fn take_exit_code(&mut self) -> Option<i32> {
+        loop {
+            // 回显开启时会先看到设置 PS1 的命令本身（其中是 $? 而不是数字），需要跳过
+            let marker = self.marker.as_str();
+            let (start, code, len) = self.pending.match_indices(marker).find_map(|(start, _)| {
+                let rest = &self.pending[start + marker.len()..];
+                let end = rest.find("__\n")?;
+                let code = rest[..end].parse::<i32>().ok()?;
+                Some((start, code, marker.len() + end + 3))
+            })?;
+
+            self.pending.replace_range(start..start + len, "");
+            if self.running {
+                self.running = false;
+                return Some(code);
+            }
+        }
+    }
+
+    fn kill(&mut self) {
+        let _ = self.child.kill();
+        self.closed = true;
+    }
+}
+
+impl Drop for ShellSession {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+/// 会话管理器（每个对话一个实例）
+pub struct ShellSessionManager {
+    session: Mutex<Option<ShellSession>>,
+    policy: ExecPolicy,
+}
+
+impl ShellSessionManager {
+    pub fn new(policy: ExecPolicy) -> Self {
+        Self {
+            session: Mutex::new(None),
+            policy,
+        }
+    }
+
+    /// 在会话中执行命令（会话不存在时自动启动）
+    pub async fn exec(
+        &self,
+        command: &str,
+        workdir: Option<PathBuf>,
+        yield_time: Duration,
+        sink: &ToolOutputSink,
+    ) -> Result<SessionOutput, anyhow::Error> {
+        let mut guard = self.session.lock().await;
+
+        if guard.as_ref().is_some_and(|s| s.closed) {
+            *guard = None;
+        }
+        if guard.is_none() {
+            let mut session = ShellSession::spawn(workdir, &self.policy)?;
+            session.initialize().await?;
+            *guard = Some(session);
+        }
+
+        let session = guard.as_mut().expect("会话已启动");
+        if session.running {
+            return Err(anyhow::anyhow!(
+                "上一个命令仍在运行，请使用 write 向其输入数据、read 读取输出，或 kill 终止会话"
+            ));
+        }
+
+        session.start_command(command)?;
+        let exit_code = session.collect(yield_time, sink).await;
+        Ok(self.drain(&mut guard, exit_code))
+    }
+
+    /// 向正在运行的进程写入标准输入
+    pub async fn write(
+        &self,
+        input: &str,
+        yield_time: Duration,
+        sink: &ToolOutputSink,
+    ) -> Result<SessionOutput, anyhow::Error> {
+        let mut guard = self.session.lock().await;
+        let session = guard
+            .as_mut()
+            .filter(|s| !s.closed)
+            .ok_or_else(|| anyhow::anyhow!("没有活动的 shell 会话"))?;
+
+        session.write_raw(input)?;
+        let exit_code = session.collect(yield_time, sink).await;
+        Ok(self.drain(&mut guard, exit_code))
+    }
+
+    /// 读取增量输出
+    pub async fn read(&self, yield_time: Duration, sink: &ToolOutputSink) -> Result<SessionOutput, anyhow::Error> {
+        let mut guard = self.session.lock().await;
+        let session = guard
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("没有活动的 shell 会话"))?;
+
+        let exit_code = session.collect(yield_time, sink).await;
+        Ok(self.drain(&mut guard, exit_code))
+    }
+
+    /// 终止会话
+    pub async fn kill(&self) -> bool {
+        let mut guard = self.session.lock().await;
+        match guard.take() {
+            Some(mut session) => {
+                session.kill();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn drain(&self, guard: &mut Option<ShellSession>, exit_code: Option<i32>) -> SessionOutput {
+        let Some(session) = guard.as_mut() else {
+            return SessionOutput {
+                output: String::new(),
+                running: false,
+                exit_code,
+                session_closed: true,
+                truncated: false,
+            };
+        };
+
+        session.streamed = 0;
+        let (output, truncated) =
+            truncate_head_tail(&std::mem::take(&mut session.pending), self.policy.max_output_bytes);
+        let result = SessionOutput {
+            output,
+            running: session.running,
+            exit_code,
+            session_closed: session.closed,
+            truncated,
+        };
+
+        if session.closed {
+            *guard = None;
+        }
+        result
+    }
+}
+
+impl Default for ShellSessionManager {
+    fn default() -> Self {
+        Self::new(ExecPolicy::default())
+    }
+}
+
+/// 持久化 shell 会话工具
+pub struct ShellSessionTool {
+    manager: Arc<ShellSessionManager>,
+}
+
+impl ShellSessionTool {
+    pub fn new(manager: Arc<ShellSessionManager>) -> Self {
+        Self { manager }
+    }
+}
+
+#[async_trait]
+impl ToolExecutor for ShellSessionTool {
+    fn name(&self) -> &str {
+        "shell_session"
+    }
+
+    fn description(&self) -> &str {
+        "Run commands in a persistent interactive shell (PTY) that keeps state such as the current directory, exported variables and activated virtualenvs between calls. Actions: 'exec' runs a command, 'write' sends raw stdin to a running process (include '\\n' to submit a line, '\\u0003' for Ctrl-C), 'read' waits for more output, 'kill' terminates the session. Returns JSON with output, running, exit_code and session_closed."
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "action": {
+                    "type": "string",
+                    "enum": ["exec", "write", "read", "kill"],
+                    "description": "Operation to perform on the session"
+                },
+                "command": {
+                    "type": "string",
+                    "description": "Command to run (required for 'exec')"
+                },
+                "input": {
+                    "type": "string",
+                    "description": "Raw text written to the running process's stdin (required for 'write')"
+                },
+                "workdir": {
+                    "type": "string",
+                    "description": "Initial working directory, only used when a new session is started"
+                },
+                "yield_ms": {
+                    "type": "integer",
+                    "description": format!("How long to wait for output before returning (default {}, max {}). Returns early when the command finishes.", DEFAULT_YIELD_MS, MAX_YIELD_MS)
+                }
+            },
+            "required": ["action"]
+        })
+    }
+
+    fn effect(&self) -> ToolEffect {
+        ToolEffect::Mutating
+    }
+
+    async fn execute(&self, arguments: serde_json::Value) -> Result<String, Box<dyn std::error::Error + Send>> {
+        self.execute_streaming(arguments, ToolOutputSink::noop()).await
+    }
+
+    async fn execute_streaming(
+        &self,
+        arguments: serde_json::Value,
+        sink: ToolOutputSink,
+    ) -> Result<String, Box<dyn std::error::Error + Send>> {
+        println!("\n🖥️  ShellSession 工具接收到参数: {}", arguments); // 调试输出
+
+        let action = arguments["action"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("缺少 'action' 参数"))?;
+        let yield_time = Duration::from_millis(
+            arguments["yield_ms"]
+                .as_u64()
+                .unwrap_or(DEFAULT_YIELD_MS)
+                .min(MAX_YIELD_MS),
+        );
+
+        let output = match action {
+            "exec" => {
+                let command = arguments["command"]
+                    .as_str()
+                    .ok_or_else(|| anyhow::anyhow!("缺少 'command' 参数"))?;
+                let workdir = arguments["workdir"].as_str().map(PathBuf::from);
+                self.manager.exec(command, workdir, yield_time, &sink).await?
+            }
+            "write" => {
+                let input = arguments["input"]
+                    .as_str()
+                    .ok_or_else(|| anyhow::anyhow!("缺少 'input' 参数"))?;
+                self.manager.write(input, yield_time, &sink).await?
+            }
+            "read" => self.manager.read(yield_time, &sink).await?,
+            "kill" => {
+                let killed = self.manager.kill().await;
+                println!("✓ 会话已终止");
+                return Ok(json!({ "killed": killed }).to_string());
+            }
+            other => return Err(anyhow::anyhow!("未知的 action: {}", other).into()),
+        };
+
+        println!(
+            "✓ 会话输出 {} 字节 (running: {}, exit_code: {:?})",
+            output.output.len(),
+            output.running,
+            output.exit_code
+        );
+        Ok(serde_json::to_string(&output)
+            .map_err(|e| anyhow::anyhow!("JSON 序列化失败: {}", e))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pty_decoder_joins_split_chunks() {
+        let bytes = "中\r\n".as_bytes();
+        let mut decoder = PtyDecoder::default();
+        // "中" 的三个字节和 \r\n 都被拆开
+        let chunks = [&bytes[..1], &bytes[1..4], &bytes[4..]];
+        let text: String = chunks.iter().map(|chunk| decoder.decode(chunk)).collect();
+        assert_eq!(text, "中\n");
+        assert_eq!(decoder.decode(b"a\rb"), "a\rb");
+    }
+
+    #[tokio::test]
+    async fn test_session_keeps_state_between_calls() {
+        let manager = ShellSessionManager::default();
+        let yield_time = Duration::from_secs(5);
+        let sink = ToolOutputSink::noop();
+
+        let first = manager
+            .exec("cd /tmp && export AGENT_SESSION_VAR=kept", None, yield_time, &sink)
+            .await
+            .unwrap();
+        assert_eq!(first.exit_code, Some(0));
+        assert!(!first.running);
+
+        let second = manager
+            .exec("pwd; echo $AGENT_SESSION_VAR", None, yield_time, &sink)
+            .await
+            .unwrap();
+        assert!(second.output.contains("/tmp"));
+        assert!(second.output.contains("kept"));
+        assert!(!second.output.contains(DONE_MARKER));
+
+        assert!(manager.kill().await);
+    }
+
+    #[tokio::test]
+    async fn test_session_write_stdin_to_running_process() {
+        let manager = ShellSessionManager::default();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let sink = ToolOutputSink::new(tx);
+
+        let started = manager
+            .exec("read line; echo got:$line", None, Duration::from_millis(300), &sink)
+            .await
+            .unwrap();
+        assert!(started.running);
+        assert_eq!(started.exit_code, None);
+
+        let finished = manager.write("hello\n", Duration::from_secs(5), &sink).await.unwrap();
+        assert!(finished.output.contains("got:hello"));
+
+        // 增量输出与最终结果一致，且不包含结束标记
+        let mut streamed = String::new();
+        while let Ok(chunk) = rx.try_recv() {
+            streamed.push_str(&chunk);
+        }
+        assert_eq!(streamed, finished.output);
+        assert_eq!(finished.exit_code, Some(0));
+        assert!(!finished.running);
+    }
+
+    #[tokio::test]
+    async fn test_session_usable_after_ctrl_c() {
+        let manager = ShellSessionManager::default();
+        let sink = ToolOutputSink::noop();
+
+        let started = manager.exec("sleep 30", None, Duration::from_millis(300), &sink).await.unwrap();
+        assert!(started.running);
+
+        // Ctrl-C 让 shell 放弃整段命令，回到提示符时仍会输出结束标记
+        let interrupted = manager.write("\u{3}", Duration::from_secs(5), &sink).await.unwrap();
+        assert!(!interrupted.running);
+        assert_eq!(interrupted.exit_code, Some(130));
+
+        let next = manager.exec("echo ok", None, Duration::from_secs(5), &sink).await.unwrap();
+        assert_eq!(next.exit_code, Some(0));
+        assert_eq!(next.output.trim(), "ok");
+        assert!(manager.kill().await);
+    }
+}
@@ -0,0 +1,115 @@
+// 结构化输出 - 让最终回复符合由 Rust 类型生成的 JSON Schema（Agent::run_structured 使用）
+//
+// - schema 由类型的 `#[derive(JsonSchema)]` 生成，接口支持时作为 response_format 发送
+// - schema 总是同时写进提示：只支持 json_object 或不支持 response_format 的接口靠它约束字段
+// - 以能否反序列化为目标类型作为校验，失败时的错误信息用于要求模型重新输出
+
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+/// 回复不符合 schema 时最多重新要求的次数
+pub const MAX_STRUCTURED_RETRIES: usize = 2;
+
+/// 类型 T 的 JSON Schema 及其名称（response_format 要求名称只含字母、数字、`_` 和 `-`）
+pub fn schema_for<T: JsonSchema>() -> (String, Value) {
+    let mut schema = schemars::schema_for!(T).to_value();
+    if let Some(object) = schema.as_object_mut() {
+        object.remove("$schema");
+    }
+    let name = T::schema_name()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    (name, schema)
+}
+
+/// 接口能否用 response_format 约束该 schema（json_schema 和 json_object 都要求顶层是对象）
+pub fn supports_response_format(schema: &Value) -> bool {
+    schema["type"] == "object"
+}
+
+/// 附加在系统提示之后的格式要求（只随请求发送，不写入对话历史）
+pub fn instruction(schema: &Value) -> String {
+    format!(
+        "请以 JSON 给出最终回答：只输出一个符合下面 JSON Schema 的 JSON 值，不要使用 Markdown 代码块，也不要附加其他文字。\
+需要查询信息时先调用工具，拿到结果后再输出 JSON。\n\nJSON Schema:\n{}",
+        serde_json::to_string_pretty(schema).unwrap_or_default()
+    )
+}
+
+/// 回复无法解析时要求模型重新输出的提示
+pub fn retry_prompt(error: &str) -> String {
+    format!("上一条回复不符合要求的 JSON Schema：{}。请只输出修正后的完整 JSON，不要附加其他文字。", error)
+}
+
+/// 从回复中取出 JSON 并反序列化为 T，失败时返回错误说明
+pub fn parse_reply<T: DeserializeOwned>(reply: &str) -> Result<T, String> {
+    let json = extract_json(reply);
+    if json.is_empty() {
+        return Err("回复为空".to_string());
+    }
+    serde_json::from_str(json).map_err(|e| match e.classify() {
+        serde_json::error::Category::Data => e.to_string(),
+        _ => format!("回复不是有效的 JSON（{}）", e),
+    })
+}
+
+/// 去掉模型常加的 ```json 代码块和前后的说明文字
+fn extract_json(reply: &str) -> &str {
+    let reply = reply.trim();
+    if let Some(start) = reply.find("```") {
+        let block = &reply[start + 3..];
+        // 跳过代码块的语言标记
+        let body = block.find('\n').map_or(block, |newline| &block[newline + 1..]);
+        if let Some(end) = body.find("```") {
+            return body[..end].trim();
+        }
+    }
+    match (reply.find(['{', '[']), reply.rfind(['}', ']'])) {
+        (Some(start), Some(end)) if start < end => &reply[start..=end],
+        _ => reply,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[allow(dead_code)]
+    #[derive(Debug, Deserialize, JsonSchema, PartialEq)]
+    struct Itinerary {
+        /// 航班号
+        flight_number: String,
+        price: Option<f64>,
+    }
+
+    #[test]
+    fn test_schema_for() {
+        let (name, schema) = schema_for::<Itinerary>();
+        assert_eq!(name, "Itinerary");
+        assert!(supports_response_format(&schema));
+        assert!(schema.get("$schema").is_none());
+        assert_eq!(schema["properties"]["flight_number"]["description"], "航班号");
+        assert_eq!(schema["required"], serde_json::json!(["flight_number"]));
+
+        let (name, schema) = schema_for::<Vec<Itinerary>>();
+        assert_eq!(name, "Array_of_Itinerary");
+        assert!(!supports_response_format(&schema));
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        let expected = Itinerary { flight_number: "1024".to_string(), price: Some(680.0) };
+        assert_eq!(parse_reply::<Itinerary>(r#"{"flight_number":"1024","price":680}"#), Ok(expected));
+        let fenced = "查询结果如下：\n```json\n{\"flight_number\": \"1024\"}\n```";
+        assert_eq!(parse_reply::<Itinerary>(fenced).unwrap().flight_number, "1024");
+        let prose = "好的，行程是 {\"flight_number\": \"1024\", \"price\": null}。";
+        assert_eq!(parse_reply::<Itinerary>(prose).unwrap().price, None);
+
+        assert!(parse_reply::<Itinerary>(r#"{"price": 1}"#).unwrap_err().contains("missing field `flight_number`"));
+        assert!(parse_reply::<Itinerary>("航班号是 1024").unwrap_err().starts_with("回复不是有效的 JSON"));
+        assert_eq!(parse_reply::<Itinerary>("  "), Err("回复为空".to_string()));
+    }
+}
@@ -0,0 +1,415 @@
+// 文本工具调用 - 为不支持 tools 参数的模型模拟函数调用（tool_calling = "text"）
+//
+// - 工具定义渲染进系统提示，请求中不发送 tools / tool_choice / parallel_tool_calls
+// - 模型在回复中用 <tool_call> 标签或 ```json 代码块输出 {"name": ..., "arguments": ...}，
+//   TextToolCallParser 在流式输出过程中增量解析，产生与原生接口相同的 ToolCall
+// - 对话历史中的工具调用和工具结果以同样的文本格式回放（工具结果作为用户消息发送）
+// - 只有名称是已提供工具的 JSON 才视为工具调用，其他代码块原样作为回复输出
+
+use crate::protocol::ToolCall;
+use crate::request_options::{RequestOptions, ToolChoice};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+
+/// 工具调用块的开始和结束标签
+pub const TOOL_CALL_OPEN: &str = "<tool_call>";
+pub const TOOL_CALL_CLOSE: &str = "</tool_call>";
+
+/// Markdown 代码块标记
+const FENCE: &str = "```";
+
+/// 可能包含工具调用的代码块语言标记
+const TOOL_FENCE_TAGS: &[&str] = &["", "json", "tool_call"];
+
+/// 工具定义（请求中 tools 的格式）渲染为系统提示；tool_choice 为 none 时不提供工具，返回 None
+pub fn tools_prompt(tools: &[Value], options: &RequestOptions) -> Option<String> {
+    let mut prompt = String::from(
+        "# Tools\n\nYou can call the following tools. Each tool is listed with its description and the JSON Schema of its arguments:\n",
+    );
+    for tool in tools {
+        let function = &tool["function"];
+        prompt.push_str(&format!(
+            "\n## {}\n{}\nArguments: {}\n",
+            function["name"].as_str().unwrap_or_default(),
+            function["description"].as_str().unwrap_or_default(),
+            function["parameters"]
+        ));
+    }
+    prompt.push_str(&format!(
+        "\nTo call a tool, output a block in exactly this format:\n\n{}\n{{\"name\": \"<tool name>\", \"arguments\": {{<arguments as JSON>}}}}\n{}\n\n\
+After your tool calls, stop and wait: the results will be sent back to you in <tool_result> blocks. \
+Never write tool results yourself. When no tool is needed, answer directly without any {} block.",
+        TOOL_CALL_OPEN, TOOL_CALL_CLOSE, TOOL_CALL_OPEN
+    ));
+
+    match &options.tool_choice {
+        Some(ToolChoice::None) => return None,
+        Some(ToolChoice::Required) => prompt.push_str("\nYou must call at least one tool in your next reply."),
+        Some(ToolChoice::Function(name)) => {
+            prompt.push_str(&format!("\nYou must call the tool `{}` in your next reply.", name))
+        }
+        Some(ToolChoice::Auto) | None => {}
+    }
+    if options.parallel_tool_calls == Some(false) {
+        prompt.push_str("\nCall at most one tool per reply.");
+    } else {
+        prompt.push_str("\nYou may output several tool call blocks in one reply.");
+    }
+    Some(prompt)
+}
+
+/// 把已转换为 OpenAI 格式的消息改写为文本工具调用的形式
+///
+/// 助手消息中的 tool_calls 追加为 <tool_call> 块，连续的工具结果合并为一条带 <tool_result> 块的用户消息；
+/// prompt 并入第一条系统消息（没有时插入一条）。
+pub fn to_text_messages(messages: Vec<Value>, prompt: Option<&str>) -> Vec<Value> {
+    let mut names: HashMap<String, String> = HashMap::new();
+    let mut out: Vec<Value> = Vec::new();
+    let mut merge_results = false;
+    for mut msg in messages {
+        match msg["role"].as_str() {
+            Some("assistant") if msg.get("tool_calls").is_some() => {
+                let mut text = msg["content"].as_str().unwrap_or_default().trim_end().to_string();
+                for call in msg["tool_calls"].as_array().into_iter().flatten() {
+                    let name = call["function"]["name"].as_str().unwrap_or_default();
+                    names.insert(call["id"].as_str().unwrap_or_default().to_string(), name.to_string());
+                    let arguments = match &call["function"]["arguments"] {
+                        Value::String(text) => serde_json::from_str(text).unwrap_or_else(|_| json!(text)),
+                        other => other.clone(),
+                    };
+                    if !text.is_empty() {
+                        text.push_str("\n\n");
+                    }
+                    text.push_str(&render_call(name, &arguments));
+                }
+                if let Some(object) = msg.as_object_mut() {
+                    object.remove("tool_calls");
+                }
+                msg["content"] = json!(text);
+                out.push(msg);
+                merge_results = false;
+            }
+            Some("tool") => {
+                let name = msg["tool_call_id"].as_str().and_then(|id| names.get(id)).map_or("", String::as_str);
+                let content = match &msg["content"] {
+                    Value::String(text) => text.clone(),
+                    other => other.to_string(),
+                };
+                let block = format!("<tool_result name=\"{}\">\n{}\n</tool_result>", name, content);
+                match out.last_mut() {
+                    Some(last) if merge_results => {
+                        let merged = format!("{}\n\n{}", last["content"].as_str().unwrap_or_default(), block);
+                        last["content"] = json!(merged);
+                    }
+                    _ => out.push(json!({ "role": "user", "content": block })),
+                }
+                merge_results = true;
+            }
+            _ => {
+                out.push(msg);
+                merge_results = false;
+            }
+        }
+    }
+
+    if let Some(prompt) = prompt {
+        match out.first_mut() {
+            Some(first) if first["role"] == "system" => {
+                let content = format!("{}\n\n{}", first["content"].as_str().unwrap_or_default(), prompt);
+                first["content"] = json!(content);
+            }
+            _ => out.insert(0, json!({ "role": "system", "content": prompt })),
+        }
+    }
+    out
+}
+
+/// 一次工具调用的文本形式
+fn render_call(name: &str, arguments: &Value) -> String {
+    format!("{}\n{{\"name\": {}, \"arguments\": {}}}\n{}", TOOL_CALL_OPEN, json!(name), arguments, TOOL_CALL_CLOSE)
+}
+
+/// 缓冲区开头的块的解析结果
+enum Block {
+    /// 块还没有结束，等待更多输出
+    Incomplete,
+    /// 工具调用，以及块的长度
+    ToolCalls(Vec<ToolCall>, usize),
+    /// 不是工具调用，按普通文本输出的长度
+    Text(usize),
+    /// 其他语言的代码块：输出开头一行后原样输出到代码块结束
+    CodeFence(usize),
+}
+
+/// 从模型的文本输出中增量解析工具调用
+///
+/// `push` 返回可以立即显示的文本：可能是工具调用开头的部分先留在缓冲区，确定不是工具调用后再输出。
+/// 第一个工具调用之后的普通文本（通常是模型编造的工具结果）被丢弃。
+#[derive(Debug)]
+pub struct TextToolCallParser {
+    /// 可以调用的工具名
+    tools: HashSet<String>,
+    buffer: String,
+    /// 正在输出一个不含工具调用的代码块
+    in_fence: bool,
+    calls: Vec<ToolCall>,
+}
+
+impl TextToolCallParser {
+    pub fn new(tools: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            tools: tools.into_iter().collect(),
+            buffer: String::new(),
+            in_fence: false,
+            calls: Vec::new(),
+        }
+    }
+
+    /// 按请求中的工具定义创建
+    pub fn for_tools(tools: &[Value]) -> Self {
+        Self::new(tools.iter().filter_map(|tool| tool["function"]["name"].as_str().map(str::to_string)))
+    }
+
+    /// 追加一段输出，返回可以显示的文本
+    pub fn push(&mut self, text: &str) -> String {
+        self.buffer.push_str(text);
+        let mut out = String::new();
+        loop {
+            if self.in_fence {
+                match self.buffer.find(FENCE) {
+                    Some(pos) => {
+                        self.emit(&mut out, pos + FENCE.len());
+                        self.in_fence = false;
+                        continue;
+                    }
+                    None => {
+                        let keep = partial_marker_len(&self.buffer, &[FENCE]);
+                        self.emit(&mut out, self.buffer.len() - keep);
+                        return out;
+                    }
+                }
+            }
+
+            let start = [TOOL_CALL_OPEN, FENCE]
+                .iter()
+                .filter_map(|marker| self.buffer.find(marker))
+                .min();
+            let Some(start) = start else {
+                let keep = partial_marker_len(&self.buffer, &[TOOL_CALL_OPEN, FENCE]);
+                self.emit(&mut out, self.buffer.len() - keep);
+                return out;
+            };
+            self.emit(&mut out, start);
+            match self.parse_block() {
+                Block::Incomplete => return out,
+                Block::ToolCalls(calls, len) => {
+                    self.buffer.drain(..len);
+                    self.calls.extend(calls);
+                }
+                Block::Text(len) => self.emit(&mut out, len),
+                Block::CodeFence(len) => {
+                    self.emit(&mut out, len);
+                    self.in_fence = true;
+                }
+            }
+        }
+    }
+
+    /// 输出结束：解析没有结束标记的最后一个块，返回剩余的文本和所有工具调用
+    pub fn finish(&mut self) -> (String, Vec<ToolCall>) {
+        if !self.in_fence {
+            let body = if let Some(rest) = self.buffer.strip_prefix(TOOL_CALL_OPEN) {
+                Some(rest)
+            } else if let Some(rest) = self.buffer.strip_prefix(FENCE) {
+                rest.split_once('\n').map(|(_, body)| body)
+            } else {
+                None
+            };
+            if let Some(calls) = body.and_then(|body| self.parse_calls(body)) {
+                self.calls.extend(calls);
+                self.buffer.clear();
+            }
+        }
+        let mut out = String::new();
+        self.emit(&mut out, self.buffer.len());
+        self.in_fence = false;
+
+        let calls = std::mem::take(&mut self.calls);
+        if !calls.is_empty() {
+            println!("\n✅ 从文本中解析工具调用: {} 个", calls.len());
+            for tc in &calls {
+                println!("  - {} ({})", tc.name, tc.id);
+            }
+        }
+        (out, calls)
+    }
+
+    /// 一次性解析完整的回复（非流式请求使用）
+    pub fn parse(&mut self, text: &str) -> (String, Vec<ToolCall>) {
+        let mut content = self.push(text);
+        let (rest, calls) = self.finish();
+        content.push_str(&rest);
+        (content, calls)
+    }
+
+    /// 从缓冲区取出 len 字节；已经解析到工具调用时丢弃
+    fn emit(&mut self, out: &mut String, len: usize) {
+        let text: String = self.buffer.drain(..len).collect();
+        if self.calls.is_empty() {
+            out.push_str(&text);
+        }
+    }
+
+    /// 解析缓冲区开头的 <tool_call> 块或代码块
+    fn parse_block(&self) -> Block {
+        if let Some(rest) = self.buffer.strip_prefix(TOOL_CALL_OPEN) {
+            let Some(end) = rest.find(TOOL_CALL_CLOSE) else {
+                return Block::Incomplete;
+            };
+            let len = TOOL_CALL_OPEN.len() + end + TOOL_CALL_CLOSE.len();
+            return match self.parse_calls(&rest[..end]) {
+                Some(calls) => Block::ToolCalls(calls, len),
+                None => Block::Text(len),
+            };
+        }
+
+        let rest = &self.buffer[FENCE.len()..];
+        let Some(newline) = rest.find('\n') else {
+            return Block::Incomplete;
+        };
+        let header = FENCE.len() + newline + 1;
+        if !TOOL_FENCE_TAGS.contains(&rest[..newline].trim()) {
+            return Block::CodeFence(header);
+        }
+        let body = &self.buffer[header..];
+        let Some(end) = body.find(FENCE) else {
+            return Block::Incomplete;
+        };
+        let len = header + end + FENCE.len();
+        match self.parse_calls(&body[..end]) {
+            Some(calls) => Block::ToolCalls(calls, len),
+            None => Block::Text(len),
+        }
+    }
+
+    /// 块内容解析为工具调用：单个对象或对象数组，每一项的 name 都必须是已提供的工具
+    fn parse_calls(&self, body: &str) -> Option<Vec<ToolCall>> {
+        let items = match serde_json::from_str::<Value>(body.trim()).ok()? {
+            Value::Array(items) if !items.is_empty() => items,
+            Value::Array(_) => return None,
+            item => vec![item],
+        };
+        items.iter().map(|item| self.parse_call(item)).collect()
+    }
+
+    fn parse_call(&self, item: &Value) -> Option<ToolCall> {
+        let name = item["name"].as_str().filter(|name| self.tools.contains(*name))?;
+        // 有的模型用 parameters 而不是 arguments
+        let arguments = match item.get("arguments").or_else(|| item.get("parameters")) {
+            None | Some(Value::Null) => json!({}),
+            Some(Value::String(text)) => serde_json::from_str(text).unwrap_or_else(|_| json!({ "raw": text })),
+            Some(arguments) => arguments.clone(),
+        };
+        Some(ToolCall {
+            id: format!("call_{}", uuid::Uuid::new_v4().simple()),
+            name: name.to_string(),
+            arguments,
+        })
+    }
+}
+
+/// 文本末尾可能是某个标记开头的部分的长度（这部分需要等更多输出才能确定）
+fn partial_marker_len(text: &str, markers: &[&str]) -> usize {
+    markers
+        .iter()
+        .flat_map(|marker| (1..marker.len()).filter(|&len| text.ends_with(&marker[..len])))
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parser() -> TextToolCallParser {
+        TextToolCallParser::new(["get_ticket_price".to_string(), "list_dir".to_string()])
+    }
+
+    #[test]
+    fn test_parse_streamed_tool_calls() {
+        let mut parser = parser();
+        let reply = "我来查一下。\n<tool_call>\n{\"name\": \"get_ticket_price\", \"arguments\": {\"flight_number\": \"1024\"}}\n</tool_call>\n\
+```json\n{\"name\": \"list_dir\", \"parameters\": {}}\n```\n<tool_result>票价 680 元</tool_result>";
+        // 逐字符输入，模拟流式输出
+        let mut shown = String::new();
+        for c in reply.chars() {
+            shown.push_str(&parser.push(&c.to_string()));
+        }
+        let (rest, calls) = parser.finish();
+        shown.push_str(&rest);
+
+        assert_eq!(shown, "我来查一下。\n");
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].name, "get_ticket_price");
+        assert_eq!(calls[0].arguments, json!({"flight_number": "1024"}));
+        assert_eq!(calls[1].arguments, json!({}));
+        assert_ne!(calls[0].id, calls[1].id);
+    }
+
+    #[test]
+    fn test_other_blocks_stay_text() {
+        let mut parser = parser();
+        let reply = "示例：\n```rust\nlet s = 1;\n```\n结果 `a<b`\n```json\n{\"name\": \"rm\", \"arguments\": {}}\n```";
+        let mut shown = parser.push(&reply[..20]);
+        shown.push_str(&parser.push(&reply[20..]));
+        let (rest, calls) = parser.finish();
+        shown.push_str(&rest);
+        assert_eq!(shown, reply);
+        assert!(calls.is_empty());
+
+        // 没有结束标签的工具调用（如被 stop 截断）在结束时解析
+        let (text, calls) = parser.parse("<tool_call>\n[{\"name\": \"list_dir\", \"arguments\": \"{\\\"path\\\": \\\"src\\\"}\"}]");
+        assert_eq!(text, "");
+        assert_eq!(calls[0].arguments, json!({"path": "src"}));
+    }
+
+    #[test]
+    fn test_to_text_messages() {
+        let messages = vec![
+            json!({ "role": "system", "content": "You are helpful." }),
+            json!({ "role": "user", "content": "列出 src" }),
+            json!({
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [
+                    { "id": "call_1", "type": "function", "function": { "name": "list_dir", "arguments": "{\"path\":\"src\"}" } },
+                    { "id": "call_2", "type": "function", "function": { "name": "list_dir", "arguments": "{}" } }
+                ]
+            }),
+            json!({ "role": "tool", "tool_call_id": "call_1", "content": "main.rs" }),
+            json!({ "role": "tool", "tool_call_id": "call_2", "content": "src/" }),
+        ];
+        let tools = vec![json!({ "type": "function", "function": { "name": "list_dir", "description": "List a directory", "parameters": {} } })];
+        let options = RequestOptions { tool_choice: Some(ToolChoice::Function("list_dir".to_string())), ..Default::default() };
+        let prompt = tools_prompt(&tools, &options).unwrap();
+        assert!(prompt.contains("## list_dir\nList a directory"));
+        assert!(prompt.contains("You must call the tool `list_dir`"));
+
+        let text = to_text_messages(messages, Some(&prompt));
+        assert_eq!(text.len(), 4);
+        assert!(text[0]["content"].as_str().unwrap().starts_with("You are helpful.\n\n# Tools"));
+        assert!(text[2].get("tool_calls").is_none());
+        assert_eq!(
+            text[2]["content"],
+            "<tool_call>\n{\"name\": \"list_dir\", \"arguments\": {\"path\":\"src\"}}\n</tool_call>\n\n<tool_call>\n{\"name\": \"list_dir\", \"arguments\": {}}\n</tool_call>"
+        );
+        assert_eq!(text[3]["role"], "user");
+        assert_eq!(
+            text[3]["content"],
+            "<tool_result name=\"list_dir\">\nmain.rs\n</tool_result>\n\n<tool_result name=\"list_dir\">\nsrc/\n</tool_result>"
+        );
+
+        let none = RequestOptions { tool_choice: Some(ToolChoice::None), ..Default::default() };
+        assert!(tools_prompt(&tools, &none).is_none());
+    }
+}
@@ -1,9 +1,52 @@
 // 工具系统实现
 
+use crate::exec::{run_command, ExecParams, ExecPolicy, MAX_TIMEOUT_MS};
+use crate::file_tools::{decode_text, format_numbered_lines, DEFAULT_READ_LIMIT, MAX_READ_FILE_BYTES};
+use crate::path_policy::PathPolicy;
 use crate::protocol::{ToolCall, ToolDefinition, ToolResult};
 use async_trait::async_trait;
 use serde_json::json;
 use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// 工具增量输出的发送端（运行中的工具通过它推送输出片段）
+#[derive(Debug, Clone, Default)]
+pub struct ToolOutputSink {
+    tx: Option<mpsc::UnboundedSender<String>>,
+}
+
+impl ToolOutputSink {
+    pub fn new(tx: mpsc::UnboundedSender<String>) -> Self {
+        Self { tx: Some(tx) }
+    }
+
+    /// 丢弃所有输出（非流式调用时使用）
+    pub fn noop() -> Self {
+        Self { tx: None }
+    }
+
+    pub fn send(&self, chunk: impl Into<String>) {
+        let chunk = chunk.into();
+        if chunk.is_empty() {
+            return;
+        }
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(chunk);
+        }
+    }
+}
+
+/// 工具对环境的影响，审批策略据此决定调用前是否需要用户确认
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolEffect {
+    /// 只读取信息，不执行命令也不修改文件
+    ReadOnly,
+    /// 执行命令或修改文件
+    Mutating,
+    /// 无法判断（WASM 插件等外部工具）
+    Unknown,
+}
 
 /// 工具执行器 trait（类似 Codex 的 ToolHandler）
 #[async_trait]
@@ -14,13 +57,38 @@ pub trait ToolExecutor: Send + Sync {
 
     fn parameters(&self) -> serde_json::Value;
 
+    /// 工具的影响范围，默认视为无法判断
+    fn effect(&self) -> ToolEffect {
+        ToolEffect::Unknown
+    }
+
+    /// 审批提示中附带的说明（如外部服务器自称只读），不影响是否需要审批
+    fn approval_hint(&self) -> Option<String> {
+        None
+    }
+
     #[allow(dead_code)]
     async fn execute(&self, arguments: serde_json::Value) -> Result<String, Box<dyn std::error::Error + Send>>;
+
+    /// 流式执行：运行中通过 sink 推送增量输出，返回值仍是交给模型的完整结果
+    ///
+    /// 默认实现直接调用 `execute`，长时间运行的工具可以覆盖此方法。
+    async fn execute_streaming(
+        &self,
+        arguments: serde_json::Value,
+        sink: ToolOutputSink,
+    ) -> Result<String, Box<dyn std::error::Error + Send>> {
+        let _ = sink;
+        self.execute(arguments).await
+    }
 }
 
 /// 工具注册表（简化版 ToolRegistry）
+///
+/// 执行器以 Arc 共享，克隆注册表只复制句柄，可以脱离持有它的 Agent 独立执行工具。
+#[derive(Clone)]
 pub struct ToolRegistry {
-    tools: HashMap<String, Box<dyn ToolExecutor>>,
+    tools: HashMap<String, Arc<dyn ToolExecutor>>,
 }
 
 impl ToolRegistry {
@@ -36,7 +104,7 @@ impl ToolRegistry {
     {
         let name = tool.name().to_string();
         println!("  ✅ 注册工具: {}", name);
-        self.tools.insert(name, Box::new(tool));
+        self.tools.insert(name, Arc::new(tool));
     }
 
     #[allow(dead_code)]
@@ -44,19 +112,43 @@ impl ToolRegistry {
         self.tools.get(name).map(|t| t.as_ref())
     }
 
+    /// 只保留满足条件的工具（用于按配置启用部分工具）
+    pub fn retain<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(&str) -> bool,
+    {
+        self.tools.retain(|name, _| keep(name));
+    }
+
+    /// 已注册的工具名（按名称排序）
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.tools.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// 所有工具的定义（按名称排序，请求之间保持稳定）
     pub fn list_definitions(&self) -> Vec<ToolDefinition> {
-        self.tools
+        let mut definitions: Vec<ToolDefinition> = self
+            .tools
             .values()
             .map(|t| ToolDefinition {
                 name: t.name().to_string(),
                 description: t.description().to_string(),
                 parameters: t.parameters().clone(),
             })
-            .collect()
+            .collect();
+        definitions.sort_by(|a, b| a.name.cmp(&b.name));
+        definitions
     }
 
     #[allow(dead_code)]
     pub async fn execute(&self, call: &ToolCall) -> Result<ToolResult, String> {
+        self.execute_streaming(call, ToolOutputSink::noop()).await
+    }
+
+    /// 执行工具调用，运行中的增量输出发送到 sink
+    pub async fn execute_streaming(&self, call: &ToolCall, sink: ToolOutputSink) -> Result<ToolResult, String> {
         let executor = self
             .get(&call.name)
             .ok_or_else(|| format!("工具 '{}' 未找到", call.name))?;
@@ -70,7 +162,7 @@ impl ToolRegistry {
         };
 
         let result = executor
-            .execute(parsed_args)
+            .execute_streaming(parsed_args, sink)
             .await
             .map_err(|e| format!("工具执行失败: {}", e))?;
 
@@ -90,7 +182,26 @@ impl Default for ToolRegistry {
 // ========== 内置工具实现 ==========
 
 /// Shell 命令执行工具
-pub struct ShellTool;
+pub struct ShellTool {
+    policy: ExecPolicy,
+}
+
+impl ShellTool {
+    pub fn new() -> Self {
+        Self::with_policy(ExecPolicy::default())
+    }
+
+    /// 使用自定义执行策略（环境变量白名单、默认超时、输出上限）
+    pub fn with_policy(policy: ExecPolicy) -> Self {
+        Self { policy }
+    }
+}
+
+impl Default for ShellTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 #[async_trait]
 impl ToolExecutor for ShellTool {
@@ -99,7 +210,7 @@ impl ToolExecutor for ShellTool {
     }
 
     fn description(&self) -> &str {
-        "Execute a shell command on macOS/Linux. Use 'ifconfig' or 'ip addr' for network information, not 'hostname -I' which may not work on macOS."
+        "Execute a shell command on macOS/Linux. Use 'ifconfig' or 'ip addr' for network information, not 'hostname -I' which may not work on macOS. Returns JSON with exit_code, stdout, stderr, duration_ms, timed_out and truncated (long outputs keep only their head and tail)."
     }
 
     fn parameters(&self) -> serde_json::Value {
@@ -109,43 +220,69 @@ impl ToolExecutor for ShellTool {
                 "command": {
                     "type": "string",
                     "description": "Shell command to execute. Use 'curl ifconfig.me' to get public IP, 'ifconfig' for local network info."
+                },
+                "workdir": {
+                    "type": "string",
+                    "description": "Working directory for the command. Defaults to the agent's current directory."
+                },
+                "timeout_ms": {
+                    "type": "integer",
+                    "description": format!("Timeout in milliseconds (default {}, max {}). The command is killed when it expires.", self.policy.default_timeout_ms, MAX_TIMEOUT_MS)
                 }
             },
             "required": ["command"]
         })
     }
 
+    fn effect(&self) -> ToolEffect {
+        ToolEffect::Mutating
+    }
+
     async fn execute(&self, arguments: serde_json::Value) -> Result<String, Box<dyn std::error::Error + Send>> {
+        self.execute_streaming(arguments, ToolOutputSink::noop()).await
+    }
+
+    async fn execute_streaming(
+        &self,
+        arguments: serde_json::Value,
+        sink: ToolOutputSink,
+    ) -> Result<String, Box<dyn std::error::Error + Send>> {
         println!("\n🔧 Shell 工具接收到参数: {}", arguments); // 调试输出
 
         let command = arguments["command"]
             .as_str()
             .ok_or_else(|| anyhow::anyhow!("缺少 'command' 参数"))?;
 
-        println!("🔧 执行命令: {}", command);
-
-        let output = tokio::process::Command::new("sh")
-            .arg("-c")
-            .arg(command)
-            .output()
-            .await
-            .map_err(|e| anyhow::anyhow!("命令执行失败: {}", e))?;
+        let workdir = arguments["workdir"].as_str().map(std::path::PathBuf::from);
+        let timeout_ms = arguments["timeout_ms"]
+            .as_u64()
+            .unwrap_or(self.policy.default_timeout_ms)
+            .min(MAX_TIMEOUT_MS);
 
-        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        println!("🔧 执行命令: {}", command);
 
-        if output.status.success() {
-            println!("✓ 命令执行成功");
-            Ok(stdout)
+        let output = run_command(
+            ExecParams {
+                command: vec!["sh".to_string(), "-c".to_string(), command.to_string()],
+                workdir,
+                timeout: std::time::Duration::from_millis(timeout_ms),
+            },
+            &self.policy,
+            &sink,
+        )
+        .await?;
+
+        if output.success() {
+            println!("✓ 命令执行成功 ({} ms)", output.duration_ms);
+        } else if output.timed_out {
+            println!("✗ 命令超时 ({} ms)", timeout_ms);
         } else {
-            let error = if stderr.is_empty() {
-                anyhow::anyhow!("命令失败 (退出码: {:?})", output.status.code())
-            } else {
-                anyhow::anyhow!("{}", stderr)
-            };
-            println!("✗ {}", error);
-            Err(error.into())
+            println!("✗ 命令失败 (退出码: {:?})", output.exit_code);
         }
+
+        // 无论成功与否都返回完整的结构化结果，让模型看到退出码和两路输出
+        Ok(serde_json::to_string(&output)
+            .map_err(|e| anyhow::anyhow!("JSON 序列化失败: {}", e))?)
     }
 }
 
@@ -170,6 +307,10 @@ impl ToolExecutor for CurrentTimeTool {
         })
     }
 
+    fn effect(&self) -> ToolEffect {
+        ToolEffect::ReadOnly
+    }
+
     async fn execute(&self, _arguments: serde_json::Value) -> Result<String, Box<dyn std::error::Error + Send>> {
         use chrono::Local;
 
@@ -181,7 +322,15 @@ impl ToolExecutor for CurrentTimeTool {
 }
 
 /// 文件读取工具
-pub struct ReadFileTool;
+pub struct ReadFileTool {
+    policy: Arc<PathPolicy>,
+}
+
+impl ReadFileTool {
+    pub fn new(policy: Arc<PathPolicy>) -> Self {
+        Self { policy }
+    }
+}
 
 #[async_trait]
 impl ToolExecutor for ReadFileTool {
@@ -190,7 +339,7 @@ impl ToolExecutor for ReadFileTool {
     }
 
     fn description(&self) -> &str {
-        "Read contents of a text file"
+        "Read a text file with line numbers. Use offset/limit to page through long files. UTF-8, UTF-16 and GBK files are decoded automatically; binary files are refused."
     }
 
     fn parameters(&self) -> serde_json::Value {
@@ -200,45 +349,107 @@ impl ToolExecutor for ReadFileTool {
                 "path": {
                     "type": "string",
                     "description": "Path to the file to read"
+                },
+                "offset": {
+                    "type": "integer",
+                    "description": "1-based line number to start reading from (default 1)"
+                },
+                "limit": {
+                    "type": "integer",
+                    "description": format!("Maximum number of lines to return (default {})", DEFAULT_READ_LIMIT)
                 }
             },
             "required": ["path"]
         })
     }
 
+    fn effect(&self) -> ToolEffect {
+        ToolEffect::ReadOnly
+    }
+
     async fn execute(&self, arguments: serde_json::Value) -> Result<String, Box<dyn std::error::Error + Send>> {
         println!("\n📄 ReadFile 工具接收到参数: {}", arguments); // 调试输出
 
         let path = arguments["path"]
             .as_str()
             .ok_or_else(|| anyhow::anyhow!("缺少 'path' 参数"))?;
+        let offset = arguments["offset"].as_u64().unwrap_or(1) as usize;
+        // limit 为 0 时至少读一行，避免返回空内容和“已显示第 N-(N-1) 行”的提示
+        let limit = (arguments["limit"].as_u64().unwrap_or(DEFAULT_READ_LIMIT as u64) as usize).max(1);
 
         println!("📄 读取文件: {}", path);
 
-        let content = tokio::fs::read_to_string(path)
+        let path = self.policy.resolve(std::path::Path::new(path))?;
+        let metadata = tokio::fs::metadata(&path)
             .await
             .map_err(|e| anyhow::anyhow!("读取文件失败: {}", e))?;
+        if metadata.is_dir() {
+            return Err(anyhow::anyhow!("{} 是一个目录", path.display()).into());
+        }
+        if metadata.len() > MAX_READ_FILE_BYTES {
+            return Err(anyhow::anyhow!("文件过大 ({} 字节)，超过 {} 字节上限", metadata.len(), MAX_READ_FILE_BYTES).into());
+        }
 
-        let preview = if content.len() > 200 {
-            format!("{}... (总 {} 字符)", &content[..200], content.len())
-        } else {
-            content.clone()
-        };
+        let bytes = tokio::fs::read(&path)
+            .await
+            .map_err(|e| anyhow::anyhow!("读取文件失败: {}", e))?;
+        let decoded = decode_text(&bytes)?;
+
+        let mut output = format_numbered_lines(&decoded.text, offset, limit);
+        if decoded.encoding != "UTF-8" || decoded.lossy {
+            output.insert_str(
+                0,
+                &format!(
+                    "(编码: {}{})\n",
+                    decoded.encoding,
+                    if decoded.lossy { "，部分字节无法解码" } else { "" }
+                ),
+            );
+        }
+
+        println!("✓ 文件读取成功 ({} 字节, {})", bytes.len(), decoded.encoding);
+        Ok(output)
+    }
+}
+
+/// 当前可用的工具（help 工具使用，由智能体在每次请求模型前按注册表更新）
+#[derive(Debug, Default)]
+pub struct ToolCatalog {
+    tools: std::sync::RwLock<Vec<(String, String)>>,
+}
 
-        println!("✓ 文件读取成功 ({} 字符)", content.len());
-        Ok(preview)
+impl ToolCatalog {
+    /// 用注册表中的工具定义替换目录
+    pub fn update(&self, definitions: &[ToolDefinition]) {
+        *self.tools.write().unwrap() = definitions
+            .iter()
+            .map(|definition| (definition.name.clone(), summary_line(&definition.description).to_string()))
+            .collect();
     }
+
+    /// 每个工具一行：名称和说明的第一行
+    pub fn render(&self) -> String {
+        let mut text = "📚 可用工具:\n".to_string();
+        for (name, description) in self.tools.read().unwrap().iter() {
+            text.push_str(&format!("  • {}: {}\n", name, description));
+        }
+        text
+    }
+}
+
+/// 工具说明的第一行（系统提示和 help 中使用）
+pub fn summary_line(description: &str) -> &str {
+    description.lines().next().unwrap_or_default().trim()
 }
 
-/// 获取帮助工具
+/// 帮助工具：列出当前注册的所有工具
 pub struct HelpTool {
-    #[allow(dead_code)]
-    available_tools: Vec<String>,
+    catalog: Arc<ToolCatalog>,
 }
 
 impl HelpTool {
-    pub fn new(available_tools: Vec<String>) -> Self {
-        Self { available_tools }
+    pub fn new(catalog: Arc<ToolCatalog>) -> Self {
+        Self { catalog }
     }
 }
 
@@ -260,20 +471,41 @@ impl ToolExecutor for HelpTool {
         })
     }
 
+    fn effect(&self) -> ToolEffect {
+        ToolEffect::ReadOnly
+    }
+
     async fn execute(&self, _arguments: serde_json::Value) -> Result<String, Box<dyn std::error::Error + Send>> {
-        let mut help_text = "📚 可用工具:\n".to_string();
-        
-        for tool_name in &self.available_tools {
-            let desc = match tool_name.as_str() {
-                "shell" => "执行 shell 命令",
-                "current_time" => "获取当前日期和时间",
-                "read_file" => "读取文本文件内容",
-                "help" => "列出所有可用工具",
-                _ => "未知工具",
-            };
-            help_text.push_str(&format!("  • {}: {}\n", tool_name, desc));
-        }
-        
-        Ok(help_text)
+        Ok(self.catalog.render())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_file_multibyte_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("航班.md");
+        let tool = ReadFileTool::new(Arc::new(PathPolicy::for_workspace(dir.path()).unwrap()));
+        // 单行超过截断长度，且截断点落在多字节字符中间也不能 panic
+        std::fs::write(&path, format!("# 标题\n{}\n第三行\n", "航".repeat(2_000))).unwrap();
+
+        let output = tool
+            .execute(json!({"path": "航班.md", "offset": 2, "limit": 1}))
+            .await
+            .unwrap();
+
+        assert!(output.starts_with("     2\t航航"));
+        assert!(output.contains("该行被截断"));
+        assert!(output.contains("offset=3"));
+
+        let output = tool.execute(json!({"path": "航班.md", "limit": 0})).await.unwrap();
+        assert!(output.starts_with("     1\t# 标题\n"));
+        assert!(output.contains("已显示第 1-1 行"));
+
+        let denied = tool.execute(json!({"path": "../secret.txt"})).await.unwrap_err();
+        assert!(denied.to_string().contains("access denied by policy"));
     }
 }
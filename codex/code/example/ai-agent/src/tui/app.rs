@@ -0,0 +1,418 @@
+// TUI 应用状态 - 只由按键和智能体事件驱动，不直接接触终端，便于测试
+
+use super::composer::Composer;
+use super::history::{HistoryCell, ReasoningCell, ToolCell, ToolStatus};
+use crate::config::ApprovalPolicy;
+use crate::protocol::{AgentEvent, ApprovalResponse, ReviewDecision};
+use crate::session::{message_role, message_text, MessageRole};
+use crate::usage::UsageTotals;
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::text::Line;
+use serde_json::Value;
+use std::collections::VecDeque;
+
+/// PageUp / PageDown 每次滚动的行数
+const PAGE_SCROLL_LINES: usize = 10;
+
+/// 智能体当前在做什么（显示在状态栏）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Activity {
+    Idle,
+    Thinking,
+    Responding,
+    RunningTool(String),
+    WaitingApproval,
+}
+
+impl std::fmt::Display for Activity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Activity::Idle => write!(f, "空闲"),
+            Activity::Thinking => write!(f, "思考中…"),
+            Activity::Responding => write!(f, "回复中…"),
+            Activity::RunningTool(name) => write!(f, "运行 {}…", name),
+            Activity::WaitingApproval => write!(f, "等待审批"),
+        }
+    }
+}
+
+/// 等待用户答复的审批请求
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingApproval {
+    pub call_id: String,
+    pub name: String,
+    pub arguments: Value,
+    pub hint: Option<String>,
+}
+
+/// 按键处理的结果，由事件循环执行
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    /// 发送用户消息，开始新的一轮
+    Submit(String),
+    /// 答复审批请求
+    Approval(ApprovalResponse),
+    /// 中断正在进行的一轮
+    Interrupt,
+    Quit,
+}
+
+pub struct App {
+    pub cells: Vec<HistoryCell>,
+    pub composer: Composer,
+    pub model: String,
+    pub approval_policy: ApprovalPolicy,
+    pub activity: Activity,
+    /// 对话历史的估算 token 数
+    pub tokens: usize,
+    /// 本会话实际消耗的 token 和费用（接口返回的 usage）
+    pub usage: UsageTotals,
+    pub approvals: VecDeque<PendingApproval>,
+    /// 距离底部的滚动行数（0 表示跟随最新输出）
+    pub scroll: usize,
+    /// 上次绘制时可滚动的最大行数
+    pub max_scroll: usize,
+    /// 工具调用和思考单元是否展开
+    pub details_expanded: bool,
+}
+
+impl App {
+    pub fn new(model: String, approval_policy: ApprovalPolicy) -> Self {
+        Self {
+            cells: Vec::new(),
+            composer: Composer::new(),
+            model,
+            approval_policy,
+            activity: Activity::Idle,
+            tokens: 0,
+            usage: UsageTotals::default(),
+            approvals: VecDeque::new(),
+            scroll: 0,
+            max_scroll: 0,
+            details_expanded: false,
+        }
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.activity != Activity::Idle
+    }
+
+    pub fn push_info(&mut self, text: impl Into<String>) {
+        self.cells.push(HistoryCell::Info(text.into()));
+    }
+
+    /// 把已保存的对话历史显示为单元（恢复会话时使用）
+    pub fn load_conversation(&mut self, conversation: &[Value]) {
+        for msg in conversation {
+            let content = message_text(msg);
+            match message_role(msg) {
+                MessageRole::User => self.cells.push(HistoryCell::User(content)),
+                MessageRole::Attachment => self.cells.push(HistoryCell::Info(format!("🖼️ {}", content))),
+                MessageRole::Reasoning => self.cells.push(HistoryCell::Reasoning(ReasoningCell {
+                    text: msg["reasoning"].as_str().unwrap_or_default().to_string(),
+                    streaming: false,
+                    expanded: self.details_expanded,
+                })),
+                MessageRole::Assistant => {
+                    if !content.is_empty() {
+                        self.cells.push(HistoryCell::Assistant { text: content, streaming: false });
+                    }
+                    for call in msg["tool_calls"].as_array().into_iter().flatten() {
+                        let mut cell = ToolCell::new(
+                            call["id"].as_str().unwrap_or_default().to_string(),
+                            call["name"].as_str().unwrap_or_default().to_string(),
+                            call["arguments"].clone(),
+                            ToolStatus::Succeeded,
+                        );
+                        cell.expanded = self.details_expanded;
+                        self.cells.push(HistoryCell::Tool(cell));
+                    }
+                }
+                MessageRole::Tool => {
+                    if let Some(cell) = self.tool_cell(msg["tool_call_id"].as_str().unwrap_or_default()) {
+                        cell.output = content;
+                    }
+                }
+            }
+        }
+    }
+
+    /// 处理按键
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<Action> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            // 运行中按 Ctrl-C 中断本轮，空闲时才退出
+            KeyCode::Char('c') if ctrl && self.is_busy() => return Some(Action::Interrupt),
+            KeyCode::Char('c') if ctrl => return Some(Action::Quit),
+            KeyCode::Char('d') if ctrl && self.composer.is_empty() && !self.is_busy() => return Some(Action::Quit),
+            _ => {}
+        }
+
+        // 审批弹窗打开时只响应审批按键
+        if let Some(approval) = self.approvals.front() {
+            let decision = match key.code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => ReviewDecision::Approved,
+                KeyCode::Char('a') | KeyCode::Char('A') => ReviewDecision::ApprovedForSession,
+                KeyCode::Char('n') | KeyCode::Char('N') => ReviewDecision::Denied,
+                KeyCode::Esc => ReviewDecision::Abort,
+                _ => return None,
+            };
+            let call_id = approval.call_id.clone();
+            self.approvals.pop_front();
+            if self.approvals.is_empty() {
+                self.activity = Activity::Thinking;
+            }
+            return Some(Action::Approval(ApprovalResponse { call_id, decision }));
+        }
+
+        match key.code {
+            KeyCode::PageUp => self.scroll = (self.scroll + PAGE_SCROLL_LINES).min(self.max_scroll),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(PAGE_SCROLL_LINES),
+            KeyCode::Char('o') if ctrl => {
+                self.details_expanded = !self.details_expanded;
+                for cell in &mut self.cells {
+                    match cell {
+                        HistoryCell::Tool(tool) => tool.expanded = self.details_expanded,
+                        HistoryCell::Reasoning(reasoning) => reasoning.expanded = self.details_expanded,
+                        _ => {}
+                    }
+                }
+            }
+            // 忙碌时保留输入框内容，不发送
+            KeyCode::Enter if key.modifiers.is_empty() && self.is_busy() => {}
+            _ => {
+                if let Some(text) = self.composer.handle_key(key) {
+                    self.cells.push(HistoryCell::User(text.clone()));
+                    self.activity = Activity::Thinking;
+                    self.scroll = 0;
+                    return Some(Action::Submit(text));
+                }
+            }
+        }
+        None
+    }
+
+    /// 处理粘贴的文本
+    pub fn handle_paste(&mut self, text: &str) {
+        if self.approvals.is_empty() {
+            self.composer.insert_str(text);
+        }
+    }
+
+    /// 处理智能体事件
+    pub fn handle_agent_event(&mut self, event: AgentEvent) {
+        match event {
+            AgentEvent::ReasoningDelta { text } => {
+                match self.cells.last_mut() {
+                    Some(HistoryCell::Reasoning(cell)) if cell.streaming => cell.text.push_str(&text),
+                    _ => {
+                        self.end_stream();
+                        self.cells.push(HistoryCell::Reasoning(ReasoningCell {
+                            text,
+                            streaming: true,
+                            expanded: self.details_expanded,
+                        }));
+                    }
+                }
+                self.activity = Activity::Thinking;
+            }
+            AgentEvent::TextDelta { text } => {
+                self.end_reasoning();
+                match self.cells.last_mut() {
+                    Some(HistoryCell::Assistant { text: current, streaming: true }) => current.push_str(&text),
+                    _ => self.cells.push(HistoryCell::Assistant { text, streaming: true }),
+                }
+                self.activity = Activity::Responding;
+            }
+            AgentEvent::TokenUsage { session, .. } => self.usage = session,
+            AgentEvent::ModelRouted { route } => {
+                self.end_stream();
+                self.push_info(format!("🔀 改用模型 {}", route));
+            }
+            // 本轮随后以错误结束，说明由 finish_turn 显示为错误单元
+            AgentEvent::BudgetExceeded { .. } => self.end_stream(),
+            AgentEvent::ApprovalRequest { call_id, name, arguments, hint } => {
+                self.end_stream();
+                self.push_tool_cell(&call_id, &name, &arguments, ToolStatus::AwaitingApproval);
+                self.approvals.push_back(PendingApproval { call_id, name, arguments, hint });
+                self.activity = Activity::WaitingApproval;
+            }
+            AgentEvent::ToolCallBegin { call_id, name, arguments } => {
+                self.end_stream();
+                match self.tool_cell(&call_id) {
+                    Some(cell) => cell.status = ToolStatus::Running,
+                    None => self.push_tool_cell(&call_id, &name, &arguments, ToolStatus::Running),
+                }
+                self.activity = Activity::RunningTool(name);
+            }
+            AgentEvent::ToolOutputDelta { call_id, chunk } => {
+                if let Some(cell) = self.tool_cell(&call_id) {
+                    cell.output.push_str(&chunk);
+                }
+            }
+            AgentEvent::ToolCallEnd { call_id, output, success } => {
+                if let Some(cell) = self.tool_cell(&call_id) {
+                    // 成功时保留运行中看到的原始输出；失败或没有增量输出时显示最终结果
+                    if !success || cell.output.is_empty() {
+                        cell.output = output;
+                    }
+                    cell.status = if success { ToolStatus::Succeeded } else { ToolStatus::Failed };
+                }
+                self.activity = Activity::Thinking;
+            }
+        }
+    }
+
+    /// 一轮结束
+    pub fn finish_turn(&mut self, result: Result<String, String>) {
+        self.end_stream();
+        if let Err(e) = result {
+            self.cells.push(HistoryCell::Error(e));
+        }
+        // 未答复的请求已随本轮结束失效
+        self.approvals.clear();
+        for cell in &mut self.cells {
+            if let HistoryCell::Tool(tool) = cell {
+                if matches!(tool.status, ToolStatus::AwaitingApproval | ToolStatus::Running) {
+                    tool.status = ToolStatus::Failed;
+                }
+            }
+        }
+        self.activity = Activity::Idle;
+    }
+
+    /// 整个对话记录的行（单元之间空一行）
+    pub fn transcript_lines(&self) -> Vec<Line<'static>> {
+        let mut lines = Vec::new();
+        for cell in &self.cells {
+            lines.extend(cell.lines());
+            lines.push(Line::default());
+        }
+        lines
+    }
+
+    /// 结束正在流式输出的回复或思考
+    fn end_stream(&mut self) {
+        self.end_reasoning();
+        if let Some(HistoryCell::Assistant { streaming, .. }) = self.cells.last_mut() {
+            *streaming = false;
+        }
+    }
+
+    fn end_reasoning(&mut self) {
+        if let Some(HistoryCell::Reasoning(cell)) = self.cells.last_mut() {
+            cell.streaming = false;
+        }
+    }
+
+    fn push_tool_cell(&mut self, call_id: &str, name: &str, arguments: &Value, status: ToolStatus) {
+        let mut cell = ToolCell::new(call_id.to_string(), name.to_string(), arguments.clone(), status);
+        cell.expanded = self.details_expanded;
+        self.cells.push(HistoryCell::Tool(cell));
+    }
+
+    fn tool_cell(&mut self, call_id: &str) -> Option<&mut ToolCell> {
+        self.cells.iter_mut().rev().find_map(|cell| match cell {
+            HistoryCell::Tool(tool) if tool.call_id == call_id => Some(tool),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_turn_driven_by_agent_events() {
+        let mut app = App::new("glm-4".to_string(), ApprovalPolicy::OnRequest);
+        app.handle_paste("列出文件");
+        assert_eq!(app.handle_key(key(KeyCode::Enter)), Some(Action::Submit("列出文件".to_string())));
+        assert_eq!(app.activity, Activity::Thinking);
+
+        // 忙碌时 Enter 不发送
+        app.handle_paste("下一条");
+        assert_eq!(app.handle_key(key(KeyCode::Enter)), None);
+        assert_eq!(app.composer.text(), "下一条");
+
+        app.handle_agent_event(AgentEvent::ReasoningDelta { text: "用户想看".into() });
+        app.handle_agent_event(AgentEvent::ReasoningDelta { text: "文件列表".into() });
+        app.handle_agent_event(AgentEvent::TextDelta { text: "好的，".into() });
+        app.handle_agent_event(AgentEvent::TextDelta { text: "马上".into() });
+        app.handle_agent_event(AgentEvent::ApprovalRequest {
+            call_id: "call_1".into(),
+            name: "shell".into(),
+            arguments: json!({ "command": ["ls"] }),
+            hint: None,
+        });
+        assert_eq!(app.activity, Activity::WaitingApproval);
+
+        // 弹窗打开时普通按键被忽略
+        assert_eq!(app.handle_key(key(KeyCode::Char('x'))), None);
+        assert_eq!(
+            app.handle_key(key(KeyCode::Char('a'))),
+            Some(Action::Approval(ApprovalResponse {
+                call_id: "call_1".into(),
+                decision: ReviewDecision::ApprovedForSession,
+            }))
+        );
+        assert!(app.approvals.is_empty());
+
+        app.handle_agent_event(AgentEvent::ToolCallBegin {
+            call_id: "call_1".into(),
+            name: "shell".into(),
+            arguments: json!({ "command": ["ls"] }),
+        });
+        app.handle_agent_event(AgentEvent::ToolOutputDelta { call_id: "call_1".into(), chunk: "Cargo.toml\n".into() });
+        app.handle_agent_event(AgentEvent::ToolCallEnd {
+            call_id: "call_1".into(),
+            output: "{\"exit_code\":0}".into(),
+            success: true,
+        });
+        app.handle_agent_event(AgentEvent::TextDelta { text: "只有 Cargo.toml".into() });
+        app.finish_turn(Ok("只有 Cargo.toml".into()));
+
+        assert_eq!(app.activity, Activity::Idle);
+        // 思考单独成为一个单元，不混入回复
+        assert!(matches!(&app.cells[1], HistoryCell::Reasoning(cell) if cell.text == "用户想看文件列表" && !cell.streaming));
+        assert!(matches!(&app.cells[2], HistoryCell::Assistant { text, streaming: false } if text == "好的，马上"));
+        let HistoryCell::Tool(tool) = &app.cells[3] else { panic!("应为工具单元") };
+        assert_eq!((tool.status, tool.output.as_str()), (ToolStatus::Succeeded, "Cargo.toml\n"));
+        assert_eq!(app.cells.len(), 5);
+
+        app.handle_key(KeyEvent::new(KeyCode::Char('o'), KeyModifiers::CONTROL));
+        assert!(matches!(&app.cells[1], HistoryCell::Reasoning(cell) if cell.expanded));
+        assert!(matches!(&app.cells[3], HistoryCell::Tool(tool) if tool.expanded));
+    }
+
+    #[test]
+    fn test_ctrl_c_interrupts_running_turn() {
+        let ctrl_c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+        let mut app = App::new("glm-4".to_string(), ApprovalPolicy::Never);
+        app.handle_paste("你好");
+        app.handle_key(key(KeyCode::Enter));
+        assert_eq!(app.handle_key(ctrl_c), Some(Action::Interrupt));
+        assert_eq!(app.handle_key(KeyEvent::new(KeyCode::Char('d'), KeyModifiers::CONTROL)), None);
+
+        app.finish_turn(Err("⏹️  本轮已中断".into()));
+        assert_eq!(app.handle_key(ctrl_c), Some(Action::Quit));
+    }
+
+    #[test]
+    fn test_load_saved_conversation() {
+        let mut app = App::new("glm-4".to_string(), ApprovalPolicy::Never);
+        app.load_conversation(&[
+            json!({ "content": "几点了" }),
+            json!({ "content": "", "tool_calls": [{ "id": "c1", "name": "current_time", "arguments": {} }] }),
+            json!({ "tool_call_id": "c1", "content": "10:30" }),
+            json!({ "content": "10 点半", "tool_calls": null }),
+        ]);
+        assert_eq!(app.cells.len(), 3);
+        assert!(matches!(&app.cells[1], HistoryCell::Tool(tool) if tool.output == "10:30"));
+    }
+}
@@ -0,0 +1,227 @@
+// 多行输入框 - Enter 发送，Alt+Enter / Shift+Enter / Ctrl+J 换行，粘贴的多行文本原样插入
+
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use unicode_width::UnicodeWidthChar;
+
+/// 输入框状态：文本和光标位置（字节偏移，总在字符边界上）
+#[derive(Debug, Default)]
+pub struct Composer {
+    text: String,
+    cursor: usize,
+}
+
+/// 按宽度折行后的输入框内容
+#[derive(Debug, PartialEq)]
+pub struct ComposerLayout {
+    pub rows: Vec<String>,
+    /// 光标所在的 (行, 列)，列为显示宽度
+    pub cursor: (u16, u16),
+}
+
+impl Composer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[allow(dead_code)]
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// 在光标处插入文本（粘贴时统一换行符）
+    pub fn insert_str(&mut self, text: &str) {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        self.text.insert_str(self.cursor, &text);
+        self.cursor += text.len();
+    }
+
+    /// 处理按键，Enter 发送时返回输入的文本
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<String> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Enter if key.modifiers.intersects(KeyModifiers::SHIFT | KeyModifiers::ALT) => self.insert_str("\n"),
+            KeyCode::Char('j') if ctrl => self.insert_str("\n"),
+            KeyCode::Enter => {
+                if self.text.trim().is_empty() {
+                    return None;
+                }
+                self.cursor = 0;
+                return Some(std::mem::take(&mut self.text));
+            }
+            KeyCode::Char('a') if ctrl => self.cursor = self.line_start(),
+            KeyCode::Char('e') if ctrl => self.cursor = self.line_end(),
+            KeyCode::Char('u') if ctrl => {
+                let start = self.line_start();
+                self.text.replace_range(start..self.cursor, "");
+                self.cursor = start;
+            }
+            KeyCode::Char(c) if !ctrl => {
+                let mut buf = [0u8; 4];
+                self.insert_str(c.encode_utf8(&mut buf));
+            }
+            KeyCode::Backspace => {
+                if let Some(prev) = self.prev_boundary() {
+                    self.text.replace_range(prev..self.cursor, "");
+                    self.cursor = prev;
+                }
+            }
+            KeyCode::Delete => {
+                if let Some(next) = self.next_boundary() {
+                    self.text.replace_range(self.cursor..next, "");
+                }
+            }
+            KeyCode::Left => self.cursor = self.prev_boundary().unwrap_or(self.cursor),
+            KeyCode::Right => self.cursor = self.next_boundary().unwrap_or(self.cursor),
+            KeyCode::Home => self.cursor = self.line_start(),
+            KeyCode::End => self.cursor = self.line_end(),
+            KeyCode::Up => self.move_vertical(false),
+            KeyCode::Down => self.move_vertical(true),
+            _ => {}
+        }
+        None
+    }
+
+    fn prev_boundary(&self) -> Option<usize> {
+        self.text[..self.cursor].char_indices().next_back().map(|(i, _)| i)
+    }
+
+    fn next_boundary(&self) -> Option<usize> {
+        self.text[self.cursor..].chars().next().map(|c| self.cursor + c.len_utf8())
+    }
+
+    fn line_start(&self) -> usize {
+        self.text[..self.cursor].rfind('\n').map_or(0, |i| i + 1)
+    }
+
+    fn line_end(&self) -> usize {
+        self.text[self.cursor..].find('\n').map_or(self.text.len(), |i| self.cursor + i)
+    }
+
+    /// 移动到上一行/下一行的同一字符列（超出时停在行尾）
+    fn move_vertical(&mut self, down: bool) {
+        let start = self.line_start();
+        let column = self.text[start..self.cursor].chars().count();
+        let target_start = if down {
+            let end = self.line_end();
+            if end == self.text.len() {
+                return;
+            }
+            end + 1
+        } else {
+            if start == 0 {
+                return;
+            }
+            self.text[..start - 1].rfind('\n').map_or(0, |i| i + 1)
+        };
+        let target_end = self.text[target_start..].find('\n').map_or(self.text.len(), |i| target_start + i);
+        self.cursor = self.text[target_start..target_end]
+            .char_indices()
+            .nth(column)
+            .map_or(target_end, |(i, _)| target_start + i);
+    }
+
+    /// 按显示宽度折行，并计算光标位置
+    pub fn layout(&self, width: u16) -> ComposerLayout {
+        let width = width.max(1) as usize;
+        let mut rows = vec![String::new()];
+        let mut col = 0usize;
+        let mut cursor = None;
+
+        for (i, c) in self.text.char_indices() {
+            if c == '\n' {
+                if i == self.cursor {
+                    cursor = Some((rows.len() - 1, col));
+                }
+                rows.push(String::new());
+                col = 0;
+                continue;
+            }
+            let w = c.width().unwrap_or(0);
+            if col + w > width {
+                rows.push(String::new());
+                col = 0;
+            }
+            if i == self.cursor {
+                cursor = Some((rows.len() - 1, col));
+            }
+            rows.last_mut().expect("至少有一行").push(c);
+            col += w;
+        }
+
+        let (row, col) = cursor.unwrap_or_else(|| {
+            // 光标在末尾；正好写满一行时移到下一行开头
+            if col >= width {
+                rows.push(String::new());
+                (rows.len() - 1, 0)
+            } else {
+                (rows.len() - 1, col)
+            }
+        });
+        ComposerLayout {
+            rows,
+            cursor: (row as u16, col as u16),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    fn type_str(composer: &mut Composer, text: &str) {
+        for c in text.chars() {
+            composer.handle_key(key(KeyCode::Char(c), KeyModifiers::NONE));
+        }
+    }
+
+    #[test]
+    fn test_edit_and_submit_multiline() {
+        let mut composer = Composer::new();
+        assert_eq!(composer.handle_key(key(KeyCode::Enter, KeyModifiers::NONE)), None);
+
+        type_str(&mut composer, "你好");
+        composer.handle_key(key(KeyCode::Enter, KeyModifiers::ALT));
+        type_str(&mut composer, "wrld");
+        composer.handle_key(key(KeyCode::Left, KeyModifiers::NONE));
+        composer.handle_key(key(KeyCode::Left, KeyModifiers::NONE));
+        composer.handle_key(key(KeyCode::Left, KeyModifiers::NONE));
+        type_str(&mut composer, "o");
+        assert_eq!(composer.text(), "你好\nworld");
+
+        // 上移到第一行同一列（超出时停在行尾），再删除一个字符
+        composer.handle_key(key(KeyCode::Up, KeyModifiers::NONE));
+        composer.handle_key(key(KeyCode::Backspace, KeyModifiers::NONE));
+        assert_eq!(composer.text(), "你\nworld");
+
+        composer.insert_str("\r\n粘贴");
+        assert_eq!(composer.text(), "你\n粘贴\nworld");
+
+        let submitted = composer.handle_key(key(KeyCode::Enter, KeyModifiers::NONE));
+        assert_eq!(submitted.as_deref(), Some("你\n粘贴\nworld"));
+        assert!(composer.is_empty());
+    }
+
+    #[test]
+    fn test_layout_wraps_by_display_width() {
+        let mut composer = Composer::new();
+        composer.insert_str("ab你好\nc");
+        let layout = composer.layout(4);
+        assert_eq!(layout.rows, vec!["ab你", "好", "c"]);
+        assert_eq!(layout.cursor, (2, 1));
+
+        composer.handle_key(key(KeyCode::Up, KeyModifiers::NONE));
+        assert_eq!(composer.layout(4).cursor, (0, 1));
+
+        let mut full = Composer::new();
+        full.insert_str("abcd");
+        assert_eq!(full.layout(4).cursor, (1, 0));
+    }
+}
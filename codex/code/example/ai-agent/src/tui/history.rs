@@ -0,0 +1,216 @@
+// 对话记录中的单元（类似 Codex TUI 的 HistoryCell）
+//
+// 每个单元自己负责渲染成若干行；工具调用单元可以折叠，折叠时只显示参数摘要和输出的最后几行；
+// 思考单元以暗色显示，折叠时只显示字数（流式输出期间再加上最新的一行）。
+
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use serde_json::Value;
+
+/// 折叠时显示的输出行数
+const COLLAPSED_OUTPUT_LINES: usize = 3;
+/// 折叠时参数摘要的最大字符数
+const ARGUMENT_SUMMARY_CHARS: usize = 80;
+
+/// 工具调用的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolStatus {
+    /// 等待用户审批
+    AwaitingApproval,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// 一次工具调用
+#[derive(Debug, Clone)]
+pub struct ToolCell {
+    pub call_id: String,
+    pub name: String,
+    pub arguments: Value,
+    pub output: String,
+    pub status: ToolStatus,
+    pub expanded: bool,
+}
+
+impl ToolCell {
+    pub fn new(call_id: String, name: String, arguments: Value, status: ToolStatus) -> Self {
+        Self {
+            call_id,
+            name,
+            arguments,
+            output: String::new(),
+            status,
+            expanded: false,
+        }
+    }
+
+    fn lines(&self) -> Vec<Line<'static>> {
+        let (mark, color) = match self.status {
+            ToolStatus::AwaitingApproval => ("⏸", Color::Yellow),
+            ToolStatus::Running => ("⏳", Color::Yellow),
+            ToolStatus::Succeeded => ("✓", Color::Green),
+            ToolStatus::Failed => ("✗", Color::Red),
+        };
+        let fold = if self.expanded { "▾" } else { "▸" };
+        let mut header = vec![
+            Span::styled(format!("{} 🔧 ", fold), Style::default().fg(Color::DarkGray)),
+            Span::styled(self.name.clone(), Style::default().fg(Color::Magenta).add_modifier(Modifier::BOLD)),
+        ];
+        if !self.expanded {
+            header.push(Span::styled(format!(" {}", summarize_arguments(&self.arguments)), Style::default().fg(Color::DarkGray)));
+        }
+        header.push(Span::styled(format!(" {}", mark), Style::default().fg(color)));
+
+        let mut lines = vec![Line::from(header)];
+        let dim = Style::default().fg(Color::DarkGray);
+        if self.expanded {
+            let arguments = serde_json::to_string_pretty(&self.arguments).unwrap_or_else(|_| self.arguments.to_string());
+            lines.extend(arguments.lines().map(|line| Line::styled(format!("  │ {}", line), dim)));
+            if !self.output.is_empty() {
+                lines.push(Line::styled("  ├─ 输出", dim));
+                lines.extend(self.output.lines().map(|line| Line::raw(format!("  │ {}", line))));
+            }
+        } else {
+            let output: Vec<&str> = self.output.lines().collect();
+            let hidden = output.len().saturating_sub(COLLAPSED_OUTPUT_LINES);
+            if hidden > 0 {
+                lines.push(Line::styled(format!("  │ …（还有 {} 行，Ctrl+O 展开）", hidden), dim));
+            }
+            lines.extend(output[hidden..].iter().map(|line| Line::styled(format!("  │ {}", line), dim)));
+        }
+        lines
+    }
+}
+
+/// 参数摘要：单行 JSON，超长时截断
+fn summarize_arguments(arguments: &Value) -> String {
+    let text = arguments.to_string();
+    if text.chars().count() > ARGUMENT_SUMMARY_CHARS {
+        let truncated: String = text.chars().take(ARGUMENT_SUMMARY_CHARS).collect();
+        format!("{}…", truncated)
+    } else {
+        text
+    }
+}
+
+/// 模型的思考过程
+#[derive(Debug, Clone)]
+pub struct ReasoningCell {
+    pub text: String,
+    pub streaming: bool,
+    pub expanded: bool,
+}
+
+impl ReasoningCell {
+    fn lines(&self) -> Vec<Line<'static>> {
+        let dim = Style::default().fg(Color::DarkGray);
+        let body = dim.add_modifier(Modifier::ITALIC);
+        let text = self.text.trim();
+        let fold = if self.expanded { "▾" } else { "▸" };
+        let mut header = vec![Span::styled(format!("{} 💭 思考", fold), dim)];
+        if !self.expanded {
+            header.push(Span::styled(format!(" · {} 字", text.chars().count()), dim));
+        }
+        if self.streaming {
+            header.push(Span::styled(" ●", Style::default().fg(Color::Yellow)));
+        }
+
+        let mut lines = vec![Line::from(header)];
+        if self.expanded {
+            lines.extend(text.lines().map(|line| Line::styled(format!("  │ {}", line), body)));
+        } else if self.streaming {
+            if let Some(last) = text.lines().next_back() {
+                lines.push(Line::styled(format!("  │ {}", last), body));
+            }
+        }
+        lines
+    }
+}
+
+/// 对话记录单元
+#[derive(Debug, Clone)]
+pub enum HistoryCell {
+    User(String),
+    Reasoning(ReasoningCell),
+    Assistant { text: String, streaming: bool },
+    Tool(ToolCell),
+    /// 提示信息（如会话恢复、审批结果）
+    Info(String),
+    Error(String),
+}
+
+impl HistoryCell {
+    /// 渲染为若干行，单元之间由调用方插入空行
+    pub fn lines(&self) -> Vec<Line<'static>> {
+        match self {
+            HistoryCell::User(text) => {
+                let mut lines = vec![Line::styled("👤 You", Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD))];
+                lines.extend(text.lines().map(|line| Line::raw(line.to_string())));
+                lines
+            }
+            HistoryCell::Assistant { text, streaming } => {
+                let mut title = vec![Span::styled("🤖 Agent", Style::default().fg(Color::Green).add_modifier(Modifier::BOLD))];
+                if *streaming {
+                    title.push(Span::styled(" ●", Style::default().fg(Color::Yellow)));
+                }
+                let mut lines = vec![Line::from(title)];
+                lines.extend(text.lines().map(|line| Line::raw(line.to_string())));
+                lines
+            }
+            HistoryCell::Reasoning(cell) => cell.lines(),
+            HistoryCell::Tool(cell) => cell.lines(),
+            HistoryCell::Info(text) => text
+                .lines()
+                .map(|line| Line::styled(line.to_string(), Style::default().fg(Color::DarkGray)))
+                .collect(),
+            HistoryCell::Error(text) => text
+                .lines()
+                .map(|line| Line::styled(format!("❌ {}", line), Style::default().fg(Color::Red)))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn text(lines: &[Line]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_tool_cell_collapses_output() {
+        let mut cell = ToolCell::new("call_1".into(), "shell".into(), json!({ "command": ["ls"] }), ToolStatus::Running);
+        cell.output = "a\nb\nc\nd\ne".to_string();
+        cell.status = ToolStatus::Succeeded;
+
+        let collapsed = text(&cell.lines());
+        assert_eq!(collapsed[0], r#"▸ 🔧 shell {"command":["ls"]} ✓"#);
+        assert_eq!(collapsed[1..], ["  │ …（还有 2 行，Ctrl+O 展开）", "  │ c", "  │ d", "  │ e"]);
+
+        cell.expanded = true;
+        let expanded = text(&cell.lines());
+        assert_eq!(expanded[0], "▾ 🔧 shell ✓");
+        assert!(expanded.contains(&"  │   \"command\": [".to_string()));
+        assert_eq!(expanded[expanded.len() - 6..], ["  ├─ 输出", "  │ a", "  │ b", "  │ c", "  │ d", "  │ e"]);
+    }
+
+    #[test]
+    fn test_reasoning_cell_collapses() {
+        let mut cell = ReasoningCell {
+            text: "先看目录\n再读文件".to_string(),
+            streaming: true,
+            expanded: false,
+        };
+        assert_eq!(text(&cell.lines()), ["▸ 💭 思考 · 9 字 ●", "  │ 再读文件"]);
+
+        cell.streaming = false;
+        assert_eq!(text(&cell.lines()), ["▸ 💭 思考 · 9 字"]);
+
+        cell.expanded = true;
+        assert_eq!(text(&cell.lines()), ["▾ 💭 思考", "  │ 先看目录", "  │ 再读文件"]);
+    }
+}
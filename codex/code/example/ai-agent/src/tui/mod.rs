@@ -0,0 +1,225 @@
+// 全屏终端界面（ratatui）- 对应 Codex 的 codex-tui
+//
+// 结构：
+// - app.rs      界面状态，只由按键和 AgentEvent 驱动
+// - history.rs  对话记录单元（用户、助手、可折叠的工具调用、提示、错误）
+// - composer.rs 多行输入框
+// - ui.rs       绘制
+//
+// 运行期间 fd 1/2 被重定向到 log 文件，工具的 println! 调试输出不会破坏界面；
+// 智能体每轮在独立任务中运行，事件经通道送回界面，界面空闲时才把 Agent 交回。
+
+mod app;
+mod composer;
+mod history;
+mod ui;
+
+#[cfg(unix)]
+use crate::io_redirect::StdioRedirect;
+use crate::protocol::AgentEvent;
+use crate::session::{estimate_tokens, Session, SessionStore};
+use crate::Agent;
+use app::{Action, App};
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyEvent, KeyEventKind};
+#[cfg(unix)]
+use ratatui::crossterm::{
+    event::{DisableBracketedPaste, EnableBracketedPaste},
+    execute,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::Terminal;
+use std::fs::File;
+use std::path::PathBuf;
+use tokio::sync::{mpsc, oneshot};
+
+/// 界面的运行参数
+pub struct TuiOptions {
+    pub model: String,
+    /// 本次会话；恢复会话时包含之前的对话
+    pub session: Session,
+    /// 会话存储，None 表示不保存
+    pub store: Option<SessionStore>,
+}
+
+/// 事件循环处理的事件
+enum AppEvent {
+    Key(KeyEvent),
+    Paste(String),
+    Redraw,
+    Agent(AgentEvent),
+    /// 一轮结束，交回 Agent
+    TurnFinished { agent: Box<Agent>, result: Result<String, String> },
+}
+
+/// 界面日志文件：数据目录下的 tui.log，无法确定时放在临时目录
+pub fn log_path() -> PathBuf {
+    crate::config::data_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("tui.log")
+}
+
+/// 运行全屏界面，退出时返回最终的会话
+#[cfg(unix)]
+pub async fn run(agent: Agent, options: TuiOptions) -> anyhow::Result<Session> {
+    let log_path = log_path();
+    if let Some(dir) = log_path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let log = std::fs::OpenOptions::new().create(true).append(true).open(&log_path)?;
+    let redirect = StdioRedirect::to_file(&log)?;
+
+    let mut terminal = setup_terminal(redirect.terminal()?)?;
+    let previous_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore_tty();
+        previous_hook(info);
+    }));
+
+    let result = event_loop(&mut terminal, agent, options).await;
+
+    restore_tty();
+    terminal.show_cursor().ok();
+    drop(redirect);
+    result
+}
+
+/// 全屏界面依赖 fd 重定向（io_redirect），目前只支持 Unix
+#[cfg(not(unix))]
+pub async fn run(_agent: Agent, _options: TuiOptions) -> anyhow::Result<Session> {
+    Err(anyhow::anyhow!("全屏界面目前只支持 Unix"))
+}
+
+#[cfg(unix)]
+fn setup_terminal(mut tty: File) -> anyhow::Result<Terminal<CrosstermBackend<File>>> {
+    terminal::enable_raw_mode()?;
+    execute!(tty, EnterAlternateScreen, EnableBracketedPaste)?;
+    Ok(Terminal::new(CrosstermBackend::new(tty))?)
+}
+
+/// 恢复终端状态（正常退出和 panic 时都会调用）
+#[cfg(unix)]
+fn restore_tty() {
+    terminal::disable_raw_mode().ok();
+    if let Ok(mut tty) = std::fs::OpenOptions::new().write(true).open("/dev/tty") {
+        execute!(tty, DisableBracketedPaste, LeaveAlternateScreen).ok();
+    }
+}
+
+#[cfg_attr(not(unix), allow(dead_code))]
+async fn event_loop(
+    terminal: &mut Terminal<CrosstermBackend<File>>,
+    mut agent: Agent,
+    options: TuiOptions,
+) -> anyhow::Result<Session> {
+    let TuiOptions { model, mut session, store } = options;
+    let approvals = agent.approval_channel();
+
+    let mut app = App::new(model, agent.approval_policy());
+    if !session.conversation.is_empty() {
+        app.push_info(format!("📂 已恢复会话 {}", session.meta.id));
+        app.load_conversation(&session.conversation);
+        app.tokens = estimate_tokens(&session.conversation);
+        app.usage = session.usage.clone();
+    }
+    app.push_info("💡 输入消息开始对话；工具调用按审批策略弹窗确认");
+
+    let (tx, mut rx) = mpsc::unbounded_channel::<AppEvent>();
+
+    // 终端输入在独立线程中阻塞读取
+    let input_tx = tx.clone();
+    std::thread::spawn(move || loop {
+        let app_event = match event::read() {
+            Ok(Event::Key(key)) if key.kind != KeyEventKind::Release => AppEvent::Key(key),
+            Ok(Event::Paste(text)) => AppEvent::Paste(text),
+            Ok(Event::Resize(..)) => AppEvent::Redraw,
+            Ok(_) => continue,
+            Err(_) => break,
+        };
+        if input_tx.send(app_event).is_err() {
+            break;
+        }
+    });
+
+    let mut idle_agent = Some(agent);
+    // 中断正在运行的一轮
+    let mut interrupt: Option<oneshot::Sender<()>> = None;
+    terminal.draw(|frame| ui::draw(frame, &mut app))?;
+
+    'outer: while let Some(first) = rx.recv().await {
+        // 一次处理完已到达的事件再重绘，避免流式输出时逐字重绘
+        let mut next = Some(first);
+        while let Some(app_event) = next.take().or_else(|| rx.try_recv().ok()) {
+            let action = match app_event {
+                AppEvent::Key(key) => app.handle_key(key),
+                AppEvent::Paste(text) => {
+                    app.handle_paste(&text);
+                    None
+                }
+                AppEvent::Redraw => None,
+                AppEvent::Agent(agent_event) => {
+                    app.handle_agent_event(agent_event);
+                    None
+                }
+                AppEvent::TurnFinished { agent, result } => {
+                    app.finish_turn(result);
+                    let conversation = agent.conversation().await;
+                    app.tokens = estimate_tokens(&conversation);
+                    if let Some(store) = &store {
+                        session.update(conversation);
+                        session.usage = agent.usage().clone();
+                        if let Err(e) = store.save(&session) {
+                            app.cells.push(history::HistoryCell::Error(format!("保存会话失败: {}", e)));
+                        }
+                    }
+                    idle_agent = Some(*agent);
+                    interrupt = None;
+                    None
+                }
+            };
+
+            match action {
+                Some(Action::Submit(prompt)) => {
+                    let Some(mut agent) = idle_agent.take() else {
+                        continue;
+                    };
+                    let turn_tx = tx.clone();
+                    let (interrupt_tx, interrupted) = oneshot::channel();
+                    interrupt = Some(interrupt_tx);
+                    tokio::spawn(async move {
+                        let events = turn_tx.clone();
+                        let turn = agent.process_message_with_events(&prompt, move |event| {
+                            events.send(AppEvent::Agent(event)).ok();
+                        });
+                        // 中断时丢弃本轮（正在运行的命令随之终止），再补全未完成的工具调用
+                        let result = tokio::select! {
+                            result = turn => Some(result.map_err(|e| e.to_string())),
+                            _ = interrupted => None,
+                        };
+                        let result = match result {
+                            Some(result) => result,
+                            None => {
+                                agent.abort_turn().await;
+                                Err("⏹️  本轮已中断".to_string())
+                            }
+                        };
+                        turn_tx.send(AppEvent::TurnFinished { agent: Box::new(agent), result }).ok();
+                    });
+                }
+                Some(Action::Approval(response)) => {
+                    approvals.send(response).ok();
+                }
+                Some(Action::Interrupt) => {
+                    if let Some(interrupt) = interrupt.take() {
+                        interrupt.send(()).ok();
+                    }
+                }
+                Some(Action::Quit) => break 'outer,
+                None => {}
+            }
+        }
+        terminal.draw(|frame| ui::draw(frame, &mut app))?;
+    }
+
+    Ok(session)
+}
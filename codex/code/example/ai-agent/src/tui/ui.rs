@@ -0,0 +1,229 @@
+// 界面绘制 - 对话记录、输入框、状态栏和审批弹窗
+
+use super::app::{App, PendingApproval};
+use crate::usage::format_count;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Clear, Paragraph, Wrap};
+use ratatui::Frame;
+
+/// 输入框最多显示的行数（超出时跟随光标滚动）
+const MAX_COMPOSER_ROWS: usize = 8;
+/// 输入框提示符及其显示宽度
+const PROMPT: &str = "› ";
+const PROMPT_WIDTH: u16 = 2;
+/// 审批弹窗中参数最多显示的行数
+const MAX_APPROVAL_ARGUMENT_LINES: usize = 12;
+
+pub fn draw(frame: &mut Frame, app: &mut App) {
+    let area = frame.area();
+    // 输入框内宽度 = 总宽 - 两侧边框 - 提示符
+    let layout = app.composer.layout(area.width.saturating_sub(2 + PROMPT_WIDTH));
+    let visible_rows = layout.rows.len().min(MAX_COMPOSER_ROWS);
+
+    let [transcript_area, composer_area, status_area] = Layout::vertical([
+        Constraint::Min(1),
+        Constraint::Length(visible_rows as u16 + 2),
+        Constraint::Length(1),
+    ])
+    .areas(area);
+
+    draw_transcript(frame, app, transcript_area);
+
+    // 输入框：只显示光标附近的行
+    let (cursor_row, cursor_col) = (layout.cursor.0 as usize, layout.cursor.1);
+    let first_row = (cursor_row + 1).saturating_sub(visible_rows);
+    let rows: Vec<Line> = layout.rows[first_row..first_row + visible_rows]
+        .iter()
+        .enumerate()
+        .map(|(i, row)| {
+            let prefix = if first_row + i == 0 { PROMPT } else { "  " };
+            Line::from(vec![Span::styled(prefix, Style::default().fg(Color::Cyan)), Span::raw(row.clone())])
+        })
+        .collect();
+    let title = if app.is_busy() {
+        " 处理中…（可以继续输入） "
+    } else {
+        " Enter 发送 · Alt+Enter 换行 "
+    };
+    let border_color = if app.is_busy() { Color::DarkGray } else { Color::Cyan };
+    frame.render_widget(
+        Paragraph::new(rows).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(border_color))
+                .title(title),
+        ),
+        composer_area,
+    );
+
+    draw_status(frame, app, status_area);
+
+    match app.approvals.front() {
+        Some(approval) => draw_approval(frame, approval, area),
+        None => frame.set_cursor_position((
+            composer_area.x + 1 + PROMPT_WIDTH + cursor_col,
+            composer_area.y + 1 + (cursor_row - first_row) as u16,
+        )),
+    }
+}
+
+fn draw_transcript(frame: &mut Frame, app: &mut App, area: Rect) {
+    let paragraph = Paragraph::new(app.transcript_lines()).wrap(Wrap { trim: false });
+    let total = paragraph.line_count(area.width);
+    app.max_scroll = total.saturating_sub(area.height as usize);
+    app.scroll = app.scroll.min(app.max_scroll);
+    let offset = (app.max_scroll - app.scroll).min(u16::MAX as usize) as u16;
+    frame.render_widget(paragraph.scroll((offset, 0)), area);
+}
+
+fn draw_status(frame: &mut Frame, app: &App, area: Rect) {
+    let separator = Span::styled(" │ ", Style::default().fg(Color::DarkGray));
+    let activity_color = if app.is_busy() { Color::Yellow } else { Color::Green };
+    let mut left = vec![
+        Span::styled(format!(" 🧠 {}", app.model), Style::default().add_modifier(Modifier::BOLD)),
+        separator.clone(),
+        Span::raw(format!("≈{} tokens", format_count(app.tokens as u64))),
+        separator.clone(),
+    ];
+    if app.usage.requests > 0 {
+        let mut used = format!("已用 {}", format_count(app.usage.tokens.total()));
+        // 部分请求未定价时费用不完整，不显示
+        if app.usage.unpriced_requests == 0 {
+            used.push_str(&format!(" · 费用 {:.4}", app.usage.cost));
+        }
+        left.push(Span::raw(used));
+        left.push(separator.clone());
+    }
+    left.extend([
+        Span::raw(format!("审批: {}", app.approval_policy)),
+        separator,
+        Span::styled(format!("● {}", app.activity), Style::default().fg(activity_color)),
+    ]);
+    if app.scroll > 0 {
+        left.push(Span::styled(format!("  ↑{}", app.scroll), Style::default().fg(Color::DarkGray)));
+    }
+
+    let hint = "PgUp/PgDn 滚动 · Ctrl+O 展开详情 · Ctrl+C 退出 ";
+    let [left_area, right_area] =
+        Layout::horizontal([Constraint::Min(1), Constraint::Length(Line::raw(hint).width() as u16)]).areas(area);
+    frame.render_widget(Paragraph::new(Line::from(left)), left_area);
+    frame.render_widget(
+        Paragraph::new(Line::styled(hint, Style::default().fg(Color::DarkGray))),
+        right_area,
+    );
+}
+
+fn draw_approval(frame: &mut Frame, approval: &PendingApproval, area: Rect) {
+    let dim = Style::default().fg(Color::DarkGray);
+    let mut lines = vec![
+        Line::from(vec![
+            Span::raw("智能体请求调用工具 "),
+            Span::styled(approval.name.clone(), Style::default().fg(Color::Magenta).add_modifier(Modifier::BOLD)),
+        ]),
+        Line::default(),
+    ];
+    if let Some(hint) = &approval.hint {
+        lines.insert(1, Line::styled(format!("ℹ️  {}", hint), Style::default().fg(Color::Yellow)));
+    }
+    let arguments = serde_json::to_string_pretty(&approval.arguments).unwrap_or_else(|_| approval.arguments.to_string());
+    let argument_lines: Vec<&str> = arguments.lines().collect();
+    for line in argument_lines.iter().take(MAX_APPROVAL_ARGUMENT_LINES) {
+        lines.push(Line::styled(format!("  {}", line), dim));
+    }
+    if argument_lines.len() > MAX_APPROVAL_ARGUMENT_LINES {
+        lines.push(Line::styled(
+            format!("  …（还有 {} 行）", argument_lines.len() - MAX_APPROVAL_ARGUMENT_LINES),
+            dim,
+        ));
+    }
+    lines.push(Line::default());
+    let key = Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD);
+    lines.push(Line::from(vec![
+        Span::styled("[y]", key),
+        Span::raw(" 批准  "),
+        Span::styled("[a]", key),
+        Span::raw(" 本次会话总是批准  "),
+        Span::styled("[n]", key),
+        Span::raw(" 拒绝  "),
+        Span::styled("[Esc]", key),
+        Span::raw(" 中止本轮"),
+    ]));
+
+    let width = area.width.saturating_sub(4).min(76);
+    let height = (lines.len() as u16 + 2).min(area.height);
+    let popup = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    };
+    frame.render_widget(Clear, popup);
+    frame.render_widget(
+        Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(Color::Yellow))
+                .title(" 需要审批 "),
+        ),
+        popup,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ApprovalPolicy;
+    use crate::protocol::AgentEvent;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+    use serde_json::json;
+
+    /// 屏幕文本；宽字符后面的占位单元格跳过
+    fn screen(terminal: &Terminal<TestBackend>) -> String {
+        let buffer = terminal.backend().buffer();
+        let mut rows = Vec::new();
+        for y in 0..buffer.area.height {
+            let mut row = String::new();
+            let mut skip = 0;
+            for x in 0..buffer.area.width {
+                if skip > 0 {
+                    skip -= 1;
+                    continue;
+                }
+                let symbol = buffer[(x, y)].symbol();
+                skip = Line::raw(symbol).width().saturating_sub(1);
+                row.push_str(symbol);
+            }
+            rows.push(row);
+        }
+        rows.join("\n")
+    }
+
+    #[test]
+    fn test_draw_transcript_status_and_approval() {
+        let mut app = App::new("glm-4-tools".to_string(), ApprovalPolicy::OnRequest);
+        app.tokens = 1234;
+        app.handle_paste("删除临时文件");
+        app.handle_key(ratatui::crossterm::event::KeyEvent::from(ratatui::crossterm::event::KeyCode::Enter));
+        app.handle_agent_event(AgentEvent::ApprovalRequest {
+            call_id: "call_1".into(),
+            name: "shell".into(),
+            arguments: json!({ "command": ["rm", "-rf", "tmp"] }),
+            hint: Some("服务器声明该工具只读".into()),
+        });
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 24)).unwrap();
+        terminal.draw(|frame| draw(frame, &mut app)).unwrap();
+        let screen = screen(&terminal);
+        assert!(screen.contains("删除临时文件"));
+        assert!(screen.contains("glm-4-tools"));
+        assert!(screen.contains("≈1.2k tokens"));
+        assert!(screen.contains("审批: on-request"));
+        assert!(screen.contains("需要审批"));
+        assert!(screen.contains("服务器声明该工具只读"));
+        assert!(screen.contains("\"rm\","));
+        assert!(screen.contains("[a] 本次会话总是批准"));
+    }
+}
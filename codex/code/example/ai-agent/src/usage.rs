@@ -0,0 +1,238 @@
+// Token 用量与费用 - 解析接口返回的 usage，按模型价格表计费，按轮次和会话累计
+//
+// 各提供方的 usage 字段略有不同：
+// - OpenAI / 智谱：prompt_tokens_details.cached_tokens、completion_tokens_details.reasoning_tokens
+// - DeepSeek：prompt_cache_hit_tokens
+// 思考 token 包含在 completion_tokens 中，缓存命中的 token 包含在 prompt_tokens 中。
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// 一次模型请求的 token 用量
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    /// 输入 token（含缓存命中的部分）
+    pub prompt_tokens: u64,
+    /// 输出 token（含思考部分）
+    pub completion_tokens: u64,
+    /// 其中的思考 token
+    #[serde(default)]
+    pub reasoning_tokens: u64,
+    /// 其中缓存命中的输入 token
+    #[serde(default)]
+    pub cached_tokens: u64,
+}
+
+impl TokenUsage {
+    /// 解析接口返回的 usage 对象，没有 usage 时返回 None
+    pub fn from_response(usage: &Value) -> Option<Self> {
+        if !usage.is_object() {
+            return None;
+        }
+        let count = |value: &Value| value.as_u64().unwrap_or(0);
+        Some(Self {
+            prompt_tokens: count(&usage["prompt_tokens"]),
+            completion_tokens: count(&usage["completion_tokens"]),
+            reasoning_tokens: count(&usage["completion_tokens_details"]["reasoning_tokens"]),
+            cached_tokens: usage["prompt_tokens_details"]["cached_tokens"]
+                .as_u64()
+                .or_else(|| usage["prompt_cache_hit_tokens"].as_u64())
+                .unwrap_or(0),
+        })
+    }
+
+    pub fn total(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    pub fn add(&mut self, other: &TokenUsage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.reasoning_tokens += other.reasoning_tokens;
+        self.cached_tokens += other.cached_tokens;
+    }
+}
+
+impl std::fmt::Display for TokenUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} tokens（输入 {}",
+            format_count(self.total()),
+            format_count(self.prompt_tokens)
+        )?;
+        if self.cached_tokens > 0 {
+            write!(f, "，缓存 {}", format_count(self.cached_tokens))?;
+        }
+        write!(f, "，输出 {}", format_count(self.completion_tokens))?;
+        if self.reasoning_tokens > 0 {
+            write!(f, "，思考 {}", format_count(self.reasoning_tokens))?;
+        }
+        write!(f, "）")
+    }
+}
+
+/// 1234 -> "1.2k"
+pub fn format_count(count: u64) -> String {
+    if count >= 1000 {
+        format!("{:.1}k", count as f64 / 1000.0)
+    } else {
+        count.to_string()
+    }
+}
+
+/// 模型价格：每百万 token 的价格（货币单位由配置自行约定）
+///
+/// ```toml
+/// [prices."glm-4-plus"]
+/// input = 5.0
+/// output = 5.0
+///
+/// [prices."deepseek-*"]
+/// input = 2.0
+/// cached_input = 0.5
+/// output = 3.0
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub output: f64,
+    /// 缓存命中的输入 token 的价格，省略时按 input 计
+    #[serde(default)]
+    pub cached_input: Option<f64>,
+}
+
+impl ModelPrice {
+    /// 一次请求的费用（思考 token 按输出价格计）
+    pub fn cost(&self, usage: &TokenUsage) -> f64 {
+        let cached = usage.cached_tokens.min(usage.prompt_tokens);
+        let uncached = usage.prompt_tokens - cached;
+        (uncached as f64 * self.input
+            + cached as f64 * self.cached_input.unwrap_or(self.input)
+            + usage.completion_tokens as f64 * self.output)
+            / 1_000_000.0
+    }
+}
+
+/// 模型价格表：键为模型名或 glob 模式（如 "glm-4*"），精确匹配优先
+#[derive(Debug, Clone, Default)]
+pub struct PriceTable {
+    prices: BTreeMap<String, ModelPrice>,
+}
+
+impl PriceTable {
+    pub fn new(prices: BTreeMap<String, ModelPrice>) -> Self {
+        Self { prices }
+    }
+
+    /// 查找模型的价格，没有配置时返回 None
+    pub fn price(&self, model: &str) -> Option<&ModelPrice> {
+        self.prices.get(model).or_else(|| {
+            self.prices.iter().find_map(|(pattern, price)| {
+                let glob = globset::Glob::new(pattern).ok()?.compile_matcher();
+                glob.is_match(model).then_some(price)
+            })
+        })
+    }
+
+    /// 按模型价格计算费用，没有价格时返回 None
+    pub fn cost(&self, model: &str, usage: &TokenUsage) -> Option<f64> {
+        self.price(model).map(|price| price.cost(usage))
+    }
+}
+
+/// 多次请求的累计用量（一轮或整个会话）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UsageTotals {
+    /// 模型请求次数
+    pub requests: u64,
+    #[serde(flatten)]
+    pub tokens: TokenUsage,
+    /// 有价格的请求的费用合计
+    pub cost: f64,
+    /// 没有配置价格的请求数（这些请求不计入 cost）
+    #[serde(default)]
+    pub unpriced_requests: u64,
+}
+
+impl UsageTotals {
+    /// 记录一次请求
+    pub fn record(&mut self, usage: &TokenUsage, cost: Option<f64>) {
+        self.requests += 1;
+        self.tokens.add(usage);
+        match cost {
+            Some(cost) => self.cost += cost,
+            None => self.unpriced_requests += 1,
+        }
+    }
+
+    /// 费用说明：全部未定价时为 None
+    pub fn cost_summary(&self) -> Option<String> {
+        if self.requests == 0 || self.unpriced_requests == self.requests {
+            return None;
+        }
+        let mut text = format!("{:.4}", self.cost);
+        if self.unpriced_requests > 0 {
+            text.push_str(&format!("（另有 {} 次请求未定价）", self.unpriced_requests));
+        }
+        Some(text)
+    }
+}
+
+impl std::fmt::Display for UsageTotals {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}，{} 次请求", self.tokens, self.requests)?;
+        if let Some(cost) = self.cost_summary() {
+            write!(f, "，费用 {}", cost)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_usage_variants() {
+        let openai = json!({
+            "prompt_tokens": 1200,
+            "completion_tokens": 300,
+            "total_tokens": 1500,
+            "prompt_tokens_details": { "cached_tokens": 1000 },
+            "completion_tokens_details": { "reasoning_tokens": 120 }
+        });
+        assert_eq!(
+            TokenUsage::from_response(&openai),
+            Some(TokenUsage { prompt_tokens: 1200, completion_tokens: 300, reasoning_tokens: 120, cached_tokens: 1000 })
+        );
+
+        let deepseek = json!({ "prompt_tokens": 50, "completion_tokens": 7, "prompt_cache_hit_tokens": 32 });
+        assert_eq!(TokenUsage::from_response(&deepseek).unwrap().cached_tokens, 32);
+        assert_eq!(TokenUsage::from_response(&Value::Null), None);
+    }
+
+    #[test]
+    fn test_price_table_and_totals() {
+        let prices = PriceTable::new(BTreeMap::from([
+            ("glm-4*".to_string(), ModelPrice { input: 1.0, output: 1.0, cached_input: None }),
+            ("glm-4-plus".to_string(), ModelPrice { input: 4.0, output: 8.0, cached_input: Some(1.0) }),
+        ]));
+        let usage = TokenUsage { prompt_tokens: 1_000_000, completion_tokens: 500_000, reasoning_tokens: 0, cached_tokens: 400_000 };
+
+        // 精确匹配优先：60 万未缓存 × 4 + 40 万缓存 × 1 + 50 万输出 × 8
+        assert_eq!(prices.cost("glm-4-plus", &usage), Some(6.8));
+        assert_eq!(prices.cost("glm-4-flash", &usage), Some(1.5));
+        assert_eq!(prices.cost("gpt-4o", &usage), None);
+
+        let mut totals = UsageTotals::default();
+        totals.record(&usage, prices.cost("glm-4-plus", &usage));
+        totals.record(&usage, None);
+        assert_eq!(totals.requests, 2);
+        assert_eq!(totals.tokens.total(), 3_000_000);
+        assert_eq!(totals.cost_summary().as_deref(), Some("6.8000（另有 1 次请求未定价）"));
+        assert_eq!(UsageTotals::default().cost_summary(), None);
+    }
+}
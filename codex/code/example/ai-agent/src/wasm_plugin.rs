@@ -0,0 +1,327 @@
+// WASM 插件工具 - 以 WebAssembly 组件形式加载的第三方工具（需要 wasm-plugins 特性）
+//
+// 与直接注册的 ToolExecutor 不同，插件运行在 wasmtime 沙箱中：
+// - 只能通过 wit/plugin.wit 中的 host 接口访问外部资源，每项能力都需要在配置中显式授予
+// - 文件访问仍经过工作区的 PathPolicy
+// - 每次执行使用新的 Store，受燃料（指令数）和线性内存上限约束
+
+use crate::apply_patch::write_atomic;
+use crate::config::PluginConfig;
+use crate::file_tools::{decode_text, MAX_READ_FILE_BYTES};
+use crate::path_policy::PathPolicy;
+use crate::tools::{ToolEffect, ToolExecutor};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::path::Path;
+use std::sync::Arc;
+use wasmtime::component::{Component, Linker};
+use wasmtime::{Engine, Store, StoreLimits, StoreLimitsBuilder};
+
+wasmtime::component::bindgen!({
+    path: "wit/plugin.wit",
+    world: "tool-plugin",
+    async: true,
+});
+
+use simple_ai_agent::plugin::host;
+
+/// 单次执行默认的燃料上限
+pub const DEFAULT_PLUGIN_FUEL: u64 = 1_000_000_000;
+
+/// 默认的线性内存上限（MB）
+pub const DEFAULT_PLUGIN_MEMORY_MB: u64 = 64;
+
+/// 插件可被授予的能力
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Capabilities {
+    pub fs_read: bool,
+    pub fs_write: bool,
+    pub clock: bool,
+}
+
+impl Capabilities {
+    /// 解析配置中的能力列表（fs-read / fs-write / clock）
+    pub fn parse(names: &[String]) -> Result<Self, anyhow::Error> {
+        let mut capabilities = Self::default();
+        for name in names {
+            match name.as_str() {
+                "fs-read" => capabilities.fs_read = true,
+                "fs-write" => capabilities.fs_write = true,
+                "clock" => capabilities.clock = true,
+                other => return Err(anyhow::anyhow!("未知的插件能力 '{}'（可选: fs-read, fs-write, clock）", other)),
+            }
+        }
+        Ok(capabilities)
+    }
+}
+
+/// 每个 Store 持有的宿主状态
+struct PluginState {
+    plugin: String,
+    capabilities: Capabilities,
+    policy: Arc<PathPolicy>,
+    limits: StoreLimits,
+}
+
+impl PluginState {
+    fn require(&self, granted: bool, capability: &str) -> Result<(), String> {
+        if granted {
+            Ok(())
+        } else {
+            Err(format!("插件 '{}' 未被授予 {} 能力", self.plugin, capability))
+        }
+    }
+
+    fn resolve(&self, path: &str) -> Result<std::path::PathBuf, String> {
+        self.policy.resolve(Path::new(path)).map_err(|e| e.to_string())
+    }
+}
+
+impl host::Host for PluginState {
+    async fn read_file(&mut self, path: String) -> Result<String, String> {
+        self.require(self.capabilities.fs_read, "fs-read")?;
+        let path = self.resolve(&path)?;
+        let metadata = std::fs::metadata(&path).map_err(|e| e.to_string())?;
+        if metadata.len() > MAX_READ_FILE_BYTES {
+            return Err(format!("文件过大 ({} 字节)", metadata.len()));
+        }
+        let bytes = std::fs::read(&path).map_err(|e| e.to_string())?;
+        decode_text(&bytes).map(|decoded| decoded.text).map_err(|e| e.to_string())
+    }
+
+    async fn write_file(&mut self, path: String, content: String) -> Result<(), String> {
+        self.require(self.capabilities.fs_write, "fs-write")?;
+        let path = self.resolve(&path)?;
+        write_atomic(&path, content.as_bytes()).map_err(|e| e.to_string())
+    }
+
+    async fn list_dir(&mut self, path: String) -> Result<Vec<String>, String> {
+        self.require(self.capabilities.fs_read, "fs-read")?;
+        let path = self.resolve(&path)?;
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(&path).map_err(|e| e.to_string())?.flatten() {
+            if !self.policy.is_allowed(&entry.path()) {
+                continue;
+            }
+            let mut name = entry.file_name().to_string_lossy().to_string();
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                name.push('/');
+            }
+            entries.push(name);
+        }
+        entries.sort();
+        Ok(entries)
+    }
+
+    async fn now_millis(&mut self) -> Result<u64, String> {
+        self.require(self.capabilities.clock, "clock")?;
+        Ok(chrono::Utc::now().timestamp_millis().max(0) as u64)
+    }
+
+    async fn log(&mut self, message: String) {
+        println!("🧩 [{}] {}", self.plugin, message);
+    }
+}
+
+/// WASM 插件工具
+pub struct WasmPluginTool {
+    name: String,
+    description: String,
+    parameters: Value,
+    engine: Engine,
+    pre: ToolPluginPre<PluginState>,
+    capabilities: Capabilities,
+    policy: Arc<PathPolicy>,
+    fuel: u64,
+    max_memory_bytes: usize,
+}
+
+impl WasmPluginTool {
+    /// 编译组件并读取工具的名称、描述和参数 schema
+    pub async fn load(config: &PluginConfig, policy: Arc<PathPolicy>) -> Result<Self, anyhow::Error> {
+        let capabilities = Capabilities::parse(&config.capabilities)?;
+
+        let mut engine_config = wasmtime::Config::new();
+        engine_config.async_support(true).consume_fuel(true).wasm_component_model(true);
+        let engine = Engine::new(&engine_config)?;
+
+        let component = Component::from_file(&engine, &config.path)
+            .map_err(|e| anyhow::anyhow!("加载插件 {} 失败: {}", config.path.display(), e))?;
+        let mut linker = Linker::new(&engine);
+        host::add_to_linker(&mut linker, |state: &mut PluginState| state)?;
+        let pre = ToolPluginPre::new(linker.instantiate_pre(&component)?)?;
+
+        let mut tool = Self {
+            name: config.path.display().to_string(),
+            description: String::new(),
+            parameters: Value::Null,
+            engine,
+            pre,
+            capabilities,
+            policy,
+            fuel: config.fuel.unwrap_or(DEFAULT_PLUGIN_FUEL),
+            max_memory_bytes: (config.max_memory_mb.unwrap_or(DEFAULT_PLUGIN_MEMORY_MB) * 1024 * 1024) as usize,
+        };
+
+        let mut store = tool.new_store()?;
+        let plugin = tool.pre.instantiate_async(&mut store).await?;
+        tool.name = plugin.call_name(&mut store).await?;
+        tool.description = plugin.call_description(&mut store).await?;
+        let schema = plugin.call_parameters(&mut store).await?;
+        tool.parameters = serde_json::from_str(&schema)
+            .map_err(|e| anyhow::anyhow!("插件 '{}' 的参数 schema 不是有效的 JSON: {}", tool.name, e))?;
+
+        Ok(tool)
+    }
+
+    fn new_store(&self) -> Result<Store<PluginState>, anyhow::Error> {
+        let state = PluginState {
+            plugin: self.name.clone(),
+            capabilities: self.capabilities,
+            policy: self.policy.clone(),
+            limits: StoreLimitsBuilder::new().memory_size(self.max_memory_bytes).build(),
+        };
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(self.fuel)?;
+        // 定期让出执行权，避免长时间运行的插件阻塞异步运行时
+        store.fuel_async_yield_interval(Some(100_000))?;
+        Ok(store)
+    }
+
+    /// 在新的实例中执行插件：外层错误表示陷入（trap）或燃料耗尽，内层错误由插件返回
+    pub async fn execute_json(&self, arguments: &str) -> Result<Result<String, String>, anyhow::Error> {
+        let mut store = self.new_store()?;
+        let plugin = self.pre.instantiate_async(&mut store).await?;
+        plugin.call_execute(&mut store, arguments).await
+    }
+}
+
+#[async_trait]
+impl ToolExecutor for WasmPluginTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn parameters(&self) -> Value {
+        self.parameters.clone()
+    }
+
+    fn effect(&self) -> ToolEffect {
+        // 只能读文件或读时钟的插件不会修改环境
+        if self.capabilities.fs_write {
+            ToolEffect::Mutating
+        } else {
+            ToolEffect::ReadOnly
+        }
+    }
+
+    async fn execute(&self, arguments: Value) -> Result<String, Box<dyn std::error::Error + Send>> {
+        println!("\n🧩 插件工具 {} 接收到参数: {}", self.name, arguments); // 调试输出
+
+        match self.execute_json(&arguments.to_string()).await {
+            Ok(Ok(output)) => Ok(output),
+            Ok(Err(message)) => Err(anyhow::anyhow!("{}", message).into()),
+            Err(e) => Err(anyhow::anyhow!("插件执行失败: {}", e).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 手写的测试组件：execute 把参数原样当作路径，交给宿主的 read-file
+    const READ_NOTE_PLUGIN: &str = r#"
+(component
+  (import "simple-ai-agent:plugin/host@0.1.0" (instance $host
+    (export "read-file" (func (param "path" string) (result (result string (error string)))))
+  ))
+
+  (core module $libc
+    (memory (export "memory") 1)
+    (global $heap (mut i32) (i32.const 1024))
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $ptr i32)
+      (local.set $ptr
+        (i32.and
+          (i32.add (global.get $heap) (i32.sub (local.get 2) (i32.const 1)))
+          (i32.sub (i32.const 0) (local.get 2))))
+      (global.set $heap (i32.add (local.get $ptr) (local.get 3)))
+      (local.get $ptr)))
+  (core instance $libc (instantiate $libc))
+
+  (core func $read_file
+    (canon lower (func $host "read-file") (memory (core memory $libc "memory")) (realloc (core func $libc "realloc"))))
+
+  (core module $main
+    (import "libc" "memory" (memory 1))
+    (import "host" "read-file" (func $read_file (param i32 i32 i32)))
+    (data (i32.const 16) "read_note")
+    (data (i32.const 32) "Read a file through the host")
+    (data (i32.const 64) "{\"type\":\"object\"}")
+    (data (i32.const 256) "\10\00\00\00\09\00\00\00\20\00\00\00\1c\00\00\00\40\00\00\00\11\00\00\00")
+    (func (export "name") (result i32) (i32.const 256))
+    (func (export "description") (result i32) (i32.const 264))
+    (func (export "parameters") (result i32) (i32.const 272))
+    (func (export "execute") (param i32 i32) (result i32)
+      (call $read_file (local.get 0) (local.get 1) (i32.const 512))
+      (i32.const 512)))
+  (core instance $main (instantiate $main
+    (with "libc" (instance $libc))
+    (with "host" (instance (export "read-file" (func $read_file))))))
+
+  (func (export "name") (result string)
+    (canon lift (core func $main "name") (memory (core memory $libc "memory"))))
+  (func (export "description") (result string)
+    (canon lift (core func $main "description") (memory (core memory $libc "memory"))))
+  (func (export "parameters") (result string)
+    (canon lift (core func $main "parameters") (memory (core memory $libc "memory"))))
+  (func (export "execute") (param "arguments" string) (result (result string (error string)))
+    (canon lift (core func $main "execute") (memory (core memory $libc "memory")) (realloc (core func $libc "realloc"))))
+)
+"#;
+
+    async fn load(capabilities: &[&str]) -> (tempfile::TempDir, WasmPluginTool) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "会议纪要").unwrap();
+        let plugin_path = dir.path().join("read_note.wat");
+        std::fs::write(&plugin_path, READ_NOTE_PLUGIN).unwrap();
+
+        let config = PluginConfig {
+            path: plugin_path,
+            capabilities: capabilities.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        };
+        let policy = Arc::new(PathPolicy::for_workspace(dir.path()).unwrap());
+        let tool = WasmPluginTool::load(&config, policy).await.unwrap();
+        (dir, tool)
+    }
+
+    #[tokio::test]
+    async fn test_plugin_metadata_and_granted_capability() {
+        let (_dir, tool) = load(&["fs-read"]).await;
+        assert_eq!(tool.name(), "read_note");
+        assert_eq!(tool.description(), "Read a file through the host");
+        assert_eq!(tool.parameters()["type"], "object");
+
+        assert_eq!(tool.execute_json("notes.txt").await.unwrap(), Ok("会议纪要".to_string()));
+
+        // 文件访问同样受路径策略约束
+        let denied = tool.execute_json("../outside.txt").await.unwrap().unwrap_err();
+        assert!(denied.contains("access denied by policy"));
+    }
+
+    #[tokio::test]
+    async fn test_plugin_without_capability_is_refused() {
+        let (_dir, tool) = load(&[]).await;
+        let err = tool.execute_json("notes.txt").await.unwrap().unwrap_err();
+        assert!(err.contains("fs-read"));
+
+        assert!(Capabilities::parse(&["network".to_string()]).is_err());
+    }
+}
@@ -2,7 +2,6 @@
 
 use simple_ai_agent::agent::Agent;
 use simple_ai_agent::client::ModelClient;
-use tokio;
 
 #[tokio::test]
 async fn test_basic_conversation() {
@@ -10,7 +9,7 @@ async fn test_basic_conversation() {
         "test-key".to_string(),
         "gpt-4".to_string(),
     );
-    let mut agent = Agent::new(model_client);
+    let mut agent = Agent::new(model_client).unwrap();
 
     // 模拟简单对话
     let result: Result<String, anyhow::Error> = agent
@@ -28,7 +27,7 @@ async fn test_tool_registration() {
         "test-key".to_string(),
         "gpt-4".to_string(),
     );
-    let agent = Agent::new(model_client);
+    let agent = Agent::new(model_client).unwrap();
 
     // 验证工具已注册
     let status = agent.get_status().await;